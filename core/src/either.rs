@@ -128,6 +128,13 @@ where
             EitherOutput::B(b) => b.remote_pub_key(),
         }
     }
+
+    fn session_id(&self) -> Option<[u8; 32]> {
+        match self {
+            EitherOutput::A(a) => a.session_id(),
+            EitherOutput::B(b) => b.session_id(),
+        }
+    }
 }
 
 impl<A, B> StreamInfo for EitherOutput<A, B>
@@ -48,6 +48,16 @@ impl Keypair {
         Ok(Keypair(Arc::new(kp)))
     }
 
+    /// Decode an RSA keypair from a DER-encoded private key in PKCS#1 RSAPrivateKey
+    /// format as defined in [RFC3447].
+    ///
+    /// [RFC3447]: https://tools.ietf.org/html/rfc3447#appendix-A.1.2
+    pub fn from_pkcs1(der: &mut [u8]) -> Result<Keypair, DecodingError> {
+        let kp = RsaKeyPair::from_der(&der).map_err(|e| DecodingError::new("RSA PKCS#1 RSAPrivateKey").source(e))?;
+        der.zeroize();
+        Ok(Keypair(Arc::new(kp)))
+    }
+
     /// Get the public key from the keypair.
     pub fn public(&self) -> PublicKey {
         PublicKey(self.0.public_key().as_ref().to_vec())
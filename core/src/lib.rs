@@ -39,6 +39,14 @@ pub mod keys_proto {
     include!(concat!(env!("OUT_DIR"), "/keys_proto.rs"));
 }
 
+mod envelope_proto {
+    include!(concat!(env!("OUT_DIR"), "/envelope_proto.rs"));
+}
+
+mod peer_record_proto {
+    include!(concat!(env!("OUT_DIR"), "/peer_record_proto.rs"));
+}
+
 // re-export multiaddr
 pub use libp2prs_multiaddr as multiaddr;
 // re-export multihash
@@ -52,6 +60,11 @@ pub mod multistream;
 pub use identity::PublicKey;
 pub use peer_id::PeerId;
 
+pub mod peer_record;
+pub mod signed_envelope;
+pub use peer_record::{verify_peer_records, PeerRecord};
+pub use signed_envelope::SignedEnvelope;
+
 pub mod transport;
 pub use libp2prs_multiaddr::Multiaddr;
 pub use transport::Transport;
@@ -70,4 +83,4 @@ pub mod peerstore;
 pub mod translation;
 
 pub mod util;
-pub use util::{ReadEx, WriteEx};
+pub use util::{Peekable, ReadEx, WriteEx};
@@ -42,6 +42,8 @@ pub struct LengthDelimited<R> {
     read_buffer: BytesMut,
     /// Write buffer for outgoing unsigned-varint length-delimited frames.
     write_buffer: BytesMut,
+    /// The maximum length of the incoming frames.
+    max_frame_size: u16,
 }
 
 impl<R: AsyncRead + AsyncWrite + Unpin> LengthDelimited<R> {
@@ -52,9 +54,16 @@ impl<R: AsyncRead + AsyncWrite + Unpin> LengthDelimited<R> {
             inner,
             read_buffer: BytesMut::with_capacity(DEFAULT_BUFFER_SIZE),
             write_buffer: BytesMut::with_capacity(DEFAULT_BUFFER_SIZE + MAX_LEN_BYTES as usize),
+            max_frame_size: MAX_FRAME_SIZE,
         }
     }
 
+    /// Sets the maximum length of the incoming frames, 16KiB at most. A longer frame is
+    /// rejected as soon as its length is read, without reading the frame itself.
+    pub fn set_max_frame_size(&mut self, size: usize) {
+        self.max_frame_size = size.min(MAX_FRAME_SIZE as usize) as u16;
+    }
+
     /// Drops the [`LengthDelimited`] resource, yielding the underlying I/O stream
     /// together with the remaining write buffer containing the uvi-framed data
     /// that has not yet been written to the underlying I/O stream.
@@ -87,7 +96,7 @@ impl<R: AsyncRead + AsyncWrite + Unpin> LengthDelimited<R> {
 
     pub async fn recv_message(&mut self) -> io::Result<Bytes> {
         let len = self.read_unsigned_varint().await?;
-        if len > MAX_FRAME_SIZE {
+        if len > self.max_frame_size {
             return Err(io::Error::new(io::ErrorKind::InvalidData, "Maximum frame length exceeded"));
         }
         let buf = &mut self.read_buffer;
@@ -100,18 +109,35 @@ impl<R: AsyncRead + AsyncWrite + Unpin> LengthDelimited<R> {
     }
 
     pub async fn send_message(&mut self, buf: Bytes) -> io::Result<()> {
+        self.write_buffer.clear();
+        self.encode_frame(buf)?;
+        self.inner.write_all(&self.write_buffer).await?;
+        self.inner.flush().await
+    }
+
+    /// Sends the frames with a single write, so that they are likely to reach the remote
+    /// in one packet.
+    pub async fn send_messages(&mut self, bufs: Vec<Bytes>) -> io::Result<()> {
+        self.write_buffer.clear();
+        for buf in bufs {
+            self.encode_frame(buf)?;
+        }
+        self.inner.write_all(&self.write_buffer).await?;
+        self.inner.flush().await
+    }
+
+    /// Appends the frame to the write buffer.
+    fn encode_frame(&mut self, buf: Bytes) -> io::Result<()> {
         let len = match u16::try_from(buf.len()) {
             Ok(len) if len <= MAX_FRAME_SIZE => len,
             _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Maximum frame size exceeded.")),
         };
         let mut uvi_buf = unsigned_varint::encode::u16_buffer();
         let uvi_len = unsigned_varint::encode::u16(len, &mut uvi_buf);
-        self.write_buffer.clear();
         self.write_buffer.reserve(len as usize + uvi_len.len());
         self.write_buffer.put(uvi_len);
         self.write_buffer.put(buf);
-        self.inner.write_all(&self.write_buffer).await?;
-        self.inner.flush().await
+        Ok(())
     }
 }
 
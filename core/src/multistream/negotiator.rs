@@ -24,13 +24,22 @@ use std::{fmt, io};
 
 use super::protocol::{Message, MessageIO, Protocol, ProtocolError, Version};
 
+/// The default maximum length (in bytes) of the protocol names received.
+const DEFAULT_MAX_PROTOCOL_LEN: usize = 256;
+
 pub struct Negotiator<TProto> {
     protocols: Vec<(TProto, Protocol)>,
+    version: Version,
+    max_protocol_len: usize,
 }
 
 impl<TProto> Default for Negotiator<TProto> {
     fn default() -> Self {
-        Negotiator { protocols: Vec::new() }
+        Negotiator {
+            protocols: Vec::new(),
+            version: Version::default(),
+            max_protocol_len: DEFAULT_MAX_PROTOCOL_LEN,
+        }
     }
 }
 
@@ -54,7 +63,24 @@ impl<TProto: AsRef<[u8]> + Clone + fmt::Debug> Negotiator<TProto> {
             })
             .collect();
 
-        Negotiator { protocols }
+        Negotiator {
+            protocols,
+            version: Version::default(),
+            max_protocol_len: DEFAULT_MAX_PROTOCOL_LEN,
+        }
+    }
+
+    /// Sets the multistream-select version used as a dialer, `V1` by default.
+    ///
+    /// As a listener, lazy dialers are always supported.
+    pub fn set_version(&mut self, version: Version) {
+        self.version = version;
+    }
+
+    /// Sets the maximum length of the protocol names received from the remote, 256 bytes
+    /// by default. The negotiation fails on a longer one, which is never read into memory.
+    pub fn set_max_protocol_len(&mut self, len: usize) {
+        self.max_protocol_len = len;
     }
 
     pub fn add_protocol(&mut self, proto: TProto) -> Result<(), ProtocolError> {
@@ -63,11 +89,33 @@ impl<TProto: AsRef<[u8]> + Clone + fmt::Debug> Negotiator<TProto> {
         Ok(())
     }
 
+    /// Sets the exact order of the protocols to propose as a dialer, or to list as a listener.
+    ///
+    /// Only the protocols in `order` are kept. The ones unknown to the negotiator are ignored.
+    pub fn set_order<Iter, P>(&mut self, order: Iter)
+    where
+        Iter: IntoIterator<Item = P>,
+        P: AsRef<[u8]>,
+    {
+        let mut protocols = std::mem::take(&mut self.protocols);
+        self.protocols = order
+            .into_iter()
+            .filter_map(|name| {
+                let pos = protocols.iter().position(|(n, _)| n.as_ref() == name.as_ref());
+                if pos.is_none() {
+                    log::warn!("Ignoring unknown protocol in order: {}", String::from_utf8_lossy(name.as_ref()));
+                }
+                pos.map(|i| protocols.remove(i))
+            })
+            .collect();
+    }
+
     pub async fn negotiate<TSocket>(&self, socket: TSocket) -> Result<(TProto, TSocket), NegotiationError>
     where
         TSocket: AsyncRead + AsyncWrite + Unpin,
     {
         let mut io = MessageIO::new(socket);
+        io.set_max_protocol_len(self.max_protocol_len);
         let msg = io.recv_message().await?;
         let version = if let Message::Header(v) = msg {
             v
@@ -118,21 +166,32 @@ impl<TProto: AsRef<[u8]> + Clone + fmt::Debug> Negotiator<TProto> {
         TSocket: AsyncRead + AsyncWrite + Unpin,
     {
         let mut io = MessageIO::new(socket);
+        io.set_max_protocol_len(self.max_protocol_len);
 
-        let version = Version::default();
-        io.send_message(Message::Header(version)).await?;
+        // V1Lazy piggybacks the first proposal on the header
+        let lazy = self.version == Version::V1Lazy && !self.protocols.is_empty();
+        if lazy {
+            let proto = &self.protocols[0];
+            io.send_messages(vec![Message::Header(Version::V1), Message::Protocol(proto.1.clone())])
+                .await?;
+            log::debug!("Dialer: Proposed protocol lazily: {}", proto.1);
+        } else {
+            io.send_message(Message::Header(Version::V1)).await?;
+        }
 
         let msg = io.recv_message().await?;
 
-        if msg != Message::Header(version) {
+        if msg != Message::Header(Version::V1) {
             return Err(ProtocolError::InvalidMessage.into());
         }
 
         let mut cause = vec![];
 
-        for proto in &self.protocols {
-            io.send_message(Message::Protocol(proto.1.clone())).await?;
-            log::debug!("Dialer: Proposed protocol: {}", proto.1);
+        for (i, proto) in self.protocols.iter().enumerate() {
+            if !lazy || i > 0 {
+                io.send_message(Message::Protocol(proto.1.clone())).await?;
+                log::debug!("Dialer: Proposed protocol: {}", proto.1);
+            }
             let msg = io.recv_message().await?;
 
             match msg {
@@ -50,8 +50,6 @@ const MAX_PROTOCOL_LEN: usize = 140;
 
 /// The encoded form of a multistream-select 1.0.0 header message.
 const MSG_MULTISTREAM_1_0: &[u8] = b"/multistream/1.0.0\n";
-/// The encoded form of a multistream-select 1.0.0 header message.
-//const MSG_MULTISTREAM_1_0_LAZY: &[u8] = b"/multistream-lazy/1\n";
 /// The encoded form of a multistream-select 'na' message.
 const MSG_PROTOCOL_NA: &[u8] = b"na\n";
 /// The encoded form of a multistream-select 'ls' message.
@@ -65,49 +63,12 @@ pub enum Version {
     /// [1]: https://github.com/libp2p/specs/blob/master/connections/README.md#protocol-negotiation
     /// [2]: https://github.com/multiformats/multistream-select
     V1,
-    // /// A lazy variant of version 1 that is identical on the wire but delays
-    // /// sending of protocol negotiation data as much as possible.
-    // ///
-    // /// Delaying the sending of protocol negotiation data can result in
-    // /// significantly fewer network roundtrips used for the negotiation,
-    // /// up to 0-RTT negotiation.
-    // ///
-    // /// 0-RTT negotiation is achieved if the dialer supports only a single
-    // /// application protocol. In that case the dialer immedidately settles
-    // /// on that protocol, buffering the negotiation messages to be sent
-    // /// with the first round of application protocol data (or an attempt
-    // /// is made to read from the `Negotiated` I/O stream).
-    // ///
-    // /// A listener receiving a `V1Lazy` header will similarly delay sending
-    // /// of the protocol confirmation.  Though typically the listener will need
-    // /// to read the request data before sending its response, thus triggering
-    // /// sending of the protocol confirmation, which, in absence of additional
-    // /// buffering on lower layers will result in at least two response frames
-    // /// to be sent.
-    // ///
-    // /// `V1Lazy` is specific to `rust-libp2p`: While the wire protocol
-    // /// is identical to `V1`, delayed sending of protocol negotiation frames
-    // /// is only safe under the following assumptions:
-    // ///
-    // ///   1. The dialer is assumed to always send the first multistream-select
-    // ///      protocol message immediately after the multistream header, without
-    // ///      first waiting for confirmation of that header. Since the listener
-    // ///      delays sending the protocol confirmation, a deadlock situation may
-    // ///      otherwise occurs that is only resolved by a timeout. This assumption
-    // ///      is trivially satisfied if both peers support and use `V1Lazy`.
-    // ///
-    // ///   2. When nesting multiple protocol negotiations, the listener is either
-    // ///      known to support all of the dialer's optimistically chosen protocols
-    // ///      or there is no intermediate protocol without a payload and none of
-    // ///      the protocol payloads has the potential for being mistaken for a
-    // ///      multistream-select protocol message. This avoids rare edge-cases whereby
-    // ///      the listener may not recognize upgrade boundaries and erroneously
-    // ///      process a request despite not supporting one of the intermediate
-    // ///      protocols that the dialer committed to. See [1] and [2].
-    // ///
-    // /// [1]: https://github.com/multiformats/go-multistream/issues/20
-    // /// [2]: https://github.com/libp2p/rust-libp2p/pull/1212
-    // V1Lazy,
+    /// Version 1 with the first protocol proposal sent along with the header, without
+    /// waiting for the header to be confirmed. It is identical to `V1` on the wire.
+    ///
+    /// If the remote confirms the first protocol, the negotiation completes in a single
+    /// round trip. Otherwise the negotiation goes on with the remaining protocols as in `V1`.
+    V1Lazy,
     // Draft: https://github.com/libp2p/specs/pull/95
     // V2,
 }
@@ -177,18 +138,11 @@ impl Message {
     /// Encodes a `Message` into its byte representation.
     pub fn encode(&self, dest: &mut BytesMut) -> Result<(), ProtocolError> {
         match self {
-            Message::Header(Version::V1) => {
+            Message::Header(Version::V1) | Message::Header(Version::V1Lazy) => {
                 dest.reserve(MSG_MULTISTREAM_1_0.len());
                 dest.put(MSG_MULTISTREAM_1_0);
                 Ok(())
             }
-            /*
-            Message::Header(Version::V1Lazy) => {
-                dest.reserve(MSG_MULTISTREAM_1_0_LAZY.len());
-                dest.put(MSG_MULTISTREAM_1_0_LAZY);
-                Ok(())
-            }
-             */
             Message::Protocol(p) => {
                 let len = p.0.as_ref().len() + 1; // + 1 for \n
                 dest.reserve(len);
@@ -223,12 +177,6 @@ impl Message {
 
     /// Decodes a `Message` from its byte representation.
     pub fn decode(mut msg: Bytes) -> Result<Message, ProtocolError> {
-        /*
-        if msg == MSG_MULTISTREAM_1_0_LAZY {
-            return Ok(Message::Header(Version::V1Lazy))
-        }
-         */
-
         if msg == MSG_MULTISTREAM_1_0 {
             return Ok(Message::Header(Version::V1));
         }
@@ -303,6 +251,13 @@ where
         self.inner.into_inner()
     }
 
+    /// Sets the maximum length of the protocol names received. The longer messages are
+    /// rejected before being read, except for the multistream-select header.
+    pub fn set_max_protocol_len(&mut self, len: usize) {
+        // + 1 for \n
+        self.inner.set_max_frame_size((len + 1).max(MSG_MULTISTREAM_1_0.len()));
+    }
+
     pub async fn recv_message(&mut self) -> Result<Message, ProtocolError> {
         let msg = self.inner.recv_message().await?;
         Message::decode(msg)
@@ -313,6 +268,17 @@ where
         msg.encode(&mut buf)?;
         self.inner.send_message(buf.freeze()).await.map_err(From::from)
     }
+
+    /// Sends the messages with a single write.
+    pub async fn send_messages(&mut self, msgs: Vec<Message>) -> Result<(), ProtocolError> {
+        let mut bufs = Vec::with_capacity(msgs.len());
+        for msg in msgs {
+            let mut buf = BytesMut::new();
+            msg.encode(&mut buf)?;
+            bufs.push(buf.freeze());
+        }
+        self.inner.send_messages(bufs).await.map_err(From::from)
+    }
 }
 
 /// A protocol error.
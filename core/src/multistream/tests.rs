@@ -24,7 +24,8 @@
 #![cfg(test)]
 
 use super::negotiator::Negotiator;
-use super::{NegotiationError, Version};
+use super::protocol::{Message, MessageIO};
+use super::{NegotiationError, ProtocolError, Version};
 
 use bytes::Bytes;
 use futures::channel::mpsc;
@@ -217,3 +218,203 @@ fn select_proto_serial() {
 
     task::block_on(run(Version::V1));
 }
+
+#[test]
+fn select_proto_ordered() {
+    async fn run(_version: Version) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let listener_addr = listener.local_addr().unwrap();
+
+        // a listener which records and rejects all proposed protocols
+        let server = task::spawn(async move {
+            let connec = listener.accept().await.unwrap().0;
+            let mut io = MessageIO::new(connec);
+            let version = io.recv_message().await.unwrap();
+            io.send_message(version).await.unwrap();
+
+            let mut proposed = vec![];
+            while let Ok(Message::Protocol(p)) = io.recv_message().await {
+                proposed.push(p.to_string());
+                io.send_message(Message::NotAvailable).await.unwrap();
+            }
+            proposed
+        });
+
+        let client = task::spawn(async move {
+            let connec = TcpStream::connect(&listener_addr).await.unwrap();
+            let mut neg = Negotiator::new_with_protocols(vec!["/proto1", "/proto2", "/proto3", "/proto4"]);
+            neg.set_order(vec!["/proto3", "/proto5", "/proto1", "/proto2"]);
+            match neg.select_one(connec).await {
+                Err(NegotiationError::Failed(cause)) => assert_eq!(cause, vec!["\"/proto3\"", "\"/proto1\"", "\"/proto2\""]),
+                _ => panic!(),
+            }
+        });
+
+        client.await;
+        assert_eq!(server.await.unwrap(), vec!["/proto3", "/proto1", "/proto2"]);
+    }
+
+    task::block_on(run(Version::V1));
+}
+
+#[test]
+fn select_proto_lazy() {
+    async fn run(version: Version) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let listener_addr = listener.local_addr().unwrap();
+
+        // a listener which only responds once it has got both the header and the proposal,
+        // a dialer waiting for the header confirmation before proposing would hang
+        let server = task::spawn(async move {
+            let connec = listener.accept().await.unwrap().0;
+            let mut io = MessageIO::new(connec);
+            let header = io.recv_message().await.unwrap();
+            let proposal = io.recv_message().await.unwrap();
+            io.send_messages(vec![header, proposal.clone()]).await.unwrap();
+
+            let mut io = io.into_inner();
+            let mut out = vec![0; 32];
+            let n = io.read(&mut out).await.unwrap();
+            out.truncate(n);
+            assert_eq!(out, b"ping");
+            proposal
+        });
+
+        let client = task::spawn(async move {
+            let connec = TcpStream::connect(&listener_addr).await.unwrap();
+            let mut neg = Negotiator::new_with_protocols(vec!["/proto2"]);
+            neg.set_version(version);
+            let (proto, mut io) = neg.select_one(connec).await.expect("select_one");
+            assert_eq!(proto, "/proto2");
+
+            io.write_all(b"ping").await.unwrap();
+            io.flush().await.unwrap();
+        });
+
+        client.await;
+        let proposal = server.await.unwrap();
+        assert!(matches!(proposal, Message::Protocol(p) if p.to_string() == "/proto2"));
+    }
+
+    task::block_on(run(Version::V1Lazy));
+}
+
+#[test]
+fn select_proto_lazy_fallback() {
+    async fn run(version: Version) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let listener_addr = listener.local_addr().unwrap();
+
+        let server = task::spawn(async move {
+            let connec = listener.accept().await.unwrap().0;
+            let neg = Negotiator::new_with_protocols(vec!["/proto1", "/proto2"]);
+            let (proto, _) = neg.negotiate(connec).await.expect("negotiate");
+            assert_eq!(proto, "/proto2");
+        });
+
+        let client = task::spawn(async move {
+            let connec = TcpStream::connect(&listener_addr).await.unwrap();
+            let mut neg = Negotiator::new_with_protocols(vec!["/proto3", "/proto2"]);
+            neg.set_version(version);
+            // the lazily proposed protocol is rejected, negotiation goes on as V1
+            let (proto, _) = neg.select_one(connec).await.expect("select_one");
+            assert_eq!(proto, "/proto2");
+        });
+
+        server.await;
+        client.await;
+    }
+
+    task::block_on(run(Version::V1Lazy));
+}
+
+/// Counts the round trips of a socket, i.e. the reads which follow a write.
+struct RoundTrips<S> {
+    inner: S,
+    writing: bool,
+    count: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for RoundTrips<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        if self.writing {
+            self.writing = false;
+            self.count.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for RoundTrips<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.writing = true;
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+#[test]
+fn select_proto_lazy_saves_round_trip() {
+    async fn run(version: Version) -> usize {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let listener_addr = listener.local_addr().unwrap();
+
+        let server = task::spawn(async move {
+            let connec = listener.accept().await.unwrap().0;
+            let neg = Negotiator::new_with_protocols(vec!["/proto1", "/proto2"]);
+            let (proto, _) = neg.negotiate(connec).await.expect("negotiate");
+            assert_eq!(proto, "/proto1");
+        });
+
+        let count = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let connec = RoundTrips {
+            inner: TcpStream::connect(&listener_addr).await.unwrap(),
+            writing: false,
+            count: count.clone(),
+        };
+        let mut neg = Negotiator::new_with_protocols(vec!["/proto1"]);
+        neg.set_version(version);
+        let (proto, _) = neg.select_one(connec).await.expect("select_one");
+        assert_eq!(proto, "/proto1");
+
+        server.await;
+        count.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    task::block_on(async {
+        assert_eq!(run(Version::V1).await, 2);
+        assert_eq!(run(Version::V1Lazy).await, 1);
+    });
+}
+
+#[test]
+fn protocol_name_too_long() {
+    task::block_on(async {
+        let (server_io, client_io) = Memory::pair();
+
+        let server = task::spawn(async move {
+            let neg = Negotiator::new_with_protocols(vec!["/proto1"]);
+            neg.negotiate(server_io).await.map(|(proto, _)| proto)
+        });
+
+        let mut io = MessageIO::new(client_io);
+        io.send_message(Message::Header(Version::V1)).await.unwrap();
+        assert_eq!(io.recv_message().await.unwrap(), Message::Header(Version::V1));
+
+        // only the length of a 300 bytes protocol name is sent, the name itself never comes
+        let mut client_io = io.into_inner();
+        client_io.write_all(&[0xac, 0x02]).await.unwrap();
+
+        match server.await.unwrap() {
+            Err(NegotiationError::ProtocolError(ProtocolError::IoError(e))) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+            r => panic!("unexpected result {:?}", r),
+        }
+    });
+}
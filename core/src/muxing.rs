@@ -62,6 +62,10 @@ use futures::future::BoxFuture;
 use crate::secure_io::SecureInfo;
 use crate::transport::{ConnectionInfo, TransportError};
 use futures::{AsyncRead, AsyncWrite};
+use std::io;
+
+#[cfg(feature = "throughput")]
+pub mod throughput;
 
 /// StreamInfo returns the information of a substream opened by stream muxer.
 ///
@@ -71,12 +75,42 @@ pub trait StreamInfo: Send {
     fn id(&self) -> usize;
 }
 
+/// The flow control state of a substream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FlowState {
+    /// The number of bytes we are allowed to send before the remote grants more credit.
+    pub send_credit: usize,
+    /// The number of bytes received but not read yet.
+    pub recv_buffered: usize,
+}
+
 /// The trait for IReadWrite. It can be made into a trait object `IReadWrite` used
 /// by Swarm Substream.
 /// `StreamInfo` must be supported.
 #[async_trait]
 pub trait ReadWriteEx: AsyncRead + AsyncWrite + StreamInfo + Unpin + std::fmt::Debug {
     fn box_clone(&self) -> IReadWrite;
+    /// Resets the stream abruptly, so that reading on the remote end fails instead of
+    /// reaching a clean EOF.
+    ///
+    /// Returns an error if the stream muxer is not capable of resetting a stream.
+    async fn reset(&mut self) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Other, "stream reset is not supported"))
+    }
+    /// Returns the flow control state of the stream, so that the application can pace
+    /// its sends.
+    ///
+    /// Returns `None` if the stream muxer doesn't have flow control.
+    fn flow_state(&self) -> Option<FlowState> {
+        None
+    }
+    /// Takes the data received but not read yet, without waiting for more. It can be
+    /// called after closing the stream, to recover what the remote sent before closing.
+    ///
+    /// Returns nothing if the stream muxer doesn't expose its receive buffer.
+    fn drain_buffer(&mut self) -> Vec<u8> {
+        Vec::new()
+    }
 }
 
 pub type IReadWrite = Box<dyn ReadWriteEx>;
@@ -119,6 +153,16 @@ pub trait StreamMuxer {
     async fn accept_stream(&mut self) -> Result<IReadWrite, TransportError>;
     /// Closes the stream muxer, the runtime of stream muxer will then exit.
     async fn close(&mut self) -> Result<(), TransportError>;
+    /// Waits until the frames queued so far by all substreams have been written to the
+    /// underlying connection, so that they reach the remote before anything sent afterwards.
+    ///
+    /// Returns an error if the stream muxer is not capable of it.
+    async fn flush_barrier(&mut self) -> Result<(), TransportError> {
+        Err(TransportError::IoError(io::Error::new(
+            io::ErrorKind::Other,
+            "flush barrier is not supported",
+        )))
+    }
     /// Returns a Future which represents the main loop of the stream muxer.
     fn task(&mut self) -> Option<BoxFuture<'static, ()>>;
     /// Returns the cloned Trait object.
@@ -0,0 +1,194 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! A throughput harness for stream muxers.
+//!
+//! Given an established pair of stream muxers, the dialer side opens a number of
+//! concurrent substreams and sends a payload over each of them, which the listener
+//! side reads and acknowledges. The aggregate throughput and the per-stream fairness
+//! are reported, so that yamux and mplex can be compared against each other and
+//! against earlier runs.
+//!
+//! The harness doesn't spawn any task, it drives the muxer tasks by itself. Hence it
+//! works with any runtime, and the numbers reflect the muxer running on a single thread.
+//!
+//! ```ignore
+//! let report = throughput::run(dialer, listener, throughput::Config::new().streams(100)).await?;
+//! println!("{:.2} MB/s, fairness {:.3}", report.throughput(), report.fairness());
+//! ```
+
+use futures::future::{self, Either};
+use futures::{AsyncReadExt, AsyncWriteExt};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::muxing::IStreamMuxer;
+use crate::transport::TransportError;
+
+/// The parameters of a throughput run.
+#[derive(Clone, Debug)]
+pub struct Config {
+    streams: usize,
+    payload: usize,
+    chunk_size: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            streams: 10,
+            payload: 1024 * 1024,
+            chunk_size: 16 * 1024,
+        }
+    }
+}
+
+impl Config {
+    /// Creates a configuration with 10 streams transferring 1MB each.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of concurrent streams.
+    pub fn streams(mut self, n: usize) -> Self {
+        self.streams = n;
+        self
+    }
+
+    /// Sets the number of bytes transferred over each stream.
+    pub fn payload(mut self, size: usize) -> Self {
+        self.payload = size;
+        self
+    }
+
+    /// Sets the size of each write on the streams.
+    pub fn chunk_size(mut self, size: usize) -> Self {
+        self.chunk_size = size.max(1);
+        self
+    }
+}
+
+/// The outcome of a throughput run.
+#[derive(Clone, Debug)]
+pub struct Report {
+    /// The total bytes transferred over all streams.
+    pub bytes: usize,
+    /// The time taken by the whole run.
+    pub elapsed: Duration,
+    /// The time taken by each stream to complete its transfer.
+    pub per_stream: Vec<Duration>,
+}
+
+impl Report {
+    /// Returns the aggregate throughput in MB/s.
+    pub fn throughput(&self) -> f64 {
+        self.bytes as f64 / (1024.0 * 1024.0) / self.elapsed.as_secs_f64()
+    }
+
+    /// Returns the Jain's fairness index of the per-stream throughput.
+    ///
+    /// It is 1.0 when all streams are served equally, down to 1/N when a single
+    /// stream takes all the bandwidth.
+    pub fn fairness(&self) -> f64 {
+        if self.per_stream.is_empty() {
+            return 1.0;
+        }
+        // the payload is the same for all streams, the rate is the reciprocal of the time taken
+        let rates = self.per_stream.iter().map(|d| 1.0 / d.as_secs_f64().max(f64::EPSILON));
+        let (sum, sum_sq) = rates.fold((0.0, 0.0), |(s, sq), r| (s + r, sq + r * r));
+        sum * sum / (self.per_stream.len() as f64 * sum_sq)
+    }
+}
+
+/// Runs the throughput test over the dialer and listener side of a connection.
+///
+/// Both stream muxers must be freshly upgraded, their tasks are taken and driven
+/// by the harness. The connection is closed when the run is over.
+pub async fn run(mut dialer: IStreamMuxer, mut listener: IStreamMuxer, config: Config) -> Result<Report, TransportError> {
+    let tasks = dialer.task().into_iter().chain(listener.task()).collect::<Vec<_>>();
+    let driver = future::join_all(tasks);
+
+    let work = async {
+        let data = Arc::new(vec![0x42u8; config.payload]);
+        let start = Instant::now();
+
+        let senders = (0..config.streams).map(|_| send(dialer.clone(), data.clone(), config.chunk_size, start));
+        let receivers = (0..config.streams).map(|_| receive(listener.clone()));
+        let (per_stream, _) = future::try_join(future::try_join_all(senders), future::try_join_all(receivers)).await?;
+        let elapsed = start.elapsed();
+
+        let _ = dialer.close().await;
+
+        Ok(Report {
+            bytes: config.streams * config.payload,
+            elapsed,
+            per_stream,
+        })
+    };
+
+    futures::pin_mut!(work);
+    match future::select(work, driver).await {
+        Either::Left((r, _)) => r,
+        // the muxer tasks exit only if the connection is gone
+        Either::Right(_) => Err(TransportError::Internal),
+    }
+}
+
+// opens a stream, sends the length-prefixed payload and waits for the acknowledgement
+async fn send(mut muxer: IStreamMuxer, data: Arc<Vec<u8>>, chunk_size: usize, start: Instant) -> Result<Duration, TransportError> {
+    let mut stream = muxer.open_stream().await?;
+    stream.write_all(&(data.len() as u32).to_be_bytes()).await?;
+    for chunk in data.chunks(chunk_size) {
+        stream.write_all(chunk).await?;
+    }
+    stream.flush().await?;
+
+    let mut ack = [0u8; 4];
+    stream.read_exact(&mut ack).await?;
+    let elapsed = start.elapsed();
+    if u32::from_be_bytes(ack) as usize != data.len() {
+        return Err(TransportError::Internal);
+    }
+
+    let _ = stream.close().await;
+    Ok(elapsed)
+}
+
+// accepts a stream, reads the length-prefixed payload and acknowledges it with the number of bytes read
+async fn receive(mut muxer: IStreamMuxer) -> Result<(), TransportError> {
+    let mut stream = muxer.accept_stream().await?;
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).await?;
+
+    let mut remaining = u32::from_be_bytes(len) as usize;
+    let mut buf = vec![0u8; 64 * 1024];
+    while remaining > 0 {
+        let n = stream.read(&mut buf[..remaining.min(buf.len())]).await?;
+        if n == 0 {
+            return Err(TransportError::Internal);
+        }
+        remaining -= n;
+    }
+
+    stream.write_all(&len).await?;
+    stream.flush().await?;
+    let _ = stream.close().await;
+    Ok(())
+}
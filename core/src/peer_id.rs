@@ -19,8 +19,13 @@
 // DEALINGS IN THE SOFTWARE.
 
 use crate::PublicKey;
+use fnv::FnvHashMap;
+use lazy_static::lazy_static;
 use multihash::{Code, Error, Multihash, MultihashDigest};
+use parking_lot::Mutex;
 use rand::Rng;
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::{convert::TryFrom, fmt, str::FromStr};
 use thiserror::Error;
 
@@ -28,6 +33,128 @@ use thiserror::Error;
 /// automatically used as the peer id using an identity multihash.
 const MAX_INLINE_KEY_LENGTH: usize = 42;
 
+/// The default number of the peer ids kept by the cache of the hashed public keys.
+const DEFAULT_CACHE_CAPACITY: usize = 256;
+
+lazy_static! {
+    /// The peer ids derived from the public keys too long to be inlined, by the
+    /// encoding of the keys.
+    static ref PEER_ID_CACHE: Mutex<PeerIdCache> = Mutex::new(PeerIdCache::default());
+}
+
+static CACHE_CAPACITY: AtomicUsize = AtomicUsize::new(DEFAULT_CACHE_CAPACITY);
+
+#[cfg(test)]
+static CACHE_HITS: AtomicUsize = AtomicUsize::new(0);
+
+/// The end of the list of the cached peer ids.
+const NIL: usize = usize::MAX;
+
+/// A cached peer id, linked in the order of use.
+struct CacheEntry {
+    key_enc: Vec<u8>,
+    peer_id: PeerId,
+    prev: usize,
+    next: usize,
+}
+
+/// A bounded cache of peer ids, evicting the least recently used one when full.
+///
+/// The entries are linked from the most recently used to the least, by their index in
+/// `entries`, so that both the lookup and the eviction take constant time.
+struct PeerIdCache {
+    index: FnvHashMap<Vec<u8>, usize>,
+    entries: Vec<CacheEntry>,
+    /// The slots in `entries` of the evicted peer ids, to be reused.
+    free: Vec<usize>,
+    head: usize,
+    tail: usize,
+}
+
+impl Default for PeerIdCache {
+    fn default() -> Self {
+        PeerIdCache {
+            index: FnvHashMap::default(),
+            entries: Vec::new(),
+            free: Vec::new(),
+            head: NIL,
+            tail: NIL,
+        }
+    }
+}
+
+impl PeerIdCache {
+    fn get(&mut self, key_enc: &[u8]) -> Option<PeerId> {
+        let i = *self.index.get(key_enc)?;
+        self.unlink(i);
+        self.push_front(i);
+        Some(self.entries[i].peer_id)
+    }
+
+    fn insert(&mut self, key_enc: Vec<u8>, peer_id: PeerId, capacity: usize) {
+        // hashed by another thread in the meantime
+        if self.get(&key_enc).is_some() {
+            return;
+        }
+        self.shrink_to(capacity - 1);
+
+        let entry = CacheEntry {
+            key_enc: key_enc.clone(),
+            peer_id,
+            prev: NIL,
+            next: NIL,
+        };
+        let i = match self.free.pop() {
+            Some(i) => {
+                self.entries[i] = entry;
+                i
+            }
+            None => {
+                self.entries.push(entry);
+                self.entries.len() - 1
+            }
+        };
+        self.push_front(i);
+        self.index.insert(key_enc, i);
+    }
+
+    /// Evicts the least recently used peer ids until at most `capacity` are left.
+    fn shrink_to(&mut self, capacity: usize) {
+        while self.index.len() > capacity {
+            let i = self.tail;
+            self.unlink(i);
+            let key_enc = std::mem::take(&mut self.entries[i].key_enc);
+            self.index.remove(&key_enc);
+            self.free.push(i);
+        }
+    }
+
+    fn unlink(&mut self, i: usize) {
+        let (prev, next) = (self.entries[i].prev, self.entries[i].next);
+        if prev == NIL {
+            self.head = next;
+        } else {
+            self.entries[prev].next = next;
+        }
+        if next == NIL {
+            self.tail = prev;
+        } else {
+            self.entries[next].prev = prev;
+        }
+    }
+
+    fn push_front(&mut self, i: usize) {
+        self.entries[i].prev = NIL;
+        self.entries[i].next = self.head;
+        if self.head == NIL {
+            self.tail = i;
+        } else {
+            self.entries[self.head].prev = i;
+        }
+        self.head = i;
+    }
+}
+
 /// Identifier of a peer of the network.
 ///
 /// The data is a multihash of the public key of the peer.
@@ -50,18 +177,41 @@ impl fmt::Display for PeerId {
 
 impl PeerId {
     /// Builds a `PeerId` from a public key.
+    ///
+    /// The peer ids hashed from the keys, e.g. the RSA ones, are cached, so that
+    /// deriving the peer id of the same key again doesn't hash it again.
     pub fn from_public_key(key: PublicKey) -> PeerId {
         let key_enc = key.into_protobuf_encoding();
 
-        let hash_algorithm = if key_enc.len() <= MAX_INLINE_KEY_LENGTH {
-            Code::Identity
-        } else {
-            Code::Sha2_256
-        };
+        if key_enc.len() <= MAX_INLINE_KEY_LENGTH {
+            let multihash = Code::Identity.digest(&key_enc);
+            return PeerId { multihash };
+        }
 
-        let multihash = hash_algorithm.digest(&key_enc);
+        let capacity = CACHE_CAPACITY.load(Ordering::Relaxed);
+        if capacity == 0 {
+            let multihash = Code::Sha2_256.digest(&key_enc);
+            return PeerId { multihash };
+        }
 
-        PeerId { multihash }
+        if let Some(peer_id) = PEER_ID_CACHE.lock().get(&key_enc) {
+            #[cfg(test)]
+            CACHE_HITS.fetch_add(1, Ordering::Relaxed);
+            return peer_id;
+        }
+
+        // hash without holding the lock
+        let multihash = Code::Sha2_256.digest(&key_enc);
+        let peer_id = PeerId { multihash };
+        PEER_ID_CACHE.lock().insert(key_enc, peer_id, capacity);
+        peer_id
+    }
+
+    /// Sets the maximum number of the peer ids cached by `from_public_key`, 0 disables
+    /// the cache. It is 256 by default.
+    pub fn set_cache_capacity(capacity: usize) {
+        CACHE_CAPACITY.store(capacity, Ordering::Relaxed);
+        PEER_ID_CACHE.lock().shrink_to(capacity);
     }
 
     /// Parses a `PeerId` from bytes.
@@ -111,6 +261,33 @@ impl PeerId {
         let enc = public_key.clone().into_protobuf_encoding();
         Some(alg.digest(&enc) == self.multihash)
     }
+
+    /// Returns the XOR distance between this and the other `PeerId`, as used by Kademlia.
+    ///
+    /// The distance is the XOR of the SHA-256 digests of the byte representations of both
+    /// peer IDs. Note the peer IDs are always hashed, no matter they are identity or SHA-256
+    /// multihashes, which is consistent with the keys of the Kademlia routing table.
+    pub fn distance(&self, other: &PeerId) -> [u8; 32] {
+        let a = Sha256::digest(&self.to_bytes());
+        let b = Sha256::digest(&other.to_bytes());
+        let mut distance = [0u8; 32];
+        for (d, (x, y)) in distance.iter_mut().zip(a.iter().zip(b.iter())) {
+            *d = x ^ y;
+        }
+        distance
+    }
+
+    /// Returns the number of leading zero bits of the distance to the other `PeerId`,
+    /// which is 256 for the `PeerId` itself.
+    ///
+    /// The bucket of `other` in a Kademlia routing table of `self` is indexed by it.
+    pub fn leading_zeros_of_distance(&self, other: &PeerId) -> u32 {
+        let distance = self.distance(other);
+        match distance.iter().position(|b| *b != 0) {
+            Some(i) => i as u32 * 8 + distance[i].leading_zeros(),
+            None => 256,
+        }
+    }
 }
 
 impl From<PublicKey> for PeerId {
@@ -189,7 +366,9 @@ impl FromStr for PeerId {
 
 #[cfg(test)]
 mod tests {
+    use super::{PeerIdCache, CACHE_HITS};
     use crate::{identity, PeerId};
+    use std::sync::atomic::Ordering;
 
     #[test]
     fn peer_id_is_public_key() {
@@ -212,6 +391,26 @@ mod tests {
         assert_eq!(peer_id, second);
     }
 
+    #[test]
+    fn peer_id_distance_to_self() {
+        let peer_id = PeerId::random();
+        assert_eq!(peer_id.distance(&peer_id), [0u8; 32]);
+        assert_eq!(peer_id.leading_zeros_of_distance(&peer_id), 256);
+    }
+
+    #[test]
+    fn peer_id_distance() {
+        let a: PeerId = "12D3KooWJ1TsijH7H5F74hfAD5XishQz3sxrmAtVY37GtNd9CqYf".parse().unwrap();
+        let b: PeerId = "QmYyQSo1c1Ym7orWxLYvCrM2EmxFTANf8wXmmE7DWjhx5N".parse().unwrap();
+        let expected = [
+            0x2e, 0x5c, 0x52, 0x39, 0xe5, 0xd5, 0x4b, 0x5e, 0x00, 0x6f, 0x32, 0x9b, 0x38, 0xc7, 0x4c, 0xc8, 0xd0, 0x57, 0x26, 0x2e,
+            0x57, 0x4b, 0x96, 0xa5, 0x6d, 0xe4, 0x95, 0x36, 0xce, 0x09, 0x9a, 0x0c,
+        ];
+        assert_eq!(a.distance(&b), expected);
+        assert_eq!(b.distance(&a), expected);
+        assert_eq!(a.leading_zeros_of_distance(&b), 2);
+    }
+
     #[test]
     fn random_peer_id_is_valid() {
         for _ in 0..5000 {
@@ -219,4 +418,42 @@ mod tests {
             assert_eq!(peer_id, PeerId::from_bytes(&peer_id.to_bytes()).unwrap());
         }
     }
+
+    #[test]
+    fn hashed_peer_id_is_cached() {
+        let mut der = include_bytes!("identity/test/rsa-2048.pk8").to_vec();
+        let key = identity::Keypair::rsa_from_pkcs8(&mut der).unwrap().public();
+
+        let first = key.clone().into_peer_id();
+        let hits = CACHE_HITS.load(Ordering::Relaxed);
+        let second = key.into_peer_id();
+        assert_eq!(first, second);
+        assert!(CACHE_HITS.load(Ordering::Relaxed) > hits);
+    }
+
+    #[test]
+    fn cache_evicts_least_recently_used() {
+        let mut cache = PeerIdCache::default();
+        let peers = (0..4).map(|_| PeerId::random()).collect::<Vec<_>>();
+        let key = |i: u8| vec![i; 64];
+
+        cache.insert(key(0), peers[0], 2);
+        cache.insert(key(1), peers[1], 2);
+        assert_eq!(cache.get(&key(0)), Some(peers[0]));
+        // the peer id of key 1 is the least recently used
+        cache.insert(key(2), peers[2], 2);
+        assert_eq!(cache.get(&key(1)), None);
+        assert_eq!(cache.get(&key(0)), Some(peers[0]));
+        assert_eq!(cache.get(&key(2)), Some(peers[2]));
+
+        // the slot of the evicted one is reused
+        cache.insert(key(3), peers[3], 2);
+        assert_eq!(cache.get(&key(0)), None);
+        assert_eq!(cache.entries.len(), 2);
+
+        cache.shrink_to(0);
+        assert_eq!(cache.get(&key(3)), None);
+        assert_eq!(cache.head, super::NIL);
+        assert_eq!(cache.tail, super::NIL);
+    }
 }
@@ -0,0 +1,350 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Signed peer records, carrying the addresses of a peer in a signed envelope.
+//!
+//! See https://github.com/libp2p/specs/blob/master/RFC/0003-routing-records.md.
+
+use crate::identity::error::{DecodingError, SigningError};
+use crate::identity::Keypair;
+use crate::signed_envelope::{ReadPayloadError, SignedEnvelope};
+use crate::{peer_record_proto, Multiaddr, PeerId};
+use libp2prs_multiaddr as multiaddr;
+use libp2prs_runtime::task;
+use prost::Message;
+use std::convert::TryFrom;
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+const DOMAIN_SEP: &str = "libp2p-routing-state";
+const PAYLOAD_TYPE: &[u8] = &[0x03, 0x01];
+
+/// The maximum length of an encoded peer record, larger inputs are rejected before decoding.
+pub const MAX_RECORD_SIZE: usize = 16 * 1024;
+
+/// The maximum number of tasks `verify_peer_records` verifies the records in.
+const MAX_VERIFY_TASKS: usize = 8;
+
+/// The addresses of a peer, signed by the peer itself.
+///
+/// The sequence number orders the records of the same peer, the latest one has the highest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerRecord {
+    peer_id: PeerId,
+    seq: u64,
+    addresses: Vec<Multiaddr>,
+    envelope: SignedEnvelope,
+}
+
+impl PeerRecord {
+    /// Creates a record of the addresses, signed by the key of the peer. The sequence number
+    /// is the current unix time, in seconds.
+    pub fn new(key: &Keypair, addresses: Vec<Multiaddr>) -> Result<Self, SigningError> {
+        let seq = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("now() is never before UNIX_EPOCH")
+            .as_secs();
+        let peer_id = key.public().into_peer_id();
+
+        let record = peer_record_proto::PeerRecord {
+            peer_id: peer_id.to_bytes(),
+            seq,
+            addresses: addresses
+                .iter()
+                .map(|addr| peer_record_proto::peer_record::AddressInfo { multiaddr: addr.to_vec() })
+                .collect(),
+        };
+        let mut payload = Vec::with_capacity(record.encoded_len());
+        record.encode(&mut payload).expect("Vec<u8> provides capacity as needed");
+
+        let envelope = SignedEnvelope::new(key, DOMAIN_SEP, PAYLOAD_TYPE.to_vec(), payload)?;
+        Ok(PeerRecord {
+            peer_id,
+            seq,
+            addresses,
+            envelope,
+        })
+    }
+
+    /// Decodes a record from the protobuf encoding of its envelope.
+    ///
+    /// The input is checked strictly, any malformed or oversized input, a payload of another
+    /// type, an invalid signature or a record signed by another peer is an error.
+    pub fn from_protobuf(bytes: &[u8]) -> Result<Self, PeerRecordError> {
+        if bytes.len() > MAX_RECORD_SIZE {
+            return Err(PeerRecordError::TooLarge(bytes.len()));
+        }
+
+        let envelope = SignedEnvelope::from_protobuf_encoding(bytes)?;
+        Self::from_envelope(envelope)
+    }
+
+    /// Reads the record from its envelope, checked like `from_protobuf` does.
+    pub fn from_envelope(envelope: SignedEnvelope) -> Result<Self, PeerRecordError> {
+        let (payload, key) = envelope.payload_and_signing_key(DOMAIN_SEP, PAYLOAD_TYPE)?;
+        let record = peer_record_proto::PeerRecord::decode(payload).map_err(PeerRecordError::BadPayload)?;
+
+        let peer_id = PeerId::from_bytes(&record.peer_id).map_err(|_| PeerRecordError::InvalidPeerId)?;
+        if peer_id.is_public_key(key) != Some(true) {
+            return Err(PeerRecordError::MismatchedSignature);
+        }
+        let addresses = record
+            .addresses
+            .into_iter()
+            .map(|info| Multiaddr::try_from(info.multiaddr))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(PeerRecord {
+            peer_id,
+            seq: record.seq,
+            addresses,
+            envelope,
+        })
+    }
+
+    /// Encodes the record, i.e. its envelope, into protobuf.
+    pub fn to_protobuf(&self) -> Vec<u8> {
+        self.envelope.clone().into_protobuf_encoding()
+    }
+
+    /// Returns the peer the record is about.
+    pub fn peer_id(&self) -> PeerId {
+        self.peer_id
+    }
+
+    /// Returns the sequence number of the record.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Returns the addresses of the peer.
+    pub fn addresses(&self) -> &[Multiaddr] {
+        &self.addresses
+    }
+
+    /// Returns the envelope the record is signed in.
+    pub fn envelope(&self) -> &SignedEnvelope {
+        &self.envelope
+    }
+}
+
+/// Verifies the peer records signed in the envelopes, e.g. the ones collected from a DHT
+/// query. The signatures of RSA and ECDSA keys are costly to verify, so the envelopes are
+/// split across a few runtime tasks.
+///
+/// The results are in the order of the envelopes.
+pub async fn verify_peer_records(records: &[SignedEnvelope]) -> Vec<Result<PeerRecord, PeerRecordError>> {
+    if records.len() < 2 {
+        return records.iter().cloned().map(PeerRecord::from_envelope).collect();
+    }
+
+    let chunk_size = (records.len() + MAX_VERIFY_TASKS - 1) / MAX_VERIFY_TASKS;
+    let workers = records
+        .chunks(chunk_size)
+        .map(|envelopes| {
+            let envelopes = envelopes.to_vec();
+            task::spawn(async move { envelopes.into_iter().map(PeerRecord::from_envelope).collect::<Vec<_>>() })
+        })
+        .collect::<Vec<_>>();
+
+    // waited in the order spawned, which keeps the order of the envelopes
+    let mut results = Vec::with_capacity(records.len());
+    for worker in workers {
+        results.extend(worker.wait().await.expect("verifying peer records never panics"));
+    }
+    results
+}
+
+/// An error decoding a peer record.
+#[derive(Debug, Error)]
+pub enum PeerRecordError {
+    #[error("record of {0} bytes, larger than {max}", max = MAX_RECORD_SIZE)]
+    TooLarge(usize),
+    #[error("failed to decode the envelope")]
+    BadEnvelope(#[from] DecodingError),
+    #[error("failed to read the payload")]
+    BadSignature(#[from] ReadPayloadError),
+    #[error("failed to decode the record")]
+    BadPayload(#[source] prost::DecodeError),
+    #[error("invalid peer id")]
+    InvalidPeerId,
+    #[error("invalid address")]
+    InvalidAddress(#[from] multiaddr::Error),
+    #[error("record signed by another peer")]
+    MismatchedSignature,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addresses() -> Vec<Multiaddr> {
+        vec!["/ip4/127.0.0.1/tcp/4001".parse().unwrap(), "/ip6/::1/udp/4001".parse().unwrap()]
+    }
+
+    fn signed(key: &Keypair, domain: &str, payload_type: &[u8], record: peer_record_proto::PeerRecord) -> Vec<u8> {
+        let mut payload = Vec::new();
+        record.encode(&mut payload).unwrap();
+        SignedEnvelope::new(key, domain, payload_type.to_vec(), payload)
+            .unwrap()
+            .into_protobuf_encoding()
+    }
+
+    fn record_of(key: &Keypair) -> peer_record_proto::PeerRecord {
+        peer_record_proto::PeerRecord {
+            peer_id: key.public().into_peer_id().to_bytes(),
+            seq: 1,
+            addresses: vec![],
+        }
+    }
+
+    #[test]
+    fn peer_record_roundtrip() {
+        let key = Keypair::generate_ed25519();
+        let record = PeerRecord::new(&key, addresses()).unwrap();
+
+        let decoded = PeerRecord::from_protobuf(&record.to_protobuf()).unwrap();
+        assert_eq!(decoded, record);
+        assert_eq!(decoded.peer_id(), key.public().into_peer_id());
+        assert_eq!(decoded.addresses(), &addresses()[..]);
+    }
+
+    #[test]
+    fn verify_peer_records_in_order() {
+        let mut der = include_bytes!("identity/test/rsa-2048.pk8").to_vec();
+        // the costly signatures of RSA and ECDSA along with the cheap ones of Ed25519
+        let mut keys = vec![Keypair::generate_ed25519(), Keypair::rsa_from_pkcs8(&mut der).unwrap()];
+        #[cfg(feature = "secp256k1")]
+        keys.push(Keypair::generate_secp256k1());
+        let other = Keypair::generate_ed25519();
+        let envelope = |bytes: Vec<u8>| SignedEnvelope::from_protobuf_encoding(&bytes).unwrap();
+
+        let mut records = Vec::new();
+        for i in 0..24 {
+            let key = &keys[i % keys.len()];
+            let bytes = match i % 4 {
+                // signed for another domain
+                1 => signed(key, "other-domain", PAYLOAD_TYPE, record_of(key)),
+                // signed by another peer
+                3 => signed(&other, DOMAIN_SEP, PAYLOAD_TYPE, record_of(key)),
+                _ => PeerRecord::new(key, addresses()).unwrap().to_protobuf(),
+            };
+            records.push(envelope(bytes));
+        }
+
+        let results = task::block_on(verify_peer_records(&records));
+        assert_eq!(results.len(), records.len());
+        for (i, r) in results.into_iter().enumerate() {
+            match i % 4 {
+                1 => assert!(matches!(r, Err(PeerRecordError::BadSignature(_))), "record {}", i),
+                3 => assert!(matches!(r, Err(PeerRecordError::MismatchedSignature)), "record {}", i),
+                _ => {
+                    let record = r.unwrap();
+                    assert_eq!(record.envelope(), &records[i]);
+                    assert_eq!(record.peer_id(), keys[i % keys.len()].public().into_peer_id());
+                }
+            }
+        }
+        assert!(task::block_on(verify_peer_records(&[])).is_empty());
+    }
+
+    #[test]
+    fn truncated_record() {
+        let key = Keypair::generate_ed25519();
+        let bytes = PeerRecord::new(&key, addresses()).unwrap().to_protobuf();
+
+        for len in 0..bytes.len() {
+            assert!(PeerRecord::from_protobuf(&bytes[..len]).is_err(), "prefix of {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn malformed_envelope() {
+        // field 1, the public key, as a varint instead of a message
+        assert!(matches!(
+            PeerRecord::from_protobuf(&[0x08, 0x01]),
+            Err(PeerRecordError::BadEnvelope(_))
+        ));
+        // length of field 1 beyond the end of the input
+        assert!(matches!(
+            PeerRecord::from_protobuf(&[0x0a, 0xff, 0xff, 0x03]),
+            Err(PeerRecordError::BadEnvelope(_))
+        ));
+        // no public key
+        assert!(matches!(PeerRecord::from_protobuf(&[]), Err(PeerRecordError::BadEnvelope(_))));
+    }
+
+    #[test]
+    fn oversized_record() {
+        let bytes = vec![0u8; MAX_RECORD_SIZE + 1];
+        assert!(matches!(PeerRecord::from_protobuf(&bytes), Err(PeerRecordError::TooLarge(_))));
+    }
+
+    #[test]
+    fn unexpected_domain_or_payload_type() {
+        let key = Keypair::generate_ed25519();
+
+        let bytes = signed(&key, "other-domain", PAYLOAD_TYPE, record_of(&key));
+        assert!(matches!(
+            PeerRecord::from_protobuf(&bytes),
+            Err(PeerRecordError::BadSignature(ReadPayloadError::InvalidSignature))
+        ));
+
+        let bytes = signed(&key, DOMAIN_SEP, b"other-type", record_of(&key));
+        assert!(matches!(
+            PeerRecord::from_protobuf(&bytes),
+            Err(PeerRecordError::BadSignature(ReadPayloadError::UnexpectedPayloadType { .. }))
+        ));
+    }
+
+    #[test]
+    fn malformed_payload() {
+        let key = Keypair::generate_ed25519();
+
+        let bytes = SignedEnvelope::new(&key, DOMAIN_SEP, PAYLOAD_TYPE.to_vec(), vec![0x0a, 0xff])
+            .unwrap()
+            .into_protobuf_encoding();
+        assert!(matches!(PeerRecord::from_protobuf(&bytes), Err(PeerRecordError::BadPayload(_))));
+
+        let mut record = record_of(&key);
+        record.peer_id = vec![0x01, 0x02];
+        let bytes = signed(&key, DOMAIN_SEP, PAYLOAD_TYPE, record);
+        assert!(matches!(PeerRecord::from_protobuf(&bytes), Err(PeerRecordError::InvalidPeerId)));
+
+        let mut record = record_of(&key);
+        record.addresses.push(peer_record_proto::peer_record::AddressInfo {
+            multiaddr: vec![0xff, 0xff],
+        });
+        let bytes = signed(&key, DOMAIN_SEP, PAYLOAD_TYPE, record);
+        assert!(matches!(PeerRecord::from_protobuf(&bytes), Err(PeerRecordError::InvalidAddress(_))));
+    }
+
+    #[test]
+    fn record_signed_by_another_peer() {
+        let key = Keypair::generate_ed25519();
+        let other = Keypair::generate_ed25519();
+
+        let bytes = signed(&key, DOMAIN_SEP, PAYLOAD_TYPE, record_of(&other));
+        assert!(matches!(
+            PeerRecord::from_protobuf(&bytes),
+            Err(PeerRecordError::MismatchedSignature)
+        ));
+    }
+}
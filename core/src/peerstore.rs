@@ -19,6 +19,7 @@
 // DEALINGS IN THE SOFTWARE.
 
 use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
 use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io;
@@ -81,8 +82,18 @@ struct AddrBookRecord {
     addr: Multiaddr,
     ttl: Duration,
     expiry: Instant,
+    /// The last time dialing the address succeeded.
+    last_success: Option<Instant>,
+    /// The last time dialing the address failed.
+    last_failure: Option<Instant>,
 }
 
+/// The key sorting the addresses for dialing, see `PeerStore::prioritize_addrs`.
+type DialPriority = (u8, Reverse<Option<Instant>>, Option<Instant>);
+
+/// The priority of the addresses never dialed.
+const UNTRIED: DialPriority = (1, Reverse(None), None);
+
 impl Into<Multiaddr> for AddrBookRecord {
     fn into(self) -> Multiaddr {
         self.addr
@@ -234,6 +245,44 @@ impl PeerStore {
         guard.get(peer_id).map(|pr| pr.addrs.iter().map(|a| a.clone().into()).collect())
     }
 
+    /// Retrieves the all multiaddr of a peer, in the order of dialing them, see `prioritize_addrs`.
+    pub fn addrs_prioritized(&self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        let addrs = self.get_addrs(peer_id).unwrap_or_default();
+        self.prioritize_addrs(peer_id, addrs)
+    }
+
+    /// Sorts the multiaddr of a peer by their dial history. The ones succeeded recently
+    /// come first, then the ones never dialed, and finally the ones failed at the last
+    /// dial. The order of the addresses with the same history is kept.
+    pub fn prioritize_addrs(&self, peer_id: &PeerId, mut addrs: Vec<Multiaddr>) -> Vec<Multiaddr> {
+        let guard = self.inner.lock().unwrap();
+        if let Some(pr) = guard.get(peer_id) {
+            addrs.sort_by_key(|addr| {
+                pr.addrs
+                    .iter()
+                    .find(|record| &record.addr == addr)
+                    .map_or(UNTRIED, AddrBookRecord::dial_priority)
+            });
+        }
+        addrs
+    }
+
+    /// Records that dialing the multiaddr of the peer succeeded.
+    pub fn record_dial_success(&self, peer_id: &PeerId, addr: &Multiaddr) {
+        let mut guard = self.inner.lock().unwrap();
+        if let Some(record) = guard.get_mut(peer_id).and_then(|pr| pr.addrs.iter_mut().find(|r| &r.addr == addr)) {
+            record.last_success = Some(Instant::now());
+        }
+    }
+
+    /// Records that dialing the multiaddr of the peer failed.
+    pub fn record_dial_failure(&self, peer_id: &PeerId, addr: &Multiaddr) {
+        let mut guard = self.inner.lock().unwrap();
+        if let Some(record) = guard.get_mut(peer_id).and_then(|pr| pr.addrs.iter_mut().find(|r| &r.addr == addr)) {
+            record.last_failure = Some(Instant::now());
+        }
+    }
+
     /// Updates the ttl of the multiaddr of the peer.
     pub fn update_addr(&self, peer_id: &PeerId, new_ttl: Duration) {
         let mut guard = self.inner.lock().unwrap();
@@ -329,6 +378,8 @@ impl AddrBookRecord {
             addr,
             ttl,
             expiry: Instant::now(),
+            last_success: None,
+            last_failure: None,
         }
     }
     /// Get the multiaddr.
@@ -341,6 +392,19 @@ impl AddrBookRecord {
         self.ttl = ttl;
         self.expiry = Instant::now();
     }
+
+    /// The succeeded ones sort first, the more recent the earlier, then the untried ones,
+    /// and finally the failed ones, the ones which succeeded before ahead of the others.
+    fn dial_priority(&self) -> DialPriority {
+        let class = if self.last_success > self.last_failure {
+            0
+        } else if self.last_failure.is_none() {
+            1
+        } else {
+            2
+        };
+        (class, Reverse(self.last_success), self.last_failure)
+    }
 }
 
 #[cfg(test)]
@@ -446,6 +510,38 @@ mod tests {
         );
     }
 
+    #[test]
+    fn addrs_prioritized_by_dial_history() {
+        let peer_id = PeerId::random();
+        let addrs: Vec<Multiaddr> = vec![
+            "/memory/1".parse().unwrap(),
+            "/memory/2".parse().unwrap(),
+            "/memory/3".parse().unwrap(),
+            "/memory/4".parse().unwrap(),
+        ];
+
+        let ps = PeerStore::default();
+        ps.add_addrs(&peer_id, addrs.clone(), ADDRESS_TTL);
+        assert_eq!(ps.addrs_prioritized(&peer_id), addrs);
+
+        ps.record_dial_failure(&peer_id, &addrs[0]);
+        ps.record_dial_success(&peer_id, &addrs[2]);
+        std::thread::sleep(Duration::from_millis(10));
+        ps.record_dial_success(&peer_id, &addrs[3]);
+        assert_eq!(
+            ps.addrs_prioritized(&peer_id),
+            vec![addrs[3].clone(), addrs[2].clone(), addrs[1].clone(), addrs[0].clone()]
+        );
+
+        // failed again after succeeding
+        std::thread::sleep(Duration::from_millis(10));
+        ps.record_dial_failure(&peer_id, &addrs[3]);
+        assert_eq!(
+            ps.addrs_prioritized(&peer_id),
+            vec![addrs[2].clone(), addrs[1].clone(), addrs[3].clone(), addrs[0].clone()]
+        );
+    }
+
     #[test]
     fn peerstore_gc() {
         let peer_id = PeerId::random();
@@ -20,6 +20,7 @@
 
 use crate::identity::Keypair;
 use crate::{PeerId, PublicKey};
+use std::{error::Error, fmt};
 
 /// SecureInfo provides the underlying secure information of a secure connection.
 ///
@@ -32,4 +33,69 @@ pub trait SecureInfo {
     fn local_priv_key(&self) -> Keypair;
 
     fn remote_pub_key(&self) -> PublicKey;
+
+    /// Returns the identifier of the secure session, derived from the handshake
+    /// transcript. Both peers of a session get the same identifier.
+    ///
+    /// `None` if the secure protocol doesn't provide one, which is the default.
+    fn session_id(&self) -> Option<[u8; 32]> {
+        None
+    }
+}
+
+/// The reason of a failed security handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeErrorKind {
+    /// No security protocol could be agreed upon with the remote.
+    ProtocolSelectFailed,
+    /// The remote failed to prove that it owns its identity key.
+    SignatureVerificationFailed,
+    /// The handshake could not be done in time.
+    Timeout,
+    /// No cipher, key agreement or digest is supported by both peers.
+    UnsupportedCipher,
+    /// Any other failure, e.g. an I/O error or a malformed handshake message.
+    Other,
+}
+
+/// The error of a security handshake, tagged with its reason.
+///
+/// The security upgraders report it as `TransportError::SecurityError`, so that the reason
+/// can be told by `TransportError::handshake_error_kind` whatever the security protocol is.
+#[derive(Debug)]
+pub struct HandshakeError {
+    kind: HandshakeErrorKind,
+    inner: Box<dyn Error + Send + Sync>,
+}
+
+impl HandshakeError {
+    /// Creates a new handshake error, wrapping the error of the security protocol.
+    pub fn new<E>(kind: HandshakeErrorKind, inner: E) -> Self
+    where
+        E: Into<Box<dyn Error + Send + Sync>>,
+    {
+        HandshakeError { kind, inner: inner.into() }
+    }
+
+    /// Returns the reason of the failure.
+    pub fn kind(&self) -> HandshakeErrorKind {
+        self.kind
+    }
+
+    /// Returns the error of the security protocol.
+    pub fn get_ref(&self) -> &(dyn Error + Send + Sync + 'static) {
+        &*self.inner
+    }
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.kind, self.inner)
+    }
+}
+
+impl Error for HandshakeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&*self.inner)
+    }
 }
@@ -0,0 +1,153 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Signed envelopes, wrapping a payload with the public key of its signer and a signature.
+//!
+//! See https://github.com/libp2p/specs/blob/master/RFC/0002-signed-envelopes.md.
+
+use crate::envelope_proto;
+use crate::identity::error::{DecodingError, SigningError};
+use crate::identity::{Keypair, PublicKey};
+use prost::Message;
+use thiserror::Error;
+
+/// A payload signed by the key of its producer. The signature covers a domain as well, so that
+/// a signature made for one purpose can't be used for another.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedEnvelope {
+    key: PublicKey,
+    payload_type: Vec<u8>,
+    payload: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl SignedEnvelope {
+    /// Signs the payload of the given type for the domain.
+    pub fn new(key: &Keypair, domain: &str, payload_type: Vec<u8>, payload: Vec<u8>) -> Result<Self, SigningError> {
+        let signature = key.sign(&signature_payload(domain, &payload_type, &payload))?;
+        Ok(SignedEnvelope {
+            key: key.public(),
+            payload_type,
+            payload,
+            signature,
+        })
+    }
+
+    /// Verifies the signature of the envelope for the domain.
+    pub fn verify(&self, domain: &str) -> bool {
+        self.key
+            .verify(&signature_payload(domain, &self.payload_type, &self.payload), &self.signature)
+    }
+
+    /// Returns the payload and the key it is signed with, once the type of the payload and the
+    /// signature for the domain are checked.
+    pub fn payload_and_signing_key(&self, domain: &str, payload_type: &[u8]) -> Result<(&[u8], &PublicKey), ReadPayloadError> {
+        if self.payload_type != payload_type {
+            return Err(ReadPayloadError::UnexpectedPayloadType {
+                expected: payload_type.to_vec(),
+                got: self.payload_type.clone(),
+            });
+        }
+        if !self.verify(domain) {
+            return Err(ReadPayloadError::InvalidSignature);
+        }
+        Ok((&self.payload, &self.key))
+    }
+
+    /// Returns the public key of the signer.
+    pub fn signing_key(&self) -> &PublicKey {
+        &self.key
+    }
+
+    /// Returns the type of the payload.
+    pub fn payload_type(&self) -> &[u8] {
+        &self.payload_type
+    }
+
+    /// Encodes the envelope into protobuf.
+    pub fn into_protobuf_encoding(self) -> Vec<u8> {
+        let envelope = envelope_proto::Envelope {
+            public_key: Some(self.key.to_protobuf()),
+            payload_type: self.payload_type,
+            payload: self.payload,
+            signature: self.signature,
+        };
+
+        let mut buf = Vec::with_capacity(envelope.encoded_len());
+        envelope.encode(&mut buf).expect("Vec<u8> provides capacity as needed");
+        buf
+    }
+
+    /// Decodes an envelope from protobuf. The signature is not verified.
+    pub fn from_protobuf_encoding(bytes: &[u8]) -> Result<Self, DecodingError> {
+        let envelope = envelope_proto::Envelope::decode(bytes).map_err(|e| DecodingError::new("Protobuf").source(e))?;
+        let key = envelope
+            .public_key
+            .ok_or_else(|| DecodingError::new("missing public key"))
+            .and_then(PublicKey::from_protobuf)?;
+
+        Ok(SignedEnvelope {
+            key,
+            payload_type: envelope.payload_type,
+            payload: envelope.payload,
+            signature: envelope.signature,
+        })
+    }
+}
+
+/// The bytes signed: the domain, the type of the payload and the payload, each prefixed with
+/// its length.
+fn signature_payload(domain: &str, payload_type: &[u8], payload: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(domain.len() + payload_type.len() + payload.len() + 3 * 10);
+    for field in [domain.as_bytes(), payload_type, payload].iter() {
+        let mut len = unsigned_varint::encode::usize_buffer();
+        buf.extend_from_slice(unsigned_varint::encode::usize(field.len(), &mut len));
+        buf.extend_from_slice(field);
+    }
+    buf
+}
+
+/// An error reading the payload of an envelope.
+#[derive(Debug, Error)]
+pub enum ReadPayloadError {
+    #[error("invalid signature")]
+    InvalidSignature,
+    #[error("unexpected payload type {got:?}, expected {expected:?}")]
+    UnexpectedPayloadType { expected: Vec<u8>, got: Vec<u8> },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn envelope_roundtrip() {
+        let key = Keypair::generate_ed25519();
+        let envelope = SignedEnvelope::new(&key, "domain", b"type".to_vec(), b"payload".to_vec()).unwrap();
+
+        let decoded = SignedEnvelope::from_protobuf_encoding(&envelope.clone().into_protobuf_encoding()).unwrap();
+        assert_eq!(decoded, envelope);
+        assert_eq!(
+            decoded.payload_and_signing_key("domain", b"type").unwrap(),
+            (&b"payload"[..], &key.public())
+        );
+        assert!(!decoded.verify("other domain"));
+    }
+}
@@ -28,6 +28,7 @@
 use async_trait::async_trait;
 use futures::prelude::*;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::time::Duration;
 use std::{error::Error, fmt};
@@ -35,9 +36,14 @@ use std::{error::Error, fmt};
 use libp2prs_multiaddr::Multiaddr;
 
 use crate::multistream::NegotiationError;
+use crate::secure_io::{HandshakeError, HandshakeErrorKind};
+use crate::PeerId;
 use libp2p_pnet::PnetError;
 
+pub mod and_then;
 pub mod dummy;
+pub mod filter;
+pub mod map;
 pub mod memory;
 pub mod protector;
 pub mod timeout;
@@ -93,6 +99,15 @@ pub trait Transport: Send {
     /// Otherwise, [`TransportError::MultiaddrNotSupported`] is returned.
     fn protocols(&self) -> Vec<u32>;
 
+    /// Installs a filter consulted with the remote peer Id of every outbound connection,
+    /// right after the security handshake, before the stream muxer is selected.
+    ///
+    /// The connections denied by the filter are closed at once, and reported as
+    /// `TransportError::SecuredDenied`. Only `TransportUpgrade` does the upgrades, for
+    /// the others this is a no-op. The listeners take their own filter for the inbound
+    /// connections, see `TransportListener::set_secured_filter`.
+    fn set_secured_filter(&mut self, _filter: SecuredFilter) {}
+
     /// Adds a timeout to the connection setup (including upgrades) for all
     /// inbound and outbound connections established through the transport.
     fn timeout(self, timeout: Duration) -> timeout::TransportTimeout<Self>
@@ -119,6 +134,40 @@ pub trait Transport: Send {
     {
         timeout::TransportTimeout::with_ingoing_timeout(self, timeout)
     }
+
+    /// Applies a function on the outputs of all inbound and outbound connections
+    /// established through the transport, e.g. to wrap them in a logging shim.
+    ///
+    /// The function is given the output and the remote address of the connection.
+    fn map<F, U>(self, f: F) -> map::MapTransport<Self, F>
+    where
+        Self: Sized,
+        F: FnMut(Self::Output, &Multiaddr) -> U + Clone,
+    {
+        map::MapTransport::new(self, f)
+    }
+
+    /// Runs an async function on the outputs of all inbound and outbound connections
+    /// established through the transport, e.g. to perform an application-level handshake.
+    ///
+    /// The function is given the output and the remote address of the connection, and
+    /// the connection fails if it returns an error.
+    fn and_then<F, Fut, U>(self, f: F) -> and_then::AndThenTransport<Self, F>
+    where
+        Self: Sized,
+        F: FnOnce(Self::Output, Multiaddr) -> Fut + Clone,
+        Fut: Future<Output = Result<U, TransportError>>,
+    {
+        and_then::AndThenTransport::new(self, f)
+    }
+
+    /// Refuses to listen on, and to report, the addresses rejected by the filter.
+    fn filter_listen(self, filter: filter::AddrFilter) -> filter::FilterTransport<Self>
+    where
+        Self: Sized,
+    {
+        filter::FilterTransport::new(self, filter)
+    }
 }
 
 /// Event produced by [`Transport::Listener`]s.
@@ -201,6 +250,38 @@ pub trait TransportListener: Send {
     /// addresses will be reported by ListenerEvent::AddressAdded in this case.
     fn multi_addr(&self) -> Option<&Multiaddr>;
 
+    /// Bounds the queue of the accepted connections waiting for an upgrade slot, and
+    /// sets the policy applied when the queue overflows.
+    ///
+    /// Only the listener of `TransportUpgrade` has such a queue, for the others this is
+    /// a no-op. Without the queue, the listener simply stops accepting when all upgrade
+    /// slots are taken.
+    fn set_accept_queue(&mut self, _depth: usize, _policy: OverflowPolicy) {}
+
+    /// Limits the rate of the upgrades of the accepted connections to `rate` per second,
+    /// allowing bursts of `burst` connections.
+    ///
+    /// The connections beyond the rate wait in the accept queue, or are not accepted at
+    /// all if there is no accept queue. Only the listener of `TransportUpgrade` does the
+    /// upgrades, for the others this is a no-op.
+    fn set_accept_rate_limit(&mut self, _rate: u32, _burst: u32) {}
+
+    /// Installs a filter consulted with the remote address of every accepted connection,
+    /// before any upgrade is started on it.
+    ///
+    /// The connections denied by the filter are closed at once, and reported as
+    /// `TransportError::AcceptDenied`. Only the listener of `TransportUpgrade` does the
+    /// upgrades, for the others this is a no-op.
+    fn set_accept_filter(&mut self, _filter: AcceptFilter) {}
+
+    /// Installs a filter consulted with the remote peer Id of every accepted connection,
+    /// right after the security handshake, before the stream muxer is selected.
+    ///
+    /// The connections denied by the filter are closed at once, and reported as
+    /// `TransportError::SecuredDenied`. Only the listener of `TransportUpgrade` does the
+    /// upgrades, for the others this is a no-op.
+    fn set_secured_filter(&mut self, _filter: SecuredFilter) {}
+
     fn incoming(&mut self) -> Incoming<Self>
     where
         Self: Sized,
@@ -220,6 +301,23 @@ pub trait TransportListener: Send {
     }
 }
 
+/// The policy to shed the accepted connections when the accept queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drops the connection just accepted.
+    DropNewest,
+    /// Drops the connection waiting for the longest time, and queues the new one.
+    DropOldest,
+}
+
+/// The filter of the accepted connections, tells whether a connection from the remote
+/// address is allowed.
+pub type AcceptFilter = Arc<dyn Fn(&Multiaddr) -> bool + Send + Sync>;
+
+/// The filter of the secured connections, tells whether a connection with the remote
+/// peer is allowed.
+pub type SecuredFilter = Arc<dyn Fn(&PeerId) -> bool + Send + Sync>;
+
 /// Trait object for `TransportListener`
 pub type IListener<TOutput> = Box<dyn TransportListener<Output = TOutput> + Send>;
 /// Trait object for `Transport`
@@ -268,6 +366,27 @@ pub enum TransportError {
     /// Contains back the same address.
     MultiaddrNotSupported(Multiaddr),
 
+    /// The [`Multiaddr`] is rejected by the address filter.
+    ///
+    /// Contains back the same address.
+    AddressNotAllowed(Multiaddr),
+
+    /// An inbound connection from the [`Multiaddr`] is dropped, because the accept
+    /// queue is full.
+    AcceptQueueOverflow(Multiaddr),
+
+    /// An inbound connection from the [`Multiaddr`] is closed, because it is denied by
+    /// the accept filter.
+    AcceptDenied(Multiaddr),
+
+    /// The connection with the [`Multiaddr`] is closed after the security handshake,
+    /// because the remote peer is denied by the secured filter.
+    SecuredDenied(Multiaddr),
+
+    /// The connection with the [`Multiaddr`] is closed, because the application
+    /// versions are incompatible.
+    IncompatibleVersion(Multiaddr),
+
     /// The connection can not be established in time.
     Timeout,
 
@@ -289,6 +408,10 @@ pub enum TransportError {
     /// Multistream selection error.
     NegotiationError(NegotiationError),
 
+    /// The remote closed the substream before any protocol could be selected, which
+    /// means it refuses the substream rather than any of the protocols proposed.
+    RemoteClosedDuringNegotiation,
+
     /// Pnet layer error.
     ProtectorError(PnetError),
 
@@ -298,10 +421,27 @@ pub enum TransportError {
     /// StreamMuxer layer error
     StreamMuxerError(Box<dyn Error + Send + Sync>),
 
+    /// The connection is closed cleanly, as told by the stream muxer, rather than failed.
+    ConnectionClosed,
+
     /// websocket error
     WsError(Box<dyn Error + Send + Sync>),
 }
 
+impl TransportError {
+    /// Tells why the security handshake failed, `None` if the error doesn't come from it.
+    ///
+    /// The security errors not tagged by the upgrader are classified as `HandshakeErrorKind::Other`.
+    pub fn handshake_error_kind(&self) -> Option<HandshakeErrorKind> {
+        match self {
+            TransportError::SecurityError(e) => {
+                Some(e.downcast_ref::<HandshakeError>().map_or(HandshakeErrorKind::Other, |e| e.kind()))
+            }
+            _ => None,
+        }
+    }
+}
+
 impl From<std::io::Error> for TransportError {
     /// Converts IO error to TransportError
     fn from(e: std::io::Error) -> Self {
@@ -325,6 +465,11 @@ impl fmt::Display for TransportError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             TransportError::MultiaddrNotSupported(addr) => write!(f, "Multiaddr is not supported: {}", addr),
+            TransportError::AddressNotAllowed(addr) => write!(f, "Multiaddr is not allowed: {}", addr),
+            TransportError::AcceptQueueOverflow(addr) => write!(f, "Accept queue overflow, dropped {}", addr),
+            TransportError::AcceptDenied(addr) => write!(f, "Accept denied, dropped {}", addr),
+            TransportError::SecuredDenied(addr) => write!(f, "Secured connection denied, dropped {}", addr),
+            TransportError::IncompatibleVersion(addr) => write!(f, "Incompatible version of {}", addr),
             TransportError::Timeout => write!(f, "Operation timeout"),
             TransportError::Unreachable => write!(f, "Memory transport unreachable"),
             TransportError::Internal => write!(f, "Internal error"),
@@ -332,9 +477,11 @@ impl fmt::Display for TransportError {
             TransportError::IoError(err) => write!(f, "IO error {}", err),
             TransportError::ResolveFail(name) => write!(f, "resolve dns {} failed", name),
             TransportError::NegotiationError(err) => write!(f, "Negotiation error {:?}", err),
+            TransportError::RemoteClosedDuringNegotiation => write!(f, "Remote closed the substream during negotiation"),
             TransportError::ProtectorError(err) => write!(f, "Protector error {:?}", err),
             TransportError::SecurityError(err) => write!(f, "SecurityError layer error {:?}", err),
             TransportError::StreamMuxerError(err) => write!(f, "StreamMuxerError layer error {:?}", err),
+            TransportError::ConnectionClosed => write!(f, "Connection closed"),
             TransportError::WsError(err) => write!(f, "Websocket transport  error: {}", err),
         }
     }
@@ -344,6 +491,11 @@ impl Error for TransportError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
             TransportError::MultiaddrNotSupported(_) => None,
+            TransportError::AddressNotAllowed(_) => None,
+            TransportError::AcceptQueueOverflow(_) => None,
+            TransportError::AcceptDenied(_) => None,
+            TransportError::SecuredDenied(_) => None,
+            TransportError::IncompatibleVersion(_) => None,
             TransportError::Timeout => None,
             TransportError::Unreachable => None,
             TransportError::Internal => None,
@@ -351,9 +503,11 @@ impl Error for TransportError {
             TransportError::IoError(err) => Some(err),
             TransportError::ResolveFail(_) => None,
             TransportError::NegotiationError(err) => Some(err),
+            TransportError::RemoteClosedDuringNegotiation => None,
             TransportError::ProtectorError(err) => Some(err),
             TransportError::SecurityError(err) => Some(&**err),
             TransportError::StreamMuxerError(err) => Some(&**err),
+            TransportError::ConnectionClosed => None,
             TransportError::WsError(err) => Some(&**err),
         }
     }
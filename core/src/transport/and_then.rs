@@ -0,0 +1,198 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Transports with an asynchronous step after the connection setup.
+//!
+//! `AndThenTransport` applies an async function on every connection set up by the wrapped
+//! `Transport`, dialed or accepted, e.g. to run an application-level handshake before the
+//! connection is handed over.
+
+use crate::transport::{
+    AcceptFilter, ConnectionInfo, IListener, ITransport, ListenerEvent, OverflowPolicy, SecuredFilter, TransportListener,
+};
+use crate::{transport::TransportError, Multiaddr, Transport};
+use async_trait::async_trait;
+use futures::Future;
+
+/// An `AndThenTransport` is a `Transport` that wraps another `Transport` and runs an
+/// async function on its outputs.
+///
+/// The function is given the output and the remote address of the connection. The
+/// connection fails if the function returns an error.
+#[derive(Debug, Clone)]
+pub struct AndThenTransport<InnerTrans, F> {
+    inner: InnerTrans,
+    f: F,
+}
+
+impl<InnerTrans, F> AndThenTransport<InnerTrans, F> {
+    /// Wraps around a `Transport` to run `f` on its outputs.
+    pub fn new(trans: InnerTrans, f: F) -> Self {
+        AndThenTransport { inner: trans, f }
+    }
+}
+
+#[async_trait]
+impl<InnerTrans, F, Fut, U> Transport for AndThenTransport<InnerTrans, F>
+where
+    InnerTrans: Transport + Clone + 'static,
+    InnerTrans::Output: ConnectionInfo + 'static,
+    F: FnOnce(InnerTrans::Output, Multiaddr) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Result<U, TransportError>> + Send + 'static,
+    U: Send + 'static,
+{
+    type Output = U;
+
+    fn listen_on(&mut self, addr: Multiaddr) -> Result<IListener<Self::Output>, TransportError> {
+        let listener = self.inner.listen_on(addr)?;
+
+        let listener = AndThenListener {
+            inner: listener,
+            f: self.f.clone(),
+        };
+
+        Ok(Box::new(listener))
+    }
+
+    async fn dial(&mut self, addr: Multiaddr) -> Result<Self::Output, TransportError> {
+        let output = self.inner.dial(addr.clone()).await?;
+        (self.f.clone())(output, addr).await
+    }
+
+    fn box_clone(&self) -> ITransport<Self::Output> {
+        Box::new(self.clone())
+    }
+
+    fn protocols(&self) -> Vec<u32> {
+        self.inner.protocols()
+    }
+
+    fn set_secured_filter(&mut self, filter: SecuredFilter) {
+        self.inner.set_secured_filter(filter)
+    }
+}
+
+pub struct AndThenListener<TOutput, F> {
+    inner: IListener<TOutput>,
+    f: F,
+}
+
+#[async_trait]
+impl<TOutput, F, Fut, U> TransportListener for AndThenListener<TOutput, F>
+where
+    TOutput: ConnectionInfo + 'static,
+    F: FnOnce(TOutput, Multiaddr) -> Fut + Clone + Send,
+    Fut: Future<Output = Result<U, TransportError>> + Send + 'static,
+    U: Send + 'static,
+{
+    type Output = U;
+
+    async fn accept(&mut self) -> Result<ListenerEvent<Self::Output>, TransportError> {
+        match self.inner.accept().await? {
+            ListenerEvent::Accepted(o) => {
+                let ra = o.remote_multiaddr();
+                (self.f.clone())(o, ra).await.map(ListenerEvent::Accepted)
+            }
+            ListenerEvent::AddressAdded(a) => Ok(ListenerEvent::AddressAdded(a)),
+            ListenerEvent::AddressDeleted(a) => Ok(ListenerEvent::AddressDeleted(a)),
+        }
+    }
+
+    fn multi_addr(&self) -> Option<&Multiaddr> {
+        self.inner.multi_addr()
+    }
+
+    fn set_accept_queue(&mut self, depth: usize, policy: OverflowPolicy) {
+        self.inner.set_accept_queue(depth, policy)
+    }
+
+    fn set_accept_rate_limit(&mut self, rate: u32, burst: u32) {
+        self.inner.set_accept_rate_limit(rate, burst)
+    }
+
+    fn set_accept_filter(&mut self, filter: AcceptFilter) {
+        self.inner.set_accept_filter(filter)
+    }
+
+    fn set_secured_filter(&mut self, filter: SecuredFilter) {
+        self.inner.set_secured_filter(filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transport::memory::{Channel, MemoryTransport};
+    use crate::transport::{ListenerEvent, TransportError};
+    use crate::{Multiaddr, Transport};
+    use futures::{AsyncReadExt, AsyncWriteExt};
+    use libp2prs_runtime::task;
+
+    const MAGIC: u8 = 0x42;
+
+    async fn exchange_magic(mut socket: Channel, _remote: Multiaddr) -> Result<Channel, TransportError> {
+        socket.write_all(&[MAGIC]).await?;
+        let mut buf = [0u8; 1];
+        socket.read_exact(&mut buf).await?;
+        if buf[0] != MAGIC {
+            return Err(TransportError::Internal);
+        }
+        Ok(socket)
+    }
+
+    #[test]
+    fn and_then_output() {
+        task::block_on(async move {
+            let mut transport = MemoryTransport::default().and_then(exchange_magic);
+
+            let mut listener = transport.listen_on("/memory/0".parse().unwrap()).unwrap();
+            let addr = listener.multi_addr().cloned().unwrap();
+            let server = task::spawn(async move {
+                let mut socket = match listener.accept().await.unwrap() {
+                    ListenerEvent::Accepted(s) => s,
+                    _ => panic!("unreachable"),
+                };
+                let mut buf = [0u8; 4];
+                socket.read_exact(&mut buf).await.unwrap();
+                assert_eq!(&buf, b"ping");
+            });
+
+            let mut socket = transport.dial(addr).await.unwrap();
+            socket.write_all(b"ping").await.unwrap();
+
+            server.await;
+        });
+    }
+
+    #[test]
+    fn and_then_error() {
+        task::block_on(async move {
+            let mut listener = MemoryTransport::default().listen_on("/memory/0".parse().unwrap()).unwrap();
+            let addr = listener.multi_addr().cloned().unwrap();
+            task::spawn(async move {
+                if let Ok(ListenerEvent::Accepted(mut socket)) = listener.accept().await {
+                    let _ = socket.write_all(&[!MAGIC]).await;
+                }
+            });
+
+            let mut transport = MemoryTransport::default().and_then(exchange_magic);
+            assert!(transport.dial(addr).await.is_err());
+        });
+    }
+}
@@ -0,0 +1,290 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Transports with their listen addresses filtered.
+//!
+//! `FilterTransport` refuses to listen on the addresses rejected by an `AddrFilter`, and
+//! never reports such addresses via `ListenerEvent::AddressAdded`, e.g. when listening
+//! on an unspecified address which is expanded to all the interface addresses.
+
+use crate::multiaddr::protocol::Protocol;
+use crate::transport::{AcceptFilter, IListener, ITransport, ListenerEvent, OverflowPolicy, SecuredFilter, TransportListener};
+use crate::{transport::TransportError, Multiaddr, Transport};
+use async_trait::async_trait;
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+/// The allow and deny lists of the listen addresses.
+///
+/// An address is rejected if it carries any of the denied protocols, or its IP address
+/// matches any of the denied ranges. When the allow list is not empty, an IP address
+/// must match one of the allowed ranges as well, except an unspecified address, which is
+/// let through for listening, its expanded addresses are filtered instead. Addresses
+/// without an IP address are only subject to the denied protocols.
+#[derive(Debug, Clone, Default)]
+pub struct AddrFilter {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+    deny_protocols: Vec<u32>,
+}
+
+impl AddrFilter {
+    /// Creates a filter which allows all addresses.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an IP range to the allow list.
+    pub fn allow(mut self, net: IpNet) -> Self {
+        self.allow.push(net);
+        self
+    }
+
+    /// Adds an IP range to the deny list.
+    pub fn deny(mut self, net: IpNet) -> Self {
+        self.deny.push(net);
+        self
+    }
+
+    /// Denies the addresses carrying the protocol, e.g. `protocol::WS`.
+    pub fn deny_protocol(mut self, code: u32) -> Self {
+        self.deny_protocols.push(code);
+        self
+    }
+
+    /// Tests whether the address is allowed by the filter.
+    pub fn is_allowed(&self, addr: &Multiaddr) -> bool {
+        let mut ip = None;
+        for p in addr.iter() {
+            if p.get_key().map_or(false, |code| self.deny_protocols.contains(&code)) {
+                return false;
+            }
+            match p {
+                Protocol::Ip4(a) if ip.is_none() => ip = Some(IpAddr::V4(a)),
+                Protocol::Ip6(a) if ip.is_none() => ip = Some(IpAddr::V6(a)),
+                _ => {}
+            }
+        }
+
+        match ip {
+            Some(ip) => {
+                !self.deny.iter().any(|net| net.contains(&ip))
+                    && (self.allow.is_empty() || ip.is_unspecified() || self.allow.iter().any(|net| net.contains(&ip)))
+            }
+            None => true,
+        }
+    }
+}
+
+/// A `FilterTransport` is a `Transport` that wraps another `Transport` and
+/// refuses to listen on the addresses rejected by the `AddrFilter`.
+///
+/// **Note**: dialing is not subject to the filter.
+#[derive(Debug, Clone)]
+pub struct FilterTransport<InnerTrans> {
+    inner: InnerTrans,
+    filter: AddrFilter,
+}
+
+impl<InnerTrans> FilterTransport<InnerTrans> {
+    /// Wraps around a `Transport` to filter its listen addresses.
+    pub fn new(trans: InnerTrans, filter: AddrFilter) -> Self {
+        FilterTransport { inner: trans, filter }
+    }
+}
+
+#[async_trait]
+impl<InnerTrans> Transport for FilterTransport<InnerTrans>
+where
+    InnerTrans: Transport + Clone + 'static,
+    InnerTrans::Output: Send + 'static,
+{
+    type Output = InnerTrans::Output;
+
+    fn listen_on(&mut self, addr: Multiaddr) -> Result<IListener<Self::Output>, TransportError> {
+        if !self.filter.is_allowed(&addr) {
+            return Err(TransportError::AddressNotAllowed(addr));
+        }
+
+        let listener = self.inner.listen_on(addr)?;
+
+        let listener = FilterListener {
+            inner: listener,
+            filter: self.filter.clone(),
+        };
+
+        Ok(Box::new(listener))
+    }
+
+    async fn dial(&mut self, addr: Multiaddr) -> Result<Self::Output, TransportError> {
+        self.inner.dial(addr).await
+    }
+
+    fn box_clone(&self) -> ITransport<Self::Output> {
+        Box::new(self.clone())
+    }
+
+    fn protocols(&self) -> Vec<u32> {
+        self.inner.protocols()
+    }
+
+    fn set_secured_filter(&mut self, filter: SecuredFilter) {
+        self.inner.set_secured_filter(filter)
+    }
+}
+
+pub struct FilterListener<TOutput> {
+    inner: IListener<TOutput>,
+    filter: AddrFilter,
+}
+
+#[async_trait]
+impl<TOutput> TransportListener for FilterListener<TOutput>
+where
+    TOutput: Send,
+{
+    type Output = TOutput;
+
+    async fn accept(&mut self) -> Result<ListenerEvent<Self::Output>, TransportError> {
+        loop {
+            match self.inner.accept().await? {
+                ListenerEvent::AddressAdded(a) | ListenerEvent::AddressDeleted(a) if !self.filter.is_allowed(&a) => {
+                    log::debug!("listen address {} filtered", a);
+                }
+                evt => return Ok(evt),
+            }
+        }
+    }
+
+    fn multi_addr(&self) -> Option<&Multiaddr> {
+        self.inner.multi_addr()
+    }
+
+    fn set_accept_queue(&mut self, depth: usize, policy: OverflowPolicy) {
+        self.inner.set_accept_queue(depth, policy)
+    }
+
+    fn set_accept_rate_limit(&mut self, rate: u32, burst: u32) {
+        self.inner.set_accept_rate_limit(rate, burst)
+    }
+
+    fn set_accept_filter(&mut self, filter: AcceptFilter) {
+        self.inner.set_accept_filter(filter)
+    }
+
+    fn set_secured_filter(&mut self, filter: SecuredFilter) {
+        self.inner.set_secured_filter(filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AddrFilter, FilterListener};
+    use crate::multiaddr::{protocol, Protocol};
+    use crate::transport::dummy::DummyTransport;
+    use crate::transport::memory::MemoryTransport;
+    use crate::transport::{ListenerEvent, TransportError, TransportListener};
+    use crate::{Multiaddr, Transport};
+    use async_trait::async_trait;
+    use libp2prs_runtime::task;
+
+    fn filter() -> AddrFilter {
+        AddrFilter::new()
+            .allow("127.0.0.0/8".parse().unwrap())
+            .allow("8.8.0.0/16".parse().unwrap())
+            .deny("8.8.8.0/24".parse().unwrap())
+    }
+
+    fn allowed(filter: &AddrFilter, addr: &str) -> bool {
+        filter.is_allowed(&addr.parse().unwrap())
+    }
+
+    #[test]
+    fn filter_by_cidr() {
+        let filter = filter();
+        assert!(allowed(&filter, "/ip4/127.0.0.1/tcp/8086"));
+        assert!(allowed(&filter, "/ip4/8.8.4.4/tcp/8086"));
+        assert!(!allowed(&filter, "/ip4/8.8.8.8/tcp/8086"));
+        assert!(allowed(&filter, "/ip4/0.0.0.0/tcp/8086"));
+        assert!(allowed(&filter, "/ip6/::/tcp/8086"));
+        assert!(!allowed(&filter, "/ip6/::1/tcp/8086"));
+        assert!(allowed(&filter, "/memory/1234"));
+
+        let filter = filter.deny_protocol(protocol::WS);
+        assert!(!allowed(&filter, "/ip4/127.0.0.1/tcp/8086/ws"));
+
+        let filter = AddrFilter::new().deny("8.8.8.0/24".parse().unwrap());
+        assert!(allowed(&filter, "/ip4/0.0.0.0/tcp/8086"));
+        assert!(!allowed(&filter, "/ip4/8.8.8.8/tcp/8086"));
+    }
+
+    #[test]
+    fn listen_on_filtered() {
+        let mut transport = DummyTransport::new().filter_listen(filter());
+
+        let addr: Multiaddr = "/ip4/8.8.8.8/tcp/8086".parse().unwrap();
+        match transport.listen_on(addr.clone()) {
+            Err(TransportError::AddressNotAllowed(a)) => assert_eq!(a, addr),
+            _ => panic!("the address should be denied"),
+        }
+
+        // passed down to the inner transport
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/8086".parse().unwrap();
+        assert!(matches!(transport.listen_on(addr), Err(TransportError::Internal)));
+
+        let mut transport = MemoryTransport::default().filter_listen(AddrFilter::new().deny_protocol(protocol::MEMORY));
+        let addr: Multiaddr = Protocol::Memory(rand::random::<u64>().saturating_add(1)).into();
+        assert!(matches!(transport.listen_on(addr), Err(TransportError::AddressNotAllowed(_))));
+    }
+
+    // reports the given addresses, as if expanded from an unspecified address
+    struct ExpandingListener(Vec<Multiaddr>);
+
+    #[async_trait]
+    impl TransportListener for ExpandingListener {
+        type Output = ();
+
+        async fn accept(&mut self) -> Result<ListenerEvent<Self::Output>, TransportError> {
+            self.0.pop().map(ListenerEvent::AddressAdded).ok_or(TransportError::Internal)
+        }
+
+        fn multi_addr(&self) -> Option<&Multiaddr> {
+            None
+        }
+    }
+
+    #[test]
+    fn expanded_addresses_filtered() {
+        task::block_on(async {
+            let addrs = vec!["/ip4/8.8.4.4/tcp/8086", "/ip4/8.8.8.8/tcp/8086", "/ip4/10.0.0.1/tcp/8086"];
+            let mut listener = FilterListener {
+                inner: Box::new(ExpandingListener(addrs.iter().map(|a| a.parse().unwrap()).collect())),
+                filter: filter(),
+            };
+
+            // the addresses out of the allow list, or in the deny list, are skipped
+            match listener.accept().await {
+                Ok(ListenerEvent::AddressAdded(a)) => assert_eq!(a, "/ip4/8.8.4.4/tcp/8086".parse().unwrap()),
+                _ => panic!("the address should be reported"),
+            }
+            assert!(listener.accept().await.is_err());
+        });
+    }
+}
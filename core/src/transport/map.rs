@@ -0,0 +1,176 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Transports with their outputs transformed.
+//!
+//! `MapTransport` applies a function on every connection set up by the wrapped
+//! `Transport`, dialed or accepted, e.g. to wrap it in a logging or metrics shim.
+
+use crate::transport::{
+    AcceptFilter, ConnectionInfo, IListener, ITransport, ListenerEvent, OverflowPolicy, SecuredFilter, TransportListener,
+};
+use crate::{transport::TransportError, Multiaddr, Transport};
+use async_trait::async_trait;
+
+/// A `MapTransport` is a `Transport` that wraps another `Transport` and maps
+/// its outputs with a function.
+///
+/// The function is given the output and the remote address of the connection.
+#[derive(Debug, Clone)]
+pub struct MapTransport<InnerTrans, F> {
+    inner: InnerTrans,
+    f: F,
+}
+
+impl<InnerTrans, F> MapTransport<InnerTrans, F> {
+    /// Wraps around a `Transport` to map its outputs with `f`.
+    pub fn new(trans: InnerTrans, f: F) -> Self {
+        MapTransport { inner: trans, f }
+    }
+}
+
+#[async_trait]
+impl<InnerTrans, F, U> Transport for MapTransport<InnerTrans, F>
+where
+    InnerTrans: Transport + Clone + 'static,
+    InnerTrans::Output: ConnectionInfo + 'static,
+    F: FnMut(InnerTrans::Output, &Multiaddr) -> U + Clone + Send + 'static,
+    U: Send + 'static,
+{
+    type Output = U;
+
+    fn listen_on(&mut self, addr: Multiaddr) -> Result<IListener<Self::Output>, TransportError> {
+        let listener = self.inner.listen_on(addr)?;
+
+        let listener = MapListener {
+            inner: listener,
+            f: self.f.clone(),
+        };
+
+        Ok(Box::new(listener))
+    }
+
+    async fn dial(&mut self, addr: Multiaddr) -> Result<Self::Output, TransportError> {
+        let output = self.inner.dial(addr.clone()).await?;
+        Ok((self.f)(output, &addr))
+    }
+
+    fn box_clone(&self) -> ITransport<Self::Output> {
+        Box::new(self.clone())
+    }
+
+    fn protocols(&self) -> Vec<u32> {
+        self.inner.protocols()
+    }
+
+    fn set_secured_filter(&mut self, filter: SecuredFilter) {
+        self.inner.set_secured_filter(filter)
+    }
+}
+
+pub struct MapListener<TOutput, F> {
+    inner: IListener<TOutput>,
+    f: F,
+}
+
+#[async_trait]
+impl<TOutput, F, U> TransportListener for MapListener<TOutput, F>
+where
+    TOutput: ConnectionInfo + 'static,
+    F: FnMut(TOutput, &Multiaddr) -> U + Send,
+    U: Send,
+{
+    type Output = U;
+
+    async fn accept(&mut self) -> Result<ListenerEvent<Self::Output>, TransportError> {
+        let f = &mut self.f;
+        self.inner.accept().await?.map(|o| {
+            let ra = o.remote_multiaddr();
+            Ok(f(o, &ra))
+        })
+    }
+
+    fn multi_addr(&self) -> Option<&Multiaddr> {
+        self.inner.multi_addr()
+    }
+
+    fn set_accept_queue(&mut self, depth: usize, policy: OverflowPolicy) {
+        self.inner.set_accept_queue(depth, policy)
+    }
+
+    fn set_accept_rate_limit(&mut self, rate: u32, burst: u32) {
+        self.inner.set_accept_rate_limit(rate, burst)
+    }
+
+    fn set_accept_filter(&mut self, filter: AcceptFilter) {
+        self.inner.set_accept_filter(filter)
+    }
+
+    fn set_secured_filter(&mut self, filter: SecuredFilter) {
+        self.inner.set_secured_filter(filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::multiaddr::Protocol;
+    use crate::transport::memory::{Channel, MemoryTransport};
+    use crate::transport::ListenerEvent;
+    use crate::{Multiaddr, Transport};
+    use futures::{AsyncReadExt, AsyncWriteExt};
+    use libp2prs_runtime::task;
+
+    struct Wrapped {
+        inner: Channel,
+        remote: Multiaddr,
+    }
+
+    #[test]
+    fn map_output() {
+        task::block_on(async move {
+            let addr: Multiaddr = Protocol::Memory(rand::random::<u64>().saturating_add(1)).into();
+            let mut transport = MemoryTransport::default().map(|inner, remote: &Multiaddr| Wrapped {
+                inner,
+                remote: remote.clone(),
+            });
+
+            let mut listener = transport.listen_on(addr.clone()).unwrap();
+            let server = task::spawn(async move {
+                let mut wrapped = match listener.accept().await.unwrap() {
+                    ListenerEvent::Accepted(w) => w,
+                    _ => panic!("unreachable"),
+                };
+                let mut buf = [0u8; 4];
+                wrapped.inner.read_exact(&mut buf).await.unwrap();
+                assert_eq!(&buf, b"ping");
+                wrapped.inner.write_all(b"pong").await.unwrap();
+            });
+
+            let mut wrapped = transport.dial(addr.clone()).await.unwrap();
+            assert_eq!(wrapped.remote, addr);
+            wrapped.inner.write_all(b"ping").await.unwrap();
+            let mut buf = [0u8; 4];
+            wrapped.inner.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"pong");
+
+            server.await;
+        });
+    }
+}
@@ -44,6 +44,9 @@ lazy_static! {
 /// Transport that supports `/memory/N` multiaddresses.
 ///
 /// MemoryTransport is mainly for test purpose, used to write test code for basic transport functionality.
+///
+/// Listening on `/memory/0` allocates a free port, which can be read back from the address of
+/// the listener.
 #[derive(Debug, Clone, Default)]
 pub struct MemoryTransport;
 
@@ -331,6 +334,31 @@ mod tests {
         assert!(transport.listen_on("/memory/1639174018481".parse().unwrap()).is_ok());
     }
 
+    #[test]
+    fn listening_on_wildcard_port() {
+        task::block_on(async move {
+            let mut transport = MemoryTransport::default();
+            let mut listener = transport.listen_on("/memory/0".parse().unwrap()).unwrap();
+            let addr = listener.multi_addr().cloned().unwrap();
+            let port = parse_memory_addr(&addr).unwrap();
+            assert_ne!(port, 0);
+
+            // a second wildcard listener gets a port of its own
+            let other = transport.listen_on("/memory/0".parse().unwrap()).unwrap();
+            assert_ne!(other.multi_addr(), Some(&addr));
+
+            let mut socket = transport.dial(addr).await.unwrap();
+            socket.write_all(&[1, 2, 3]).await.unwrap();
+            let mut accepted = match listener.accept().await.unwrap() {
+                ListenerEvent::Accepted(socket) => socket,
+                _ => panic!("unreachable"),
+            };
+            let mut buf = [0; 3];
+            accepted.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf, [1, 2, 3]);
+        });
+    }
+
     #[test]
     fn port_not_in_use() {
         task::block_on(async move {
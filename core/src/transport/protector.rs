@@ -33,12 +33,118 @@ use async_trait::async_trait;
 use futures::task::{Context, Poll};
 use futures::{AsyncRead, AsyncWrite};
 use libp2p_pnet::{PnetConfig, PnetOutput};
-use std::{io, pin::Pin};
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::{error, fmt, io, pin::Pin};
+
+/// The size of the nonce sent by each side of the pnet handshake.
+const NONCE_SIZE: usize = 24;
+
+/// The error of a pnet handshake whose remote nonce has been seen recently, which means the
+/// handshake is likely replayed.
+///
+/// It is carried by `PnetError::HandshakeError`, as the source of the I/O error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayedNonce;
+
+impl fmt::Display for ReplayedNonce {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("replayed pnet nonce")
+    }
+}
+
+impl error::Error for ReplayedNonce {}
+
+/// The bounded cache of the remote nonces seen recently, the oldest is evicted when full.
+#[derive(Debug)]
+pub(crate) struct NonceCache {
+    capacity: usize,
+    order: VecDeque<[u8; NONCE_SIZE]>,
+    seen: HashSet<[u8; NONCE_SIZE]>,
+}
+
+impl NonceCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        NonceCache {
+            capacity: capacity.max(1),
+            order: VecDeque::new(),
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Records the nonce, returns false if it has been seen already.
+    fn insert(&mut self, nonce: [u8; NONCE_SIZE]) -> bool {
+        if !self.seen.insert(nonce) {
+            return false;
+        }
+        self.order.push_back(nonce);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+/// Watches the remote nonce read by the pnet handshake, failing the read if the nonce is
+/// in the cache. It passes everything else through.
+#[pin_project::pin_project]
+struct NonceCheck<T> {
+    #[pin]
+    inner: T,
+    cache: Option<Arc<Mutex<NonceCache>>>,
+    nonce: [u8; NONCE_SIZE],
+    filled: usize,
+}
+
+impl<T> NonceCheck<T> {
+    fn new(inner: T, cache: Option<Arc<Mutex<NonceCache>>>) -> Self {
+        NonceCheck {
+            inner,
+            cache,
+            nonce: [0; NONCE_SIZE],
+            filled: 0,
+        }
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for NonceCheck<T> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.project();
+        let n = futures::ready!(this.inner.poll_read(cx, buf))?;
+        if let Some(cache) = this.cache.as_ref() {
+            if *this.filled < NONCE_SIZE {
+                let len = n.min(NONCE_SIZE - *this.filled);
+                this.nonce[*this.filled..*this.filled + len].copy_from_slice(&buf[..len]);
+                *this.filled += len;
+                if *this.filled == NONCE_SIZE && !cache.lock().unwrap().insert(*this.nonce) {
+                    return Poll::Ready(Err(io::Error::new(io::ErrorKind::InvalidData, ReplayedNonce)));
+                }
+            }
+        }
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for NonceCheck<T> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.project().inner.poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().inner.poll_close(cx)
+    }
+}
 
 #[pin_project::pin_project]
 pub struct PnetStream<T> {
     #[pin]
-    io: PnetOutput<T>,
+    io: PnetOutput<NonceCheck<T>>,
     la: Multiaddr,
     ra: Multiaddr,
 }
@@ -78,12 +184,23 @@ impl<T: Send> ConnectionInfo for PnetStream<T> {
 pub struct ProtectorTransport<InnerTrans> {
     inner: InnerTrans,
     pnet: PnetConfig,
+    /// The remote nonces seen recently, if replays are to be detected.
+    nonces: Option<Arc<Mutex<NonceCache>>>,
 }
 
 #[allow(dead_code)]
 impl<InnerTrans> ProtectorTransport<InnerTrans> {
     pub fn new(inner: InnerTrans, pnet: PnetConfig) -> Self {
-        Self { inner, pnet }
+        Self { inner, pnet, nonces: None }
+    }
+
+    /// Keeps the last `capacity` remote nonces, shared by the dialer and all the listeners
+    /// of the transport, and rejects a handshake reusing one of them with `ReplayedNonce`.
+    ///
+    /// As pnet isn't authenticated, this is merely a diagnostic aid against replayed handshakes.
+    pub fn with_nonce_cache(mut self, capacity: usize) -> Self {
+        self.nonces = Some(Arc::new(Mutex::new(NonceCache::new(capacity))));
+        self
     }
 }
 
@@ -97,7 +214,7 @@ where
 
     fn listen_on(&mut self, addr: Multiaddr) -> Result<IListener<Self::Output>, TransportError> {
         let inner_listener = self.inner.listen_on(addr)?;
-        let listener = ProtectorListener::new(inner_listener, self.pnet);
+        let listener = ProtectorListener::new(inner_listener, self.pnet, self.nonces.clone());
         Ok(Box::new(listener))
     }
 
@@ -106,7 +223,7 @@ where
         let la = socket.local_multiaddr();
         let ra = socket.remote_multiaddr();
         self.pnet
-            .handshake(socket)
+            .handshake(NonceCheck::new(socket, self.nonces.clone()))
             .await
             .map(|io| PnetStream { io, la, ra })
             .map_err(|e| e.into())
@@ -124,11 +241,12 @@ where
 pub struct ProtectorListener<TOutput> {
     inner: IListener<TOutput>,
     pnet: PnetConfig,
+    nonces: Option<Arc<Mutex<NonceCache>>>,
 }
 
 impl<TOutput> ProtectorListener<TOutput> {
-    pub(crate) fn new(inner: IListener<TOutput>, pnet: PnetConfig) -> Self {
-        Self { inner, pnet }
+    pub(crate) fn new(inner: IListener<TOutput>, pnet: PnetConfig, nonces: Option<Arc<Mutex<NonceCache>>>) -> Self {
+        Self { inner, pnet, nonces }
     }
 }
 
@@ -147,7 +265,7 @@ where
                 let ra = o.remote_multiaddr();
                 self.pnet
                     .clone()
-                    .handshake(o)
+                    .handshake(NonceCheck::new(o, self.nonces.clone()))
                     .await
                     .map(|io| ListenerEvent::Accepted(PnetStream { io, la, ra }))
                     .map_err(|e| e.into())
@@ -161,3 +279,63 @@ where
         self.inner.multi_addr()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::memory::MemoryTransport;
+    use futures::{AsyncReadExt, AsyncWriteExt};
+    use libp2p_pnet::{PnetError, PreSharedKey};
+    use libp2prs_runtime::task;
+
+    fn is_replayed(err: &TransportError) -> bool {
+        match err {
+            TransportError::ProtectorError(PnetError::HandshakeError(e)) => {
+                e.get_ref().and_then(|e| e.downcast_ref::<ReplayedNonce>()).is_some()
+            }
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn replayed_nonce_rejected() {
+        task::block_on(async {
+            let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+            let pnet = PnetConfig::new(PreSharedKey::new([7; 32]));
+            let mut transport = ProtectorTransport::new(MemoryTransport::default(), pnet).with_nonce_cache(16);
+            let mut listener = transport.listen_on(addr.clone()).unwrap();
+
+            // a raw dialer sending the same nonce on every connection
+            let nonce = [42u8; NONCE_SIZE];
+            let dialer = task::spawn(async move {
+                let mut sockets = vec![];
+                for _ in 0..2 {
+                    let mut socket = MemoryTransport::default().dial(addr.clone()).await.unwrap();
+                    socket.write_all(&nonce).await.unwrap();
+                    let mut remote_nonce = [0u8; NONCE_SIZE];
+                    socket.read_exact(&mut remote_nonce).await.unwrap();
+                    sockets.push(socket);
+                }
+                sockets
+            });
+
+            assert!(listener.accept().await.is_ok());
+            match listener.accept().await {
+                Err(e) => assert!(is_replayed(&e), "{:?}", e),
+                Ok(_) => panic!("the replayed handshake is accepted"),
+            }
+            dialer.await;
+        });
+    }
+
+    #[test]
+    fn nonce_cache_evicts_oldest() {
+        let mut cache = NonceCache::new(2);
+        assert!(cache.insert([1; NONCE_SIZE]));
+        assert!(cache.insert([2; NONCE_SIZE]));
+        assert!(!cache.insert([1; NONCE_SIZE]));
+        assert!(cache.insert([3; NONCE_SIZE]));
+        assert!(cache.insert([1; NONCE_SIZE]));
+        assert!(!cache.insert([3; NONCE_SIZE]));
+    }
+}
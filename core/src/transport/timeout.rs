@@ -25,7 +25,9 @@
 //! underlying `Transport`.
 // TODO: add example
 
-use crate::transport::{ConnectionInfo, IListener, ITransport, ListenerEvent, TransportListener};
+use crate::transport::{
+    AcceptFilter, ConnectionInfo, IListener, ITransport, ListenerEvent, OverflowPolicy, SecuredFilter, TransportListener,
+};
 use crate::{transport::TransportError, Multiaddr, Transport};
 use async_trait::async_trait;
 use futures::future::{select, Either};
@@ -116,6 +118,10 @@ where
     fn protocols(&self) -> Vec<u32> {
         self.inner.protocols()
     }
+
+    fn set_secured_filter(&mut self, filter: SecuredFilter) {
+        self.inner.set_secured_filter(filter)
+    }
 }
 
 pub struct TimeoutListener<TOutput> {
@@ -144,6 +150,22 @@ impl<TOutput: Send> TransportListener for TimeoutListener<TOutput> {
     fn multi_addr(&self) -> Option<&Multiaddr> {
         self.inner.multi_addr()
     }
+
+    fn set_accept_queue(&mut self, depth: usize, policy: OverflowPolicy) {
+        self.inner.set_accept_queue(depth, policy)
+    }
+
+    fn set_accept_rate_limit(&mut self, rate: u32, burst: u32) {
+        self.inner.set_accept_rate_limit(rate, burst)
+    }
+
+    fn set_accept_filter(&mut self, filter: AcceptFilter) {
+        self.inner.set_accept_filter(filter)
+    }
+
+    fn set_secured_filter(&mut self, filter: SecuredFilter) {
+        self.inner.set_secured_filter(filter)
+    }
 }
 
 #[cfg(test)]
@@ -23,28 +23,73 @@
 // TODO: add example
 
 use crate::muxing::{IStreamMuxer, StreamMuxer, StreamMuxerEx};
-use crate::secure_io::SecureInfo;
-use crate::transport::{ConnectionInfo, IListener, ITransport, ListenerEvent, TransportListener};
+use crate::secure_io::{HandshakeError, HandshakeErrorKind, SecureInfo};
+use crate::transport::{
+    AcceptFilter, ConnectionInfo, IListener, ITransport, ListenerEvent, OverflowPolicy, SecuredFilter, TransportListener,
+};
 use crate::upgrade::multistream::Multistream;
 use crate::upgrade::Upgrader;
+use crate::util::{Semaphore, TokenBucket};
 use crate::{transport::TransportError, Multiaddr, Transport};
 use async_trait::async_trait;
-use futures::{future::Either, stream::FuturesUnordered, AsyncRead, AsyncWrite, FutureExt, StreamExt};
+use futures::future::{select, Either};
+use futures::{stream::FuturesUnordered, AsyncRead, AsyncWrite, FutureExt, StreamExt};
+use futures_timer::Delay;
 use std::{
+    collections::VecDeque,
+    fmt,
     future::Future,
+    io,
     num::NonZeroUsize,
     pin::Pin,
     task::{Context, Poll},
+    time::Duration,
 };
 
+/// Runs the future in a `tracing` span, if the `tracing` feature is enabled. The span
+/// is created before the future, so that its fields can borrow what the future takes.
+#[cfg(feature = "tracing")]
+macro_rules! in_span {
+    ($fut:expr, $($span:tt)+) => {{
+        let span = tracing::debug_span!($($span)+);
+        tracing::Instrument::instrument($fut, span)
+    }};
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! in_span {
+    ($fut:expr, $($span:tt)+) => {
+        $fut
+    };
+}
+
 /// A `TransportUpgrade` is a `Transport` that wraps another `Transport` and adds
 /// upgrade capabilities to all inbound and outbound connection attempts.
 ///
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct TransportUpgrade<InnerTrans, TMux, TSec> {
     inner: InnerTrans,
     mux: Multistream<TMux>,
     sec: Multistream<TSec>,
+    /// Limits the security handshakes in progress, shared by the clones and the listeners.
+    handshakes: Option<Semaphore>,
+    /// The timeout of the security negotiation and handshake, if any.
+    handshake_timeout: Option<Duration>,
+    /// Denies the outbound connections before the stream muxer is selected, if any.
+    secured_filter: Option<SecuredFilter>,
+}
+
+impl<InnerTrans: fmt::Debug, TMux: fmt::Debug, TSec: fmt::Debug> fmt::Debug for TransportUpgrade<InnerTrans, TMux, TSec> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TransportUpgrade")
+            .field("inner", &self.inner)
+            .field("mux", &self.mux)
+            .field("sec", &self.sec)
+            .field("handshakes", &self.handshakes)
+            .field("handshake_timeout", &self.handshake_timeout)
+            .field("secured_filter", &self.secured_filter.is_some())
+            .finish()
+    }
 }
 
 impl<InnerTrans, TMux, TSec> TransportUpgrade<InnerTrans, TMux, TSec>
@@ -62,8 +107,56 @@ where
             inner,
             sec: Multistream::new(sec),
             mux: Multistream::new(mux),
+            handshakes: None,
+            handshake_timeout: None,
+            secured_filter: None,
         }
     }
+
+    /// Limits the security handshakes in progress to `max`, counting both the inbound and
+    /// outbound ones, as they are CPU intensive. The excess waits for its turn.
+    ///
+    /// The limit is shared by the clones of the transport and the listeners created by them,
+    /// so it must be set before cloning it or listening.
+    pub fn with_max_concurrent_handshakes(mut self, max: usize) -> Self {
+        self.handshakes = Some(Semaphore::new(max));
+        self
+    }
+
+    /// Limits the time of the security negotiation and handshake to `timeout`, for both the
+    /// inbound and outbound connections. Unlimited by default.
+    ///
+    /// The connections running out of time are reported as `TransportError::SecurityError`
+    /// of `HandshakeErrorKind::Timeout`. It must be set before listening.
+    pub fn with_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = Some(timeout);
+        self
+    }
+
+    /// Forces the security protocols to be negotiated exactly in the given order.
+    ///
+    /// This is mostly useful for interop testing, to reproduce the negotiation
+    /// sequence expected by another peer. Protocols not in the list are not negotiated.
+    pub fn with_security_order<I, P>(mut self, order: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<[u8]>,
+    {
+        self.sec.set_order(order.into_iter().map(|p| p.as_ref().to_vec()).collect());
+        self
+    }
+
+    /// Forces the stream muxer protocols to be negotiated exactly in the given order.
+    ///
+    /// See `with_security_order`.
+    pub fn with_muxer_order<I, P>(mut self, order: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<[u8]>,
+    {
+        self.mux.set_order(order.into_iter().map(|p| p.as_ref().to_vec()).collect());
+        self
+    }
 }
 
 #[async_trait]
@@ -80,20 +173,43 @@ where
 
     fn listen_on(&mut self, addr: Multiaddr) -> Result<IListener<Self::Output>, TransportError> {
         let inner_listener = self.inner.listen_on(addr)?;
-        let listener = ListenerUpgrade::new(inner_listener, self.mux.clone(), self.sec.clone());
+        let mut listener = ListenerUpgrade::new(inner_listener, self.mux.clone(), self.sec.clone());
+        listener.handshakes = self.handshakes.clone();
+        listener.handshake_timeout = self.handshake_timeout;
 
         Ok(Box::new(listener))
     }
 
     async fn dial(&mut self, addr: Multiaddr) -> Result<Self::Output, TransportError> {
-        let socket = self.inner.dial(addr).await?;
-        let sec = self.sec.clone();
-        log::debug!("upgrading outbound security towards {}...", socket.remote_multiaddr());
-        let sec_socket = sec.select_outbound(socket).await?;
-        let mux = self.mux.clone();
-        log::debug!("security applied, upgrading outbound stream muxer...");
-        let o = mux.select_outbound(sec_socket).await?;
-        Ok(Box::new(o))
+        in_span!(
+            async {
+                let socket = in_span!(self.inner.dial(addr), "transport_connect").await?;
+                let sec = self.sec.clone();
+                let remote_addr = socket.remote_multiaddr();
+                let permit = match self.handshakes.as_ref() {
+                    Some(handshakes) => Some(handshakes.acquire().await),
+                    None => None,
+                };
+                log::debug!("upgrading outbound security towards {}...", remote_addr);
+                let handshake = secure(sec.select_outbound(socket), self.handshake_timeout);
+                let sec_socket = in_span!(handshake, "security_handshake", %remote_addr).await?;
+                drop(permit);
+                let mux = self.mux.clone();
+                let peer_id = sec_socket.remote_peer();
+                if let Some(filter) = self.secured_filter.as_ref() {
+                    if !filter(&peer_id) {
+                        log::debug!("connection to {} {} denied by the secured filter", peer_id, remote_addr);
+                        return Err(TransportError::SecuredDenied(remote_addr));
+                    }
+                }
+                log::debug!("security applied with {}, upgrading outbound stream muxer...", peer_id);
+                let o = in_span!(mux.select_outbound(sec_socket), "muxer_negotiation", %peer_id).await?;
+                Ok::<_, TransportError>(Box::new(o) as IStreamMuxer)
+            },
+            "upgrade_outbound",
+            %addr
+        )
+        .await
     }
 
     fn box_clone(&self) -> ITransport<Self::Output> {
@@ -103,10 +219,53 @@ where
     fn protocols(&self) -> Vec<u32> {
         self.inner.protocols()
     }
+
+    fn set_secured_filter(&mut self, filter: SecuredFilter) {
+        self.secured_filter = Some(filter);
+    }
+}
+
+/// Negotiates the security protocol and runs its handshake, within the timeout if any.
+///
+/// The failed negotiation and the timeout are reported as `TransportError::SecurityError`,
+/// tagged with the reason, like the failures of the handshake itself.
+async fn secure<F, O>(handshake: F, timeout: Option<Duration>) -> Result<O, TransportError>
+where
+    F: Future<Output = Result<O, TransportError>>,
+{
+    let r = match timeout {
+        Some(timeout) => {
+            futures::pin_mut!(handshake);
+            match select(handshake, Delay::new(timeout)).await {
+                Either::Left((r, _)) => r,
+                Either::Right(_) => {
+                    let e = io::Error::new(io::ErrorKind::TimedOut, "security handshake timeout");
+                    Err(TransportError::SecurityError(Box::new(HandshakeError::new(
+                        HandshakeErrorKind::Timeout,
+                        e,
+                    ))))
+                }
+            }
+        }
+        None => handshake.await,
+    };
+    r.map_err(|e| match e {
+        TransportError::NegotiationError(e) => {
+            TransportError::SecurityError(Box::new(HandshakeError::new(HandshakeErrorKind::ProtocolSelectFailed, e)))
+        }
+        e => e,
+    })
 }
 
 type UpgradeFuture<Output> = Pin<Box<dyn Future<Output = Result<Output, TransportError>> + Send>>;
 
+/// What the listener is woken up for.
+enum Next<I, U> {
+    Incoming(I),
+    Upgraded(U),
+    Refilled,
+}
+
 pub struct ListenerUpgrade<TOutput, TMux, TSec>
 where
     TOutput: ConnectionInfo + AsyncRead + AsyncWrite + Unpin + 'static,
@@ -120,6 +279,21 @@ where
     sec: Multistream<TSec>,
     futures: FuturesUnordered<UpgradeFuture<TMux::Output>>,
     limit: Option<NonZeroUsize>,
+    /// The accepted connections waiting for an upgrade slot.
+    pending: VecDeque<TOutput>,
+    /// The depth of the accept queue, `None` means no queue at all.
+    queue_depth: Option<usize>,
+    policy: OverflowPolicy,
+    /// Limits the rate of the upgrades started, if any.
+    rate_limit: Option<TokenBucket>,
+    /// Limits the security handshakes in progress, if any.
+    handshakes: Option<Semaphore>,
+    /// The timeout of the security negotiation and handshake, if any.
+    handshake_timeout: Option<Duration>,
+    /// Denies the accepted connections before they are upgraded, if any.
+    accept_filter: Option<AcceptFilter>,
+    /// Denies the accepted connections before the stream muxer is selected, if any.
+    secured_filter: Option<SecuredFilter>,
 }
 
 impl<TOutput, TMux, TSec> ListenerUpgrade<TOutput, TMux, TSec>
@@ -137,6 +311,14 @@ where
             sec,
             futures: FuturesUnordered::new(),
             limit: NonZeroUsize::new(10),
+            pending: VecDeque::new(),
+            queue_depth: None,
+            policy: OverflowPolicy::DropNewest,
+            rate_limit: None,
+            handshakes: None,
+            handshake_timeout: None,
+            accept_filter: None,
+            secured_filter: None,
         }
     }
 
@@ -147,6 +329,80 @@ where
     pub fn set_limit(&mut self, limit: Option<NonZeroUsize>) {
         self.limit = limit;
     }
+
+    fn has_upgrade_slot(&self) -> bool {
+        self.limit.map(|limit| limit.get() > self.futures.len()).unwrap_or(true)
+    }
+
+    /// Checks if an upgrade can be started now, considering both the upgrade slots and
+    /// the rate limit.
+    fn can_upgrade(&mut self) -> bool {
+        self.has_upgrade_slot() && self.rate_limit.as_mut().map(|b| b.has_token()).unwrap_or(true)
+    }
+
+    /// Starts upgrading the queued connections, as long as it is allowed.
+    fn start_pending(&mut self) {
+        while !self.pending.is_empty() && self.can_upgrade() {
+            if let Some(socket) = self.pending.pop_front() {
+                self.start_upgrade(socket);
+            }
+        }
+    }
+
+    fn start_upgrade(&mut self, socket: TOutput) {
+        if let Some(bucket) = self.rate_limit.as_mut() {
+            bucket.take();
+        }
+        let sec = self.sec.clone();
+        let mux = self.mux.clone();
+        let handshakes = self.handshakes.clone();
+        let handshake_timeout = self.handshake_timeout;
+        let secured_filter = self.secured_filter.clone();
+
+        let remote_addr = socket.remote_multiaddr();
+        let addr = remote_addr.clone();
+        log::trace!("accept a new connection from {}, upgrading...", remote_addr);
+        let upgrade = in_span!(
+            async move {
+                //futures_timer::Delay::new(Duration::from_secs(3)).await;
+                let permit = match handshakes.as_ref() {
+                    Some(handshakes) => Some(handshakes.acquire().await),
+                    None => None,
+                };
+                let sec_socket = in_span!(secure(sec.select_inbound(socket), handshake_timeout), "security_handshake").await?;
+                drop(permit);
+                let peer_id = sec_socket.remote_peer();
+                if let Some(filter) = secured_filter.as_ref() {
+                    if !filter(&peer_id) {
+                        log::debug!("connection from {} {} denied by the secured filter", peer_id, addr);
+                        return Err(TransportError::SecuredDenied(addr));
+                    }
+                }
+                log::trace!("security applied with {}, upgrading inbound stream muxer...", peer_id);
+                in_span!(mux.select_inbound(sec_socket), "muxer_negotiation", %peer_id).await
+            },
+            "upgrade_inbound",
+            %remote_addr
+        );
+        self.futures.push(upgrade.boxed());
+    }
+
+    /// Queues the connection which can't be upgraded for now. Returns the connection
+    /// shed by the overflow policy, if the queue is full.
+    fn enqueue(&mut self, socket: TOutput, depth: usize) -> Option<TOutput> {
+        if self.pending.len() < depth {
+            self.pending.push_back(socket);
+            return None;
+        }
+        match self.policy {
+            OverflowPolicy::DropOldest if depth > 0 => {
+                let oldest = self.pending.pop_front();
+                self.pending.push_back(socket);
+                oldest
+            }
+            _ => Some(socket),
+        }
+    }
 }
 
 #[async_trait]
@@ -162,7 +418,17 @@ where
 
     async fn accept(&mut self) -> Result<ListenerEvent<Self::Output>, TransportError> {
         loop {
-            let mut next_incoming = if self.limit.map(|limit| limit.get() > self.futures.len()).unwrap_or(true) {
+            self.start_pending();
+            let can_upgrade = self.can_upgrade();
+
+            // wake up when the rate limit lets a held back connection go
+            let mut next_refill = match self.rate_limit.as_ref() {
+                Some(bucket) if !can_upgrade && self.has_upgrade_slot() => futures_timer::Delay::new(bucket.wait_time()).boxed(),
+                _ => futures::future::pending().boxed(),
+            };
+
+            // keep accepting when there is an accept queue, the queue will take care of the overflow
+            let mut next_incoming = if self.queue_depth.is_some() || can_upgrade {
                 self.inner.accept()
             } else {
                 futures::future::pending().boxed()
@@ -172,48 +438,57 @@ where
 
             let next = futures::future::poll_fn(move |cx: &mut Context| {
                 if let Poll::Ready(ret) = next_incoming.poll_unpin(cx) {
-                    return Poll::Ready(Either::Left(ret));
+                    return Poll::Ready(Next::Incoming(ret));
+                }
+                if next_refill.poll_unpin(cx).is_ready() {
+                    return Poll::Ready(Next::Refilled);
                 }
                 match next_upgraded.poll_unpin(cx) {
                     Poll::Pending | Poll::Ready(None) => {
                         // when the queue is empty, FuturesUnordered next return none
                         Poll::Pending
                     }
-                    Poll::Ready(Some(ret)) => Poll::Ready(Either::Right(ret)),
+                    Poll::Ready(Some(ret)) => Poll::Ready(Next::Upgraded(ret)),
                 }
             });
 
             let event_or_upgraded = next.await;
 
             match event_or_upgraded {
-                Either::Left(ret) => {
-                    match ret? {
-                        ListenerEvent::AddressAdded(a) => {
-                            return Ok(ListenerEvent::AddressAdded(a));
-                        }
-                        ListenerEvent::AddressDeleted(a) => {
-                            return Ok(ListenerEvent::AddressDeleted(a));
+                Next::Incoming(ret) => match ret? {
+                    ListenerEvent::AddressAdded(a) => {
+                        return Ok(ListenerEvent::AddressAdded(a));
+                    }
+                    ListenerEvent::AddressDeleted(a) => {
+                        return Ok(ListenerEvent::AddressDeleted(a));
+                    }
+                    ListenerEvent::Accepted(socket) => {
+                        if let Some(filter) = self.accept_filter.as_ref() {
+                            let remote_addr = socket.remote_multiaddr();
+                            if !filter(&remote_addr) {
+                                log::debug!("connection from {} denied by the accept filter", remote_addr);
+                                return Err(TransportError::AcceptDenied(remote_addr));
+                            }
                         }
-                        ListenerEvent::Accepted(socket) => {
-                            let sec = self.sec.clone();
-                            let mux = self.mux.clone();
-
-                            self.futures.push(
-                                async move {
-                                    log::trace!("accept a new connection from {}, upgrading...", socket.remote_multiaddr());
-                                    //futures_timer::Delay::new(Duration::from_secs(3)).await;
-                                    let sec_socket = sec.select_inbound(socket).await?;
-                                    mux.select_inbound(sec_socket).await
-                                }
-                                .boxed(),
-                            );
+                        if self.can_upgrade() {
+                            self.start_upgrade(socket);
+                        } else if let Some(depth) = self.queue_depth {
+                            if let Some(dropped) = self.enqueue(socket, depth) {
+                                let remote_addr = dropped.remote_multiaddr();
+                                log::debug!("accept queue is full, dropping the connection from {}", remote_addr);
+                                return Err(TransportError::AcceptQueueOverflow(remote_addr));
+                            }
                         }
                     }
-                }
-                Either::Right(ret) => {
+                },
+                Next::Upgraded(ret) => {
+                    // an upgrade slot is freed, take the oldest connection in the queue
+                    self.start_pending();
                     let o = ret?;
                     return Ok(ListenerEvent::Accepted(Box::new(o)));
                 }
+                // the queued connections are started at the top of the loop
+                Next::Refilled => {}
             }
         }
 
@@ -252,6 +527,23 @@ where
     fn multi_addr(&self) -> Option<&Multiaddr> {
         self.inner.multi_addr()
     }
+
+    fn set_accept_queue(&mut self, depth: usize, policy: OverflowPolicy) {
+        self.queue_depth = Some(depth);
+        self.policy = policy;
+    }
+
+    fn set_accept_rate_limit(&mut self, rate: u32, burst: u32) {
+        self.rate_limit = Some(TokenBucket::new(rate, burst));
+    }
+
+    fn set_accept_filter(&mut self, filter: AcceptFilter) {
+        self.accept_filter = Some(filter);
+    }
+
+    fn set_secured_filter(&mut self, filter: SecuredFilter) {
+        self.secured_filter = Some(filter);
+    }
 }
 
 /// Trait object for TransportListener which is actually ListenerUpgrade
@@ -271,7 +563,9 @@ mod tests {
     use crate::transport::memory::MemoryTransport;
     use crate::transport::protector::ProtectorTransport;
     use crate::upgrade::dummy::DummyUpgrader;
+    use futures::AsyncReadExt;
     use libp2p_pnet::*;
+    use std::time::{Duration, Instant};
     #[test]
     fn test_dialer_and_listener() {
         // Setup listener.
@@ -308,4 +602,385 @@ mod tests {
         // Wait for both to finish.
         futures::executor::block_on(futures::future::join(listener, dialer));
     }
+
+    /// Records the spans created, with the name of their parent.
+    #[cfg(feature = "tracing")]
+    #[derive(Clone, Default)]
+    struct SpanRecorder(std::sync::Arc<std::sync::Mutex<Recorded>>);
+
+    #[cfg(feature = "tracing")]
+    #[derive(Default)]
+    struct Recorded {
+        names: Vec<&'static str>,
+        entered: Vec<u64>,
+        spans: Vec<(&'static str, Option<&'static str>)>,
+    }
+
+    #[cfg(feature = "tracing")]
+    impl tracing::Subscriber for SpanRecorder {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, attrs: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let mut r = self.0.lock().unwrap();
+            let name = attrs.metadata().name();
+            let parent = r.entered.last().map(|id| r.names[*id as usize - 1]);
+            r.names.push(name);
+            r.spans.push((name, parent));
+            tracing::span::Id::from_u64(r.names.len() as u64)
+        }
+
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, _: &tracing::Event<'_>) {}
+
+        fn enter(&self, span: &tracing::span::Id) {
+            self.0.lock().unwrap().entered.push(span.into_u64());
+        }
+
+        fn exit(&self, _: &tracing::span::Id) {
+            self.0.lock().unwrap().entered.pop();
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn upgrade_spans_emitted_in_order() {
+        let recorder = SpanRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            futures::executor::block_on(async {
+                let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+                let mut t1 = TransportUpgrade::new(MemoryTransport::default(), DummyUpgrader::new(), DummyUpgrader::new());
+                let mut t2 = t1.clone();
+                let mut listener = t1.listen_on(addr.clone()).unwrap();
+
+                let (dialed, accepted) = futures::future::join(t2.dial(addr), listener.accept()).await;
+                dialed.unwrap();
+                assert!(matches!(accepted, Ok(ListenerEvent::Accepted(_))));
+            })
+        });
+
+        let spans = recorder.0.lock().unwrap().spans.clone();
+        let of = |root| {
+            spans
+                .iter()
+                .filter(|(name, parent)| *name == root || *parent == Some(root))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            of("upgrade_outbound"),
+            vec![
+                ("upgrade_outbound", None),
+                ("transport_connect", Some("upgrade_outbound")),
+                ("security_handshake", Some("upgrade_outbound")),
+                ("muxer_negotiation", Some("upgrade_outbound")),
+            ]
+        );
+        assert_eq!(
+            of("upgrade_inbound"),
+            vec![
+                ("upgrade_inbound", None),
+                ("security_handshake", Some("upgrade_inbound")),
+                ("muxer_negotiation", Some("upgrade_inbound")),
+            ]
+        );
+    }
+
+    fn accept_queue_overflow(policy: OverflowPolicy) -> Vec<bool> {
+        futures::executor::block_on(async move {
+            let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+            let inner = MemoryTransport::default().listen_on(addr.clone()).unwrap();
+            let mut listener = ListenerUpgrade::new(
+                inner,
+                Multistream::new(DummyUpgrader::new()),
+                Multistream::new(DummyUpgrader::new()),
+            );
+            // one connection being upgraded and two queued, the dialers never speak so that
+            // the upgrade never completes
+            listener.set_limit(NonZeroUsize::new(1));
+            listener.set_accept_queue(2, policy);
+
+            // the memory listener holds only a couple of connections, so the dials go along
+            // with accepting them
+            let dial_addr = addr.clone();
+            let dialer = async move {
+                let mut sockets = vec![];
+                for _ in 0..4 {
+                    sockets.push(MemoryTransport::default().dial(dial_addr.clone()).await.unwrap());
+                }
+                sockets
+            };
+            let accept = async move {
+                match listener.accept().await {
+                    Err(TransportError::AcceptQueueOverflow(a)) => assert_eq!(a, addr),
+                    _ => panic!("the accept queue should overflow"),
+                }
+                // handed back to keep the queued connections open
+                listener
+            };
+            let (mut sockets, _listener) = futures::future::join(dialer, accept).await;
+
+            // tells which connections have been dropped by the listener, a memory socket
+            // reports it as an error
+            let mut buf = [0u8; 1];
+            sockets
+                .iter_mut()
+                .skip(1)
+                .map(|s| matches!(s.read(&mut buf).now_or_never(), Some(Ok(0)) | Some(Err(_))))
+                .collect()
+        })
+    }
+
+    #[test]
+    fn accept_queue_drop_newest() {
+        assert_eq!(accept_queue_overflow(OverflowPolicy::DropNewest), vec![false, false, true]);
+    }
+
+    #[test]
+    fn accept_queue_drop_oldest() {
+        assert_eq!(accept_queue_overflow(OverflowPolicy::DropOldest), vec![true, false, false]);
+    }
+
+    #[test]
+    fn accept_rate_limit() {
+        const RATE: u32 = 20;
+        const BURST: u32 = 2;
+        const CONNS: usize = 8;
+
+        let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+        let mut t1 = TransportUpgrade::new(MemoryTransport::default(), DummyUpgrader::new(), DummyUpgrader::new());
+        let mut listener = t1.listen_on(addr.clone()).unwrap();
+        listener.set_accept_rate_limit(RATE, BURST);
+
+        let listener = async move {
+            let start = Instant::now();
+            let mut accepted = vec![];
+            while accepted.len() < CONNS {
+                if let ListenerEvent::Accepted(muxer) = listener.accept().await.unwrap() {
+                    accepted.push(muxer);
+                }
+            }
+            start.elapsed()
+        };
+
+        // all the connections come in at once
+        let dialers = (0..CONNS).map(|_| {
+            let mut t2 = TransportUpgrade::new(MemoryTransport::default(), DummyUpgrader::new(), DummyUpgrader::new());
+            let addr = addr.clone();
+            async move { t2.dial(addr).await.unwrap() }
+        });
+
+        let (elapsed, _muxers) = futures::executor::block_on(futures::future::join(listener, futures::future::join_all(dialers)));
+
+        // the burst is upgraded at once, then one connection every 1/RATE second
+        let min = Duration::from_secs_f64((CONNS as u32 - BURST) as f64 / f64::from(RATE));
+        assert!(elapsed >= min.mul_f64(0.9), "{:?} < {:?}", elapsed, min);
+    }
+
+    /// Security upgrader taking a while, recording the number of handshakes in progress.
+    #[derive(Clone, Default)]
+    struct SlowUpgrader {
+        current: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+        max: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl SlowUpgrader {
+        async fn handshake<T: Send + 'static>(self, socket: T) -> Result<crate::upgrade::dummy::DummyStream<T>, TransportError> {
+            use std::sync::atomic::Ordering;
+            let current = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max.fetch_max(current, Ordering::SeqCst);
+            futures_timer::Delay::new(Duration::from_millis(50)).await;
+            self.current.fetch_sub(1, Ordering::SeqCst);
+            DummyUpgrader::new().upgrade_outbound(socket, b"/dummy/1.0.0").await
+        }
+    }
+
+    impl crate::upgrade::UpgradeInfo for SlowUpgrader {
+        type Info = &'static [u8];
+
+        fn protocol_info(&self) -> Vec<Self::Info> {
+            vec![b"/dummy/1.0.0"]
+        }
+    }
+
+    #[async_trait]
+    impl<T: Send + 'static> Upgrader<T> for SlowUpgrader {
+        type Output = crate::upgrade::dummy::DummyStream<T>;
+
+        async fn upgrade_inbound(self, socket: T, _info: Self::Info) -> Result<Self::Output, TransportError> {
+            self.handshake(socket).await
+        }
+
+        async fn upgrade_outbound(self, socket: T, _info: Self::Info) -> Result<Self::Output, TransportError> {
+            self.handshake(socket).await
+        }
+    }
+
+    /// Upgrades the connections concurrently, returning the maximum number of the inbound and
+    /// outbound handshakes in progress at the same time.
+    fn concurrent_handshakes(listener_limit: Option<usize>, dialer_limit: Option<usize>) -> (usize, usize) {
+        const CONNS: usize = 4;
+        fn transport(sec: &SlowUpgrader, limit: Option<usize>) -> TransportUpgrade<MemoryTransport, DummyUpgrader, SlowUpgrader> {
+            let t = TransportUpgrade::new(MemoryTransport::default(), DummyUpgrader::new(), sec.clone());
+            match limit {
+                Some(max) => t.with_max_concurrent_handshakes(max),
+                None => t,
+            }
+        }
+
+        let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+        let inbound = SlowUpgrader::default();
+        let mut listener = transport(&inbound, listener_limit).listen_on(addr.clone()).unwrap();
+        let listener = async move {
+            let mut accepted = vec![];
+            while accepted.len() < CONNS {
+                if let ListenerEvent::Accepted(muxer) = listener.accept().await.unwrap() {
+                    accepted.push(muxer);
+                }
+            }
+            accepted
+        };
+
+        // the dialers share the limit
+        let outbound = SlowUpgrader::default();
+        let t2 = transport(&outbound, dialer_limit);
+        let dialers = (0..CONNS).map(|_| {
+            let mut t2 = t2.clone();
+            let addr = addr.clone();
+            async move { t2.dial(addr).await.unwrap() }
+        });
+
+        let (accepted, dialed) = futures::executor::block_on(futures::future::join(listener, futures::future::join_all(dialers)));
+        assert_eq!(accepted.len(), CONNS);
+        assert_eq!(dialed.len(), CONNS);
+
+        let max = |sec: &SlowUpgrader| sec.max.load(std::sync::atomic::Ordering::SeqCst);
+        (max(&inbound), max(&outbound))
+    }
+
+    #[test]
+    fn concurrent_handshakes_limited() {
+        assert_eq!(concurrent_handshakes(Some(1), None).0, 1);
+        assert_eq!(concurrent_handshakes(None, Some(1)).1, 1);
+        // unlimited, the handshakes overlap
+        let (inbound, outbound) = concurrent_handshakes(None, None);
+        assert!(inbound > 1 && outbound > 1);
+    }
+
+    /// Stream muxer upgrader counting the upgrades started.
+    #[derive(Clone, Default)]
+    struct CountingUpgrader(std::sync::Arc<std::sync::atomic::AtomicUsize>);
+
+    impl crate::upgrade::UpgradeInfo for CountingUpgrader {
+        type Info = &'static [u8];
+
+        fn protocol_info(&self) -> Vec<Self::Info> {
+            vec![b"/dummy/1.0.0"]
+        }
+    }
+
+    #[async_trait]
+    impl<T: ConnectionInfo + Send + 'static> Upgrader<T> for CountingUpgrader {
+        type Output = crate::upgrade::dummy::DummyStream<T>;
+
+        async fn upgrade_inbound(self, socket: T, info: Self::Info) -> Result<Self::Output, TransportError> {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            DummyUpgrader::new().upgrade_inbound(socket, info).await
+        }
+
+        async fn upgrade_outbound(self, socket: T, info: Self::Info) -> Result<Self::Output, TransportError> {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            DummyUpgrader::new().upgrade_outbound(socket, info).await
+        }
+    }
+
+    #[test]
+    fn secured_filter_denies_before_muxer() {
+        let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+        let deny: SecuredFilter = std::sync::Arc::new(|_: &crate::PeerId| false);
+
+        let inbound = CountingUpgrader::default();
+        let mut t1 = TransportUpgrade::new(MemoryTransport::default(), inbound.clone(), DummyUpgrader::new());
+        let mut listener = t1.listen_on(addr.clone()).unwrap();
+        listener.set_secured_filter(deny.clone());
+
+        let outbound = CountingUpgrader::default();
+        let mut t2 = TransportUpgrade::new(MemoryTransport::default(), outbound.clone(), DummyUpgrader::new());
+        t2.set_secured_filter(deny);
+
+        let (dialed, accepted) = futures::executor::block_on(futures::future::join(t2.dial(addr.clone()), listener.accept()));
+        assert!(matches!(dialed, Err(TransportError::SecuredDenied(a)) if a == addr));
+        assert!(matches!(accepted, Err(TransportError::SecuredDenied(_))));
+
+        // the stream muxer is never selected on the denied connections
+        assert_eq!(inbound.0.load(std::sync::atomic::Ordering::SeqCst), 0);
+        assert_eq!(outbound.0.load(std::sync::atomic::Ordering::SeqCst), 0);
+    }
+
+    /// Security upgrader speaking a protocol `DummyUpgrader` doesn't know.
+    #[derive(Clone)]
+    struct OtherUpgrader;
+
+    impl crate::upgrade::UpgradeInfo for OtherUpgrader {
+        type Info = &'static [u8];
+
+        fn protocol_info(&self) -> Vec<Self::Info> {
+            vec![b"/other/1.0.0"]
+        }
+    }
+
+    #[async_trait]
+    impl<T: Send + 'static> Upgrader<T> for OtherUpgrader {
+        type Output = crate::upgrade::dummy::DummyStream<T>;
+
+        async fn upgrade_inbound(self, socket: T, info: Self::Info) -> Result<Self::Output, TransportError> {
+            DummyUpgrader::new().upgrade_inbound(socket, info).await
+        }
+
+        async fn upgrade_outbound(self, socket: T, info: Self::Info) -> Result<Self::Output, TransportError> {
+            DummyUpgrader::new().upgrade_outbound(socket, info).await
+        }
+    }
+
+    #[test]
+    fn handshake_protocol_select_failed() {
+        let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+        let mut t1 = TransportUpgrade::new(MemoryTransport::default(), DummyUpgrader::new(), DummyUpgrader::new());
+        let mut listener = t1.listen_on(addr.clone()).unwrap();
+        let mut t2 = TransportUpgrade::new(MemoryTransport::default(), DummyUpgrader::new(), OtherUpgrader);
+
+        let (dialed, accepted) = futures::executor::block_on(futures::future::join(t2.dial(addr), listener.accept()));
+        assert_eq!(
+            dialed.unwrap_err().handshake_error_kind(),
+            Some(HandshakeErrorKind::ProtocolSelectFailed)
+        );
+        match accepted {
+            Err(e) => assert_eq!(e.handshake_error_kind(), Some(HandshakeErrorKind::ProtocolSelectFailed)),
+            Ok(_) => panic!("the negotiation should fail"),
+        }
+    }
+
+    #[test]
+    fn handshake_timeout() {
+        let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+        // the handshakes take 50ms
+        let t1 = TransportUpgrade::new(MemoryTransport::default(), DummyUpgrader::new(), SlowUpgrader::default());
+        let mut listener = t1
+            .with_handshake_timeout(Duration::from_millis(10))
+            .listen_on(addr.clone())
+            .unwrap();
+        let t2 = TransportUpgrade::new(MemoryTransport::default(), DummyUpgrader::new(), SlowUpgrader::default());
+        let mut t2 = t2.with_handshake_timeout(Duration::from_millis(10));
+
+        let (dialed, accepted) = futures::executor::block_on(futures::future::join(t2.dial(addr), listener.accept()));
+        assert_eq!(dialed.unwrap_err().handshake_error_kind(), Some(HandshakeErrorKind::Timeout));
+        match accepted {
+            Err(e) => assert_eq!(e.handshake_error_kind(), Some(HandshakeErrorKind::Timeout)),
+            Ok(_) => panic!("the handshake should time out"),
+        }
+    }
 }
@@ -58,10 +58,12 @@ use async_trait::async_trait;
 
 use crate::transport::TransportError;
 
-pub use self::{dummy::DummyUpgrader, select::Selector};
+pub use self::{dummy::DummyUpgrader, select::Selector, version::VersionGate};
 pub(crate) mod dummy;
 pub(crate) mod multistream;
+pub(crate) mod optional;
 pub(crate) mod select;
+pub(crate) mod version;
 
 /// Types serving as protocol names.
 ///
@@ -102,6 +104,11 @@ pub trait ProtocolName {
     /// **Note:** Valid protocol names must start with `/` and
     /// not exceed 140 bytes in length.
     fn protocol_name(&self) -> &[u8];
+
+    /// The protocol name as a string, empty if the name is not valid UTF-8.
+    fn protocol_name_str(&self) -> &str {
+        std::str::from_utf8(self.protocol_name()).unwrap_or_default()
+    }
 }
 
 impl<T: AsRef<[u8]>> ProtocolName for T {
@@ -130,6 +137,70 @@ impl fmt::Display for ProtocolId {
     }
 }
 
+impl ProtocolId {
+    /// Returns the protocol id without the trailing version, e.g. `/myproto` for `/myproto/1.2.0`.
+    ///
+    /// The whole protocol id is returned if there is no version.
+    pub fn base_name(&self) -> &'static [u8] {
+        match self.split_version() {
+            Some((base, _)) => base,
+            None => self.0,
+        }
+    }
+
+    /// Returns the trailing version of the protocol id, if any.
+    pub fn version(&self) -> Option<Version> {
+        self.split_version().map(|(_, v)| v)
+    }
+
+    /// Checks if both protocol ids have the same base name and major version.
+    pub fn is_compatible_with(&self, other: &ProtocolId) -> bool {
+        self.base_name() == other.base_name() && self.version().map(|v| v.major) == other.version().map(|v| v.major)
+    }
+
+    fn split_version(&self) -> Option<(&'static [u8], Version)> {
+        let pos = self.0.iter().rposition(|b| *b == b'/')?;
+        let version = std::str::from_utf8(&self.0[pos + 1..]).ok()?.parse().ok()?;
+        Some((&self.0[..pos], version))
+    }
+}
+
+/// The version of a protocol, as found at the end of a protocol id like `/myproto/1.2.0`.
+///
+/// The minor and patch numbers may be omitted, they default to 0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl std::str::FromStr for Version {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut nums = [0u64; 3];
+        let mut parts = s.splitn(3, '.');
+        for n in nums.iter_mut() {
+            match parts.next() {
+                Some(p) => *n = p.parse()?,
+                None => break,
+            }
+        }
+        Ok(Version {
+            major: nums[0],
+            minor: nums[1],
+            patch: nums[2],
+        })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
 // impl ProtocolName for ProtocolId {
 //     fn protocol_name(&self) -> &[u8] {
 //         self.0.as_ref()
@@ -185,6 +256,35 @@ pub trait Upgrader<C>: UpgradeInfo + Clone {
 mod tests {
     use super::*;
 
+    #[test]
+    fn protocol_id_versions() {
+        let p10 = ProtocolId::from(b"/p/1.0.0" as &[u8]);
+        let p11 = ProtocolId::from(b"/p/1.1.0" as &[u8]);
+        let p2 = ProtocolId::from(b"/p/2.0" as &[u8]);
+        let q10 = ProtocolId::from(b"/q/1.0.0" as &[u8]);
+        let plain = ProtocolId::from(b"/p" as &[u8]);
+
+        assert_eq!(p10.base_name(), b"/p");
+        assert_eq!(p2.base_name(), b"/p");
+        assert_eq!(plain.base_name(), b"/p");
+        assert_eq!(
+            p2.version(),
+            Some(Version {
+                major: 2,
+                minor: 0,
+                patch: 0
+            })
+        );
+        assert_eq!(plain.version(), None);
+        assert!(p10.version() < p11.version());
+
+        assert!(p10.is_compatible_with(&p11));
+        assert!(p11.is_compatible_with(&p10));
+        assert!(!p10.is_compatible_with(&q10));
+        assert!(!p10.is_compatible_with(&p2));
+        assert!(!p10.is_compatible_with(&plain));
+    }
+
     #[test]
     fn upgrade_info_multi_versions() {
         #[derive(PartialEq, Debug, Clone)]
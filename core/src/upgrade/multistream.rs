@@ -32,6 +32,8 @@ use log::{debug, trace};
 #[derive(Debug, Clone)]
 pub(crate) struct Multistream<U> {
     inner: U,
+    /// The explicit order of the protocols to negotiate, if any.
+    order: Option<Vec<Vec<u8>>>,
 }
 
 impl<U> Multistream<U> {
@@ -39,7 +41,21 @@ impl<U> Multistream<U> {
     ///
     /// The protocols supported by the first element have a higher priority.
     pub fn new(inner: U) -> Self {
-        Self { inner }
+        Self { inner, order: None }
+    }
+
+    /// Forces the protocols to be negotiated exactly in the given order, instead of
+    /// the order of `protocol_info`. Protocols not in the list are not negotiated.
+    pub(crate) fn set_order(&mut self, order: Vec<Vec<u8>>) {
+        self.order = Some(order);
+    }
+
+    fn negotiator<N: ProtocolName + Clone>(&self, protocols: Vec<N>) -> Negotiator<NameWrap<N>> {
+        let mut neg = Negotiator::new_with_protocols(protocols.into_iter().map(NameWrap as fn(_) -> NameWrap<_>));
+        if let Some(order) = &self.order {
+            neg.set_order(order);
+        }
+        neg
     }
 }
 
@@ -51,7 +67,7 @@ impl<U> Multistream<U> {
     {
         trace!("starting multistream select for inbound...");
         let protocols = self.inner.protocol_info();
-        let neg = Negotiator::new_with_protocols(protocols.into_iter().map(NameWrap as fn(_) -> NameWrap<_>));
+        let neg = self.negotiator(protocols);
 
         let (proto, socket) = neg.negotiate(socket).await?;
 
@@ -66,7 +82,7 @@ impl<U> Multistream<U> {
     {
         trace!("starting multistream select for outbound...");
         let protocols = self.inner.protocol_info();
-        let neg = Negotiator::new_with_protocols(protocols.into_iter().map(NameWrap as fn(_) -> NameWrap<_>));
+        let neg = self.negotiator(protocols);
 
         let (proto, socket) = neg.select_one(socket).await?;
 
@@ -0,0 +1,89 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! An upgrader can be wrapped in an `Option` to make it opt-in.
+//!
+//! `None` supports no protocol at all, so it never takes part in the negotiation.
+//! Combined with `Selector`, this allows an upgrader to be appended to the list
+//! of preferences only when it is explicitly enabled, e.g. the plaintext
+//! security upgrader on a trusted network:
+//!
+//! ```ignore
+//! let plaintext = if trusted { Some(PlainTextConfig::new(key)) } else { None };
+//! let sec = Selector::new(noise, plaintext);
+//! ```
+
+use async_trait::async_trait;
+
+use crate::transport::TransportError;
+use crate::upgrade::{UpgradeInfo, Upgrader};
+
+impl<U> UpgradeInfo for Option<U>
+where
+    U: UpgradeInfo,
+{
+    type Info = U::Info;
+
+    fn protocol_info(&self) -> Vec<Self::Info> {
+        self.as_ref().map(|u| u.protocol_info()).unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl<U, C> Upgrader<C> for Option<U>
+where
+    U: Upgrader<C> + Send,
+    C: Send + 'static,
+{
+    type Output = U::Output;
+
+    async fn upgrade_inbound(self, socket: C, info: <Self as UpgradeInfo>::Info) -> Result<Self::Output, TransportError> {
+        match self {
+            Some(u) => u.upgrade_inbound(socket, info).await,
+            None => Err(TransportError::Internal),
+        }
+    }
+
+    async fn upgrade_outbound(self, socket: C, info: <Self as UpgradeInfo>::Info) -> Result<Self::Output, TransportError> {
+        match self {
+            Some(u) => u.upgrade_outbound(socket, info).await,
+            None => Err(TransportError::Internal),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::either::EitherName;
+    use crate::upgrade::dummy::DummyUpgrader;
+    use crate::upgrade::Selector;
+
+    #[test]
+    fn disabled_upgrader_is_not_offered() {
+        let enabled = Selector::new(DummyUpgrader::new(), Some(DummyUpgrader::new()));
+        assert_eq!(enabled.protocol_info().len(), 2);
+        assert!(matches!(enabled.protocol_info()[1], EitherName::B(_)));
+
+        let disabled = Selector::new(DummyUpgrader::new(), None::<DummyUpgrader>);
+        assert_eq!(disabled.protocol_info().len(), 1);
+        assert!(matches!(disabled.protocol_info()[0], EitherName::A(_)));
+    }
+}
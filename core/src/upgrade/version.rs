@@ -0,0 +1,169 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! An upgrader gating the connections on the application version.
+//!
+//! `VersionGate` wraps the stream muxer upgrader, so that both sides exchange
+//! their version tokens over the secured connection, right before the stream
+//! muxer is set up. The connection is closed if the versions are incompatible,
+//! and the upgrade fails with `TransportError::IncompatibleVersion`.
+//!
+//! ```ignore
+//! let mux = VersionGate::new(yamux::Config::new(), b"my-app/2".to_vec());
+//! let tu = TransportUpgrade::new(TcpConfig::new(), mux, sec);
+//! ```
+
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncWrite};
+use std::io;
+
+use crate::transport::{ConnectionInfo, TransportError};
+use crate::upgrade::{UpgradeInfo, Upgrader};
+use crate::{ReadEx, WriteEx};
+
+/// The maximum size of a version token.
+const MAX_TOKEN_SIZE: usize = 1024;
+
+/// `VersionGate` runs a version handshake before the upgrade of the wrapped upgrader.
+#[derive(Debug, Clone)]
+pub struct VersionGate<U> {
+    inner: U,
+    token: Vec<u8>,
+    check: fn(&[u8], &[u8]) -> bool,
+}
+
+impl<U> VersionGate<U> {
+    /// Wraps the upgrader, the versions are compatible if the tokens are equal.
+    pub fn new(inner: U, token: impl Into<Vec<u8>>) -> Self {
+        VersionGate {
+            inner,
+            token: token.into(),
+            check: |local, remote| local == remote,
+        }
+    }
+
+    /// Sets the function telling if the versions are compatible, given the local
+    /// token and the remote one.
+    pub fn with_check(mut self, check: fn(&[u8], &[u8]) -> bool) -> Self {
+        self.check = check;
+        self
+    }
+}
+
+/// Exchanges the version tokens, closing the connection if they are incompatible.
+async fn handshake<C>(socket: &mut C, token: &[u8], check: fn(&[u8], &[u8]) -> bool) -> Result<(), TransportError>
+where
+    C: ConnectionInfo + AsyncRead + AsyncWrite + Unpin + Send,
+{
+    socket.write_one(token).await?;
+    let remote = socket.read_one(MAX_TOKEN_SIZE).await?;
+    if remote.is_empty() {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+
+    if !check(token, &remote) {
+        let remote_addr = socket.remote_multiaddr();
+        log::info!(
+            "incompatible version {:?} of {}, local {:?}",
+            String::from_utf8_lossy(&remote),
+            remote_addr,
+            String::from_utf8_lossy(token)
+        );
+        let _ = socket.close2().await;
+        return Err(TransportError::IncompatibleVersion(remote_addr));
+    }
+    Ok(())
+}
+
+impl<U: UpgradeInfo> UpgradeInfo for VersionGate<U> {
+    type Info = U::Info;
+
+    fn protocol_info(&self) -> Vec<Self::Info> {
+        self.inner.protocol_info()
+    }
+}
+
+#[async_trait]
+impl<U, C> Upgrader<C> for VersionGate<U>
+where
+    U: Upgrader<C>,
+    C: ConnectionInfo + AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Output = U::Output;
+
+    async fn upgrade_inbound(self, mut socket: C, info: <Self as UpgradeInfo>::Info) -> Result<Self::Output, TransportError> {
+        handshake(&mut socket, &self.token, self.check).await?;
+        self.inner.upgrade_inbound(socket, info).await
+    }
+
+    async fn upgrade_outbound(self, mut socket: C, info: <Self as UpgradeInfo>::Info) -> Result<Self::Output, TransportError> {
+        handshake(&mut socket, &self.token, self.check).await?;
+        self.inner.upgrade_outbound(socket, info).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::memory::MemoryTransport;
+    use crate::transport::upgrade::TransportUpgrade;
+    use crate::transport::{ListenerEvent, Transport};
+    use crate::upgrade::DummyUpgrader;
+    use crate::Multiaddr;
+
+    fn gate(token: &[u8]) -> TransportUpgrade<MemoryTransport, VersionGate<DummyUpgrader>, DummyUpgrader> {
+        TransportUpgrade::new(
+            MemoryTransport::default(),
+            VersionGate::new(DummyUpgrader::new(), token),
+            DummyUpgrader::new(),
+        )
+    }
+
+    fn connect(listener_token: &'static [u8], dialer_token: &'static [u8]) -> (bool, bool) {
+        let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+        let mut listener = gate(listener_token).listen_on(addr.clone()).unwrap();
+
+        let accepted = async move {
+            match listener.accept().await {
+                Ok(ListenerEvent::Accepted(_)) => true,
+                Err(TransportError::IncompatibleVersion(_)) => false,
+                _ => panic!("unexpected accept result"),
+            }
+        };
+        let dialed = async move {
+            match gate(dialer_token).dial(addr).await {
+                Ok(_) => true,
+                Err(TransportError::IncompatibleVersion(_)) => false,
+                Err(e) => panic!("unexpected dial error {:?}", e),
+            }
+        };
+        futures::executor::block_on(futures::future::join(accepted, dialed))
+    }
+
+    #[test]
+    fn same_versions_connected() {
+        assert_eq!(connect(b"app/1", b"app/1"), (true, true));
+    }
+
+    #[test]
+    fn different_versions_refused() {
+        assert_eq!(connect(b"app/1", b"app/2"), (false, false));
+    }
+}
@@ -0,0 +1,194 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! A wrapper batching the small writes to `WriteEx` streams.
+
+use async_trait::async_trait;
+use std::io;
+
+use crate::util::{ReadEx, WriteEx};
+
+/// The default size of the buffer of `BufferedWriter`.
+const DEFAULT_CAPACITY: usize = 4096;
+
+/// `BufferedWriter` wraps a `WriteEx` stream and buffers the writes to it, so that
+/// many small writes end up in a few large ones, e.g. a few frames of a muxer.
+///
+/// The buffered bytes are written when the buffer is full, or by `flush2` and
+/// `close2`. They are lost if the writer is dropped without flushing it.
+#[derive(Debug)]
+pub struct BufferedWriter<T> {
+    inner: T,
+    buf: Vec<u8>,
+    capacity: usize,
+}
+
+impl<T> BufferedWriter<T> {
+    /// Wraps the stream with a buffer of 4096 bytes.
+    pub fn new(inner: T) -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY, inner)
+    }
+
+    /// Wraps the stream with a buffer of `capacity` bytes.
+    pub fn with_capacity(capacity: usize, inner: T) -> Self {
+        BufferedWriter {
+            inner,
+            buf: Vec::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Returns a reference to the underlying stream.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    ///
+    /// Writing directly to the underlying stream skips the buffered bytes.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Returns the bytes buffered but not written yet.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Unwraps the stream, the bytes buffered but not written yet are lost.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: WriteEx> BufferedWriter<T> {
+    /// Writes all the buffered bytes to the underlying stream.
+    async fn flush_buf(&mut self) -> Result<(), io::Error> {
+        while !self.buf.is_empty() {
+            let n = self.inner.write2(&self.buf).await?;
+            if n == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            self.buf.drain(..n);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<T: ReadEx> ReadEx for BufferedWriter<T> {
+    async fn read2(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        self.inner.read2(buf).await
+    }
+
+    async fn peek2(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        self.inner.peek2(buf).await
+    }
+}
+
+#[async_trait]
+impl<T: WriteEx> WriteEx for BufferedWriter<T> {
+    async fn write2(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        if self.buf.len() + buf.len() > self.capacity {
+            self.flush_buf().await?;
+        }
+        // too large to be buffered, write it through
+        if buf.len() >= self.capacity {
+            return self.inner.write2(buf).await;
+        }
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    async fn flush2(&mut self) -> Result<(), io::Error> {
+        self.flush_buf().await?;
+        self.inner.flush2().await
+    }
+
+    async fn close2(&mut self) -> Result<(), io::Error> {
+        self.flush_buf().await?;
+        self.inner.close2().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2prs_runtime::task;
+
+    #[derive(Default)]
+    struct Recorder {
+        writes: Vec<Vec<u8>>,
+        closed: bool,
+    }
+
+    #[async_trait]
+    impl WriteEx for Recorder {
+        async fn write2(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+            self.writes.push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        async fn flush2(&mut self) -> Result<(), io::Error> {
+            Ok(())
+        }
+
+        async fn close2(&mut self) -> Result<(), io::Error> {
+            self.closed = true;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn small_writes_batched() {
+        task::block_on(async {
+            let mut writer = BufferedWriter::with_capacity(64, Recorder::default());
+            for i in 0..10u8 {
+                writer.write_all2(&[i; 4]).await.unwrap();
+            }
+            assert!(writer.get_ref().writes.is_empty());
+
+            writer.flush2().await.unwrap();
+            let expected = (0..10u8).flat_map(|i| vec![i; 4]).collect::<Vec<_>>();
+            assert_eq!(writer.get_ref().writes, vec![expected]);
+        });
+    }
+
+    #[test]
+    fn full_buffer_written() {
+        task::block_on(async {
+            let mut writer = BufferedWriter::with_capacity(8, Recorder::default());
+            writer.write_all2(b"hello").await.unwrap();
+            writer.write_all2(b"world").await.unwrap();
+            assert_eq!(writer.get_ref().writes, vec![b"hello".to_vec()]);
+
+            // written through, after the buffered bytes
+            writer.write_all2(b"a large message").await.unwrap();
+            writer.write_all2(b"!").await.unwrap();
+            writer.close2().await.unwrap();
+            let recorder = writer.into_inner();
+            assert_eq!(
+                recorder.writes,
+                vec![b"hello".to_vec(), b"world".to_vec(), b"a large message".to_vec(), b"!".to_vec()]
+            );
+            assert!(recorder.closed);
+        });
+    }
+}
@@ -18,6 +18,7 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
+use std::convert::TryFrom;
 use std::io;
 use std::io::ErrorKind;
 
@@ -25,6 +26,22 @@ use async_trait::async_trait;
 use futures::prelude::*;
 use futures::{AsyncReadExt, AsyncWriteExt};
 
+mod buffered;
+mod peekable;
+mod relay;
+mod semaphore;
+mod token_bucket;
+mod traced;
+pub use buffered::BufferedWriter;
+pub use peekable::Peekable;
+pub use relay::relay_streams;
+pub use semaphore::{Semaphore, SemaphorePermit};
+pub use token_bucket::TokenBucket;
+pub use traced::Traced;
+
+/// The maximum length of an unsigned varint, i.e. of a `u64`.
+pub const MAX_UVARINT_LEN: usize = 10;
+
 /// Read Trait for async/await
 ///
 #[async_trait]
@@ -49,6 +66,19 @@ pub trait ReadEx: Send {
     /// Otherwise, returns `Err(io:Error)`
     async fn read2(&mut self, buf: &mut [u8]) -> Result<usize, io::Error>;
 
+    /// Reads some bytes from the byte stream without consuming them, so that
+    /// the next `read2` returns the same bytes again.
+    ///
+    /// On success, returns `Ok(n)`, `n` being the number of bytes peeked.
+    /// Otherwise, returns `Err(io:Error)`.
+    ///
+    /// > **Note**: Peeking is optional. The default implementation returns an error,
+    /// >           wrap the stream with `Peekable` to get it supported.
+    async fn peek2(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        let _ = buf;
+        Err(io::Error::new(ErrorKind::Other, "peek is not supported"))
+    }
+
     /// Reads the exact number of bytes requested.
     ///
     /// On success, returns `Ok(())`.
@@ -90,36 +120,51 @@ pub trait ReadEx: Send {
     /// > **Note**: This function reads bytes one by one from the underlying IO. It is therefore encouraged
     /// >           to use some sort of buffering mechanism.
     async fn read_varint(&mut self) -> Result<usize, io::Error> {
-        let mut buffer = unsigned_varint::encode::usize_buffer();
-        let mut buffer_len = 0;
-
-        loop {
-            match self.read2(&mut buffer[buffer_len..=buffer_len]).await? {
-                0 => {
-                    // Reaching EOF before finishing to read the length is an error, unless the EOF is
-                    // at the very beginning of the substream, in which case we assume that the data is
-                    // empty.
-                    if buffer_len == 0 {
-                        return Ok(0);
-                    } else {
-                        return Err(io::ErrorKind::UnexpectedEof.into());
-                    }
-                }
-                n => debug_assert_eq!(n, 1),
-            }
+        let mut first = [0u8; 1];
+        // Reaching EOF before finishing to read the length is an error, unless the EOF is
+        // at the very beginning of the substream, in which case we assume that the data is
+        // empty.
+        if self.read2(&mut first).await? == 0 {
+            return Ok(0);
+        }
 
-            buffer_len += 1;
+        let max_bytes = unsigned_varint::encode::usize_buffer().len();
+        let n = self.read_uvarint_from(first[0], max_bytes).await?;
+        usize::try_from(n).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "overflow in variable-length integer"))
+    }
 
-            match unsigned_varint::decode::usize(&buffer[..buffer_len]) {
-                Ok((len, _)) => return Ok(len),
-                Err(unsigned_varint::decode::Error::Overflow) => {
-                    return Err(io::Error::new(io::ErrorKind::InvalidData, "overflow in variable-length integer"));
-                }
-                // TODO: why do we have a `__Nonexhaustive` variant in the error? I don't know how to process it
-                // Err(unsigned_varint::decode::Error::Insufficient) => {}
-                Err(_) => {}
+    /// Reads an unsigned variable-length integer of at most `max_bytes` bytes, which is capped
+    /// to `MAX_UVARINT_LEN`.
+    ///
+    /// A longer one, i.e. the continuation bit still set in the last byte allowed, is rejected
+    /// with `io::ErrorKind::InvalidData`, without reading any further.
+    ///
+    /// > **Note**: This function reads bytes one by one from the underlying IO, just like `read_varint`.
+    async fn read_uvarint(&mut self, max_bytes: usize) -> Result<u64, io::Error> {
+        let mut first = [0u8; 1];
+        self.read_exact2(&mut first).await?;
+        self.read_uvarint_from(first[0], max_bytes).await
+    }
+
+    /// Reads the rest of an unsigned variable-length integer, whose first byte `first` has been
+    /// read already. See `read_uvarint`.
+    async fn read_uvarint_from(&mut self, first: u8, max_bytes: usize) -> Result<u64, io::Error> {
+        let max_bytes = max_bytes.max(1).min(MAX_UVARINT_LEN);
+        let mut buffer = unsigned_varint::encode::u64_buffer();
+        buffer[0] = first;
+        let mut buffer_len = 1;
+
+        while !unsigned_varint::decode::is_last(buffer[buffer_len - 1]) {
+            if buffer_len == max_bytes {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "variable-length integer too long"));
             }
+            self.read_exact2(&mut buffer[buffer_len..=buffer_len]).await?;
+            buffer_len += 1;
         }
+
+        unsigned_varint::decode::u64(&buffer[..buffer_len])
+            .map(|(n, _)| n)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "overflow in variable-length integer"))
     }
 
     /// Reads a fixed length-prefixed message from the underlying IO.
@@ -260,6 +305,19 @@ pub trait WriteEx: Send {
     /// On success, returns `Ok(())`.
     /// Otherwise, returns `Err(io:Error)`
     async fn close2(&mut self) -> Result<(), io::Error>;
+
+    /// Flushes the object, then closes it, so that the buffered data is not lost.
+    ///
+    /// The object is closed even if flushing fails, in which case the error of flushing
+    /// is returned.
+    ///
+    /// On success, returns `Ok(())`.
+    /// Otherwise, returns `Err(io:Error)`
+    async fn flush_and_close2(&mut self) -> Result<(), io::Error> {
+        let r = self.flush2().await;
+        let c = self.close2().await;
+        r.and(c)
+    }
 }
 
 #[async_trait]
@@ -315,6 +373,36 @@ mod tests {
         }
     }
 
+    /// Writer buffering the data until flushed, recording the flushed data when closed.
+    #[derive(Default)]
+    struct Buffering {
+        buf: Vec<u8>,
+        flushed: Vec<u8>,
+        closed_with: Option<Vec<u8>>,
+        fail_flush: bool,
+    }
+
+    #[async_trait]
+    impl WriteEx for Buffering {
+        async fn write2(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+            self.buf.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        async fn flush2(&mut self) -> Result<(), io::Error> {
+            if self.fail_flush {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            self.flushed.append(&mut self.buf);
+            Ok(())
+        }
+
+        async fn close2(&mut self) -> Result<(), io::Error> {
+            self.closed_with = Some(self.flushed.clone());
+            Ok(())
+        }
+    }
+
     /// Read Vec<u8>
     #[test]
     fn test_read() {
@@ -372,6 +460,27 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_read_uvarint() {
+        task::block_on(async {
+            let mut reader = Test(Cursor::new(vec![0xac, 0x02, 0x01]));
+            assert_eq!(reader.read_uvarint(2).await.unwrap(), 300);
+            assert_eq!(reader.read_uvarint(2).await.unwrap(), 1);
+
+            // all continuation bytes, rejected once the bytes allowed are read
+            let mut reader = Test(Cursor::new(vec![0xff; 11]));
+            let err = reader.read_uvarint(MAX_UVARINT_LEN).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(reader.0.position(), 10);
+
+            let mut reader = Test(Cursor::new(vec![0xff; 11]));
+            assert_eq!(reader.read_varint().await.unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+            let mut reader = Test(Cursor::new(vec![0x80]));
+            assert_eq!(reader.read_uvarint(2).await.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        });
+    }
+
     #[test]
     fn test_read_one() {
         task::block_on(async {
@@ -437,4 +546,25 @@ mod tests {
             assert_eq!(writer.0.get_mut(), &[11, 104, 101, 108, 108, 111, 32, 119, 111, 114, 108, 100]);
         });
     }
+
+    #[test]
+    fn test_flush_and_close() {
+        task::block_on(async {
+            let mut writer = Buffering::default();
+            writer.write_all2(b"hello").await.unwrap();
+            writer.flush_and_close2().await.unwrap();
+            assert!(writer.buf.is_empty());
+            assert_eq!(writer.closed_with, Some(b"hello".to_vec()));
+
+            // closed anyway, reporting the failure of flushing
+            let mut writer = Buffering {
+                fail_flush: true,
+                ..Default::default()
+            };
+            writer.write_all2(b"hello").await.unwrap();
+            let r = writer.flush_and_close2().await;
+            assert_eq!(r.unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+            assert!(writer.closed_with.is_some());
+        });
+    }
 }
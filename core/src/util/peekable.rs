@@ -0,0 +1,145 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! A buffered wrapper adding peek support to `ReadEx` streams.
+
+use async_trait::async_trait;
+use std::{cmp, io};
+
+use crate::util::{ReadEx, WriteEx};
+
+/// `Peekable` wraps a `ReadEx` stream and implements `ReadEx::peek2` for it.
+///
+/// Peeked bytes are kept in an internal buffer and handed out first by the
+/// following `read2`, hence nothing is lost when sniffing the beginning of a
+/// stream before passing it on.
+#[derive(Debug)]
+pub struct Peekable<T> {
+    inner: T,
+    peeked: Vec<u8>,
+}
+
+impl<T> Peekable<T> {
+    /// Wraps the stream.
+    pub fn new(inner: T) -> Self {
+        Peekable { inner, peeked: Vec::new() }
+    }
+
+    /// Returns a reference to the underlying stream.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    ///
+    /// Reading directly from the underlying stream skips the peeked bytes.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Unwraps the stream, returning it along with the bytes peeked but not read yet.
+    pub fn into_inner(self) -> (T, Vec<u8>) {
+        (self.inner, self.peeked)
+    }
+}
+
+#[async_trait]
+impl<T: ReadEx> ReadEx for Peekable<T> {
+    async fn read2(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        if self.peeked.is_empty() {
+            return self.inner.read2(buf).await;
+        }
+
+        let n = cmp::min(buf.len(), self.peeked.len());
+        buf[..n].copy_from_slice(&self.peeked[..n]);
+        self.peeked.drain(..n);
+        Ok(n)
+    }
+
+    async fn peek2(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        // not enough bytes buffered, try reading some more
+        let buffered = self.peeked.len();
+        if buffered < buf.len() {
+            self.peeked.resize(buf.len(), 0);
+            match self.inner.read2(&mut self.peeked[buffered..]).await {
+                Ok(n) => self.peeked.truncate(buffered + n),
+                Err(e) => {
+                    self.peeked.truncate(buffered);
+                    return Err(e);
+                }
+            }
+        }
+
+        let n = cmp::min(buf.len(), self.peeked.len());
+        buf[..n].copy_from_slice(&self.peeked[..n]);
+        Ok(n)
+    }
+}
+
+#[async_trait]
+impl<T: WriteEx> WriteEx for Peekable<T> {
+    async fn write2(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        self.inner.write2(buf).await
+    }
+
+    async fn flush2(&mut self) -> Result<(), io::Error> {
+        self.inner.flush2().await
+    }
+
+    async fn close2(&mut self) -> Result<(), io::Error> {
+        self.inner.close2().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::io::Cursor;
+    use libp2prs_runtime::task;
+
+    #[test]
+    fn peek_then_read() {
+        task::block_on(async {
+            let mut stream = Peekable::new(Cursor::new(b"/multistream/1.0.0\n".to_vec()));
+
+            let mut header = [0u8; 12];
+            let n = stream.peek2(&mut header).await.unwrap();
+            assert_eq!(&header[..n], b"/multistream");
+
+            // peeking again returns the same bytes
+            let mut again = [0u8; 12];
+            assert_eq!(stream.peek2(&mut again).await.unwrap(), n);
+            assert_eq!(header, again);
+
+            let mut output = [0u8; 19];
+            stream.read_exact2(&mut output).await.unwrap();
+            assert_eq!(&output, b"/multistream/1.0.0\n");
+        });
+    }
+
+    #[test]
+    fn peek_unsupported() {
+        task::block_on(async {
+            let mut stream = Cursor::new(vec![1, 2, 3]);
+            let mut buf = [0u8; 2];
+            assert!(stream.peek2(&mut buf).await.is_err());
+        });
+    }
+}
@@ -0,0 +1,173 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Relaying data between two streams.
+
+use futures::future::try_join;
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use std::io;
+
+use crate::util::{ReadEx, WriteEx};
+
+/// The size of the buffer used for each direction of a relay.
+const RELAY_BUFFER_SIZE: usize = 8192;
+
+/// Relays data between the two streams, until both directions are done.
+///
+/// Data read from `a` is written to `b`, and data read from `b` is written
+/// to `a`, concurrently. When a direction reaches EOF, the writing side of the
+/// other stream is closed, so that the half-close is propagated.
+///
+/// On success, returns `Ok((a_to_b, b_to_a))`, the total bytes relayed in each direction.
+/// Otherwise, returns `Err(io:Error)`, as soon as either direction fails.
+pub async fn relay_streams<A, B>(a: A, b: B) -> Result<(u64, u64), io::Error>
+where
+    A: AsyncRead + AsyncWrite + Unpin + Send,
+    B: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    let (ra, wa) = a.split();
+    let (rb, wb) = b.split();
+    try_join(copy_half(ra, wb), copy_half(rb, wa)).await
+}
+
+/// Copies all data from `reader` to `writer`, then closes `writer`.
+async fn copy_half<R: ReadEx, W: WriteEx>(mut reader: R, mut writer: W) -> Result<u64, io::Error> {
+    let mut buf = vec![0u8; RELAY_BUFFER_SIZE];
+    let mut total = 0u64;
+    loop {
+        let n = match reader.read2(&mut buf).await {
+            Ok(0) => break,
+            Ok(n) => n,
+            // some stream muxers, i.e. yamux, report EOF as an error
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        };
+        writer.write_all2(&buf[..n]).await?;
+        writer.flush2().await?;
+        total += n as u64;
+    }
+
+    writer.close2().await?;
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::channel::mpsc;
+    use futures::StreamExt;
+    use libp2prs_runtime::task;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    /// One end of an in-memory duplex pipe, which supports half-close.
+    struct Pipe {
+        incoming: mpsc::UnboundedReceiver<Vec<u8>>,
+        outgoing: Option<mpsc::UnboundedSender<Vec<u8>>>,
+        buf: Vec<u8>,
+    }
+
+    fn pipe() -> (Pipe, Pipe) {
+        let (tx1, rx1) = mpsc::unbounded();
+        let (tx2, rx2) = mpsc::unbounded();
+        (
+            Pipe {
+                incoming: rx1,
+                outgoing: Some(tx2),
+                buf: Vec::new(),
+            },
+            Pipe {
+                incoming: rx2,
+                outgoing: Some(tx1),
+                buf: Vec::new(),
+            },
+        )
+    }
+
+    impl AsyncRead for Pipe {
+        fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+            if self.buf.is_empty() {
+                match self.incoming.poll_next_unpin(cx) {
+                    Poll::Ready(Some(data)) => self.buf = data,
+                    Poll::Ready(None) => return Poll::Ready(Ok(0)),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+            let n = std::cmp::min(buf.len(), self.buf.len());
+            buf[..n].copy_from_slice(&self.buf[..n]);
+            self.buf.drain(..n);
+            Poll::Ready(Ok(n))
+        }
+    }
+
+    impl AsyncWrite for Pipe {
+        fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            let sent = self.outgoing.as_ref().map(|tx| tx.unbounded_send(buf.to_vec()).is_ok());
+            match sent {
+                Some(true) => Poll::Ready(Ok(buf.len())),
+                _ => Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+            }
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            self.outgoing = None;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn relay_with_half_close() {
+        task::block_on(async {
+            let (mut client, relay_a) = pipe();
+            let (relay_b, mut server) = pipe();
+
+            let relay = task::spawn(async move { relay_streams(relay_a, relay_b).await.unwrap() });
+
+            client.write_all2(b"ping").await.unwrap();
+            let mut buf = [0u8; 4];
+            server.read_exact2(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"ping");
+
+            server.write_all2(b"pong!").await.unwrap();
+            let mut buf = [0u8; 5];
+            client.read_exact2(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"pong!");
+
+            // the client closes its writing side, the server sees EOF
+            client.close2().await.unwrap();
+            assert_eq!(server.read2(&mut buf).await.unwrap(), 0);
+
+            // the other direction still works
+            server.write_all2(b"bye").await.unwrap();
+            let mut buf = [0u8; 3];
+            client.read_exact2(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"bye");
+
+            server.close2().await.unwrap();
+            assert_eq!(client.read2(&mut buf).await.unwrap(), 0);
+
+            assert_eq!(relay.await.unwrap(), (4, 8));
+        });
+    }
+}
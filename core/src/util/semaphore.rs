@@ -0,0 +1,148 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use futures::channel::oneshot;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// An asynchronous counting semaphore, shared by its clones.
+///
+/// The tasks waiting for a permit are served in order.
+#[derive(Debug, Clone)]
+pub struct Semaphore {
+    inner: Arc<Mutex<State>>,
+}
+
+#[derive(Debug)]
+struct State {
+    available: usize,
+    waiters: VecDeque<oneshot::Sender<SemaphorePermit>>,
+}
+
+/// A permit of `Semaphore`, given back when dropped.
+#[derive(Debug)]
+pub struct SemaphorePermit {
+    inner: Option<Arc<Mutex<State>>>,
+}
+
+impl Semaphore {
+    /// Creates a semaphore with the given number of permits, at least 1.
+    pub fn new(permits: usize) -> Self {
+        Semaphore {
+            inner: Arc::new(Mutex::new(State {
+                available: permits.max(1),
+                waiters: VecDeque::new(),
+            })),
+        }
+    }
+
+    /// The number of permits available right now.
+    pub fn available(&self) -> usize {
+        self.inner.lock().available
+    }
+
+    /// Acquires a permit, waiting until one is given back if none is available.
+    pub async fn acquire(&self) -> SemaphorePermit {
+        let rx = {
+            let mut state = self.inner.lock();
+            if state.available > 0 {
+                state.available -= 1;
+                return SemaphorePermit {
+                    inner: Some(self.inner.clone()),
+                };
+            }
+            let (tx, rx) = oneshot::channel();
+            state.waiters.push_back(tx);
+            rx
+        };
+        // the sender is never dropped without sending, unless the semaphore is gone
+        rx.await.expect("semaphore dropped while waiting")
+    }
+
+    /// Acquires a permit if one is available right now, without waiting.
+    pub fn try_acquire(&self) -> Option<SemaphorePermit> {
+        let mut state = self.inner.lock();
+        if state.available > 0 {
+            state.available -= 1;
+            Some(SemaphorePermit {
+                inner: Some(self.inner.clone()),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for SemaphorePermit {
+    fn drop(&mut self) {
+        let inner = match self.inner.take() {
+            Some(inner) => inner,
+            None => return,
+        };
+        let mut state = inner.lock();
+        // hand the permit over to the first waiter still waiting
+        while let Some(waiter) = state.waiters.pop_front() {
+            let permit = SemaphorePermit {
+                inner: Some(inner.clone()),
+            };
+            match waiter.send(permit) {
+                Ok(()) => return,
+                // the waiter has given up, don't release the permit twice
+                Err(mut permit) => {
+                    permit.inner = None;
+                }
+            }
+        }
+        state.available += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+
+    #[test]
+    fn permits_handed_over_in_order() {
+        futures::executor::block_on(async {
+            let semaphore = Semaphore::new(1);
+            let permit = semaphore.acquire().await;
+            assert_eq!(semaphore.available(), 0);
+
+            let mut first = semaphore.acquire().boxed();
+            let mut second = semaphore.acquire().boxed();
+            assert!((&mut first).now_or_never().is_none());
+            assert!((&mut second).now_or_never().is_none());
+
+            drop(permit);
+            assert!((&mut second).now_or_never().is_none());
+            let permit = first.now_or_never().expect("first waiter served");
+
+            assert!(semaphore.try_acquire().is_none());
+
+            // the waiter gone doesn't keep the permit
+            drop(second);
+            drop(permit);
+            assert_eq!(semaphore.available(), 1);
+            assert!(semaphore.try_acquire().is_some());
+        });
+    }
+}
@@ -0,0 +1,169 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! A wrapper tracing the I/O of `ReadEx`/`WriteEx` streams.
+
+use async_trait::async_trait;
+use log::Level;
+use std::fmt::Write;
+use std::io;
+
+use crate::util::{ReadEx, WriteEx};
+
+/// `Traced` wraps a `ReadEx`/`WriteEx` stream and logs every read and write
+/// at trace level, optionally with a hex dump of the bytes transferred.
+///
+/// It can be put anywhere in a stack of streams, e.g. below or above the
+/// security layer. Nothing is formatted unless the trace level is enabled for
+/// this module.
+#[derive(Debug)]
+pub struct Traced<T> {
+    inner: T,
+    name: String,
+    hex_dump: bool,
+}
+
+impl<T> Traced<T> {
+    /// Wraps the stream, `name` identifies it in the logs.
+    pub fn new(inner: T, name: impl Into<String>) -> Self {
+        Traced {
+            inner,
+            name: name.into(),
+            hex_dump: false,
+        }
+    }
+
+    /// Sets whether the bytes transferred are dumped in hex.
+    pub fn with_hex_dump(mut self, hex_dump: bool) -> Self {
+        self.hex_dump = hex_dump;
+        self
+    }
+
+    /// Returns a reference to the underlying stream.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Unwraps the stream.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn trace(&self, op: &str, r: &Result<usize, io::Error>, buf: &[u8]) {
+        if log::log_enabled!(Level::Trace) {
+            log::trace!("{}", self.describe(op, r, buf));
+        }
+    }
+
+    /// Formats the log line of an operation.
+    fn describe(&self, op: &str, r: &Result<usize, io::Error>, buf: &[u8]) -> String {
+        match r {
+            Ok(n) if self.hex_dump => format!("{} {} {} bytes: {}", self.name, op, n, to_hex(&buf[..*n])),
+            Ok(n) => format!("{} {} {} bytes", self.name, op, n),
+            Err(e) => format!("{} {} failed: {}", self.name, op, e),
+        }
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        let _ = write!(s, "{:02x}", b);
+    }
+    s
+}
+
+#[async_trait]
+impl<T: ReadEx> ReadEx for Traced<T> {
+    async fn read2(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        let r = self.inner.read2(buf).await;
+        self.trace("read", &r, buf);
+        r
+    }
+
+    async fn peek2(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        let r = self.inner.peek2(buf).await;
+        self.trace("peek", &r, buf);
+        r
+    }
+}
+
+#[async_trait]
+impl<T: WriteEx> WriteEx for Traced<T> {
+    async fn write2(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        let r = self.inner.write2(buf).await;
+        self.trace("write", &r, buf);
+        r
+    }
+
+    async fn flush2(&mut self) -> Result<(), io::Error> {
+        let r = self.inner.flush2().await;
+        if let Err(e) = &r {
+            log::trace!("{} flush failed: {}", self.name, e);
+        }
+        r
+    }
+
+    async fn close2(&mut self) -> Result<(), io::Error> {
+        let r = self.inner.close2().await;
+        log::trace!("{} closed: {:?}", self.name, r);
+        r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::io::Cursor;
+    use libp2prs_runtime::task;
+
+    #[test]
+    fn traced_passes_data_through() {
+        task::block_on(async {
+            let mut stream = Traced::new(Cursor::new(Vec::new()), "test").with_hex_dump(true);
+            stream.write_all2(b"hello").await.unwrap();
+            assert_eq!(stream.get_ref().get_ref(), b"hello");
+
+            stream.get_mut().set_position(0);
+            let mut buf = [0u8; 5];
+            stream.read_exact2(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"hello");
+        });
+    }
+
+    #[test]
+    fn traced_log_lines() {
+        let stream = Traced::new(Cursor::new(Vec::<u8>::new()), "test");
+        assert_eq!(stream.describe("write", &Ok(5), b"hello"), "test write 5 bytes");
+
+        let stream = stream.with_hex_dump(true);
+        assert_eq!(stream.describe("read", &Ok(5), b"hello"), "test read 5 bytes: 68656c6c6f");
+        // only the bytes transferred are dumped
+        assert_eq!(stream.describe("read", &Ok(2), b"hello"), "test read 2 bytes: 6865");
+
+        let r = Err(io::ErrorKind::BrokenPipe.into());
+        assert!(stream.describe("write", &r, b"hello").starts_with("test write failed: "));
+    }
+}
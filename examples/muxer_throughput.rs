@@ -0,0 +1,111 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Measures the throughput of yamux or mplex over the memory transport.
+//!
+//! Usage: muxer_throughput [yamux|mplex] [streams] [payload in KB]
+
+use futures::future;
+use libp2prs_core::identity::Keypair;
+use libp2prs_core::multiaddr::protocol::Protocol;
+use libp2prs_core::muxing::throughput::{self, Config};
+use libp2prs_core::muxing::IStreamMuxer;
+use libp2prs_core::transport::memory::MemoryTransport;
+use libp2prs_core::transport::upgrade::TransportUpgrade;
+use libp2prs_core::transport::ListenerEvent;
+use libp2prs_core::{Multiaddr, Transport};
+use libp2prs_mplex as mplex;
+use libp2prs_runtime::task;
+use libp2prs_secio as secio;
+use libp2prs_yamux as yamux;
+use log::info;
+
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let args = std::env::args().collect::<Vec<_>>();
+    let muxer = args.get(1).map(String::as_str).unwrap_or("yamux").to_string();
+    let streams = args.get(2).and_then(|s| s.parse().ok()).unwrap_or(100);
+    let payload_kb = args.get(3).and_then(|s| s.parse().ok()).unwrap_or(1024usize);
+
+    task::block_on(async {
+        let (dialer, listener) = match muxer.as_str() {
+            "mplex" => {
+                connect(
+                    TransportUpgrade::new(
+                        MemoryTransport::default(),
+                        mplex::Config::new(),
+                        secio::Config::new(Keypair::generate_ed25519()),
+                    ),
+                    TransportUpgrade::new(
+                        MemoryTransport::default(),
+                        mplex::Config::new(),
+                        secio::Config::new(Keypair::generate_ed25519()),
+                    ),
+                )
+                .await
+            }
+            _ => {
+                connect(
+                    TransportUpgrade::new(
+                        MemoryTransport::default(),
+                        yamux::Config::client(),
+                        secio::Config::new(Keypair::generate_ed25519()),
+                    ),
+                    TransportUpgrade::new(
+                        MemoryTransport::default(),
+                        yamux::Config::server(),
+                        secio::Config::new(Keypair::generate_ed25519()),
+                    ),
+                )
+                .await
+            }
+        };
+
+        info!("{}: {} streams, {}KB each", muxer, streams, payload_kb);
+        let config = Config::new().streams(streams).payload(payload_kb * 1024);
+        let report = throughput::run(dialer, listener, config).await.expect("throughput run");
+        info!(
+            "{}: {} bytes in {:?}, {:.2} MB/s, fairness {:.3}",
+            muxer,
+            report.bytes,
+            report.elapsed,
+            report.throughput(),
+            report.fairness()
+        );
+    });
+}
+
+async fn connect<T>(mut dialer: T, mut listener: T) -> (IStreamMuxer, IStreamMuxer)
+where
+    T: Transport<Output = IStreamMuxer>,
+{
+    let addr: Multiaddr = Protocol::Memory(8088).into();
+    let mut listener = listener.listen_on(addr.clone()).unwrap();
+    let accept = async move {
+        loop {
+            if let ListenerEvent::Accepted(muxer) = listener.accept().await.unwrap() {
+                break muxer;
+            }
+        }
+    };
+    let (dialer, listener) = future::join(dialer.dial(addr), accept).await;
+    (dialer.unwrap(), listener)
+}
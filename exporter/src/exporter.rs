@@ -21,8 +21,7 @@
 use futures::executor::block_on;
 use libp2prs_swarm::Control;
 use prometheus::core::{Collector, Desc};
-use prometheus::Counter;
-use prometheus::{proto, CounterVec, Opts};
+use prometheus::{proto, Counter, CounterVec, GaugeVec, Opts};
 use std::collections::HashMap;
 
 /// Exporter is used to expose Metrics data.
@@ -57,6 +56,23 @@ impl Collector for Exporter {
         family.push(sent_count.collect()[0].clone());
         family.push(sent_size.collect()[0].clone());
 
+        // bandwidth
+        let bandwidth = self.control.bandwidth();
+        let total_in = Counter::new("bandwidth_in_bytes", "Total bytes received by all connections").unwrap();
+        total_in.inc_by(bandwidth.total_in as f64);
+        family.push(total_in.collect()[0].clone());
+        let total_out = Counter::new("bandwidth_out_bytes", "Total bytes sent by all connections").unwrap();
+        total_out.inc_by(bandwidth.total_out as f64);
+        family.push(total_out.collect()[0].clone());
+        let opt = Opts::new("bandwidth_rate", "Rolling transfer rate in bytes per second");
+        let rate = GaugeVec::new(opt, &["direction", "window"]).unwrap();
+        for (direction, rates) in vec![("in", bandwidth.rate_in), ("out", bandwidth.rate_out)] {
+            rate.with_label_values(&[direction, "1s"]).set(rates.one_sec);
+            rate.with_label_values(&[direction, "1m"]).set(rates.one_min);
+            rate.with_label_values(&[direction, "15m"]).set(rates.fifteen_min);
+        }
+        family.push(rate.collect()[0].clone());
+
         // connection data
         let mut state = self.control.clone();
         block_on(async {
@@ -122,6 +138,16 @@ impl Collector for Exporter {
         }
         family.push(protocol.collect()[0].clone());
 
+        // stream bytes, labeled by protocol and direction
+        let opt = Opts::new("libp2p_stream_bytes_total", "Total bytes of the substreams by protocol");
+        let stream_bytes = CounterVec::new(opt, &["protocol", "direction"]).unwrap();
+        for (direction, bytes) in vec![("in", self.control.protocol_in_iter()), ("out", self.control.protocol_out_iter())] {
+            for (protocol, v) in bytes {
+                stream_bytes.with_label_values(&[protocol.as_str(), direction]).inc_by(v as f64);
+            }
+        }
+        family.push(stream_bytes.collect()[0].clone());
+
         family
     }
 }
@@ -137,3 +163,79 @@ impl Exporter {
         Exporter { control, desc: d.unwrap() }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{AsyncReadExt, AsyncWriteExt};
+    use libp2prs_core::identity::Keypair;
+    use libp2prs_core::multiaddr::protocol::Protocol;
+    use libp2prs_core::transport::memory::MemoryTransport;
+    use libp2prs_core::transport::upgrade::TransportUpgrade;
+    use libp2prs_core::{Multiaddr, ProtocolId};
+    use libp2prs_runtime::task;
+    use libp2prs_secio as secio;
+    use libp2prs_swarm::substream::Substream;
+    use libp2prs_swarm::Swarm;
+    use libp2prs_yamux as yamux;
+    use prometheus::{Encoder, TextEncoder};
+
+    const ECHO_PROTOCOL: &[u8] = b"/echo/1.0.0";
+
+    fn setup_swarm(keys: Keypair) -> Swarm {
+        let sec = secio::Config::new(keys.clone());
+        let mux = yamux::Config::new();
+        let tu = TransportUpgrade::new(MemoryTransport::default(), mux, sec);
+        Swarm::new(keys.public()).with_transport(Box::new(tu))
+    }
+
+    async fn echo(mut stream: Substream) {
+        let mut buf = [0u8; 5];
+        if stream.read_exact(&mut buf).await.is_ok() {
+            let _ = stream.write_all(&buf).await;
+        }
+    }
+
+    #[test]
+    fn stream_bytes_labeled_by_protocol() {
+        let control = task::block_on(async {
+            let srv_keys = Keypair::generate_ed25519();
+            let srv_peer_id = srv_keys.public().into_peer_id();
+            let mut srv_swarm = setup_swarm(srv_keys);
+            let mut srv_ctrl = srv_swarm.control();
+            let addr: Multiaddr = Protocol::Memory(1 + rand::random::<u64>()).into();
+            srv_swarm.listen_on(vec![addr.clone()]).unwrap();
+            srv_swarm.start();
+            srv_ctrl.set_stream_handler(ProtocolId::from(ECHO_PROTOCOL), echo).await.unwrap();
+
+            let cli_swarm = setup_swarm(Keypair::generate_ed25519());
+            let mut cli_ctrl = cli_swarm.control();
+            cli_swarm.start();
+            cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+
+            let mut stream = cli_ctrl
+                .new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+                .await
+                .unwrap();
+            stream.write_all(b"hello").await.unwrap();
+            let mut buf = [0u8; 5];
+            stream.read_exact(&mut buf).await.unwrap();
+            cli_ctrl
+        });
+
+        let families = Exporter::new(control).collect();
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&families, &mut buffer).unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+
+        for direction in &["in", "out"] {
+            assert!(
+                text.lines().any(|l| l.starts_with("libp2p_stream_bytes_total{")
+                    && l.contains(r#"protocol="/echo/1.0.0""#)
+                    && l.contains(&format!(r#"direction="{}""#, direction))),
+                "{}",
+                text
+            );
+        }
+    }
+}
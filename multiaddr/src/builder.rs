@@ -0,0 +1,111 @@
+use crate::{Multiaddr, Protocol};
+use multihash::Multihash;
+use std::{
+    borrow::Cow,
+    net::{Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+};
+
+/// A fluent builder of [`Multiaddr`], writing the components as bytes without parsing
+/// a string, so that no error is to be handled when the components are known.
+///
+/// The builder starts with a network address, then the protocols on top of it are
+/// appended in order.
+///
+/// # Example
+///
+/// ```
+/// use libp2prs_multiaddr::{Multiaddr, MultiaddrBuilder};
+///
+/// let addr = MultiaddrBuilder::ip4([127, 0, 0, 1]).tcp(4001).ws().build();
+/// assert_eq!(addr, "/ip4/127.0.0.1/tcp/4001/ws".parse::<Multiaddr>().unwrap());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MultiaddrBuilder {
+    bytes: Vec<u8>,
+}
+
+impl MultiaddrBuilder {
+    /// Starts with no component at all, see [`MultiaddrBuilder::with`].
+    pub fn new() -> Self {
+        MultiaddrBuilder::default()
+    }
+
+    /// Starts with `/ip4/<addr>`.
+    pub fn ip4(addr: impl Into<Ipv4Addr>) -> Self {
+        Self::new().with(Protocol::Ip4(addr.into()))
+    }
+
+    /// Starts with `/ip6/<addr>`.
+    pub fn ip6(addr: impl Into<Ipv6Addr>) -> Self {
+        Self::new().with(Protocol::Ip6(addr.into()))
+    }
+
+    /// Starts with `/dns/<name>`.
+    pub fn dns<'a>(name: impl Into<Cow<'a, str>>) -> Self {
+        Self::new().with(Protocol::Dns(name.into()))
+    }
+
+    /// Starts with `/dns4/<name>`.
+    pub fn dns4<'a>(name: impl Into<Cow<'a, str>>) -> Self {
+        Self::new().with(Protocol::Dns4(name.into()))
+    }
+
+    /// Starts with `/dns6/<name>`.
+    pub fn dns6<'a>(name: impl Into<Cow<'a, str>>) -> Self {
+        Self::new().with(Protocol::Dns6(name.into()))
+    }
+
+    /// Starts with `/memory/<port>`.
+    pub fn memory(port: u64) -> Self {
+        Self::new().with(Protocol::Memory(port))
+    }
+
+    /// Appends `/tcp/<port>`.
+    pub fn tcp(self, port: u16) -> Self {
+        self.with(Protocol::Tcp(port))
+    }
+
+    /// Appends `/udp/<port>`.
+    pub fn udp(self, port: u16) -> Self {
+        self.with(Protocol::Udp(port))
+    }
+
+    /// Appends `/quic`.
+    pub fn quic(self) -> Self {
+        self.with(Protocol::Quic)
+    }
+
+    /// Appends `/ws`.
+    pub fn ws(self) -> Self {
+        self.with(Protocol::Ws(Cow::Borrowed("/")))
+    }
+
+    /// Appends `/wss`.
+    pub fn wss(self) -> Self {
+        self.with(Protocol::Wss(Cow::Borrowed("/")))
+    }
+
+    /// Appends `/p2p/<peer>`, a `PeerId` converts into the multihash.
+    pub fn p2p(self, peer: impl Into<Multihash>) -> Self {
+        self.with(Protocol::P2p(peer.into()))
+    }
+
+    /// Appends `/p2p-circuit`.
+    pub fn p2p_circuit(self) -> Self {
+        self.with(Protocol::P2pCircuit)
+    }
+
+    /// Appends any component.
+    pub fn with(mut self, p: Protocol<'_>) -> Self {
+        p.write_bytes(&mut self.bytes).expect("Writing to a `Vec<u8>` never fails.");
+        self
+    }
+
+    /// Builds the `Multiaddr`.
+    pub fn build(self) -> Multiaddr {
+        Multiaddr {
+            bytes: Arc::new(self.bytes),
+        }
+    }
+}
@@ -1,6 +1,7 @@
 ///! Implementation of [multiaddr](https://github.com/jbenet/multiaddr) in Rust.
 pub use multihash;
 
+mod builder;
 mod errors;
 mod onion_addr;
 pub mod protocol;
@@ -8,19 +9,23 @@ pub mod protocol;
 #[cfg(feature = "url")]
 mod from_url;
 
+pub use self::builder::MultiaddrBuilder;
 pub use self::errors::{Error, Result};
 pub use self::onion_addr::Onion3Addr;
 pub use self::protocol::Protocol;
+#[cfg(feature = "serde")]
 use serde::{
     de::{self, Error as DeserializerError},
     Deserialize, Deserializer, Serialize, Serializer,
 };
+#[cfg(feature = "serde")]
+use std::result::Result as StdResult;
 use std::{
+    borrow::Cow,
     convert::TryFrom,
     fmt, io,
     iter::FromIterator,
-    net::{IpAddr, Ipv4Addr, Ipv6Addr},
-    result::Result as StdResult,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     str::FromStr,
     sync::Arc,
 };
@@ -33,6 +38,19 @@ static_assertions::const_assert! {
     std::mem::size_of::<usize>() <= std::mem::size_of::<u64>()
 }
 
+/// The protocols which can be put on top of an IP address, see [`Multiaddr::from_socket_addr`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TransportProto {
+    /// `/tcp/<port>`
+    Tcp,
+    /// `/udp/<port>`
+    Udp,
+    /// `/tcp/<port>/ws`
+    Ws,
+    /// `/tcp/<port>/wss`
+    Wss,
+}
+
 /// Representation of a Multiaddr.
 #[allow(clippy::rc_buffer)]
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
@@ -174,6 +192,65 @@ impl Multiaddr {
         find
     }
 
+    /// Returns true if this multiaddress goes through a relay, i.e. it contains `/p2p-circuit`.
+    pub fn is_relayed(&self) -> bool {
+        self.iter().any(|p| p == Protocol::P2pCircuit)
+    }
+
+    /// Splits a relayed multiaddress into the address of the relay and the multihash of the
+    /// target peer, which can be turned into a `PeerId`.
+    ///
+    /// The address must be of the form `<relay-addr>/p2p-circuit/p2p/<target>`. `None` is
+    /// returned if it is not relayed, or if either part is missing or malformed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use libp2prs_multiaddr::Multiaddr;
+    ///
+    /// let address: Multiaddr = "/ip4/1.2.3.4/tcp/4001/p2p/QmNnooDu7bfjPFoTZYxMNLWUQJyrVwtbZg5gBMjTezGAJN/p2p-circuit/p2p/QmcgpsyWgH8Y8ajJz1Cu72KnS5uo2Aa2LpzU7kinSupNKC"
+    ///     .parse()
+    ///     .unwrap();
+    /// let (relay, _target) = address.relay_and_target().unwrap();
+    /// assert_eq!(relay.to_string(), "/ip4/1.2.3.4/tcp/4001/p2p/QmNnooDu7bfjPFoTZYxMNLWUQJyrVwtbZg5gBMjTezGAJN");
+    /// ```
+    ///
+    pub fn relay_and_target(&self) -> Option<(Multiaddr, multihash::Multihash)> {
+        let mut iter = self.iter();
+        let relay: Multiaddr = iter.by_ref().take_while(|p| *p != Protocol::P2pCircuit).collect();
+        let target = match iter.next() {
+            Some(Protocol::P2p(hash)) => hash,
+            _ => return None,
+        };
+        if relay.is_empty() || iter.next().is_some() {
+            return None;
+        }
+        Some((relay, target))
+    }
+
+    /// Builds a multiaddress from a socket address, with the given transport protocol
+    /// on top of the IP address.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use libp2prs_multiaddr::{Multiaddr, TransportProto};
+    ///
+    /// let address = Multiaddr::from_socket_addr("1.2.3.4:443".parse().unwrap(), TransportProto::Tcp);
+    /// assert_eq!(address.to_string(), "/ip4/1.2.3.4/tcp/443");
+    /// ```
+    ///
+    pub fn from_socket_addr(addr: SocketAddr, proto: TransportProto) -> Multiaddr {
+        let address = Multiaddr::from(addr.ip());
+        let port = addr.port();
+        match proto {
+            TransportProto::Tcp => address.with(Protocol::Tcp(port)),
+            TransportProto::Udp => address.with(Protocol::Udp(port)),
+            TransportProto::Ws => address.with(Protocol::Tcp(port)).with(Protocol::Ws(Cow::Borrowed("/"))),
+            TransportProto::Wss => address.with(Protocol::Tcp(port)).with(Protocol::Wss(Cow::Borrowed("/"))),
+        }
+    }
+
     /// Like [`Multiaddr::push`] but consumes `self`.
     pub fn with(mut self, p: Protocol<'_>) -> Self {
         let mut w = io::Cursor::<&mut Vec<u8>>::new(Arc::make_mut(&mut self.bytes));
@@ -202,6 +279,56 @@ impl Multiaddr {
         Iter(&self.bytes)
     }
 
+    /// Returns the canonical form of this `Multiaddr`.
+    ///
+    /// Semantically equal multiaddrs might differ in encoding, e.g. `/ws`, `/x-parity-ws/%2F`
+    /// and `/x-parity-ws/`. The canonical form is built by re-encoding every component, with
+    /// websocket paths and DNS names normalized, so that it can be used for equality test and
+    /// deduplication. Note that onion addresses are kept in binary, which is case-insensitive
+    /// already.
+    ///
+    /// An `/ip6zone` is only meaningful for the scoped IPv6 addresses, i.e. link-local ones,
+    /// so it is dropped from the others. The link-local addresses with different zones, or
+    /// without a zone, are different addresses and remain so.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libp2prs_multiaddr::Multiaddr;
+    ///
+    /// let a: Multiaddr = "/ip4/127.0.0.1/tcp/80/ws".parse().unwrap();
+    /// let b: Multiaddr = "/ip4/127.0.0.1/tcp/80/x-parity-ws/".parse().unwrap();
+    /// assert_ne!(a, b);
+    /// assert_eq!(a.canonical(), b.canonical());
+    /// ```
+    ///
+    pub fn canonical(&self) -> Multiaddr {
+        let mut iter = self.iter().peekable();
+        let mut protocols = Vec::new();
+        while let Some(p) = iter.next() {
+            if let Protocol::Ip6zone(_) = p {
+                if let Some(Protocol::Ip6(ip)) = iter.peek() {
+                    if !is_scoped_ip6(ip) {
+                        continue;
+                    }
+                }
+            }
+            protocols.push(p);
+        }
+        protocols
+            .into_iter()
+            .map(|p| match p {
+                Protocol::Ws(path) => Protocol::Ws(canonical_ws_path(path)),
+                Protocol::Wss(path) => Protocol::Wss(canonical_ws_path(path)),
+                Protocol::Dns(name) => Protocol::Dns(Cow::Owned(name.to_lowercase())),
+                Protocol::Dns4(name) => Protocol::Dns4(Cow::Owned(name.to_lowercase())),
+                Protocol::Dns6(name) => Protocol::Dns6(Cow::Owned(name.to_lowercase())),
+                Protocol::Dnsaddr(name) => Protocol::Dnsaddr(Cow::Owned(name.to_lowercase())),
+                p => p,
+            })
+            .collect()
+    }
+
     /// Replace a [`Protocol`] at some position in this `Multiaddr`.
     ///
     /// The parameter `at` denotes the index of the protocol at which the function
@@ -239,6 +366,21 @@ impl Multiaddr {
     }
 }
 
+// Link-local unicast, and multicast with interface-local or link-local scope.
+fn is_scoped_ip6(ip: &Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    (first & 0xffc0) == 0xfe80 || (ip.is_multicast() && matches!(first & 0x000f, 1 | 2))
+}
+
+// Websocket path must be absolute, an empty path is equivalent to the root.
+fn canonical_ws_path(path: Cow<'_, str>) -> Cow<'_, str> {
+    if path.starts_with('/') {
+        path
+    } else {
+        Cow::Owned(format!("/{}", path))
+    }
+}
+
 impl fmt::Debug for Multiaddr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.to_string().fmt(f)
@@ -391,6 +533,9 @@ impl<'a> TryFrom<&'a str> for Multiaddr {
     }
 }
 
+/// Serializes to the string form for the human-readable formats, e.g. JSON or TOML,
+/// and to the binary form for the compact ones, e.g. bincode.
+#[cfg(feature = "serde")]
 impl Serialize for Multiaddr {
     fn serialize<S>(&self, serializer: S) -> StdResult<S::Ok, S::Error>
     where
@@ -404,6 +549,7 @@ impl Serialize for Multiaddr {
     }
 }
 
+#[cfg(feature = "serde")]
 impl<'de> Deserialize<'de> for Multiaddr {
     fn deserialize<D>(deserializer: D) -> StdResult<Self, D::Error>
     where
@@ -24,6 +24,7 @@ pub const HTTP: u32 = 480;
 pub const HTTPS: u32 = 443;
 pub const IP4: u32 = 4;
 pub const IP6: u32 = 41;
+pub const IP6ZONE: u32 = 42;
 pub const P2P_WEBRTC_DIRECT: u32 = 276;
 pub const P2P_WEBRTC_STAR: u32 = 275;
 pub const P2P_WEBSOCKET_STAR: u32 = 479;
@@ -74,6 +75,8 @@ pub enum Protocol<'a> {
     Https,
     Ip4(Ipv4Addr),
     Ip6(Ipv6Addr),
+    /// The IPv6 zone (scope) of the `Ip6` address that follows, e.g. an interface name.
+    Ip6zone(Cow<'a, str>),
     P2pWebRtcDirect,
     P2pWebRtcStar,
     P2pWebSocketStar,
@@ -126,6 +129,10 @@ impl<'a> Protocol<'a> {
                 let s = iter.next().ok_or(Error::InvalidProtocolString)?;
                 Ok(Protocol::Ip6(Ipv6Addr::from_str(s)?))
             }
+            "ip6zone" => {
+                let s = iter.next().ok_or(Error::InvalidProtocolString)?;
+                Ok(Protocol::Ip6zone(Cow::Borrowed(s)))
+            }
             "dns" => {
                 let s = iter.next().ok_or(Error::InvalidProtocolString)?;
                 Ok(Protocol::Dns(Cow::Borrowed(s)))
@@ -212,6 +219,7 @@ impl<'a> Protocol<'a> {
             DNS => Ok(Protocol::Dns(Cow::Borrowed(""))),
             DNS4 => Ok(Protocol::Dns4(Cow::Borrowed(""))),
             DNS6 => Ok(Protocol::Dns6(Cow::Borrowed(""))),
+            IP6ZONE => Ok(Protocol::Ip6zone(Cow::Borrowed(""))),
             DNSADDR => Ok(Protocol::Dnsaddr(Cow::Borrowed(""))),
             WS => Ok(Protocol::Ws(Cow::Borrowed("/"))),
             WSS => Ok(Protocol::Wss(Cow::Borrowed("/"))),
@@ -230,6 +238,7 @@ impl<'a> Protocol<'a> {
         match self {
             Protocol::Ip4(_) => Ok(IP4),
             Protocol::Ip6(_) => Ok(IP6),
+            Protocol::Ip6zone(_) => Ok(IP6ZONE),
             Protocol::Memory(_) => Ok(MEMORY),
             Protocol::Onion(_, _) => Ok(ONION),
             Protocol::Onion3(_) => Ok(ONION3),
@@ -288,6 +297,11 @@ impl<'a> Protocol<'a> {
                 let (data, rest) = split_at(n, input)?;
                 Ok((Protocol::Dns6(Cow::Borrowed(str::from_utf8(data)?)), rest))
             }
+            IP6ZONE => {
+                let (n, input) = decode::usize(input)?;
+                let (data, rest) = split_at(n, input)?;
+                Ok((Protocol::Ip6zone(Cow::Borrowed(str::from_utf8(data)?)), rest))
+            }
             DNSADDR => {
                 let (n, input) = decode::usize(input)?;
                 let (data, rest) = split_at(n, input)?;
@@ -428,6 +442,12 @@ impl<'a> Protocol<'a> {
                 w.write_all(encode::usize(bytes.len(), &mut encode::usize_buffer()))?;
                 w.write_all(&bytes)?
             }
+            Protocol::Ip6zone(s) => {
+                w.write_all(encode::u32(IP6ZONE, &mut buf))?;
+                let bytes = s.as_bytes();
+                w.write_all(encode::usize(bytes.len(), &mut encode::usize_buffer()))?;
+                w.write_all(&bytes)?
+            }
             Protocol::Dnsaddr(s) => {
                 w.write_all(encode::u32(DNSADDR, &mut buf))?;
                 let bytes = s.as_bytes();
@@ -495,6 +515,7 @@ impl<'a> Protocol<'a> {
             Dns(cow) => Dns(Cow::Owned(cow.into_owned())),
             Dns4(cow) => Dns4(Cow::Owned(cow.into_owned())),
             Dns6(cow) => Dns6(Cow::Owned(cow.into_owned())),
+            Ip6zone(cow) => Ip6zone(Cow::Owned(cow.into_owned())),
             Dnsaddr(cow) => Dnsaddr(Cow::Owned(cow.into_owned())),
             Http => Http,
             Https => Https,
@@ -529,6 +550,7 @@ impl<'a> fmt::Display for Protocol<'a> {
             Dns(s) => write!(f, "/dns/{}", s),
             Dns4(s) => write!(f, "/dns4/{}", s),
             Dns6(s) => write!(f, "/dns6/{}", s),
+            Ip6zone(s) => write!(f, "/ip6zone/{}", s),
             Dnsaddr(s) => write!(f, "/dnsaddr/{}", s),
             Http => f.write_str("/http"),
             Https => f.write_str("/https"),
@@ -1,7 +1,7 @@
 use data_encoding::HEXUPPER;
 use libp2prs_multiaddr::multiaddr;
 use libp2prs_multiaddr::protocol::Protocol;
-use libp2prs_multiaddr::Multiaddr;
+use libp2prs_multiaddr::{Multiaddr, MultiaddrBuilder, TransportProto};
 use multihash::Multihash;
 use quickcheck::{Arbitrary, Gen, QuickCheck};
 use rand::Rng;
@@ -75,7 +75,7 @@ struct Proto(Protocol<'static>);
 impl Arbitrary for Proto {
     fn arbitrary<G: Gen>(g: &mut G) -> Self {
         use Protocol::*;
-        match g.gen_range(0, 25) {
+        match g.gen_range(0, 26) {
             // TODO: Add Protocol::Quic
             0 => Proto(Dccp(g.gen())),
             1 => Proto(Dns(Cow::Owned(SubString::arbitrary(g).0))),
@@ -111,6 +111,7 @@ impl Arbitrary for Proto {
                 g.fill_bytes(&mut a);
                 Proto(Onion3((a, g.gen_range(1, std::u16::MAX)).into()))
             }
+            25 => Proto(Ip6zone(Cow::Owned(SubString::arbitrary(g).0))),
             _ => panic!("outside range"),
         }
     }
@@ -283,6 +284,11 @@ fn construct_success() {
         "3819736A632D312E626F6F7473747261702E6C69627032702E696F0604D2A50322122006B3608AA000274049EB28AD8E793A26FF6FAB281A7D3BD77CD18EB745DFAABB",
         vec![Dnsaddr(Cow::Borrowed("sjc-1.bootstrap.libp2p.io")), Tcp(1234), P2p(multihash("QmNnooDu7bfjPFoTZYxMNLWUQJyrVwtbZg5gBMjTezGAJN"))]
     );
+    ma_valid(
+        "/ip6zone/eth0/ip6/fe80::1/tcp/1",
+        "2A046574683029FE800000000000000000000000000001060001",
+        vec![Ip6zone(Cow::Borrowed("eth0")), Ip6("fe80::1".parse().unwrap()), Tcp(1)],
+    );
 }
 
 #[test]
@@ -357,6 +363,7 @@ fn from_bytes_fail() {
     assert!(Multiaddr::try_from(bytes).is_err());
 }
 
+#[cfg(feature = "serde")]
 #[test]
 fn ser_and_deser_json() {
     let addr: Multiaddr = "/ip4/0.0.0.0/tcp/0".parse::<Multiaddr>().unwrap();
@@ -366,6 +373,7 @@ fn ser_and_deser_json() {
     assert_eq!(addr, deserialized);
 }
 
+#[cfg(feature = "serde")]
 #[test]
 fn ser_and_deser_bincode() {
     let addr: Multiaddr = "/ip4/0.0.0.0/tcp/0".parse::<Multiaddr>().unwrap();
@@ -389,6 +397,74 @@ fn append() {
     assert_eq!(None, i.next())
 }
 
+#[test]
+fn canonical() {
+    let addrs = [
+        "/ip4/127.0.0.1/tcp/80/ws",
+        "/ip4/127.0.0.1/tcp/80/x-parity-ws/",
+        "/ip4/127.0.0.1/tcp/80/x-parity-ws/%2F",
+    ];
+    let expected: Multiaddr = "/ip4/127.0.0.1/tcp/80/ws".parse().unwrap();
+    for a in addrs.iter() {
+        assert_eq!(a.parse::<Multiaddr>().unwrap().canonical(), expected, "{}", a);
+    }
+
+    // WS_WITH_PATH encoded with the root path
+    let mut bytes = "/ip4/127.0.0.1/tcp/80".parse::<Multiaddr>().unwrap().to_vec();
+    bytes.extend_from_slice(&[0xA2, 0x25, 0x01, b'/']);
+    let a = Multiaddr::try_from(bytes).unwrap();
+    assert_ne!(a, expected);
+    assert_eq!(a.canonical(), expected);
+
+    let a: Multiaddr = "/dns4/Example.COM/tcp/443/x-parity-wss/".parse().unwrap();
+    let b: Multiaddr = "/dns4/example.com/tcp/443/wss".parse().unwrap();
+    assert_eq!(a.canonical(), b);
+
+    let a: Multiaddr = "/onion3/VWW6YBAL4BD7SZMGNCYRUUCPGFKQAHZDDI37KTCEO3AH7NGMCOPNPYYD:1234"
+        .parse()
+        .unwrap();
+    let b: Multiaddr = "/onion3/vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd:1234"
+        .parse()
+        .unwrap();
+    assert_eq!(a.canonical(), b.canonical());
+
+    // canonical form is idempotent
+    assert_eq!(b.canonical().canonical(), b.canonical());
+}
+
+#[test]
+fn canonical_ip6zone() {
+    fn dedup(addrs: &[&str]) -> Vec<Multiaddr> {
+        let mut addrs: Vec<Multiaddr> = addrs.iter().map(|a| a.parse::<Multiaddr>().unwrap().canonical()).collect();
+        addrs.sort_by_key(|a| a.to_vec());
+        addrs.dedup();
+        addrs
+    }
+
+    // same zone, same link-local address
+    let addrs = dedup(&["/ip6zone/eth0/ip6/fe80::1/tcp/4001", "/ip6zone/eth0/ip6/fe80::1/tcp/4001"]);
+    assert_eq!(addrs.len(), 1);
+
+    // different zones, or no zone at all, are different link-local addresses
+    let addrs = dedup(&[
+        "/ip6zone/eth0/ip6/fe80::1/tcp/4001",
+        "/ip6zone/eth1/ip6/fe80::1/tcp/4001",
+        "/ip6/fe80::1/tcp/4001",
+    ]);
+    assert_eq!(addrs.len(), 3);
+
+    // so are link-local multicast addresses
+    let addrs = dedup(&["/ip6zone/eth0/ip6/ff02::1/udp/5353", "/ip6zone/eth1/ip6/ff02::1/udp/5353"]);
+    assert_eq!(addrs.len(), 2);
+
+    // a zone means nothing for a global address
+    let a: Multiaddr = "/ip6zone/eth0/ip6/2001:db8::1/tcp/4001".parse().unwrap();
+    let b: Multiaddr = "/ip6/2001:db8::1/tcp/4001".parse().unwrap();
+    assert_eq!(a.canonical(), b);
+    let addrs = dedup(&["/ip6zone/eth0/ip6/2001:db8::1/tcp/4001", "/ip6zone/eth1/ip6/2001:db8::1/tcp/4001"]);
+    assert_eq!(addrs.len(), 1);
+}
+
 fn replace_ip_addr(a: &Multiaddr, p: Protocol) -> Option<Multiaddr> {
     a.replace(0, move |x| match x {
         Protocol::Ip4(_) | Protocol::Ip6(_) => Some(p),
@@ -428,3 +504,83 @@ fn unknown_protocol_string() {
         },
     }
 }
+
+#[test]
+fn relay_and_target() {
+    let relay = "/ip4/1.2.3.4/tcp/4001/p2p/QmNnooDu7bfjPFoTZYxMNLWUQJyrVwtbZg5gBMjTezGAJN";
+    let target = "QmcgpsyWgH8Y8ajJz1Cu72KnS5uo2Aa2LpzU7kinSupNKC";
+
+    let direct: Multiaddr = relay.parse().unwrap();
+    assert!(!direct.is_relayed());
+    assert_eq!(direct.relay_and_target(), None);
+
+    let relayed: Multiaddr = format!("{}/p2p-circuit/p2p/{}", relay, target).parse().unwrap();
+    assert!(relayed.is_relayed());
+    let (r, t) = relayed.relay_and_target().unwrap();
+    assert_eq!(r, direct);
+    assert_eq!(t, Multihash::from_bytes(&bs58::decode(target).into_vec().unwrap()).unwrap());
+
+    // malformed relay addresses
+    for s in &[
+        format!("{}/p2p-circuit", relay),
+        format!("/p2p-circuit/p2p/{}", target),
+        format!("{}/p2p-circuit/ip4/5.6.7.8", relay),
+        format!("{}/p2p-circuit/p2p/{}/p2p-circuit", relay, target),
+    ] {
+        let addr: Multiaddr = s.parse().unwrap();
+        assert!(addr.is_relayed());
+        assert_eq!(addr.relay_and_target(), None, "{}", s);
+    }
+}
+
+#[test]
+fn from_socket_addr() {
+    let addr = Multiaddr::from_socket_addr("1.2.3.4:443".parse().unwrap(), TransportProto::Tcp);
+    assert_eq!(addr, multiaddr!(Ip4([1, 2, 3, 4]), Tcp(443u16)));
+    assert_eq!(addr.to_string(), "/ip4/1.2.3.4/tcp/443");
+
+    let addr = Multiaddr::from_socket_addr("[2001:db8::1]:53".parse().unwrap(), TransportProto::Udp);
+    assert_eq!(addr.to_string(), "/ip6/2001:db8::1/udp/53");
+
+    let addr = Multiaddr::from_socket_addr("127.0.0.1:8080".parse().unwrap(), TransportProto::Ws);
+    assert_eq!(addr.to_string(), "/ip4/127.0.0.1/tcp/8080/ws");
+}
+
+#[test]
+fn builder_matches_parsed() {
+    let peer = "QmcgpsyWgH8Y8ajJz1Cu72KnS5uo2Aa2LpzU7kinSupNKC";
+    let hash = Multihash::from_bytes(&bs58::decode(peer).into_vec().unwrap()).unwrap();
+
+    let cases = vec![
+        (
+            MultiaddrBuilder::ip4([127, 0, 0, 1]).tcp(4001).p2p(hash).build(),
+            format!("/ip4/127.0.0.1/tcp/4001/p2p/{}", peer),
+        ),
+        (
+            MultiaddrBuilder::ip6(Ipv6Addr::LOCALHOST).udp(53).quic().build(),
+            "/ip6/::1/udp/53/quic".to_string(),
+        ),
+        (
+            MultiaddrBuilder::dns4("example.com").tcp(443).wss().build(),
+            "/dns4/example.com/tcp/443/wss".to_string(),
+        ),
+        (
+            MultiaddrBuilder::dns("example.com").tcp(80).ws().build(),
+            "/dns/example.com/tcp/80/ws".to_string(),
+        ),
+        (
+            MultiaddrBuilder::dns6("example.com").udp(1).build(),
+            "/dns6/example.com/udp/1".to_string(),
+        ),
+        (
+            MultiaddrBuilder::memory(1234).p2p_circuit().build(),
+            "/memory/1234/p2p-circuit".to_string(),
+        ),
+        (MultiaddrBuilder::new().with(Protocol::Udt).build(), "/udt".to_string()),
+    ];
+    for (built, s) in cases {
+        assert_eq!(built, s.parse::<Multiaddr>().unwrap());
+        assert_eq!(built.to_string(), s);
+    }
+    assert!(MultiaddrBuilder::new().build().is_empty());
+}
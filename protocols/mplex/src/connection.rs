@@ -87,12 +87,14 @@
 //   One solution is spawn runtime for reader and writer But depend on async runtime
 //   is not attractive. See detail from concurrent in tests
 
+mod budget;
 pub mod control;
+pub mod stats;
 pub mod stream;
 
 use futures::{
     channel::{mpsc, oneshot},
-    future::{select, Either},
+    future::{self, select, Either},
     prelude::*,
     select,
     stream::FusedStream,
@@ -104,12 +106,16 @@ use crate::{
     frame::{io, Frame, FrameDecodeError, StreamID, Tag},
     pause::Pausable,
 };
+use budget::{Inbox, ReadBudget};
 use control::Control;
 use futures::io::WriteHalf;
 use nohash_hasher::IntMap;
+use stats::{Counters, MplexStats};
 use std::collections::VecDeque;
 use std::fmt;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 use stream::{State, Stream};
 
@@ -133,6 +139,8 @@ pub(crate) enum StreamCommand {
     CloseStream(Frame),
     /// Reset a stream.
     ResetStream(Frame, oneshot::Sender<()>),
+    /// Reply once the frames queued before have been written.
+    Flush(oneshot::Sender<()>),
 }
 
 /// The connection identifier.
@@ -186,6 +194,14 @@ impl Shutdown {
     }
 }
 
+/// The connection side of a `Stream`.
+struct StreamHandle {
+    /// Pushes incoming data to the `Stream`.
+    sender: mpsc::Sender<Vec<u8>>,
+    /// Tells the `Stream` that the remote has reset it.
+    remote_reset: Arc<AtomicBool>,
+}
+
 /// Arbitrary limit of our internal command channels.
 ///
 /// Since each `mpsc::Sender` gets a guaranteed slot in a channel the
@@ -193,6 +209,10 @@ impl Shutdown {
 const MAX_COMMAND_BACKLOG: usize = 32;
 const RECEIVE_TIMEOUT: Duration = Duration::from_secs(5);
 
+/// The default maximum of the bytes buffered for all streams of a connection,
+/// beyond which the connection stops reading from the socket.
+pub const MAX_BUFFERED_BYTES: usize = 16 * 1024 * 1024;
+
 type Result<T> = std::result::Result<T, ConnectionError>;
 
 pub struct Connection<T> {
@@ -202,7 +222,7 @@ pub struct Connection<T> {
     is_closed: bool,
     shutdown: Shutdown,
     next_stream_id: u32,
-    streams: IntMap<StreamID, mpsc::Sender<Vec<u8>>>,
+    streams: IntMap<StreamID, StreamHandle>,
     streams_stat: IntMap<StreamID, State>,
     stream_sender: mpsc::Sender<StreamCommand>,
     stream_receiver: mpsc::Receiver<StreamCommand>,
@@ -210,20 +230,40 @@ pub struct Connection<T> {
     control_receiver: Pausable<mpsc::Receiver<ControlCommand>>,
     waiting_stream_sender: Option<oneshot::Sender<Result<stream::Stream>>>,
     pending_streams: VecDeque<stream::Stream>,
+    stats: Arc<Counters>,
+    budget: Arc<ReadBudget>,
+    /// Set once the connection ends on a failure, rather than being closed or reaching EOF.
+    failed: Arc<AtomicBool>,
 }
 
 impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Connection<T> {
     /// Create a new `Connection` from the given I/O resource.
     pub fn new(socket: T) -> Self {
+        Self::with_max_buffered_bytes(socket, MAX_BUFFERED_BYTES)
+    }
+
+    /// Create a new `Connection` from the given I/O resource, which buffers at most `cap`
+    /// bytes for all its streams.
+    pub fn with_max_buffered_bytes(socket: T, cap: usize) -> Self {
+        Self::with_options(socket, cap, false, None)
+    }
+
+    /// Create a new `Connection` from the given I/O resource, which buffers at most `cap`
+    /// bytes for all its streams, checksums the frames if `checksums` is set, and fails
+    /// once a frame isn't read in full within `read_timeout`.
+    pub(crate) fn with_options(socket: T, cap: usize, checksums: bool, read_timeout: Option<Duration>) -> Self {
         let id = Id::random();
         log::debug!("new connection: {}", id);
 
+        let stats = Arc::new(Counters::default());
         let (reader, writer) = socket.split();
-        let reader = io::IO::new(id, reader);
+        let reader = io::IO::new(id, reader, stats.clone())
+            .with_checksums(checksums)
+            .with_read_timeout(read_timeout);
         let reader = futures::stream::unfold(reader, |mut io| async { Some((io.recv_frame().await, io)) });
         let reader = Box::pin(reader);
 
-        let writer = io::IO::new(id, writer);
+        let writer = io::IO::new(id, writer, stats.clone()).with_checksums(checksums);
         let (stream_sender, stream_receiver) = mpsc::channel(MAX_COMMAND_BACKLOG);
         let (control_sender, control_receiver) = mpsc::channel(MAX_COMMAND_BACKLOG);
 
@@ -242,6 +282,9 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Connection<T> {
             control_receiver: Pausable::new(control_receiver),
             waiting_stream_sender: None,
             pending_streams: VecDeque::default(),
+            stats,
+            budget: Arc::new(ReadBudget::new(cap)),
+            failed: Arc::new(AtomicBool::new(false)),
         }
     }
     /// Returns the id of the connection
@@ -251,7 +294,22 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Connection<T> {
 
     /// Get a controller for this connection.
     pub fn control(&self) -> Control {
-        Control::new(self.control_sender.clone())
+        Control::new(
+            self.control_sender.clone(),
+            self.stream_sender.clone(),
+            self.stats.clone(),
+            self.failed.clone(),
+        )
+    }
+
+    /// Returns a snapshot of the frames sent and received so far.
+    pub fn stats(&self) -> MplexStats {
+        self.stats.snapshot()
+    }
+
+    /// Returns the bytes received but not yet read by the streams.
+    pub fn buffered_bytes(&self) -> usize {
+        self.budget.buffered()
     }
 
     /// Get the next incoming stream, opened by the remote.
@@ -271,6 +329,11 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Connection<T> {
 
         self.is_closed = true;
 
+        // flag a failure before any waiting `Control` learns the connection is gone
+        if !is_clean_end(&result) {
+            self.failed.store(true, Ordering::SeqCst);
+        }
+
         if let Some(sender) = self.waiting_stream_sender.take() {
             sender.send(Err(ConnectionError::Closed)).expect("send err");
         }
@@ -321,8 +384,8 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Connection<T> {
     pub async fn handle_coming(&mut self) -> Result<()> {
         loop {
             select! {
-                // handle incoming
-                frame = self.reader.next() => {
+                // handle incoming, unless too much data is waiting for the streams to read
+                frame = next_frame(&mut self.reader, &self.budget).fuse() => {
                     if let Some(f) = frame {
                         let frame = f?;
                         self.on_frame(frame).await?;
@@ -354,11 +417,7 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Connection<T> {
                     return Err(ConnectionError::Io(std::io::ErrorKind::InvalidData.into()));
                 }
 
-                let (stream_sender, stream_receiver) = mpsc::channel(MAX_COMMAND_BACKLOG);
-                self.streams.insert(stream_id, stream_sender);
-                self.streams_stat.insert(stream_id, State::Open);
-
-                let stream = Stream::new(stream_id, self.id, self.stream_sender.clone(), stream_receiver);
+                let stream = self.new_stream(stream_id);
 
                 log::debug!("{}: new inbound {} of {}", self.id, stream, self);
                 if let Some(sender) = self.waiting_stream_sender.take() {
@@ -381,16 +440,27 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Connection<T> {
                 let mut reset = false;
                 let mut dropped = false;
                 // If stream is closed, ignore frame
-                if let Some(sender) = self.streams.get_mut(&stream_id) {
-                    if !sender.is_closed() {
-                        let sender = sender.send(frame.body());
-                        if send_channel_timeout(sender, RECEIVE_TIMEOUT).await.is_err() {
-                            // reset stream
-                            log::debug!("stream {} send timeout, Reset it", stream_id);
-                            reset = true;
-                            // info.sender.close().await;
-                            let frame = Frame::reset_frame(stream_id);
-                            self.writer.send_frame(&frame).await.or(Err(ConnectionError::Closed))?;
+                if let Some(handle) = self.streams.get_mut(&stream_id) {
+                    if !handle.sender.is_closed() {
+                        // account the data before sending, the stream might read it at once
+                        let len = frame.body_len();
+                        self.budget.take(len);
+                        let sender = handle.sender.send(frame.body());
+                        match send_channel_timeout(sender, RECEIVE_TIMEOUT).await {
+                            Ok(Ok(())) => {}
+                            Ok(Err(_)) => {
+                                self.budget.release(len);
+                                dropped = true;
+                            }
+                            Err(_) => {
+                                self.budget.release(len);
+                                // reset stream
+                                log::debug!("stream {} send timeout, Reset it", stream_id);
+                                reset = true;
+                                // info.sender.close().await;
+                                let frame = Frame::reset_frame(stream_id);
+                                self.writer.send_frame(&frame).await.or(Err(ConnectionError::Closed))?;
+                            }
                         }
                     } else {
                         dropped = true;
@@ -427,7 +497,9 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Connection<T> {
                 let stream_id = frame.header().stream_id();
                 log::trace!("{}: remote reset stream {} of {}", self.id, stream_id, self);
                 self.streams_stat.remove(&stream_id);
-                self.streams.remove(&stream_id);
+                if let Some(handle) = self.streams.remove(&stream_id) {
+                    handle.remote_reset.store(true, Ordering::Relaxed);
+                }
             }
         };
 
@@ -483,6 +555,12 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Connection<T> {
                 }
                 let _ = reply.send(());
             }
+            Some(StreamCommand::Flush(reply)) => {
+                // the frames are queued in order on the same channel, and each of them
+                // has been flushed to the socket when sent
+                log::trace!("{}: flush barrier reached", self.id);
+                let _ = reply.send(());
+            }
             None => {
                 // We only get to this point when `self.stream_receiver`
                 // was closed which only happens in response to a close control
@@ -512,9 +590,7 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Connection<T> {
                 }
 
                 let stream_id = self.next_stream_id()?;
-                let (stream_sender, stream_receiver) = mpsc::channel(MAX_COMMAND_BACKLOG);
-                self.streams.insert(stream_id, stream_sender);
-                self.streams_stat.insert(stream_id, State::Open);
+                let stream = self.new_stream(stream_id);
 
                 log::debug!("{}: new outbound {} of {}", self.id, stream_id, self);
 
@@ -523,7 +599,6 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Connection<T> {
                 let frame = Frame::new_stream_frame(stream_id, body.as_bytes());
                 self.writer.send_frame(&frame).await.or(Err(ConnectionError::Closed))?;
 
-                let stream = Stream::new(stream_id, self.id, self.stream_sender.clone(), stream_receiver);
                 reply.send(Ok(stream)).expect("send err");
             }
             Some(ControlCommand::AcceptStream(reply)) => {
@@ -556,12 +631,28 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Connection<T> {
                 debug_assert!(self.shutdown.is_in_progress());
                 log::debug!("{}: closing {}", self.id, self);
 
+                // end the streams still open, then close the socket, so that the remote sees
+                // the streams shut down by the muxer rather than a broken connection
+                let open = self.streams_stat.drain().collect::<Vec<_>>();
+                for (stream_id, stat) in open {
+                    let frame = if stat.can_write() {
+                        log::trace!("{}: closing stream {} on close", self.id, stream_id);
+                        Frame::close_frame(stream_id)
+                    } else {
+                        // closed by ourselves already, the remote can only be told to stop writing
+                        log::trace!("{}: resetting stream {} on close", self.id, stream_id);
+                        Frame::reset_frame(stream_id)
+                    };
+                    self.writer.send_frame(&frame).await.or(Err(ConnectionError::Closed))?;
+                }
+                let closed = self.writer.close().await.or(Err(ConnectionError::Closed));
+
+                // Inform the `Control` that initiated the shutdown, once the socket is closed.
                 let shutdown = std::mem::replace(&mut self.shutdown, Shutdown::Complete);
                 if let Shutdown::InProgress(tx) = shutdown {
-                    // Inform the `Control` that initiated the shutdown.
                     let _ = tx.send(());
                 }
-                self.writer.close().await.or(Err(ConnectionError::Closed))?;
+                closed?;
 
                 return Err(ConnectionError::Closed);
             }
@@ -570,17 +661,40 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Connection<T> {
     }
 }
 
-async fn send_channel_timeout<F>(future: F, timeout: Duration) -> std::io::Result<()>
+async fn send_channel_timeout<F>(future: F, timeout: Duration) -> std::io::Result<F::Output>
 where
     F: Future + Unpin,
 {
     let output = select(future, Delay::new(timeout)).await;
     match output {
-        Either::Left((_, _)) => Ok(()),
+        Either::Left((r, _)) => Ok(r),
         Either::Right(_) => Err(std::io::ErrorKind::TimedOut.into()),
     }
 }
 
+/// Reads the next frame, once the data buffered for the streams is below the cap.
+/// Whether the connection ended without a failure, that is closed by us, or the socket
+/// reaching EOF or being reset by the remote.
+fn is_clean_end(result: &Result<()>) -> bool {
+    let kind = match result {
+        Ok(()) | Err(ConnectionError::Closed) => return true,
+        Err(ConnectionError::Io(e)) | Err(ConnectionError::Decode(FrameDecodeError::Io(e))) => e.kind(),
+        Err(_) => return false,
+    };
+    matches!(
+        kind,
+        std::io::ErrorKind::UnexpectedEof | std::io::ErrorKind::ConnectionReset | std::io::ErrorKind::BrokenPipe
+    )
+}
+
+async fn next_frame<S>(reader: &mut S, budget: &ReadBudget) -> Option<S::Item>
+where
+    S: futures::Stream + Unpin,
+{
+    future::poll_fn(|cx| budget.poll_available(cx)).await;
+    reader.next().await
+}
+
 impl<T> fmt::Display for Connection<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "(Connection {} (streams {}))", self.id, self.streams.len())
@@ -596,6 +710,23 @@ impl<T> Connection<T> {
         Ok(proposed)
     }
 
+    // new_stream registers a new stream and returns the user side of it
+    fn new_stream(&mut self, stream_id: StreamID) -> Stream {
+        let (sender, receiver) = mpsc::channel(MAX_COMMAND_BACKLOG);
+        let remote_reset = Arc::new(AtomicBool::new(false));
+        self.streams.insert(
+            stream_id,
+            StreamHandle {
+                sender,
+                remote_reset: remote_reset.clone(),
+            },
+        );
+        self.streams_stat.insert(stream_id, State::Open);
+
+        let inbox = Inbox::new(receiver, self.budget.clone());
+        Stream::new(stream_id, self.id, self.stream_sender.clone(), inbox, remote_reset)
+    }
+
     pub fn streams_length(&self) -> usize {
         self.streams_stat.len()
     }
@@ -603,7 +734,7 @@ impl<T> Connection<T> {
     /// Close and drop all `Stream`s sender and stat.
     async fn drop_all_streams(&mut self) {
         log::trace!("{}: Drop all Streams sender count={}", self.id, self.streams.len());
-        for (id, _sender) in self.streams.drain().take(1) {
+        for (id, _handle) in self.streams.drain().take(1) {
             // drop it
             log::trace!("{}: drop stream sender {:?}", self.id, id);
         }
@@ -0,0 +1,120 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Connection-wide accounting of the data received but not yet read by the streams.
+
+use futures::channel::mpsc;
+use futures::task::{AtomicWaker, Context, Poll};
+use futures::Stream;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Bounds the bytes buffered for all streams of a connection.
+///
+/// The connection stops reading from the socket once the cap is reached, until the
+/// streams have read enough data, which applies backpressure to the remote.
+#[derive(Debug)]
+pub(crate) struct ReadBudget {
+    buffered: AtomicUsize,
+    cap: usize,
+    waker: AtomicWaker,
+}
+
+impl ReadBudget {
+    pub(crate) fn new(cap: usize) -> Self {
+        ReadBudget {
+            buffered: AtomicUsize::new(0),
+            cap,
+            waker: AtomicWaker::new(),
+        }
+    }
+
+    /// Returns the bytes buffered at the moment.
+    pub(crate) fn buffered(&self) -> usize {
+        self.buffered.load(Ordering::SeqCst)
+    }
+
+    /// Accounts the bytes about to be buffered.
+    pub(crate) fn take(&self, n: usize) {
+        self.buffered.fetch_add(n, Ordering::SeqCst);
+    }
+
+    /// Gives back the bytes read by a stream, resuming the connection if it was paused.
+    pub(crate) fn release(&self, n: usize) {
+        self.buffered.fetch_sub(n, Ordering::SeqCst);
+        self.waker.wake();
+    }
+
+    /// Polls until the buffered bytes are below the cap.
+    pub(crate) fn poll_available(&self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.buffered() < self.cap {
+            return Poll::Ready(());
+        }
+        self.waker.register(cx.waker());
+        // check again, the streams might have read in the meantime
+        if self.buffered() < self.cap {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// The receiving side of the data channel of a stream.
+///
+/// The bytes are given back to the budget as soon as they are taken out of the channel,
+/// or when the stream is gone.
+#[derive(Debug)]
+pub(crate) struct Inbox {
+    receiver: mpsc::Receiver<Vec<u8>>,
+    budget: Arc<ReadBudget>,
+}
+
+impl Inbox {
+    pub(crate) fn new(receiver: mpsc::Receiver<Vec<u8>>, budget: Arc<ReadBudget>) -> Self {
+        Inbox { receiver, budget }
+    }
+
+    pub(crate) fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Vec<u8>>> {
+        let r = futures::ready!(Pin::new(&mut self.receiver).poll_next(cx));
+        if let Some(data) = r.as_ref() {
+            self.budget.release(data.len());
+        }
+        Poll::Ready(r)
+    }
+
+    /// Takes the next data out of the channel, if there is any at the moment.
+    pub(crate) fn try_next(&mut self) -> Option<Vec<u8>> {
+        let data = self.receiver.try_next().ok().flatten()?;
+        self.budget.release(data.len());
+        Some(data)
+    }
+}
+
+impl Drop for Inbox {
+    fn drop(&mut self) {
+        // no more data can be sent after closing, release what is left in the channel
+        self.receiver.close();
+        while let Ok(Some(data)) = self.receiver.try_next() {
+            self.budget.release(data.len());
+        }
+    }
+}
@@ -24,19 +24,48 @@ use futures::{
 };
 
 use crate::{
-    connection::{stream::Stream, ControlCommand},
+    connection::{
+        stats::{Counters, MplexStats},
+        stream::Stream,
+        ControlCommand, StreamCommand,
+    },
     error::ConnectionError,
 };
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 pub struct Control {
     sender: mpsc::Sender<ControlCommand>,
+    stream_sender: mpsc::Sender<StreamCommand>,
+    stats: Arc<Counters>,
+    failed: Arc<AtomicBool>,
 }
 
 type Result<T> = std::result::Result<T, ConnectionError>;
 
 impl Control {
-    pub fn new(sender: mpsc::Sender<ControlCommand>) -> Self {
-        Control { sender }
+    pub(crate) fn new(
+        sender: mpsc::Sender<ControlCommand>,
+        stream_sender: mpsc::Sender<StreamCommand>,
+        stats: Arc<Counters>,
+        failed: Arc<AtomicBool>,
+    ) -> Self {
+        Control {
+            sender,
+            stream_sender,
+            stats,
+            failed,
+        }
+    }
+
+    /// Whether the connection has ended on a failure, rather than being closed or reaching EOF.
+    pub fn is_failed(&self) -> bool {
+        self.failed.load(Ordering::SeqCst)
+    }
+
+    /// Returns a snapshot of the frames sent and received by the connection so far.
+    pub fn stats(&self) -> MplexStats {
+        self.stats.snapshot()
     }
 
     /// Open a new stream to the remote.
@@ -53,6 +82,18 @@ impl Control {
         rx.await?
     }
 
+    /// Wait until all the frames queued so far by the streams of the connection have
+    /// been written to the socket.
+    ///
+    /// The barrier is queued behind the frames, so a write completed on any stream before
+    /// calling this is on the wire once it returns.
+    pub async fn flush_barrier(&mut self) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.stream_sender.send(StreamCommand::Flush(tx)).await?;
+        rx.await?;
+        Ok(())
+    }
+
     /// Close the connection.
     pub async fn close(&mut self) -> Result<()> {
         let (tx, rx) = oneshot::channel();
@@ -71,6 +112,9 @@ impl Clone for Control {
     fn clone(&self) -> Self {
         Control {
             sender: self.sender.clone(),
+            stream_sender: self.stream_sender.clone(),
+            stats: self.stats.clone(),
+            failed: self.failed.clone(),
         }
     }
 }
@@ -0,0 +1,106 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use crate::frame::{Frame, Tag};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Frame and byte counters of a single frame flag.
+///
+/// Bytes only account for the frame body, the header and length prefix are not included.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FlagStats {
+    pub frames_sent: u64,
+    pub bytes_sent: u64,
+    pub frames_received: u64,
+    pub bytes_received: u64,
+}
+
+/// A snapshot of the frames sent and received by a mplex connection, per frame flag.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MplexStats {
+    /// NewStream frames.
+    pub open: FlagStats,
+    /// Message frames.
+    pub message: FlagStats,
+    /// Close frames.
+    pub close: FlagStats,
+    /// Reset frames.
+    pub reset: FlagStats,
+}
+
+#[derive(Default)]
+struct FlagCounter {
+    frames_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    frames_received: AtomicU64,
+    bytes_received: AtomicU64,
+}
+
+impl FlagCounter {
+    fn snapshot(&self) -> FlagStats {
+        FlagStats {
+            frames_sent: self.frames_sent.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            frames_received: self.frames_received.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The live counters shared by the frame reader/writer and the `Control`s of a connection.
+#[derive(Default)]
+pub(crate) struct Counters {
+    open: FlagCounter,
+    message: FlagCounter,
+    close: FlagCounter,
+    reset: FlagCounter,
+}
+
+impl Counters {
+    fn by_tag(&self, tag: Tag) -> &FlagCounter {
+        match tag {
+            Tag::NewStream => &self.open,
+            Tag::Message => &self.message,
+            Tag::Close => &self.close,
+            Tag::Reset => &self.reset,
+        }
+    }
+
+    pub(crate) fn on_sent(&self, frame: &Frame) {
+        let counter = self.by_tag(frame.header().tag());
+        counter.frames_sent.fetch_add(1, Ordering::Relaxed);
+        counter.bytes_sent.fetch_add(frame.body_len() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_received(&self, frame: &Frame) {
+        let counter = self.by_tag(frame.header().tag());
+        counter.frames_received.fetch_add(1, Ordering::Relaxed);
+        counter.bytes_received.fetch_add(frame.body_len() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> MplexStats {
+        MplexStats {
+            open: self.open.snapshot(),
+            message: self.message.snapshot(),
+            close: self.close.snapshot(),
+            reset: self.reset.snapshot(),
+        }
+    }
+}
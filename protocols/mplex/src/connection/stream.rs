@@ -19,7 +19,7 @@
 // DEALINGS IN THE SOFTWARE.
 
 use crate::{
-    connection::{Id, StreamCommand},
+    connection::{budget::Inbox, Id, StreamCommand},
     frame::{Frame, StreamID},
 };
 use bytes::{Buf, BufMut};
@@ -28,6 +28,7 @@ use futures::lock::Mutex;
 use futures::task::{Context, Poll};
 use futures::{channel::mpsc, AsyncRead, AsyncWrite, FutureExt, Sink, SinkExt};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::{fmt, io};
 
@@ -59,7 +60,9 @@ pub struct Stream {
     conn_id: Id,
     read_buffer: bytes::BytesMut,
     sender: mpsc::Sender<StreamCommand>,
-    receiver: Arc<Mutex<mpsc::Receiver<Vec<u8>>>>,
+    receiver: Arc<Mutex<Inbox>>,
+    /// Set by the connection when the remote has reset the stream.
+    remote_reset: Arc<AtomicBool>,
 }
 
 impl fmt::Debug for Stream {
@@ -83,18 +86,26 @@ impl Clone for Stream {
             read_buffer: Default::default(),
             sender: self.sender.clone(),
             receiver: self.receiver.clone(),
+            remote_reset: self.remote_reset.clone(),
         }
     }
 }
 
 impl Stream {
-    pub(crate) fn new(id: StreamID, conn_id: Id, sender: mpsc::Sender<StreamCommand>, receiver: mpsc::Receiver<Vec<u8>>) -> Self {
+    pub(crate) fn new(
+        id: StreamID,
+        conn_id: Id,
+        sender: mpsc::Sender<StreamCommand>,
+        receiver: Inbox,
+        remote_reset: Arc<AtomicBool>,
+    ) -> Self {
         Stream {
             id,
             conn_id,
             read_buffer: Default::default(),
             sender,
             receiver: Arc::new(Mutex::new(receiver)),
+            remote_reset,
         }
     }
 
@@ -125,6 +136,21 @@ impl Stream {
         Ok(())
     }
 
+    /// Takes the bytes received but not read yet, without waiting for more.
+    ///
+    /// It can be called after closing the stream, to recover the trailing data the
+    /// remote sent before closing its side.
+    pub fn drain_buffer(&mut self) -> Vec<u8> {
+        let mut buf = self.read_buffer.split().to_vec();
+        // a reader elsewhere holds the inbox, it gets the data then
+        if let Some(mut receiver) = self.receiver.try_lock() {
+            while let Some(data) = receiver.try_next() {
+                buf.extend_from_slice(&data);
+            }
+        }
+        buf
+    }
+
     /// connection is closed
     fn write_zero_err(&self) -> io::Error {
         let msg = format!("{}/{}: connection is closed", self.conn_id, self.id);
@@ -136,6 +162,12 @@ impl Stream {
         let msg = format!("{}/{}: stream is closed / reset", self.conn_id, self.id);
         io::Error::new(io::ErrorKind::WriteZero, msg)
     }
+
+    /// stream is reset by remote
+    fn reset_err(&self) -> io::Error {
+        let msg = format!("{}/{}: stream is reset by remote", self.conn_id, self.id);
+        io::Error::new(io::ErrorKind::ConnectionReset, msg)
+    }
 }
 
 impl AsyncRead for Stream {
@@ -151,8 +183,7 @@ impl AsyncRead for Stream {
 
         let mut receiver = futures::ready!(this.receiver.lock().poll_unpin(cx));
 
-        let x = futures::Stream::poll_next(Pin::new(&mut *receiver), cx);
-        if let Some(data) = futures::ready!(x) {
+        if let Some(data) = futures::ready!(receiver.poll_next(cx)) {
             let dlen = data.len();
             let len = std::cmp::min(data.len(), buf.len());
             buf[..len].copy_from_slice(&data[..len]);
@@ -163,15 +194,25 @@ impl AsyncRead for Stream {
             }
             return Poll::Ready(Ok(len));
         }
+        if this.remote_reset.load(Ordering::Relaxed) {
+            return Poll::Ready(Err(this.reset_err()));
+        }
         Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()))
     }
 }
 
 impl AsyncWrite for Stream {
     fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        if self.remote_reset.load(Ordering::Relaxed) {
+            return Poll::Ready(Err(self.reset_err()));
+        }
         if self.sender.is_closed() {
             return Poll::Ready(Err(self.closed_err()));
         }
+        // the stream has been opened already, an empty message frame would carry nothing
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
 
         futures::ready!(self.sender.poll_ready(cx).map_err(|_| self.write_zero_err())?);
 
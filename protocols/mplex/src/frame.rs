@@ -45,6 +45,10 @@ impl Frame {
         self.body
     }
 
+    pub fn body_len(&self) -> usize {
+        self.body.len()
+    }
+
     // new stream frame
     pub fn new_stream_frame(stream_id: StreamID, body: &[u8]) -> Self {
         Frame {
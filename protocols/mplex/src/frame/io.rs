@@ -20,26 +20,55 @@
 
 use std::io;
 
+use crate::connection::stats::Counters;
 use crate::connection::Id;
 use crate::frame::header;
 use crate::frame::length_delimited::LengthDelimited;
 use crate::frame::Frame;
+use futures::future::{select, Either};
 use futures::{AsyncRead, AsyncWrite};
+use futures_timer::Delay;
+use std::sync::Arc;
+use std::time::Duration;
 
 const MAX_MESSAGE_SIZE: u32 = 1 << 20;
+/// The length of the CRC32 appended to the frame bodies in the debug checksum mode.
+const CHECKSUM_LEN: usize = 4;
 
 pub struct IO<T> {
     id: Id,
     io: LengthDelimited<T>,
+    stats: Arc<Counters>,
+    checksums: bool,
+    read_timeout: Option<Duration>,
 }
 
 impl<T> IO<T>
 where
     T: Unpin + Send,
 {
-    pub(crate) fn new(id: Id, io: T) -> Self {
+    pub(crate) fn new(id: Id, io: T, stats: Arc<Counters>) -> Self {
         let io = LengthDelimited::new(io, MAX_MESSAGE_SIZE);
-        IO { id, io }
+        IO {
+            id,
+            io,
+            stats,
+            checksums: false,
+            read_timeout: None,
+        }
+    }
+
+    /// Appends a CRC32 to the body of the frames sent, and verifies it on the frames received.
+    pub(crate) fn with_checksums(mut self, enabled: bool) -> Self {
+        self.checksums = enabled;
+        self
+    }
+
+    /// Fails `recv_frame` with `FrameDecodeError::Timeout` if a frame isn't read in full within
+    /// `timeout` of its first byte.
+    pub(crate) fn with_read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.read_timeout = timeout;
+        self
     }
 }
 
@@ -48,8 +77,29 @@ where
     T: AsyncRead + Send + Unpin,
 {
     pub(crate) async fn recv_frame(&mut self) -> Result<Frame, FrameDecodeError> {
+        // wait for a frame without a deadline, the connection might just be idle
+        let mut first = [0; 1];
+        self.io.read_byte(&mut first).await?;
+
+        let timeout = match self.read_timeout {
+            Some(timeout) => timeout,
+            None => return self.read_frame(first[0]).await,
+        };
+        let id = self.id;
+        let output = select(Box::pin(self.read_frame(first[0])), Delay::new(timeout)).await;
+        match output {
+            Either::Left((frame, _)) => frame,
+            Either::Right(_) => {
+                log::debug!("{}: frame not read within {:?}", id, timeout);
+                Err(FrameDecodeError::Timeout)
+            }
+        }
+    }
+
+    /// Reads the rest of a frame, whose first byte is `first`.
+    async fn read_frame(&mut self, first: u8) -> Result<Frame, FrameDecodeError> {
         // get header
-        let header_byte = self.io.read_uvarint().await?;
+        let header_byte = self.io.read_uvarint_from(first).await?;
         let header = header::decode(header_byte)?;
 
         log::trace!("{}: read stream header: {}", self.id, header);
@@ -59,14 +109,18 @@ where
         if len > MAX_MESSAGE_SIZE {
             return Err(FrameDecodeError::FrameTooLarge(len as usize));
         }
-        if len == 0 {
-            return Ok(Frame { header, body: Vec::new() });
+        let mut body = vec![0; len as usize];
+        if len > 0 {
+            // get body
+            self.io.read_body(&mut body).await?;
+        }
+        if self.checksums {
+            body = strip_checksum(body)?;
         }
 
-        // get body
-        let mut body = vec![0; len as usize];
-        self.io.read_body(&mut body).await?;
-        Ok(Frame { header, body })
+        let frame = Frame { header, body };
+        self.stats.on_received(&frame);
+        Ok(frame)
     }
 }
 
@@ -79,12 +133,25 @@ where
 
         let hdr = header::encode(&frame.header);
 
+        let checksum = if self.checksums {
+            Some(crc32fast::hash(&frame.body).to_be_bytes())
+        } else {
+            None
+        };
+        let len = frame.body.len() + checksum.map_or(0, |c| c.len());
+
         self.io.write_header(hdr).await?;
-        self.io.write_length(frame.body.len() as u32).await?;
+        self.io.write_length(len as u32).await?;
         if !frame.body.is_empty() {
             self.io.write_body(&frame.body).await?;
         }
-        self.io.flush().await
+        if let Some(checksum) = checksum {
+            self.io.write_body(&checksum).await?;
+        }
+        self.io.flush().await?;
+
+        self.stats.on_sent(frame);
+        Ok(())
     }
 
     pub(crate) async fn close(&mut self) -> io::Result<()> {
@@ -92,6 +159,18 @@ where
     }
 }
 
+/// Splits the CRC32 off the end of the frame body, and verifies it.
+fn strip_checksum(mut body: Vec<u8>) -> Result<Vec<u8>, FrameDecodeError> {
+    if body.len() < CHECKSUM_LEN {
+        return Err(FrameDecodeError::ChecksumMismatch);
+    }
+    let checksum = body.split_off(body.len() - CHECKSUM_LEN);
+    if checksum[..] != crc32fast::hash(&body).to_be_bytes()[..] {
+        return Err(FrameDecodeError::ChecksumMismatch);
+    }
+    Ok(body)
+}
+
 /// Possible errors while decoding a message frame.
 #[non_exhaustive]
 #[derive(Debug)]
@@ -102,6 +181,10 @@ pub enum FrameDecodeError {
     Header(header::HeaderDecodeError),
     /// A data frame body length is larger than the configured maximum.
     FrameTooLarge(usize),
+    /// The checksum of a frame body doesn't match it, in the debug checksum mode.
+    ChecksumMismatch,
+    /// A frame wasn't read in full within the read timeout.
+    Timeout,
 }
 
 impl std::fmt::Display for FrameDecodeError {
@@ -110,6 +193,8 @@ impl std::fmt::Display for FrameDecodeError {
             FrameDecodeError::Io(e) => write!(f, "i/o error: {}", e),
             FrameDecodeError::Header(e) => write!(f, "decode error: {}", e),
             FrameDecodeError::FrameTooLarge(n) => write!(f, "frame body is too large ({})", n),
+            FrameDecodeError::ChecksumMismatch => write!(f, "frame body checksum mismatch"),
+            FrameDecodeError::Timeout => write!(f, "frame read timed out"),
         }
     }
 }
@@ -119,7 +204,7 @@ impl std::error::Error for FrameDecodeError {
         match self {
             FrameDecodeError::Io(e) => Some(e),
             FrameDecodeError::Header(e) => Some(e),
-            FrameDecodeError::FrameTooLarge(_) => None,
+            FrameDecodeError::FrameTooLarge(_) | FrameDecodeError::ChecksumMismatch | FrameDecodeError::Timeout => None,
         }
     }
 }
@@ -135,3 +220,59 @@ impl From<header::HeaderDecodeError> for FrameDecodeError {
         FrameDecodeError::Header(e)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::StreamID;
+    use libp2prs_runtime::task;
+
+    fn send(frame: &Frame, checksums: bool) -> Vec<u8> {
+        let mut bytes = vec![];
+        {
+            let mut io = IO::new(Id::random(), &mut bytes, Arc::new(Counters::default())).with_checksums(checksums);
+            task::block_on(io.send_frame(frame)).unwrap();
+        }
+        bytes
+    }
+
+    fn recv(bytes: &[u8], checksums: bool) -> Result<Frame, FrameDecodeError> {
+        let mut io = IO::new(Id::random(), bytes, Arc::new(Counters::default())).with_checksums(checksums);
+        task::block_on(io.recv_frame())
+    }
+
+    #[test]
+    fn checksummed_frame_roundtrip() {
+        let frame = Frame::message_frame(StreamID::new(1, true), b"hello");
+        let bytes = send(&frame, true);
+        assert_eq!(bytes.len(), send(&frame, false).len() + CHECKSUM_LEN);
+        // the receiver sees the stream as opened by the remote
+        assert_eq!(recv(&bytes, true).unwrap(), Frame::message_frame(StreamID::new(1, false), b"hello"));
+
+        let frame = Frame::close_frame(StreamID::new(1, true));
+        assert_eq!(
+            recv(&send(&frame, true), true).unwrap(),
+            Frame::close_frame(StreamID::new(1, false))
+        );
+    }
+
+    #[test]
+    fn oversized_varint_rejected() {
+        // the header never ends, nor does the length after a valid header
+        for bytes in &[vec![0xff; 10], vec![0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]] {
+            match recv(bytes, false) {
+                Err(FrameDecodeError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn flipped_byte_detected() {
+        let frame = Frame::message_frame(StreamID::new(1, true), b"hello");
+        let mut bytes = send(&frame, true);
+        // the body follows the header and the length, one byte each
+        bytes[2] ^= 0x01;
+        assert!(matches!(recv(&bytes, true), Err(FrameDecodeError::ChecksumMismatch)));
+    }
+}
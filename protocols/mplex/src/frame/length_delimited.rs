@@ -19,6 +19,8 @@
 // DEALINGS IN THE SOFTWARE.
 
 use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2prs_core::ReadEx;
+use std::convert::TryFrom;
 use std::io::{self, ErrorKind};
 
 const U32_LEN: usize = 5;
@@ -53,19 +55,20 @@ where
     }
 
     pub async fn read_uvarint(&mut self) -> io::Result<u32> {
-        let mut buf: [u8; U32_LEN] = [0; U32_LEN];
-        for (pos, _) in (0..U32_LEN).enumerate() {
-            self.read_byte(&mut buf[pos..pos + 1]).await?;
-            if buf[pos] < 0x80 {
-                // MSB is not set, indicating the end of the length prefix.
-                let (len, _) = unsigned_varint::decode::u32(&buf).map_err(|e| {
-                    log::debug!("invalid length prefix: {}", e);
-                    io::Error::new(io::ErrorKind::InvalidData, "invalid length prefix")
-                })?;
-                return Ok(len);
-            }
-        }
-        Ok(0)
+        let mut first = [0; 1];
+        self.read_byte(&mut first).await?;
+        self.read_uvarint_from(first[0]).await
+    }
+
+    /// Reads the rest of an unsigned varint, whose first byte is `first`.
+    ///
+    /// A varint longer than a `u32` is rejected with `io::ErrorKind::InvalidData`.
+    pub async fn read_uvarint_from(&mut self, first: u8) -> io::Result<u32> {
+        let n = self.inner.read_uvarint_from(first, U32_LEN).await.map_err(|e| {
+            log::debug!("invalid length prefix: {}", e);
+            e
+        })?;
+        u32::try_from(n).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid length prefix"))
     }
 
     pub async fn read_body(&mut self, buf: &mut [u8]) -> io::Result<()> {
@@ -26,13 +26,14 @@ mod pause;
 use async_trait::async_trait;
 use futures::{AsyncRead, AsyncWrite, FutureExt};
 use log::{info, trace};
-use std::fmt;
+use std::{fmt, io};
 
 use libp2prs_core::muxing::{IReadWrite, IStreamMuxer, ReadWriteEx, StreamInfo, StreamMuxer, StreamMuxerEx};
 use libp2prs_core::transport::{ConnectionInfo, TransportError};
 use libp2prs_core::upgrade::{UpgradeInfo, Upgrader};
 
 use crate::connection::Connection;
+pub use connection::stats::{FlagStats, MplexStats};
 use connection::{control::Control, stream::Stream, Id};
 use error::ConnectionError;
 use futures::future::BoxFuture;
@@ -41,13 +42,49 @@ use libp2prs_core::secure_io::SecureInfo;
 use libp2prs_core::{Multiaddr, PeerId, PublicKey};
 use parking_lot::Mutex;
 use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Clone)]
-pub struct Config {}
+pub struct Config {
+    max_buffered_bytes: usize,
+    debug_checksums: bool,
+    read_timeout: Option<Duration>,
+}
 
 impl Config {
     pub fn new() -> Self {
-        Config {}
+        Config {
+            max_buffered_bytes: connection::MAX_BUFFERED_BYTES,
+            debug_checksums: false,
+            read_timeout: None,
+        }
+    }
+
+    /// Sets the maximum of the bytes buffered for all streams of a connection.
+    ///
+    /// The connection stops reading from the socket once the cap is reached, until
+    /// the streams have read enough data.
+    pub fn max_buffered_bytes(mut self, n: usize) -> Self {
+        self.max_buffered_bytes = n;
+        self
+    }
+
+    /// Appends a CRC32 to the body of each frame, and verifies it on receive, failing the
+    /// connection with `FrameDecodeError::ChecksumMismatch` on corruption.
+    ///
+    /// This is a debug mode, which only interoperates with the peers in the same mode.
+    pub fn debug_checksums(mut self, enabled: bool) -> Self {
+        self.debug_checksums = enabled;
+        self
+    }
+
+    /// Sets the time a frame must be read in full within, once its first byte is received.
+    ///
+    /// A peer which stalls in the middle of a frame fails the connection with
+    /// `FrameDecodeError::Timeout`, resetting all its streams. There is no timeout by default.
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
     }
 }
 
@@ -83,6 +120,8 @@ pub struct Mplex<C> {
     pub remote_pub_key: PublicKey,
     /// For convenience, put a PeerId here, which is actually calculated from remote_key
     pub remote_peer_id: PeerId,
+    /// The id of the underlying secure session, if any
+    pub session_id: Option<[u8; 32]>,
 }
 
 impl<C> Clone for Mplex<C> {
@@ -97,6 +136,7 @@ impl<C> Clone for Mplex<C> {
             local_peer_id: self.local_peer_id,
             remote_pub_key: self.remote_pub_key.clone(),
             remote_peer_id: self.remote_peer_id,
+            session_id: self.session_id,
         }
     }
 }
@@ -113,6 +153,10 @@ impl<C> fmt::Debug for Mplex<C> {
 
 impl<C: ConnectionInfo + SecureInfo + AsyncRead + AsyncWrite + Send + Unpin + 'static> Mplex<C> {
     pub fn new(io: C) -> Self {
+        Self::with_config(io, Config::new())
+    }
+
+    pub fn with_config(io: C, config: Config) -> Self {
         // `io` will be moved into Connection soon, make a copy of the connection & secure info
         let la = io.local_multiaddr();
         let ra = io.remote_multiaddr();
@@ -120,8 +164,9 @@ impl<C: ConnectionInfo + SecureInfo + AsyncRead + AsyncWrite + Send + Unpin + 's
         let local_peer_id = io.local_peer();
         let remote_pub_key = io.remote_pub_key();
         let remote_peer_id = io.remote_peer();
+        let session_id = io.session_id();
 
-        let conn = Connection::new(io);
+        let conn = Connection::with_options(io, config.max_buffered_bytes, config.debug_checksums, config.read_timeout);
         let id = conn.id();
         let ctrl = conn.control();
         Mplex {
@@ -134,10 +179,18 @@ impl<C: ConnectionInfo + SecureInfo + AsyncRead + AsyncWrite + Send + Unpin + 's
             local_peer_id,
             remote_pub_key,
             remote_peer_id,
+            session_id,
         }
     }
 }
 
+impl<C> Mplex<C> {
+    /// Returns a snapshot of the frames sent and received by this connection so far.
+    pub fn stats(&self) -> MplexStats {
+        self.ctrl.stats()
+    }
+}
+
 impl<C: Send> ConnectionInfo for Mplex<C> {
     fn local_multiaddr(&self) -> Multiaddr {
         self.la.clone()
@@ -163,6 +216,10 @@ impl<C> SecureInfo for Mplex<C> {
     fn remote_pub_key(&self) -> PublicKey {
         self.remote_pub_key.clone()
     }
+
+    fn session_id(&self) -> Option<[u8; 32]> {
+        self.session_id
+    }
 }
 
 /// StreamInfo for Mplex::Stream
@@ -177,6 +234,14 @@ impl ReadWriteEx for Stream {
     fn box_clone(&self) -> IReadWrite {
         Box::new(self.clone())
     }
+
+    async fn reset(&mut self) -> io::Result<()> {
+        Stream::reset(self).await
+    }
+
+    fn drain_buffer(&mut self) -> Vec<u8> {
+        Stream::drain_buffer(self)
+    }
 }
 
 impl<C: AsyncRead + AsyncWrite + Send + Unpin + 'static> StreamMuxerEx for Mplex<C> {}
@@ -191,8 +256,11 @@ impl<C: AsyncRead + AsyncWrite + Send + Unpin + 'static> StreamMuxer for Mplex<C
 
     async fn accept_stream(&mut self) -> Result<IReadWrite, TransportError> {
         trace!("waiting for a new inbound substream for yamux...");
-        let s = self.ctrl.accept_stream().await?;
-        Ok(Box::new(s))
+        match self.ctrl.accept_stream().await {
+            Ok(s) => Ok(Box::new(s)),
+            Err(ConnectionError::Closed) if !self.ctrl.is_failed() => Err(TransportError::ConnectionClosed),
+            Err(e) => Err(e.into()),
+        }
     }
 
     async fn close(&mut self) -> Result<(), TransportError> {
@@ -200,6 +268,11 @@ impl<C: AsyncRead + AsyncWrite + Send + Unpin + 'static> StreamMuxer for Mplex<C
         Ok(())
     }
 
+    async fn flush_barrier(&mut self) -> Result<(), TransportError> {
+        self.ctrl.flush_barrier().await?;
+        Ok(())
+    }
+
     fn task(&mut self) -> Option<BoxFuture<'static, ()>> {
         if let Some(mut conn) = self.conn.lock().take() {
             return Some(
@@ -235,12 +308,12 @@ where
 
     async fn upgrade_inbound(self, socket: T, _info: <Self as UpgradeInfo>::Info) -> Result<Self::Output, TransportError> {
         trace!("upgrading mplex inbound");
-        Ok(Mplex::new(socket))
+        Ok(Mplex::with_config(socket, self))
     }
 
     async fn upgrade_outbound(self, socket: T, _info: <Self as UpgradeInfo>::Info) -> Result<Self::Output, TransportError> {
         trace!("upgrading mplex outbound");
-        Ok(Mplex::new(socket))
+        Ok(Mplex::with_config(socket, self))
     }
 }
 
@@ -253,8 +326,404 @@ impl From<ConnectionError> for TransportError {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use futures::{AsyncReadExt, AsyncWriteExt};
+    use libp2prs_core::transport::memory::MemoryTransport;
+    use libp2prs_core::transport::ListenerEvent;
+    use libp2prs_core::upgrade::DummyUpgrader;
+    use libp2prs_core::Transport;
+    use libp2prs_runtime::task;
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn frame_stats() {
+        task::block_on(async {
+            let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+            let mut listener = MemoryTransport.listen_on(addr.clone()).unwrap();
+
+            let server = task::spawn(async move {
+                let socket = match listener.accept().await.unwrap() {
+                    ListenerEvent::Accepted(s) => s,
+                    _ => panic!("unreachable"),
+                };
+                let socket = DummyUpgrader::new().upgrade_inbound(socket, b"").await.unwrap();
+                let mut conn = Config::new().upgrade_inbound(socket, b"").await.unwrap();
+                task::spawn(conn.task().unwrap());
+
+                let mut stream = conn.ctrl.accept_stream().await.unwrap();
+                let mut buf = [0u8; 5];
+                stream.read_exact(&mut buf).await.unwrap();
+                assert_eq!(&buf, b"hello");
+                // the remote has closed the stream
+                assert!(stream.read(&mut buf).await.is_err());
+
+                stream.write_all(b"bye").await.unwrap();
+                stream.close().await.unwrap();
+                conn
+            });
+
+            let socket = MemoryTransport.dial(addr).await.unwrap();
+            let socket = DummyUpgrader::new().upgrade_outbound(socket, b"").await.unwrap();
+            let mut conn = Config::new().upgrade_outbound(socket, b"").await.unwrap();
+            task::spawn(conn.task().unwrap());
+
+            let mut stream = conn.ctrl.open_stream().await.unwrap();
+            stream.write_all(b"hello").await.unwrap();
+            stream.close().await.unwrap();
+
+            let mut buf = [0u8; 3];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"bye");
+            assert!(stream.read(&mut buf).await.is_err());
+
+            let stats = conn.stats();
+            assert_eq!(stats.open.frames_sent, 1);
+            assert_eq!(stats.open.frames_received, 0);
+            assert_eq!(stats.message.frames_sent, 1);
+            assert_eq!(stats.message.bytes_sent, 5);
+            assert_eq!(stats.message.frames_received, 1);
+            assert_eq!(stats.message.bytes_received, 3);
+            assert_eq!(stats.close.frames_sent, 1);
+            assert_eq!(stats.close.frames_received, 1);
+            assert_eq!(stats.reset, FlagStats::default());
+
+            // the client has read all the frames sent by the server
+            let stats = server.await.unwrap().stats();
+            assert_eq!(stats.open.frames_received, 1);
+            assert_eq!(stats.open.frames_sent, 0);
+            assert_eq!(stats.message.frames_received, 1);
+            assert_eq!(stats.message.bytes_received, 5);
+            assert_eq!(stats.message.frames_sent, 1);
+            assert_eq!(stats.message.bytes_sent, 3);
+            assert_eq!(stats.close.frames_received, 1);
+            assert_eq!(stats.close.frames_sent, 1);
+            assert_eq!(stats.reset, FlagStats::default());
+        });
+    }
+
+    #[test]
+    fn empty_write_sends_no_frame() {
+        task::block_on(async {
+            let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+            let mut listener = MemoryTransport.listen_on(addr.clone()).unwrap();
+
+            let server = task::spawn(async move {
+                let socket = match listener.accept().await.unwrap() {
+                    ListenerEvent::Accepted(s) => s,
+                    _ => panic!("unreachable"),
+                };
+                let socket = DummyUpgrader::new().upgrade_inbound(socket, b"").await.unwrap();
+                let mut conn = Config::new().upgrade_inbound(socket, b"").await.unwrap();
+                task::spawn(conn.task().unwrap());
+
+                let mut stream = conn.ctrl.accept_stream().await.unwrap();
+                let mut buf = [0u8; 5];
+                stream.read_exact(&mut buf).await.unwrap();
+                assert_eq!(&buf, b"hello");
+                assert!(stream.read(&mut buf).await.is_err());
+                conn
+            });
+
+            let socket = MemoryTransport.dial(addr).await.unwrap();
+            let socket = DummyUpgrader::new().upgrade_outbound(socket, b"").await.unwrap();
+            let mut conn = Config::new().upgrade_outbound(socket, b"").await.unwrap();
+            task::spawn(conn.task().unwrap());
+
+            // the stream is opened at once, an empty write has nothing to send
+            let mut stream = conn.ctrl.open_stream().await.unwrap();
+            assert_eq!(stream.write(&[]).await.unwrap(), 0);
+            stream.write_all(b"hello").await.unwrap();
+            assert_eq!(stream.write(&[]).await.unwrap(), 0);
+            stream.close().await.unwrap();
+
+            let stats = server.await.unwrap().stats();
+            assert_eq!(stats.open.frames_received, 1);
+            assert_eq!(stats.message.frames_received, 1);
+            assert_eq!(stats.message.bytes_received, 5);
+            assert_eq!(conn.stats().message.frames_sent, 1);
+        });
+    }
+
+    #[test]
+    fn close_ends_open_streams_before_socket() {
+        task::block_on(async {
+            let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+            let mut listener = MemoryTransport.listen_on(addr.clone()).unwrap();
+
+            let server = task::spawn(async move {
+                let socket = match listener.accept().await.unwrap() {
+                    ListenerEvent::Accepted(s) => s,
+                    _ => panic!("unreachable"),
+                };
+                let socket = DummyUpgrader::new().upgrade_inbound(socket, b"").await.unwrap();
+                let mut conn = Config::new().upgrade_inbound(socket, b"").await.unwrap();
+                let task = task::spawn(conn.task().unwrap());
+
+                let mut open = conn.ctrl.accept_stream().await.unwrap();
+                let mut half_closed = conn.ctrl.accept_stream().await.unwrap();
+                open.write_all(b"ok").await.unwrap();
+
+                // the stream still open ends with EOF rather than a reset
+                let mut buf = [0u8; 5];
+                open.read_exact(&mut buf).await.unwrap();
+                let err = open.read(&mut buf).await.unwrap_err();
+                assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+
+                // the stream closed by the remote already is reset, we are still writing
+                half_closed.read_exact(&mut buf[..3]).await.unwrap();
+                assert!(half_closed.read(&mut buf).await.is_err());
+
+                // the frames arrive before the socket is closed
+                task.await;
+                conn.stats()
+            });
+
+            let socket = MemoryTransport.dial(addr).await.unwrap();
+            let socket = DummyUpgrader::new().upgrade_outbound(socket, b"").await.unwrap();
+            let mut conn = Config::new().upgrade_outbound(socket, b"").await.unwrap();
+            task::spawn(conn.task().unwrap());
+
+            let mut open = conn.ctrl.open_stream().await.unwrap();
+            open.write_all(b"hello").await.unwrap();
+            let mut half_closed = conn.ctrl.open_stream().await.unwrap();
+            half_closed.write_all(b"bye").await.unwrap();
+            half_closed.close().await.unwrap();
+
+            // both streams have been accepted
+            let mut buf = [0u8; 2];
+            open.read_exact(&mut buf).await.unwrap();
+            conn.close().await.unwrap();
+            let stats = conn.stats();
+            assert_eq!(stats.close.frames_sent, 2);
+            assert_eq!(stats.reset.frames_sent, 1);
+
+            let stats = server.await.unwrap();
+            assert_eq!(stats.close.frames_received, 2);
+            assert_eq!(stats.reset.frames_received, 1);
+        });
+    }
+
+    #[test]
+    fn drain_buffer_recovers_trailing_data() {
+        task::block_on(async {
+            let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+            let mut listener = MemoryTransport.listen_on(addr.clone()).unwrap();
+
+            let client = task::spawn(async move {
+                let socket = MemoryTransport.dial(addr).await.unwrap();
+                let socket = DummyUpgrader::new().upgrade_outbound(socket, b"").await.unwrap();
+                let mut conn = Config::new().upgrade_outbound(socket, b"").await.unwrap();
+                task::spawn(conn.task().unwrap());
+
+                let mut stream = conn.ctrl.open_stream().await.unwrap();
+                stream.write_all(b"hello").await.unwrap();
+                // the final burst, right before closing
+                stream.write_all(b"world").await.unwrap();
+                stream.close().await.unwrap();
+                conn
+            });
+
+            let socket = match listener.accept().await.unwrap() {
+                ListenerEvent::Accepted(s) => s,
+                _ => panic!("unreachable"),
+            };
+            let socket = DummyUpgrader::new().upgrade_inbound(socket, b"").await.unwrap();
+            let mut conn = Config::new().upgrade_inbound(socket, b"").await.unwrap();
+            task::spawn(conn.task().unwrap());
+
+            let mut stream = conn.ctrl.accept_stream().await.unwrap();
+            let mut buf = [0u8; 3];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"hel");
+            // the frames are handed to the streams in order, the close frame comes last
+            while conn.stats().close.frames_received < 1 {
+                task::sleep(std::time::Duration::from_millis(10)).await;
+            }
+
+            stream.close().await.unwrap();
+            assert_eq!(stream.drain_buffer(), b"loworld");
+            assert!(stream.drain_buffer().is_empty());
+
+            let _client = client.await.unwrap();
+        });
+    }
+
+    #[test]
+    fn reads_pause_when_buffered_bytes_reach_cap() {
+        const CAP: usize = 1024;
+        const CHUNK: usize = 256;
+        const STREAMS: usize = 8;
+        const PER_STREAM: usize = 4 * CHUNK;
+
+        task::block_on(async {
+            let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+            let mut listener = MemoryTransport.listen_on(addr.clone()).unwrap();
+
+            let client = task::spawn(async move {
+                let socket = MemoryTransport.dial(addr).await.unwrap();
+                let socket = DummyUpgrader::new().upgrade_outbound(socket, b"").await.unwrap();
+                let mut conn = Config::new().upgrade_outbound(socket, b"").await.unwrap();
+                task::spawn(conn.task().unwrap());
+
+                let mut streams = Vec::new();
+                for _ in 0..STREAMS {
+                    streams.push(conn.ctrl.open_stream().await.unwrap());
+                }
+                for mut stream in streams {
+                    task::spawn(async move {
+                        for _ in 0..PER_STREAM / CHUNK {
+                            stream.write_all(&[1u8; CHUNK]).await.unwrap();
+                        }
+                        stream.close().await.unwrap();
+                    });
+                }
+                conn
+            });
+
+            let socket = match listener.accept().await.unwrap() {
+                ListenerEvent::Accepted(s) => s,
+                _ => panic!("unreachable"),
+            };
+            let socket = DummyUpgrader::new().upgrade_inbound(socket, b"").await.unwrap();
+            let mut conn = Config::new().max_buffered_bytes(CAP).upgrade_inbound(socket, b"").await.unwrap();
+            task::spawn(conn.task().unwrap());
+
+            let mut streams = Vec::new();
+            for _ in 0..STREAMS {
+                streams.push(conn.ctrl.accept_stream().await.unwrap());
+            }
+
+            // nobody reads, the connection stops once the cap is reached
+            task::sleep(std::time::Duration::from_millis(200)).await;
+            let received = conn.stats().message.bytes_received as usize;
+            assert!(received >= CAP);
+            assert!(received < CAP + CHUNK);
+
+            // the streams must be read together, data for one stream may wait behind the others
+            let readers = streams.into_iter().map(|mut stream| async move {
+                let mut buf = vec![0u8; PER_STREAM];
+                stream.read_exact(&mut buf).await.unwrap();
+                buf
+            });
+            for buf in futures::future::join_all(readers).await {
+                assert_eq!(buf, vec![1u8; PER_STREAM]);
+            }
+            assert_eq!(conn.stats().message.bytes_received as usize, STREAMS * PER_STREAM);
+
+            let _client = client.await.unwrap();
+        });
+    }
+
+    #[test]
+    fn stalled_frame_fails_connection() {
+        task::block_on(async {
+            let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+            let mut listener = MemoryTransport.listen_on(addr.clone()).unwrap();
+
+            let server = task::spawn(async move {
+                let socket = match listener.accept().await.unwrap() {
+                    ListenerEvent::Accepted(s) => s,
+                    _ => panic!("unreachable"),
+                };
+                let socket = DummyUpgrader::new().upgrade_inbound(socket, b"").await.unwrap();
+                let mut conn = Config::new()
+                    .read_timeout(Duration::from_millis(100))
+                    .upgrade_inbound(socket, b"")
+                    .await
+                    .unwrap();
+                task::spawn(conn.task().unwrap());
+                let accepted = task::timeout(Duration::from_secs(5), conn.ctrl.accept_stream()).await;
+                (accepted, conn.ctrl.is_failed())
+            });
+
+            let mut socket = MemoryTransport.dial(addr).await.unwrap();
+            // the first byte of a two-byte header, then nothing
+            socket.write_all(&[0x80]).await.unwrap();
+
+            let (accepted, failed) = server.await.unwrap();
+            assert!(matches!(accepted, Ok(Err(ConnectionError::Closed))));
+            assert!(failed);
+            drop(socket);
+        });
+    }
+
+    #[test]
+    fn remote_close_is_not_a_failure() {
+        task::block_on(async {
+            let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+            let mut listener = MemoryTransport.listen_on(addr.clone()).unwrap();
+
+            let server = task::spawn(async move {
+                let socket = match listener.accept().await.unwrap() {
+                    ListenerEvent::Accepted(s) => s,
+                    _ => panic!("unreachable"),
+                };
+                let socket = DummyUpgrader::new().upgrade_inbound(socket, b"").await.unwrap();
+                let mut conn = Config::new().upgrade_inbound(socket, b"").await.unwrap();
+                task::spawn(conn.task().unwrap());
+                let accepted = task::timeout(Duration::from_secs(5), conn.accept_stream()).await.unwrap();
+                (matches!(accepted, Err(TransportError::ConnectionClosed)), conn.ctrl.is_failed())
+            });
+
+            let socket = MemoryTransport.dial(addr).await.unwrap();
+            let socket = DummyUpgrader::new().upgrade_outbound(socket, b"").await.unwrap();
+            let mut conn = Config::new().upgrade_outbound(socket, b"").await.unwrap();
+            task::spawn(conn.task().unwrap());
+            conn.close().await.unwrap();
+
+            assert_eq!(server.await.unwrap(), (true, false));
+        });
+    }
+
+    #[test]
+    fn flush_barrier_waits_for_queued_frames() {
+        task::block_on(async {
+            let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+            let mut listener = MemoryTransport.listen_on(addr.clone()).unwrap();
+
+            let server = task::spawn(async move {
+                let socket = match listener.accept().await.unwrap() {
+                    ListenerEvent::Accepted(s) => s,
+                    _ => panic!("unreachable"),
+                };
+                let socket = DummyUpgrader::new().upgrade_inbound(socket, b"").await.unwrap();
+                let mut conn = Config::new().upgrade_inbound(socket, b"").await.unwrap();
+                task::spawn(conn.task().unwrap());
+
+                let mut received = Vec::new();
+                for _ in 0..3 {
+                    let mut stream = conn.ctrl.accept_stream().await.unwrap();
+                    let mut buf = [0u8; 5];
+                    stream.read_exact(&mut buf).await.unwrap();
+                    received.push(buf.to_vec());
+                }
+                received
+            });
+
+            let socket = MemoryTransport.dial(addr).await.unwrap();
+            let socket = DummyUpgrader::new().upgrade_outbound(socket, b"").await.unwrap();
+            let mut conn = Config::new().upgrade_outbound(socket, b"").await.unwrap();
+            task::spawn(conn.task().unwrap());
+
+            // the writes only queue the frames to the connection
+            let mut streams = Vec::new();
+            for data in &[b"first", b"secnd", b"third"] {
+                let mut stream = conn.ctrl.open_stream().await.unwrap();
+                stream.write_all(*data).await.unwrap();
+                streams.push(stream);
+            }
+
+            conn.flush_barrier().await.unwrap();
+            let stats = conn.stats();
+            assert_eq!(stats.message.frames_sent, 3);
+            assert_eq!(stats.message.bytes_sent, 15);
+
+            let received = server.await.unwrap();
+            assert_eq!(received, vec![b"first".to_vec(), b"secnd".to_vec(), b"third".to_vec()]);
+        });
+    }
 }
@@ -18,6 +18,7 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
+use libp2prs_core::secure_io::{HandshakeError, HandshakeErrorKind};
 use libp2prs_core::{identity, transport::TransportError};
 use snow::error::Error as SnowError;
 use std::{error::Error, fmt, io};
@@ -32,6 +33,8 @@ pub enum NoiseError {
     Noise(SnowError),
     /// A public key is invalid.
     InvalidKey,
+    /// The static DH public key of the remote is not signed by its identity key.
+    BadSignature,
     /// Authentication in a [`NoiseAuthenticated`](crate::NoiseAuthenticated)
     /// upgrade failed.
     AuthenticationFailed,
@@ -49,6 +52,7 @@ impl fmt::Display for NoiseError {
             NoiseError::Io(e) => write!(f, "{}", e),
             NoiseError::Noise(e) => write!(f, "{}", e),
             NoiseError::InvalidKey => f.write_str("invalid public key"),
+            NoiseError::BadSignature => f.write_str("invalid signature of the static DH public key"),
             NoiseError::InvalidPayload(e) => write!(f, "{}", e),
             NoiseError::AuthenticationFailed => f.write_str("Authentication failed"),
             NoiseError::SigningError(e) => write!(f, "{}", e),
@@ -63,6 +67,7 @@ impl Error for NoiseError {
             NoiseError::Io(e) => Some(e),
             NoiseError::Noise(_) => None, // TODO: `SnowError` should implement `Error`.
             NoiseError::InvalidKey => None,
+            NoiseError::BadSignature => None,
             NoiseError::AuthenticationFailed => None,
             NoiseError::InvalidPayload(e) => Some(e),
             NoiseError::SigningError(e) => Some(e),
@@ -96,8 +101,11 @@ impl From<identity::error::SigningError> for NoiseError {
 }
 
 impl From<NoiseError> for TransportError {
-    fn from(_: NoiseError) -> Self {
-        // TODO: make a security error catalog for noise
-        TransportError::Internal
+    fn from(e: NoiseError) -> Self {
+        let kind = match e {
+            NoiseError::BadSignature | NoiseError::AuthenticationFailed => HandshakeErrorKind::SignatureVerificationFailed,
+            _ => HandshakeErrorKind::Other,
+        };
+        TransportError::SecurityError(Box::new(HandshakeError::new(kind, e)))
     }
 }
@@ -44,6 +44,7 @@ pub struct NoiseOutput<T> {
     recv_offset: usize,
     send_buffer: Vec<u8>,
     send_offset: usize,
+    session_id: [u8; 32],
 }
 
 impl<T> fmt::Debug for NoiseOutput<T> {
@@ -53,18 +54,28 @@ impl<T> fmt::Debug for NoiseOutput<T> {
 }
 
 impl<T> NoiseOutput<T> {
-    fn new(io: NoiseFramed<T, snow::TransportState>) -> Self {
+    fn new(io: NoiseFramed<T, snow::TransportState>, session_id: [u8; 32]) -> Self {
         NoiseOutput {
             io,
             recv_buffer: Bytes::new(),
             recv_offset: 0,
             send_buffer: Vec::new(),
             send_offset: 0,
+            session_id,
         }
     }
+
+    /// Returns the session id, i.e. the (truncated) handshake hash, which is the
+    /// same for both peers of the session.
+    pub fn session_id(&self) -> [u8; 32] {
+        self.session_id
+    }
 }
 
 impl<T: AsyncRead + Unpin> AsyncRead for NoiseOutput<T> {
+    // Cancellation safe: a decoded frame is kept in `recv_buffer` until it is fully
+    // copied out, and a partially received frame is kept by `NoiseFramed`, so nothing
+    // is lost if a pending read is dropped.
     fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
         loop {
             let len = self.recv_buffer.len();
@@ -101,6 +101,9 @@ impl<T> NoiseFramed<T, snow::HandshakeState> {
                 Ok(dh_pk) => Some(dh_pk),
             },
         };
+        // the handshake hash is as long as the output of the hash function, at least 32 bytes
+        let mut session_id = [0u8; 32];
+        session_id.copy_from_slice(&self.session.get_handshake_hash()[..32]);
         match self.session.into_transport_mode() {
             Err(e) => Err(e.into()),
             Ok(s) => {
@@ -113,7 +116,7 @@ impl<T> NoiseFramed<T, snow::HandshakeState> {
                     write_buffer: self.write_buffer,
                     decrypt_buffer: self.decrypt_buffer,
                 };
-                Ok((dh_remote_pubkey, NoiseOutput::new(io)))
+                Ok((dh_remote_pubkey, NoiseOutput::new(io, session_id)))
             }
         }
     }
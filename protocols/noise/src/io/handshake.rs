@@ -314,7 +314,7 @@ impl<T> State<T> {
                 if C::verify(&id_pk, &dh_pk, &self.dh_remote_pubkey_sig) {
                     RemoteIdentity::IdentityKey(id_pk)
                 } else {
-                    return Err(NoiseError::InvalidKey);
+                    return Err(NoiseError::BadSignature);
                 }
             }
         };
@@ -401,7 +401,7 @@ where
     if !pb.identity_key.is_empty() {
         let pk = identity::PublicKey::from_protobuf_encoding(&pb.identity_key).map_err(|_| NoiseError::InvalidKey)?;
         if let Some(ref k) = state.id_remote_pubkey {
-            if k != &pk {
+            if !k.ct_eq(&pk) {
                 return Err(NoiseError::InvalidKey);
             }
         }
@@ -53,6 +53,14 @@ use futures::prelude::*;
 use libp2prs_core::identity;
 use zeroize::Zeroize;
 
+/// Returns the names of the Noise protocols `NoiseConfig` can run, i.e. the `XX` handshake
+/// pattern, plain or with a pre-shared key.
+pub fn supported_protocols() -> Vec<String> {
+    let xx = X25519Spec::params_xx();
+    let xx_psk = xx.clone().with_psk0();
+    vec![xx.name().to_string(), xx_psk.name().to_string()]
+}
+
 /// The protocol upgrade configuration.
 #[derive(Clone)]
 pub struct NoiseConfig<P, C: Zeroize, R = ()> {
@@ -61,6 +69,7 @@ pub struct NoiseConfig<P, C: Zeroize, R = ()> {
     params: ProtocolParams,
     legacy: LegacyConfig,
     remote: R,
+    psk: Option<[u8; 32]>,
     _marker: std::marker::PhantomData<P>,
 }
 
@@ -84,15 +93,31 @@ where
             params: C::params_xx(),
             legacy: LegacyConfig::default(),
             remote: (),
+            psk: None,
             _marker: std::marker::PhantomData,
         }
     }
 
+    /// Mixes a pre-shared key into the handshake, for private networks.
+    ///
+    /// The `XXpsk0` handshake pattern is used instead of `XX`, the handshake fails
+    /// unless both peers use the same pre-shared key.
+    pub fn with_psk(mut self, psk: [u8; 32]) -> Self {
+        if self.psk.is_none() {
+            self.params = self.params.with_psk0();
+        }
+        self.psk = Some(psk);
+        self
+    }
+
     pub async fn handshake<T>(self, socket: T, initiator: bool) -> Result<(RemoteIdentity<C>, NoiseOutput<T>), NoiseError>
     where
         T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
     {
-        let builder = self.params.into_builder().local_private_key(self.dh_keys.secret().as_ref());
+        let mut builder = self.params.into_builder().local_private_key(self.dh_keys.secret().as_ref());
+        if let Some(psk) = self.psk.as_ref() {
+            builder = builder.psk(0, psk);
+        }
 
         let session = {
             if initiator {
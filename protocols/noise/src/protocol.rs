@@ -38,6 +38,19 @@ impl ProtocolParams {
     pub(crate) fn into_builder(self) -> snow::Builder<'static> {
         snow::Builder::with_resolver(self.0, Box::new(Resolver))
     }
+
+    /// The name of the Noise protocol, e.g. `Noise_XX_25519_ChaChaPoly_SHA256`.
+    pub(crate) fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    /// Turn the protocol parameters into the ones of the `psk0` variant of the
+    /// handshake pattern, e.g. `Noise_XX_...` into `Noise_XXpsk0_...`.
+    pub(crate) fn with_psk0(self) -> Self {
+        let mut parts = self.0.name.split('_').map(String::from).collect::<Vec<_>>();
+        parts[1].push_str("psk0");
+        parts.join("_").parse().map(ProtocolParams).expect("Invalid protocol name")
+    }
 }
 
 /// Type tag for the IK handshake pattern.
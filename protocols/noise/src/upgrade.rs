@@ -48,6 +48,10 @@ impl<T> SecureInfo for NoiseStream<T> {
     fn remote_pub_key(&self) -> PublicKey {
         self.remote_pub_key.clone().unwrap()
     }
+
+    fn session_id(&self) -> Option<[u8; 32]> {
+        Some(self.io.session_id())
+    }
 }
 
 impl<T: AsyncRead + Send + Unpin + 'static> AsyncRead for NoiseStream<T> {
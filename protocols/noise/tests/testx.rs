@@ -1,14 +1,21 @@
-use futures::{AsyncReadExt, AsyncWriteExt};
+use futures::future::{self, Either};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Future};
+use libp2prs_core::secure_io::SecureInfo;
 use libp2prs_core::upgrade::{UpgradeInfo, Upgrader};
 use libp2prs_core::{identity, Transport};
+use libp2prs_noise::{supported_protocols, NoiseConfig, X25519Spec};
 use libp2prs_noise::{Keypair, X25519};
-use libp2prs_noise::{NoiseConfig, X25519Spec};
 use libp2prs_runtime::{
     net::{TcpListener, TcpStream},
     task,
 };
 use libp2prs_tcp::TcpConfig;
 use log::info;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
 
 #[test]
 fn test_mode_xx() {
@@ -108,3 +115,272 @@ fn test_xx_upgrader() {
         info!("read finished, {:?}", String::from_utf8(buf).unwrap());
     });
 }
+
+#[test]
+fn test_xx_session_id() {
+    task::block_on(async {
+        let mut l = TcpConfig::new()
+            .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .expect("listen on");
+        let addr = l.multi_addr().cloned().unwrap();
+
+        let server = task::spawn(async move {
+            let server_id = identity::Keypair::generate_ed25519();
+            let server_dh = Keypair::<X25519Spec>::new().into_authentic(&server_id).unwrap();
+            let cfg = NoiseConfig::xx(server_dh, server_id);
+            let info = cfg.protocol_info().get(0).unwrap().clone();
+
+            let socket = l.accept_output().await.expect("accept");
+            let output = cfg.upgrade_inbound(socket, info).await.expect("upgrade inbound");
+            output.session_id()
+        });
+
+        let client_id = identity::Keypair::generate_ed25519();
+        let client_dh = Keypair::<X25519Spec>::new().into_authentic(&client_id).unwrap();
+        let cfg = NoiseConfig::xx(client_dh, client_id);
+        let info = cfg.protocol_info().get(0).unwrap().clone();
+
+        let socket = TcpConfig::new().dial(addr).await.expect("dial");
+        let output = cfg.upgrade_outbound(socket, info).await.expect("upgrade outbound");
+
+        assert!(output.session_id().is_some());
+        assert_eq!(output.session_id(), server.await.unwrap());
+    });
+}
+
+fn xx_config(psk: Option<[u8; 32]>) -> NoiseConfig<libp2prs_noise::XX, X25519Spec> {
+    let id = identity::Keypair::generate_ed25519();
+    let dh = Keypair::<X25519Spec>::new().into_authentic(&id).unwrap();
+    let cfg = NoiseConfig::xx(dh, id);
+    match psk {
+        Some(psk) => cfg.with_psk(psk),
+        None => cfg,
+    }
+}
+
+/// Runs a handshake, returns whether it succeeded on the listener and on the dialer.
+async fn xx_psk_handshake(server_psk: Option<[u8; 32]>, client_psk: Option<[u8; 32]>) -> (bool, bool) {
+    let mut l = TcpConfig::new()
+        .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .expect("listen on");
+    let addr = l.multi_addr().cloned().unwrap();
+
+    let server = task::spawn(async move {
+        let cfg = xx_config(server_psk);
+        let info = cfg.protocol_info().get(0).unwrap().clone();
+        let socket = l.accept_output().await.expect("accept");
+        let mut output = match cfg.upgrade_inbound(socket, info).await {
+            Ok(output) => output,
+            Err(_) => return false,
+        };
+        let mut buf = [0u8; 5];
+        output.read_exact(&mut buf).await.is_ok() && &buf == b"hello"
+    });
+
+    let cfg = xx_config(client_psk);
+    let info = cfg.protocol_info().get(0).unwrap().clone();
+    let socket = TcpConfig::new().dial(addr).await.expect("dial");
+    let client = match cfg.upgrade_outbound(socket, info).await {
+        Ok(mut output) => output.write_all(b"hello").await.is_ok() && output.flush().await.is_ok(),
+        Err(_) => false,
+    };
+
+    (server.await.unwrap(), client)
+}
+
+#[test]
+fn test_xx_psk() {
+    task::block_on(async {
+        assert_eq!(xx_psk_handshake(Some([1; 32]), Some([1; 32])).await, (true, true));
+    });
+}
+
+#[test]
+fn test_xx_psk_mismatch() {
+    task::block_on(async {
+        assert_eq!(xx_psk_handshake(Some([1; 32]), Some([2; 32])).await, (false, false));
+    });
+}
+
+#[test]
+fn test_xx_psk_missing() {
+    task::block_on(async {
+        assert_eq!(xx_psk_handshake(None, Some([1; 32])).await, (false, false));
+        assert_eq!(xx_psk_handshake(Some([1; 32]), None).await, (false, false));
+    });
+}
+
+/// Delivers the inner reader one byte at a time, returning `Pending` in between,
+/// so that reads on top of it are left half-way through a frame.
+struct Trickle<T> {
+    inner: T,
+    stall: bool,
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Trickle<T> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        self.stall = !self.stall;
+        if self.stall {
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        let n = buf.len().min(1);
+        Pin::new(&mut self.inner).poll_read(cx, &mut buf[..n])
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Trickle<T> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+/// Completes on the second poll, used to cancel a concurrent read.
+struct YieldOnce(bool);
+
+impl Future for YieldOnce {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+#[test]
+fn test_cancelled_read_loses_no_data() {
+    task::block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let data: Vec<u8> = (0..1500u32).map(|i| i as u8).collect();
+        let expected = data.clone();
+
+        task::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let (_, mut output) = xx_config(None).handshake(socket, false).await.unwrap();
+            // several frames, so that reads are cancelled both inside and across frames
+            for chunk in data.chunks(600) {
+                output.write_all(chunk).await.unwrap();
+                output.flush().await.unwrap();
+            }
+            // keep the session open until the client is done
+            let _ = output.read(&mut [0u8; 1]).await;
+        });
+
+        let socket = TcpStream::connect(addr).await.unwrap();
+        let socket = Trickle {
+            inner: socket,
+            stall: false,
+        };
+        let (_, mut output) = xx_config(None).handshake(socket, true).await.unwrap();
+
+        let mut received = Vec::new();
+        let mut cancelled = 0;
+        let mut buf = [0u8; 7];
+        while received.len() < expected.len() {
+            match future::select(output.read(&mut buf), YieldOnce(false)).await {
+                Either::Left((r, _)) => {
+                    let n = r.unwrap();
+                    assert!(n > 0);
+                    received.extend_from_slice(&buf[..n]);
+                }
+                // the read future is dropped half-way
+                Either::Right(_) => cancelled += 1,
+            }
+        }
+
+        assert!(cancelled > 0);
+        assert_eq!(received, expected);
+    });
+}
+
+#[test]
+fn supported_protocols_listed() {
+    let protocols = supported_protocols();
+    assert!(!protocols.is_empty());
+    assert!(protocols.iter().any(|p| p == "Noise_XX_25519_ChaChaPoly_SHA256"));
+    assert!(protocols.iter().any(|p| p == "Noise_XXpsk0_25519_ChaChaPoly_SHA256"));
+}
+
+/// Counts the bytes written to the inner I/O resource.
+struct CountWrites<T> {
+    inner: T,
+    written: Arc<AtomicUsize>,
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for CountWrites<T> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for CountWrites<T> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let n = futures::ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        self.written.fetch_add(n, Ordering::SeqCst);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+#[test]
+fn small_writes_accumulate_into_one_frame() {
+    task::block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = task::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let (_, mut output) = xx_config(None).handshake(socket, false).await.unwrap();
+            // not up to EOF, closing the socket may not shut it down for writing
+            let mut buf = vec![0; 53];
+            output.read_exact(&mut buf).await.unwrap();
+            buf
+        });
+
+        let written = Arc::new(AtomicUsize::new(0));
+        let socket = CountWrites {
+            inner: TcpStream::connect(addr).await.unwrap(),
+            written: written.clone(),
+        };
+        let (_, mut output) = xx_config(None).handshake(socket, true).await.unwrap();
+        let handshake = written.load(Ordering::SeqCst);
+
+        for _ in 0..10 {
+            output.write_all(b"hello").await.unwrap();
+        }
+        // buffered, nothing is sent until flushed
+        assert_eq!(written.load(Ordering::SeqCst), handshake);
+
+        output.flush().await.unwrap();
+        // a single frame: the length prefix, the payload and the authentication tag
+        assert_eq!(written.load(Ordering::SeqCst) - handshake, 2 + 50 + 16);
+
+        output.write_all(b"bye").await.unwrap();
+        output.close().await.unwrap();
+
+        let mut expected = b"hello".repeat(10);
+        expected.extend_from_slice(b"bye");
+        assert_eq!(server.await.unwrap(), expected);
+    });
+}
@@ -48,6 +48,9 @@ pub enum PlaintextError {
 
     /// We received an invalid proposition from remote.
     InvalidProposition(&'static str),
+
+    /// The remote speaks some other plaintext protocol, e.g. HTTP.
+    ProtocolConfusion,
 }
 
 impl PartialEq for PlaintextError {
@@ -60,7 +63,8 @@ impl PartialEq for PlaintextError {
             | (EmptyPublicKey, EmptyPublicKey)
             | (HandshakeParsingFailure, HandshakeParsingFailure)
             | (MismatchedIDANDPubKey, MismatchedIDANDPubKey)
-            | (InvalidMessage, InvalidMessage) => true,
+            | (InvalidMessage, InvalidMessage)
+            | (ProtocolConfusion, ProtocolConfusion) => true,
             _ => false,
         }
     }
@@ -95,6 +99,7 @@ impl fmt::Display for PlaintextError {
             PlaintextError::InvalidMessage => write!(f, "Invalid Message"),
             PlaintextError::InvalidProposition(e) => write!(f, "Invalid Proposition: {}", e),
             PlaintextError::EmptyPublicKey => write!(f, "Public key is empty"),
+            PlaintextError::ProtocolConfusion => write!(f, "Protocol Confusion"),
         }
     }
 }
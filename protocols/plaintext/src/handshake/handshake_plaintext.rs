@@ -60,7 +60,7 @@ where
 
     socket.write_one_fixed(local_context.state.exchange_bytes.as_ref()).await?;
 
-    let buf = socket.read_one_fixed(max_frame_len).await?;
+    let buf = read_exchange(&mut socket, max_frame_len).await?;
     let remote_context = local_context.with_remote(buf)?;
 
     let local_id = config.clone().key.public().into_peer_id();
@@ -132,8 +132,38 @@ impl HandshakeContext<Local> {
     }
 }
 
+/// Reads the exchange message of the remote, failing with `PlaintextError::ProtocolConfusion`
+/// if its length prefix is printable ASCII, as sent by e.g. an HTTP client.
+async fn read_exchange<T>(socket: &mut T, max_size: usize) -> Result<Vec<u8>, PlaintextError>
+where
+    T: AsyncRead + Send + Unpin,
+{
+    let mut prefix = [0; 4];
+    socket.read_exact2(&mut prefix).await?;
+    if prefix.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+        error!(
+            "remote sent plaintext {:?} instead of an exchange",
+            String::from_utf8_lossy(&prefix)
+        );
+        return Err(PlaintextError::ProtocolConfusion);
+    }
+
+    let len = u32::from_be_bytes(prefix) as usize;
+    if len > max_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Received data size over maximum frame length: {}>{}", len, max_size),
+        )
+        .into());
+    }
+    let mut buf = vec![0; len];
+    socket.read_exact2(&mut buf).await?;
+    Ok(buf)
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::error::PlaintextError;
     use crate::PlainTextConfig;
 
     use bytes::BytesMut;
@@ -183,4 +213,23 @@ mod tests {
         let key_2 = Keypair::generate_secp256k1();
         handshake_with_self_success(PlainTextConfig::new(key_1), PlainTextConfig::new(key_2), b"hello world")
     }
+
+    #[test]
+    fn http_request_is_protocol_confusion() {
+        task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let listener_addr = listener.local_addr().unwrap();
+
+            let client = task::spawn(async move {
+                let mut connect = TcpStream::connect(&listener_addr).await.unwrap();
+                connect.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+                connect
+            });
+
+            let (connect, _) = listener.accept().await.unwrap();
+            let result = PlainTextConfig::new(Keypair::generate_ed25519()).handshake(connect).await;
+            assert_eq!(result.err(), Some(PlaintextError::ProtocolConfusion));
+            client.await;
+        });
+    }
 }
@@ -47,6 +47,16 @@ pub mod structs_proto {
 const MAX_FRAME_SIZE: usize = 1024 * 1024 * 8;
 
 /// Config for PlainText
+///
+/// Plaintext provides no confidentiality nor integrity, it should only be used on a
+/// trusted network. To fall back to it when the remote doesn't support any encrypted
+/// security protocol, wrap it in an `Option` and put it after the preferred upgrader,
+/// so that it is only offered when explicitly enabled:
+///
+/// ```ignore
+/// let plaintext = if trusted { Some(PlainTextConfig::new(key)) } else { None };
+/// let sec = Selector::new(noise, plaintext);
+/// ```
 #[derive(Clone)]
 pub struct PlainTextConfig {
     pub(crate) key: Keypair,
@@ -0,0 +1,161 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use futures::{AsyncReadExt, AsyncWriteExt};
+use libp2prs_core::either::EitherOutput;
+use libp2prs_core::identity;
+use libp2prs_core::multiaddr::protocol::Protocol;
+use libp2prs_core::multistream::Negotiator;
+use libp2prs_core::transport::memory::{Channel, MemoryTransport};
+use libp2prs_core::transport::TransportError;
+use libp2prs_core::upgrade::{ProtocolName, Selector, Upgrader};
+use libp2prs_core::{Multiaddr, Transport};
+use libp2prs_noise::{Keypair, NoiseConfig, X25519Spec, XX};
+use libp2prs_plaintext::PlainTextConfig;
+use libp2prs_runtime::task;
+use rand::random;
+
+/// Negotiates the security protocol with multistream-select, then performs the handshake.
+async fn secure<U>(upgrader: U, socket: Channel, dialer: bool) -> Result<U::Output, TransportError>
+where
+    U: Upgrader<Channel> + Send,
+{
+    let protocols: Vec<Vec<u8>> = upgrader.protocol_info().iter().map(|p| p.protocol_name().to_vec()).collect();
+    let neg = Negotiator::new_with_protocols(protocols);
+    let (proto, socket) = if dialer {
+        neg.select_one(socket).await?
+    } else {
+        neg.negotiate(socket).await?
+    };
+    let info = upgrader
+        .protocol_info()
+        .into_iter()
+        .find(|p| p.protocol_name() == proto.as_slice())
+        .expect("negotiated protocol");
+    if dialer {
+        upgrader.upgrade_outbound(socket, info).await
+    } else {
+        upgrader.upgrade_inbound(socket, info).await
+    }
+}
+
+fn noise_config() -> NoiseConfig<XX, X25519Spec> {
+    let key = identity::Keypair::generate_ed25519();
+    let dh = Keypair::<X25519Spec>::new().into_authentic(&key).unwrap();
+    NoiseConfig::xx(dh, key)
+}
+
+fn plaintext_config(enabled: bool) -> Option<PlainTextConfig> {
+    if enabled {
+        Some(PlainTextConfig::new(identity::Keypair::generate_ed25519()))
+    } else {
+        None
+    }
+}
+
+#[test]
+fn fallback_to_plaintext() {
+    task::block_on(async {
+        let addr: Multiaddr = Protocol::Memory(1 + random::<u64>()).into();
+        let mut listener = MemoryTransport::default().listen_on(addr.clone()).unwrap();
+
+        // the listener only supports plaintext
+        let server = task::spawn(async move {
+            let socket = listener.accept_output().await.unwrap();
+            let upgrader = PlainTextConfig::new(identity::Keypair::generate_ed25519());
+            let mut output = secure(upgrader, socket, false).await.expect("upgrade inbound");
+            let mut buf = [0u8; 5];
+            output.read_exact(&mut buf).await.unwrap();
+            output.write_all(&buf).await.unwrap();
+        });
+
+        let socket = MemoryTransport::default().dial(addr).await.unwrap();
+        let upgrader = Selector::new(noise_config(), plaintext_config(true));
+        let output = secure(upgrader, socket, true).await.expect("upgrade outbound");
+        let mut output = match output {
+            EitherOutput::A(_) => panic!("noise is not supported by the listener"),
+            EitherOutput::B(output) => output,
+        };
+
+        output.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        output.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        server.await;
+    });
+}
+
+#[test]
+fn plaintext_not_offered_unless_enabled() {
+    task::block_on(async {
+        let addr: Multiaddr = Protocol::Memory(1 + random::<u64>()).into();
+        let mut listener = MemoryTransport::default().listen_on(addr.clone()).unwrap();
+
+        let server = task::spawn(async move {
+            let socket = listener.accept_output().await.unwrap();
+            let upgrader = PlainTextConfig::new(identity::Keypair::generate_ed25519());
+            secure(upgrader, socket, false).await.map(|_| ())
+        });
+
+        let socket = MemoryTransport::default().dial(addr).await.unwrap();
+        let upgrader = Selector::new(noise_config(), plaintext_config(false));
+        assert!(secure(upgrader, socket, true).await.is_err());
+        assert!(server.await.unwrap().is_err());
+    });
+}
+
+#[test]
+fn noise_preferred_over_plaintext() {
+    task::block_on(async {
+        let addr: Multiaddr = Protocol::Memory(1 + random::<u64>()).into();
+        let mut listener = MemoryTransport::default().listen_on(addr.clone()).unwrap();
+
+        let server = task::spawn(async move {
+            let socket = listener.accept_output().await.unwrap();
+            let upgrader = Selector::new(noise_config(), plaintext_config(true));
+            let output = secure(upgrader, socket, false).await.expect("upgrade inbound");
+            let mut output = match output {
+                EitherOutput::A(output) => output,
+                EitherOutput::B(_) => panic!("plaintext must not be used"),
+            };
+            let mut buf = [0u8; 5];
+            output.read_exact(&mut buf).await.unwrap();
+            output.write_all(&buf).await.unwrap();
+            output.flush().await.unwrap();
+        });
+
+        let socket = MemoryTransport::default().dial(addr).await.unwrap();
+        let upgrader = Selector::new(noise_config(), plaintext_config(true));
+        let output = secure(upgrader, socket, true).await.expect("upgrade outbound");
+        let mut output = match output {
+            EitherOutput::A(output) => output,
+            EitherOutput::B(_) => panic!("plaintext must not be used"),
+        };
+
+        output.write_all(b"hello").await.unwrap();
+        output.flush().await.unwrap();
+        let mut buf = [0u8; 5];
+        output.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        server.await;
+    });
+}
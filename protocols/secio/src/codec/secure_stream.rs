@@ -5,11 +5,14 @@ use futures::io::{ReadHalf, WriteHalf};
 
 use crate::{codec::Hmac, crypto::BoxStreamCipher, error::SecioError};
 use futures::task::{Context, Poll};
-use futures::{stream::BoxStream, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Sink, Stream, StreamExt};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Sink};
 use quicksink::Action;
 use std::io::Read;
 
 /// Encrypted stream
+///
+/// The data written is sent as encrypted frames when flushed, so it is recommended to
+/// finish writing with `WriteEx::flush_and_close2`.
 #[pin_project::pin_project]
 pub struct SecureStream<T> {
     #[pin]
@@ -20,6 +23,9 @@ pub struct SecureStream<T> {
     /// denotes a sequence of bytes which are expected to be
     /// found at the beginning of the stream and are checked for equality
     nonce: Vec<u8>,
+
+    /// the session id, derived from the handshake transcript
+    session_id: [u8; 32],
 }
 
 impl<T> SecureStream<T>
@@ -35,6 +41,7 @@ where
         encode_cipher: BoxStreamCipher,
         encode_hmac: Option<Hmac>,
         nonce: Vec<u8>,
+        session_id: [u8; 32],
     ) -> Self {
         let (r, w) = socket.split();
         let decrypter = Decrypter::new(r, max_frame_len, decode_cipher, decode_hmac);
@@ -44,9 +51,30 @@ where
             decrypter,
             encrypter,
             nonce,
+            session_id,
         }
     }
 
+    /// Reserves the given capacity for reading the frames, instead of allocating it on the first
+    /// read. The allocation is reused for all the frames.
+    pub fn with_read_buffer_capacity(mut self, capacity: usize) -> Self {
+        self.decrypter.recv_buf.reserve(capacity);
+        self
+    }
+
+    /// Returns the capacity of the buffer the frames are read into.
+    pub fn read_buffer_capacity(&self) -> usize {
+        self.decrypter.recv_buf.capacity()
+    }
+
+    /// Finishes writing, while the stream can still be read from.
+    ///
+    /// The encrypted frames pending are flushed, then only the write half of the socket is
+    /// closed. The remote reads EOF once it has read all the data.
+    pub async fn close_write(&mut self) -> io::Result<()> {
+        self.encrypter.close().await
+    }
+
     /// Verify nonce between local and remote
     pub(crate) async fn verify_nonce(&mut self) -> Result<(), SecioError> {
         if !self.nonce.is_empty() {
@@ -68,6 +96,13 @@ where
     }
 }
 
+impl<T> SecureStream<T> {
+    /// Returns the session id, which is the same for both peers of the session.
+    pub fn session_id(&self) -> [u8; 32] {
+        self.session_id
+    }
+}
+
 impl<T> AsyncRead for SecureStream<T>
 where
     T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
@@ -98,14 +133,19 @@ where
     }
 }
 
-#[pin_project::pin_project]
 struct Decrypter<R> {
+    reader: R,
+    max_len: usize,
     current_item: Option<std::io::Cursor<Vec<u8>>>,
-    #[pin]
-    stream: BoxStream<'static, io::Result<Vec<u8>>>,
+    /// The length prefix of the frame being read
+    len_buf: [u8; 4],
+    len_read: usize,
+    /// The frame being read, the allocation is reused from one frame to the next
+    recv_buf: Vec<u8>,
+    recv_read: usize,
+    frame_len: Option<usize>,
     decode_cipher: BoxStreamCipher,
     decode_hmac: Option<Hmac>,
-    _mark: std::marker::PhantomData<R>,
 }
 
 impl<R> Decrypter<R>
@@ -113,65 +153,80 @@ where
     R: AsyncRead + Unpin + Send + 'static,
 {
     fn new(r: R, max_len: usize, decode_cipher: BoxStreamCipher, decode_hmac: Option<Hmac>) -> Self {
-        let stream = futures::stream::unfold(r, move |mut r| async move {
-            let mut len = [0; 4];
-            if let Err(e) = r.read_exact(&mut len).await {
-                if e.kind() == io::ErrorKind::UnexpectedEof {
-                    return None;
-                }
-                return Some((Err(e), r));
-            }
-            let n = u32::from_be_bytes(len) as usize;
-            if n > max_len {
-                let msg = format!("data length {} exceeds allowed maximum {}", n, max_len);
-                return Some((Err(io::Error::new(io::ErrorKind::PermissionDenied, msg)), r));
-            }
-            let mut v = vec![0; n];
-            if let Err(e) = r.read_exact(&mut v).await {
-                return Some((Err(e), r));
-            }
-            Some((Ok(v), r))
-        });
-
         Decrypter {
+            reader: r,
+            max_len,
             current_item: None,
-            stream: stream.boxed(),
+            len_buf: [0; 4],
+            len_read: 0,
+            recv_buf: Vec::new(),
+            recv_read: 0,
+            frame_len: None,
             decode_hmac,
             decode_cipher,
-            _mark: std::marker::PhantomData,
         }
     }
 
-    /*
-    /// Decoding data
-    #[inline]
-    fn decode_buffer(self: Pin<&mut Self>, mut frame: Vec<u8>) -> Result<Vec<u8>, SecioError> {
-        let this = self.project();
-        if let Some(ref mut hmac) = this.decode_hmac {
-            if frame.len() < hmac.num_bytes() {
+    /// Reads a whole frame into `recv_buf`, returns false when the stream has ended.
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<bool>> {
+        loop {
+            match self.frame_len {
+                None if self.len_read == self.len_buf.len() => {
+                    self.len_read = 0;
+                    let n = u32::from_be_bytes(self.len_buf) as usize;
+                    if n > self.max_len {
+                        let msg = format!("data length {} exceeds allowed maximum {}", n, self.max_len);
+                        return Poll::Ready(Err(io::Error::new(io::ErrorKind::PermissionDenied, msg)));
+                    }
+                    // clear rather than reallocate, the capacity is kept
+                    self.recv_buf.clear();
+                    self.recv_buf.resize(n, 0);
+                    self.recv_read = 0;
+                    self.frame_len = Some(n);
+                }
+                None => {
+                    let n = futures::ready!(Pin::new(&mut self.reader).poll_read(cx, &mut self.len_buf[self.len_read..]))?;
+                    if n == 0 {
+                        return Poll::Ready(Ok(false));
+                    }
+                    self.len_read += n;
+                }
+                Some(len) if self.recv_read == len => {
+                    self.frame_len = None;
+                    return Poll::Ready(Ok(true));
+                }
+                Some(len) => {
+                    let n = futures::ready!(Pin::new(&mut self.reader).poll_read(cx, &mut self.recv_buf[self.recv_read..len]))?;
+                    if n == 0 {
+                        return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                    }
+                    self.recv_read += n;
+                }
+            }
+        }
+    }
+
+    /// Verifies and decrypts the frame in `recv_buf`.
+    fn decode_frame(&mut self) -> Result<Vec<u8>, SecioError> {
+        let mut content_length = self.recv_buf.len();
+        if let Some(ref mut hmac) = self.decode_hmac {
+            if content_length < hmac.num_bytes() {
                 debug!("frame too short when decoding secio frame");
                 return Err(SecioError::FrameTooShort);
             }
 
-            let content_length = frame.len() - hmac.num_bytes();
-            {
-                let (crypted_data, expected_hash) = frame.split_at(content_length);
-                debug_assert_eq!(expected_hash.len(), hmac.num_bytes());
+            content_length -= hmac.num_bytes();
+            let (crypted_data, expected_hash) = self.recv_buf.split_at(content_length);
+            debug_assert_eq!(expected_hash.len(), hmac.num_bytes());
 
-                if !hmac.verify(crypted_data, expected_hash) {
-                    debug!("hmac mismatch when decoding secio frame");
-                    return Err(SecioError::HmacNotMatching);
-                }
+            if !hmac.verify(crypted_data, expected_hash) {
+                debug!("hmac mismatch when decoding secio frame");
+                return Err(SecioError::HmacNotMatching);
             }
-
-            frame.truncate(content_length);
         }
 
-        let out = this.decode_cipher.decrypt(&frame)?;
-
-        Ok(out)
+        self.decode_cipher.as_mut().decrypt(&self.recv_buf[..content_length])
     }
-     */
 }
 
 impl<R> AsyncRead for Decrypter<R>
@@ -179,45 +234,23 @@ where
     R: AsyncRead + Unpin + Send + 'static,
 {
     fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
-        let mut this = self.project();
-        let item_to_copy = loop {
-            if let Some(ref mut i) = this.current_item {
+        let this = self.get_mut();
+        loop {
+            if let Some(ref i) = this.current_item {
                 if i.position() < i.get_ref().len() as u64 {
-                    break i;
+                    break;
                 }
             }
 
-            let mut frame = match this.stream.as_mut().poll_next(cx) {
-                Poll::Ready(Some(Ok(frame))) => frame,
-                Poll::Ready(Some(Err(e))) => return Poll::Ready(Err(e)),
-                Poll::Pending => return Poll::Pending,
-                Poll::Ready(None) => return Poll::Ready(Ok(0)),
-            };
-            if let Some(ref mut hmac) = this.decode_hmac {
-                if frame.len() < hmac.num_bytes() {
-                    debug!("frame too short when decoding secio frame");
-                    return Poll::Ready(Err(map_secio_error_to_io_error(SecioError::FrameTooShort)));
-                }
-
-                let content_length = frame.len() - hmac.num_bytes();
-                {
-                    let (crypted_data, expected_hash) = frame.split_at(content_length);
-                    debug_assert_eq!(expected_hash.len(), hmac.num_bytes());
-
-                    if !hmac.verify(crypted_data, expected_hash) {
-                        debug!("hmac mismatch when decoding secio frame");
-                        return Poll::Ready(Err(map_secio_error_to_io_error(SecioError::HmacNotMatching)));
-                    }
-                }
-
-                frame.truncate(content_length);
+            if !futures::ready!(this.poll_fill(cx))? {
+                return Poll::Ready(Ok(0));
             }
+            let frame = this.decode_frame().map_err(map_secio_error_to_io_error)?;
 
-            let frame = this.decode_cipher.as_mut().decrypt(&frame).map_err(map_secio_error_to_io_error)?;
-
-            *this.current_item = Some(io::Cursor::new(frame));
-        };
+            this.current_item = Some(io::Cursor::new(frame));
+        }
         // Copy it!
+        let item_to_copy = this.current_item.as_mut().expect("checked above");
         Poll::Ready(Ok(item_to_copy.read(buf)?))
     }
 }
@@ -248,6 +281,8 @@ where
                 Action::Flush => w.flush().await?,
                 Action::Close => {
                     log::info!("close action");
+                    // make sure nothing buffered by the writer is lost
+                    w.flush().await?;
                     w.close().await?
                 }
             }
@@ -301,6 +336,9 @@ where
     }
 
     fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        self.project().sink.poll_close(cx)
+        let mut this = self.project();
+        // drain the encoded frames before closing
+        futures::ready!(this.sink.as_mut().poll_flush(cx))?;
+        this.sink.poll_close(cx)
     }
 }
@@ -33,6 +33,8 @@ use crate::{
 };
 use log::trace;
 
+/// The nonce sequence of the AEAD ciphers, a little-endian counter which starts from zero,
+/// it is never derived from the handshake IV.
 struct RingNonce(BytesMut);
 
 impl NonceSequence for RingNonce {
@@ -50,6 +52,10 @@ enum RingAeadCryptoVariant {
 pub(crate) struct RingAeadCipher {
     cipher: RingAeadCryptoVariant,
     cipher_type: CipherType,
+    /// The number of nonces consumed so far.
+    nonce_count: u128,
+    /// The maximum number of nonces, beyond which the counter would wrap around.
+    nonce_limit: u128,
 }
 
 impl RingAeadCipher {
@@ -77,7 +83,22 @@ impl RingAeadCipher {
                 RingAeadCryptoVariant::Open(OpeningKey::new(UnboundKey::new(algorithm, key).unwrap(), RingNonce(nonce)))
             }
         };
-        RingAeadCipher { cipher, cipher_type }
+        RingAeadCipher {
+            cipher,
+            cipher_type,
+            nonce_count: 0,
+            nonce_limit: (1u128 << (8 * nonce_size)) - 1,
+        }
+    }
+
+    /// Consumes a nonce, refusing to go on once the counter is about to wrap, because
+    /// a nonce must never be reused under the same key.
+    fn next_nonce(&mut self) -> Result<(), SecioError> {
+        if self.nonce_count >= self.nonce_limit {
+            return Err(SecioError::NonceOverflow);
+        }
+        self.nonce_count += 1;
+        Ok(())
     }
 
     /// Encrypt `input` to `output` with `tag`. `output.len()` should equals to `input.len() + tag.len()`.
@@ -87,6 +108,7 @@ impl RingAeadCipher {
     /// +----------------------------------------+-----------------------+
     /// ```
     pub fn encrypt(&mut self, input: &[u8]) -> Result<Vec<u8>, SecioError> {
+        self.next_nonce()?;
         let mut output = Vec::with_capacity(input.len() + self.cipher_type.tag_size());
         unsafe {
             output.set_len(input.len());
@@ -126,6 +148,7 @@ impl RingAeadCipher {
         }
         buf.copy_from_slice(input);
 
+        self.next_nonce()?;
         if let RingAeadCryptoVariant::Open(ref mut key) = self.cipher {
             match key.open_in_place(Aad::empty(), &mut buf) {
                 Ok(out_buf) => output.copy_from_slice(out_buf),
@@ -151,6 +174,8 @@ impl StreamCipher for RingAeadCipher {
 #[cfg(test)]
 mod test {
     use super::{CipherType, CryptoMode, RingAeadCipher};
+    use crate::error::SecioError;
+    use std::collections::HashSet;
 
     fn test_ring_aead(cipher: CipherType) {
         let key = (0..cipher.key_size()).map(|_| rand::random::<u8>()).collect::<Vec<_>>();
@@ -181,6 +206,39 @@ mod test {
         assert_eq!(&decrypted_msg[..], message);
     }
 
+    #[test]
+    fn test_distinct_nonces() {
+        let cipher = CipherType::Aes128Gcm;
+        let key = (0..cipher.key_size()).map(|_| rand::random::<u8>()).collect::<Vec<_>>();
+        let message = b"HELLO WORLD";
+
+        let mut enc = RingAeadCipher::new(cipher, &key[..], CryptoMode::Encrypt);
+        let mut dec = RingAeadCipher::new(cipher, &key[..], CryptoMode::Decrypt);
+
+        // the same plaintext yields a different ciphertext for every frame
+        let mut seen = HashSet::new();
+        for _ in 0..10000 {
+            let encrypted_msg = enc.encrypt(message).unwrap();
+            assert!(seen.insert(encrypted_msg.clone()));
+            assert_eq!(&dec.decrypt(&encrypted_msg[..]).unwrap()[..], message);
+        }
+    }
+
+    #[test]
+    fn test_nonce_overflow() {
+        let cipher = CipherType::ChaCha20Poly1305;
+        let key = (0..cipher.key_size()).map(|_| rand::random::<u8>()).collect::<Vec<_>>();
+
+        let mut enc = RingAeadCipher::new(cipher, &key[..], CryptoMode::Encrypt);
+        assert_eq!(enc.nonce_limit, (1 << 96) - 1);
+        enc.nonce_limit = 3;
+
+        for _ in 0..3 {
+            enc.encrypt(b"HELLO WORLD").unwrap();
+        }
+        assert_eq!(enc.encrypt(b"HELLO WORLD").unwrap_err(), SecioError::NonceOverflow);
+    }
+
     #[test]
     fn test_aes_128_gcm() {
         test_ring_aead(CipherType::Aes128Gcm)
@@ -46,6 +46,9 @@ pub enum SecioError {
     /// The received frame was of invalid length.
     FrameTooShort,
 
+    /// The nonce counter of the AEAD cipher is exhausted, the key must not be used any more.
+    NonceOverflow,
+
     /// The hashes of the message didn't match.
     HmacNotMatching,
 
@@ -63,6 +66,9 @@ pub enum SecioError {
 
     /// We received an invalid proposition from remote.
     InvalidProposition(&'static str),
+
+    /// The remote speaks some plaintext protocol, e.g. HTTP, instead of secio.
+    ProtocolConfusion,
 }
 
 impl PartialEq for SecioError {
@@ -75,11 +81,13 @@ impl PartialEq for SecioError {
             | (NoSupportIntersection, NoSupportIntersection)
             | (NonceVerificationFailed, NonceVerificationFailed)
             | (FrameTooShort, FrameTooShort)
+            | (NonceOverflow, NonceOverflow)
             | (HmacNotMatching, HmacNotMatching)
             | (ConnectSelf, ConnectSelf)
             | (HandshakeParsingFailure, HandshakeParsingFailure)
             | (SignatureVerificationFailed, SignatureVerificationFailed)
-            | (InvalidMessage, InvalidMessage) => true,
+            | (InvalidMessage, InvalidMessage)
+            | (ProtocolConfusion, ProtocolConfusion) => true,
             _ => false,
         }
     }
@@ -119,12 +127,14 @@ impl fmt::Display for SecioError {
             SecioError::NoSupportIntersection => write!(f, "No Support Intersection"),
             SecioError::NonceVerificationFailed => write!(f, "Nonce Verification Failed"),
             SecioError::FrameTooShort => write!(f, "Frame Too Short"),
+            SecioError::NonceOverflow => write!(f, "Nonce Overflow"),
             SecioError::HmacNotMatching => write!(f, "Hmac Not Matching"),
             SecioError::ConnectSelf => write!(f, "Connect Self"),
             SecioError::HandshakeParsingFailure => write!(f, "Handshake Parsing Failure"),
             SecioError::InvalidMessage => write!(f, "Invalid Message"),
             SecioError::SignatureVerificationFailed => write!(f, "Signature Verification Failed"),
             SecioError::InvalidProposition(e) => write!(f, "Invalid Proposition: {}", e),
+            SecioError::ProtocolConfusion => write!(f, "Protocol Confusion"),
         }
     }
 }
@@ -132,10 +132,17 @@ impl HandshakeContext<Local> {
         // NOTE: Libp2p uses protobuf bytes to calculate order, but here we only use the original pubkey and nonce
         let nonce = propose.rand;
 
-        let public_key = propose.pubkey;
-        trace!("remote public_key: {:?}", &public_key);
+        let pubkey_bytes = propose.pubkey;
+        trace!("remote public_key: {:?}", &pubkey_bytes);
 
-        if public_key == self.state.public_key {
+        let public_key = match PublicKey::from_protobuf_encoding(pubkey_bytes.as_ref()) {
+            Ok(key) => key,
+            Err(_e) => {
+                return Err(SecioError::HandshakeParsingFailure);
+            }
+        };
+
+        if public_key.ct_eq(&self.config.key.public()) {
             return Err(SecioError::ConnectSelf);
         }
 
@@ -144,7 +151,7 @@ impl HandshakeContext<Local> {
         let hashes_ordering = {
             let oh1 = {
                 let mut ctx = ring::digest::Context::new(&ring::digest::SHA256);
-                ctx.update(public_key.as_ref());
+                ctx.update(pubkey_bytes.as_ref());
                 ctx.update(&self.state.nonce);
                 ctx.finish()
             };
@@ -216,13 +223,6 @@ impl HandshakeContext<Local> {
             }
         };
 
-        let public_key = match PublicKey::from_protobuf_encoding(public_key.as_ref()) {
-            Ok(key) => key,
-            Err(_e) => {
-                return Err(SecioError::HandshakeParsingFailure);
-            }
-        };
-
         Ok(HandshakeContext {
             config: self.config,
             state: Remote {
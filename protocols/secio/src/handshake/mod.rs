@@ -1,3 +1,24 @@
 /// Most of the code for this module comes from `rust-libp2p`, but modified some logic(struct).
 mod handshake_context;
 pub(crate) mod procedure;
+
+use std::cmp::Ordering;
+
+/// The transcript of a handshake, as handed to the `on_handshake_transcript` hook of `Config`.
+///
+/// It is meant for tracing interoperability issues, e.g. peers disagreeing on the chosen
+/// algorithms or rejecting each other's signature.
+#[derive(Clone, Debug)]
+pub struct HandshakeTranscript {
+    /// The raw bytes of our proposition.
+    pub local_proposition: Vec<u8>,
+    /// The raw bytes of the remote's proposition.
+    pub remote_proposition: Vec<u8>,
+    /// The ordering of `hash(remote pubkey, local nonce)` and `hash(local pubkey, remote nonce)`,
+    /// which decides whose preferences win the algorithm selection.
+    pub ordering: Ordering,
+    /// The data we signed in our `Exchange`.
+    pub data_to_sign: Vec<u8>,
+    /// The data the signature of the remote's `Exchange` is verified against.
+    pub data_to_verify: Vec<u8>,
+}
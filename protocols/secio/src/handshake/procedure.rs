@@ -25,6 +25,7 @@
 ///
 use log::{debug, trace};
 use std::cmp::Ordering;
+use std::io;
 
 use futures::{AsyncRead, AsyncWrite, AsyncWriteExt};
 
@@ -33,7 +34,8 @@ use crate::{
     crypto::{cipher::CipherType, new_stream, BoxStreamCipher, CryptoMode},
     error::SecioError,
     exchange,
-    handshake::handshake_context::HandshakeContext,
+    handshake::handshake_context::{HandshakeContext, Remote},
+    handshake::HandshakeTranscript,
     handshake_proto::Exchange,
     Config, Digest, EphemeralPublicKey,
 };
@@ -57,6 +59,10 @@ where
     T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
 {
     let max_frame_len = config.max_frame_length;
+    let read_buffer_capacity = config.read_buffer_capacity;
+    // The handshake messages are bounded by a much smaller size, so that an unauthenticated
+    // remote can't force us into large allocations.
+    let max_handshake_size = config.max_handshake_size;
     // The handshake messages all start with a 4-bytes message length prefix.
     // let mut socket = LengthPrefixSocket::new(socket, config.max_frame_length);
 
@@ -68,7 +74,7 @@ where
     socket.write_one_fixed(&local_context.state.proposition_bytes).await?;
 
     // Receive the remote's proposition.
-    let remote_proposition = socket.read_one_fixed(max_frame_len).await?;
+    let remote_proposition = read_proposition(&mut socket, max_handshake_size).await?;
     let remote_context = local_context.with_remote(remote_proposition)?;
 
     trace!(
@@ -83,12 +89,11 @@ where
     // Send the ephemeral pub key to the remote in an `Exchange` struct. The `Exchange` also
     // contains a signature of the two propositions encoded with our static public key.
     let ephemeral_context = remote_context.with_ephemeral(tmp_priv_key, tmp_pub_key.clone());
-    let exchanges = {
-        let mut data_to_sign = ephemeral_context.state.remote.local.proposition_bytes.clone();
-
-        data_to_sign.extend_from_slice(&ephemeral_context.state.remote.proposition_bytes);
-        data_to_sign.extend_from_slice(&tmp_pub_key);
+    let mut data_to_sign = ephemeral_context.state.remote.local.proposition_bytes.clone();
+    data_to_sign.extend_from_slice(&ephemeral_context.state.remote.proposition_bytes);
+    data_to_sign.extend_from_slice(&tmp_pub_key);
 
+    let exchanges = {
         let kpair: Keypair = ephemeral_context.config.key.clone();
         let signature = match kpair.sign(data_to_sign.as_ref()) {
             Ok(signature) => signature,
@@ -114,7 +119,7 @@ where
     socket.write_one_fixed(&local_exchanges).await?;
 
     // Receive the remote's `Exchange`.
-    let raw_exchanges = socket.read_one_fixed(max_frame_len).await?;
+    let raw_exchanges = socket.read_one_fixed(max_handshake_size).await?;
     let remote_exchanges = match Exchange::decode(&raw_exchanges[..]) {
         Ok(e) => e,
         Err(err) => {
@@ -132,6 +137,16 @@ where
     data_to_verify.extend_from_slice(&ephemeral_context.state.remote.local.proposition_bytes);
     data_to_verify.extend_from_slice(&remote_exchanges.epubkey);
 
+    if let Some(hook) = ephemeral_context.config.transcript_hook.as_ref() {
+        hook(&HandshakeTranscript {
+            local_proposition: ephemeral_context.state.remote.local.proposition_bytes.clone(),
+            remote_proposition: ephemeral_context.state.remote.proposition_bytes.clone(),
+            ordering: ephemeral_context.state.remote.hashes_ordering,
+            data_to_sign: data_to_sign.clone(),
+            data_to_verify: data_to_verify.clone(),
+        });
+    }
+
     let remote_public_key = ephemeral_context.state.remote.public_key.clone();
 
     if !remote_public_key.verify(data_to_verify.as_ref(), remote_exchanges.signature.as_ref()) {
@@ -192,6 +207,12 @@ where
         iv_size,
     );
 
+    let session_id = session_id(
+        &pub_ephemeral_context.state.remote,
+        &pub_ephemeral_context.state.local_tmp_pub_key,
+        &remote_exchanges.epubkey,
+    );
+
     let mut secure_stream = SecureStream::new(
         socket,
         max_frame_len,
@@ -200,7 +221,9 @@ where
         encode_cipher,
         encode_hmac,
         pub_ephemeral_context.state.remote.local.nonce.to_vec(),
-    );
+        session_id,
+    )
+    .with_read_buffer_capacity(read_buffer_capacity);
 
     // We send back their nonce to check if the connection works.
     trace!("checking encryption by sending back remote's nonce");
@@ -220,6 +243,29 @@ where
     ))
 }
 
+/// Derives the session id from the handshake transcript, i.e. the nonces and the
+/// ephemeral public keys of both peers.
+///
+/// The transcript is ordered by `hashes_ordering`, so that both peers compute the same id.
+fn session_id(state: &Remote, local_tmp_pub_key: &[u8], remote_tmp_pub_key: &[u8]) -> [u8; 32] {
+    let local = (&state.local.nonce[..], local_tmp_pub_key);
+    let remote = (&state.nonce[..], remote_tmp_pub_key);
+    let (first, second) = match state.hashes_ordering {
+        Ordering::Greater => (local, remote),
+        _ => (remote, local),
+    };
+
+    let mut context = ring::digest::Context::new(&ring::digest::SHA256);
+    context.update(first.0);
+    context.update(second.0);
+    context.update(first.1);
+    context.update(second.1);
+
+    let mut id = [0u8; 32];
+    id.copy_from_slice(context.finish().as_ref());
+    id
+}
+
 /// Custom algorithm translated from reference implementations. Needs to be the same algorithm
 /// amongst all implementations.
 fn stretch_key(hmac: Hmac, result: &mut [u8]) {
@@ -266,10 +312,45 @@ fn generate_stream_cipher_and_hmac(
     (cipher, hmac)
 }
 
+/// Reads the proposition of the remote, the first message of the handshake.
+///
+/// Fails with `SecioError::ProtocolConfusion` if its length prefix is printable ASCII, which
+/// means the remote speaks a plaintext protocol, e.g. `GET / HTTP/1.1`. No genuine handshake
+/// message is ever that long.
+async fn read_proposition<T>(socket: &mut T, max_size: usize) -> Result<Vec<u8>, SecioError>
+where
+    T: AsyncRead + Send + Unpin,
+{
+    let mut prefix = [0; 4];
+    socket.read_exact2(&mut prefix).await?;
+    if prefix.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+        debug!(
+            "remote sent plaintext {:?} instead of a proposition",
+            String::from_utf8_lossy(&prefix)
+        );
+        return Err(SecioError::ProtocolConfusion);
+    }
+
+    let len = u32::from_be_bytes(prefix) as usize;
+    if len > max_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Received data size over maximum frame length: {}>{}", len, max_size),
+        )
+        .into());
+    }
+    let mut buf = vec![0; len];
+    socket.read_exact2(&mut buf).await?;
+    Ok(buf)
+}
+
 #[cfg(test)]
 mod tests {
     use super::stretch_key;
-    use crate::{codec::Hmac, Config, Digest};
+    use crate::{codec::Hmac, crypto::cipher::CipherType, error::SecioError, Config, Digest};
+    use libp2prs_core::secure_io::HandshakeErrorKind;
+    use libp2prs_core::transport::TransportError;
+    use std::io;
 
     use bytes::BytesMut;
     use futures::{channel, AsyncReadExt, AsyncWriteExt};
@@ -320,6 +401,343 @@ mod tests {
         handshake_with_self_success(Config::new(key_1), Config::new(key_2), b"hello world")
     }
 
+    #[test]
+    fn close_write_keeps_reading() {
+        task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let listener_addr = listener.local_addr().unwrap();
+
+            let server = task::spawn(async move {
+                let (connect, _) = listener.accept().await.unwrap();
+                let (mut handle, _, _) = Config::new(Keypair::generate_ed25519()).handshake(connect).await.unwrap();
+                // the request ends when the remote closes writing
+                let mut request = vec![];
+                handle.read_to_end(&mut request).await.unwrap();
+                assert_eq!(request, b"request");
+                handle.write_all(b"response").await.unwrap();
+                handle.close().await.unwrap();
+            });
+
+            let connect = TcpStream::connect(&listener_addr).await.unwrap();
+            let (mut handle, _, _) = Config::new(Keypair::generate_ed25519()).handshake(connect).await.unwrap();
+            handle.write_all(b"request").await.unwrap();
+            handle.close_write().await.unwrap();
+
+            let mut response = vec![];
+            handle.read_to_end(&mut response).await.unwrap();
+            assert_eq!(response, b"response");
+
+            server.await.unwrap();
+        });
+    }
+
+    #[test]
+    fn http_request_is_protocol_confusion() {
+        task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let listener_addr = listener.local_addr().unwrap();
+
+            let client = task::spawn(async move {
+                let mut connect = TcpStream::connect(&listener_addr).await.unwrap();
+                connect.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+                connect
+            });
+
+            let (connect, _) = listener.accept().await.unwrap();
+            let result = Config::new(Keypair::generate_ed25519()).handshake(connect).await;
+            assert_eq!(result.err(), Some(SecioError::ProtocolConfusion));
+            client.await;
+        });
+    }
+
+    #[test]
+    fn handshake_session_id() {
+        task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let listener_addr = listener.local_addr().unwrap();
+
+            let server = task::spawn(async move {
+                let (connect, _) = listener.accept().await.unwrap();
+                let (handle, _, _) = Config::new(Keypair::generate_ed25519()).handshake(connect).await.unwrap();
+                handle.session_id()
+            });
+
+            let connect = TcpStream::connect(&listener_addr).await.unwrap();
+            let (handle, _, _) = Config::new(Keypair::generate_ed25519()).handshake(connect).await.unwrap();
+
+            assert_eq!(handle.session_id(), server.await.unwrap());
+            assert_ne!(handle.session_id(), [0u8; 32]);
+        });
+    }
+
+    #[test]
+    fn handshake_transcript_hook() {
+        use crate::handshake::HandshakeTranscript;
+        use crate::handshake_proto::Propose;
+        use prost::Message;
+        use std::sync::{Arc, Mutex};
+
+        fn capture(config: Config) -> (Config, Arc<Mutex<Option<HandshakeTranscript>>>) {
+            let transcript = Arc::new(Mutex::new(None));
+            let t = transcript.clone();
+            let config = config.on_handshake_transcript(move |tr| *t.lock().unwrap() = Some(tr.clone()));
+            (config, transcript)
+        }
+
+        fn hash(pubkey: &[u8], nonce: &[u8]) -> Vec<u8> {
+            let mut ctx = ring::digest::Context::new(&ring::digest::SHA256);
+            ctx.update(pubkey);
+            ctx.update(nonce);
+            ctx.finish().as_ref().to_vec()
+        }
+
+        let (srv_config, srv_transcript) = capture(Config::new(Keypair::generate_ed25519()));
+        let (cli_config, cli_transcript) = capture(Config::new(Keypair::generate_ed25519()));
+
+        task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let listener_addr = listener.local_addr().unwrap();
+
+            let server = task::spawn(async move {
+                let (connect, _) = listener.accept().await.unwrap();
+                srv_config.handshake(connect).await.unwrap();
+            });
+
+            let connect = TcpStream::connect(&listener_addr).await.unwrap();
+            cli_config.handshake(connect).await.unwrap();
+            server.await;
+        });
+
+        let srv = srv_transcript.lock().unwrap().take().unwrap();
+        let cli = cli_transcript.lock().unwrap().take().unwrap();
+
+        let local = Propose::decode(&cli.local_proposition[..]).unwrap();
+        let remote = Propose::decode(&cli.remote_proposition[..]).unwrap();
+        let ordering = hash(&remote.pubkey, &local.rand).cmp(&hash(&local.pubkey, &remote.rand));
+        assert_eq!(cli.ordering, ordering);
+        assert_eq!(srv.ordering, ordering.reverse());
+
+        assert_eq!(cli.local_proposition, srv.remote_proposition);
+        assert_eq!(cli.data_to_sign, srv.data_to_verify);
+        assert_eq!(cli.data_to_verify, srv.data_to_sign);
+    }
+
+    #[test]
+    fn upgrade_existing_stream() {
+        let srv_key = Keypair::generate_ed25519();
+        let cli_key = Keypair::generate_ed25519();
+        let srv_peer_id = srv_key.public().into_peer_id();
+        let cli_peer_id = cli_key.public().into_peer_id();
+
+        task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let listener_addr = listener.local_addr().unwrap();
+
+            let server = task::spawn(async move {
+                let (mut connect, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 8];
+                connect.read_exact(&mut buf).await.unwrap();
+                assert_eq!(&buf, b"STARTTLS");
+                connect.write_all(b"OK").await.unwrap();
+
+                let mut output = Config::new(srv_key).upgrade_existing(connect).await.unwrap();
+                let mut buf = [0u8; 6];
+                output.read_exact(&mut buf).await.unwrap();
+                output.write_all(&buf).await.unwrap();
+                output.flush().await.unwrap();
+                output.remote_peer_id
+            });
+
+            let mut connect = TcpStream::connect(&listener_addr).await.unwrap();
+            connect.write_all(b"STARTTLS").await.unwrap();
+            let mut buf = [0u8; 2];
+            connect.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"OK");
+
+            let mut output = Config::new(cli_key).upgrade_existing(connect).await.unwrap();
+            assert_eq!(output.remote_peer_id, srv_peer_id);
+            output.write_all(b"secret").await.unwrap();
+            output.flush().await.unwrap();
+            let mut buf = [0u8; 6];
+            output.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"secret");
+
+            assert_eq!(server.await.unwrap(), cli_peer_id);
+        });
+    }
+
+    #[test]
+    fn small_reads_reuse_read_buffer() {
+        task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let listener_addr = listener.local_addr().unwrap();
+
+            let server = task::spawn(async move {
+                let (connect, _) = listener.accept().await.unwrap();
+                let (mut handle, _, _) = Config::new(Keypair::generate_ed25519()).handshake(connect).await.unwrap();
+                for i in 0..1000usize {
+                    handle.write_all(&vec![i as u8; i % 64 + 1]).await.unwrap();
+                }
+                handle.close().await.unwrap();
+            });
+
+            let connect = TcpStream::connect(&listener_addr).await.unwrap();
+            let (mut handle, _, _) = Config::new(Keypair::generate_ed25519())
+                .read_buffer_capacity(256)
+                .handshake(connect)
+                .await
+                .unwrap();
+            let initial = handle.read_buffer_capacity();
+            assert!(initial >= 256);
+
+            for i in 0..1000usize {
+                let mut buf = vec![0u8; i % 64 + 1];
+                handle.read_exact(&mut buf).await.unwrap();
+                assert!(buf.iter().all(|b| *b == i as u8));
+                // the frames all fit, the buffer is never reallocated
+                assert_eq!(handle.read_buffer_capacity(), initial);
+            }
+            server.await;
+        });
+    }
+
+    #[test]
+    fn write_then_close_delivers_everything() {
+        task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let listener_addr = listener.local_addr().unwrap();
+
+            let server = task::spawn(async move {
+                let (connect, _) = listener.accept().await.unwrap();
+                let (mut handle, _, _) = Config::new(Keypair::generate_ed25519()).handshake(connect).await.unwrap();
+                let mut data = Vec::new();
+                handle.read_to_end(&mut data).await.unwrap();
+                data
+            });
+
+            let connect = TcpStream::connect(&listener_addr).await.unwrap();
+            let (mut handle, _, _) = Config::new(Keypair::generate_ed25519()).handshake(connect).await.unwrap();
+            for i in 0..100u8 {
+                handle.write_all(&[i; 1000]).await.unwrap();
+            }
+            handle.close().await.unwrap();
+
+            let data = server.await.unwrap();
+            assert_eq!(data.len(), 100 * 1000);
+            assert!(data.chunks(1000).enumerate().all(|(i, c)| c.iter().all(|b| *b == i as u8)));
+        });
+    }
+
+    #[test]
+    fn oversized_proposition_rejected() {
+        task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let listener_addr = listener.local_addr().unwrap();
+
+            let server = task::spawn(async move {
+                let (connect, _) = listener.accept().await.unwrap();
+                Config::new(Keypair::generate_ed25519())
+                    .max_handshake_size(1024)
+                    .handshake(connect)
+                    .await
+                    .map(|_| ())
+            });
+
+            // only the length prefix of a 1 MiB proposition is sent, the frame must be
+            // rejected without waiting for its body
+            let mut connect = TcpStream::connect(&listener_addr).await.unwrap();
+            connect.write_all(&(1024u32 * 1024).to_be_bytes()).await.unwrap();
+
+            match server.await.unwrap().unwrap_err() {
+                SecioError::IoError(e) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+                e => panic!("unexpected error {:?}", e),
+            }
+        });
+    }
+
+    #[test]
+    fn unsupported_cipher_classified() {
+        task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let listener_addr = listener.local_addr().unwrap();
+
+            let server = task::spawn(async move {
+                let (connect, _) = listener.accept().await.unwrap();
+                Config::new(Keypair::generate_ed25519())
+                    .ciphers(&[CipherType::Aes128Gcm])
+                    .handshake(connect)
+                    .await
+                    .map(|_| ())
+            });
+
+            let connect = TcpStream::connect(&listener_addr).await.unwrap();
+            let result = Config::new(Keypair::generate_ed25519())
+                .ciphers(&[CipherType::ChaCha20Poly1305])
+                .handshake(connect)
+                .await;
+
+            for e in vec![result.map(|_| ()).unwrap_err(), server.await.unwrap().unwrap_err()] {
+                assert_eq!(e, SecioError::NoSupportIntersection);
+                let e = TransportError::from(e);
+                assert_eq!(e.handshake_error_kind(), Some(HandshakeErrorKind::UnsupportedCipher));
+            }
+        });
+    }
+
+    #[test]
+    fn tampered_signature_classified() {
+        task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let listener_addr = listener.local_addr().unwrap();
+
+            let server = task::spawn(async move {
+                let (connect, _) = listener.accept().await.unwrap();
+                Config::new(Keypair::generate_ed25519()).handshake(connect).await.map(|_| ())
+            });
+
+            // relays the handshake of the client, flipping a bit of the signature which ends
+            // its exchange message
+            let relay_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let relay_addr = relay_listener.local_addr().unwrap();
+            let relay = task::spawn(async move {
+                let (client, _) = relay_listener.accept().await.unwrap();
+                let server = TcpStream::connect(&listener_addr).await.unwrap();
+                let (mut from_client, mut to_client) = client.split();
+                let (mut from_server, mut to_server) = server.split();
+                task::spawn(async move {
+                    let _ = futures::io::copy(&mut from_server, &mut to_client).await;
+                });
+                // the proposition, then the exchange
+                for i in 0..2 {
+                    let mut len = [0u8; 4];
+                    from_client.read_exact(&mut len).await.unwrap();
+                    let mut frame = vec![0u8; u32::from_be_bytes(len) as usize];
+                    from_client.read_exact(&mut frame).await.unwrap();
+                    if i == 1 {
+                        *frame.last_mut().unwrap() ^= 1;
+                    }
+                    to_server.write_all(&len).await.unwrap();
+                    to_server.write_all(&frame).await.unwrap();
+                }
+                (from_client, to_server)
+            });
+
+            let client = task::spawn(async move {
+                let connect = TcpStream::connect(&relay_addr).await.unwrap();
+                let _ = Config::new(Keypair::generate_ed25519()).handshake(connect).await;
+            });
+
+            let e = server.await.unwrap().unwrap_err();
+            assert_eq!(e, SecioError::SignatureVerificationFailed);
+            let e = TransportError::from(e);
+            assert_eq!(e.handshake_error_kind(), Some(HandshakeErrorKind::SignatureVerificationFailed));
+
+            // the client fails once the server is gone
+            drop(relay.await);
+            client.await;
+        });
+    }
+
     #[test]
     fn stretch() {
         let mut output = [0u8; 32];
@@ -25,19 +25,21 @@
 
 use async_trait::async_trait;
 
-use crate::{crypto::cipher::CipherType, error::SecioError, exchange::KeyAgreement, handshake::procedure::handshake};
+use crate::{crypto::cipher::CipherType, error::SecioError, handshake::procedure::handshake};
 
 use libp2prs_core::identity::Keypair;
 use libp2prs_core::{Multiaddr, PeerId, PublicKey};
 
 use crate::codec::secure_stream::SecureStream;
+use crate::handshake::HandshakeTranscript;
 use futures::{AsyncRead, AsyncWrite};
-use libp2prs_core::secure_io::SecureInfo;
+use libp2prs_core::secure_io::{HandshakeError, HandshakeErrorKind, SecureInfo};
 use libp2prs_core::transport::{ConnectionInfo, TransportError};
 use libp2prs_core::upgrade::{UpgradeInfo, Upgrader};
 use std::{
     io,
     pin::Pin,
+    sync::Arc,
     task::{Context, Poll},
 };
 
@@ -53,6 +55,8 @@ mod exchange;
 pub mod handshake;
 /// Supported algorithms
 mod support;
+pub use exchange::KeyAgreement;
+pub use support::{supported_agreements, supported_ciphers, supported_digests};
 
 mod handshake_proto {
     include!(concat!(env!("OUT_DIR"), "/handshake_proto.rs"));
@@ -83,6 +87,7 @@ impl Digest {
 //////////////////////////////////////////////////////////////////////////////////
 
 const MAX_FRAME_SIZE: usize = 1024 * 1024 * 8;
+const MAX_HANDSHAKE_SIZE: usize = 64 * 1024;
 
 /// Config for Secio
 #[derive(Clone)]
@@ -92,6 +97,9 @@ pub struct Config {
     pub(crate) ciphers_proposal: Option<String>,
     pub(crate) digests_proposal: Option<String>,
     pub(crate) max_frame_length: usize,
+    pub(crate) max_handshake_size: usize,
+    pub(crate) read_buffer_capacity: usize,
+    pub(crate) transcript_hook: Option<Arc<dyn Fn(&HandshakeTranscript) + Send + Sync>>,
 }
 
 impl Config {
@@ -103,6 +111,9 @@ impl Config {
             ciphers_proposal: None,
             digests_proposal: None,
             max_frame_length: MAX_FRAME_SIZE,
+            max_handshake_size: MAX_HANDSHAKE_SIZE,
+            read_buffer_capacity: 0,
+            transcript_hook: None,
         }
     }
 
@@ -112,6 +123,21 @@ impl Config {
         self
     }
 
+    /// Max size of the proposition and exchange messages received during the handshake,
+    /// 64 KiB by default.
+    pub fn max_handshake_size(mut self, size: usize) -> Self {
+        self.max_handshake_size = size;
+        self
+    }
+
+    /// Initial capacity of the buffer the encrypted frames are read into. The buffer grows
+    /// up to the largest frame received and is reused for all frames. It is allocated on
+    /// the first read by default.
+    pub fn read_buffer_capacity(mut self, capacity: usize) -> Self {
+        self.read_buffer_capacity = capacity;
+        self
+    }
+
     /// Override the default set of supported key agreement algorithms.
     pub fn key_agreements<'a, I>(mut self, xs: I) -> Self
     where
@@ -139,6 +165,16 @@ impl Config {
         self
     }
 
+    /// Set a debug hook that is called with the transcript of every handshake, once the
+    /// exchanges have been received and before the remote's signature is verified.
+    pub fn on_handshake_transcript<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&HandshakeTranscript) + Send + Sync + 'static,
+    {
+        self.transcript_hook = Some(Arc::new(f));
+        self
+    }
+
     /// Attempts to perform a handshake on the given socket.
     ///
     /// On success, produces a `SecureStream` that can then be used to encode/decode
@@ -149,6 +185,32 @@ impl Config {
     {
         handshake(socket, self).await
     }
+
+    /// Upgrades a stream already in use to secio, for protocols which start in the clear and
+    /// negotiate encryption later on.
+    ///
+    /// Both sides must call this at the same point of the stream, with nothing left unread
+    /// from the cleartext exchange. The addresses reported by the output are empty, as a
+    /// stream doesn't know about them.
+    pub async fn upgrade_existing<T>(self, socket: T) -> Result<SecioOutput<T>, SecioError>
+    where
+        T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        // TODO: to be more elegant, local private key could be returned by handshake()
+        let pri_key = self.key.clone();
+
+        let (stream, remote_pub_key, _ephemeral_public_key) = self.handshake(socket).await?;
+        let output = SecioOutput {
+            stream,
+            la: Multiaddr::empty(),
+            ra: Multiaddr::empty(),
+            local_priv_key: pri_key.clone(),
+            local_peer_id: pri_key.public().into(),
+            remote_pub_key: remote_pub_key.clone(),
+            remote_peer_id: remote_pub_key.into(),
+        };
+        Ok(output)
+    }
 }
 
 impl UpgradeInfo for Config {
@@ -163,21 +225,12 @@ async fn make_secure_output<T>(config: Config, socket: T) -> Result<SecioOutput<
 where
     T: ConnectionInfo + AsyncRead + AsyncWrite + Send + Unpin + 'static,
 {
-    // TODO: to be more elegant, local private key could be returned by handshake()
-    let pri_key = config.key.clone();
     let la = socket.local_multiaddr();
     let ra = socket.remote_multiaddr();
 
-    let (stream, remote_pub_key, _ephemeral_public_key) = config.handshake(socket).await?;
-    let output = SecioOutput {
-        stream,
-        la,
-        ra,
-        local_priv_key: pri_key.clone(),
-        local_peer_id: pri_key.public().into(),
-        remote_pub_key: remote_pub_key.clone(),
-        remote_peer_id: remote_pub_key.into(),
-    };
+    let mut output = config.upgrade_existing(socket).await?;
+    output.la = la;
+    output.ra = ra;
     Ok(output)
 }
 
@@ -243,6 +296,18 @@ impl<S> SecureInfo for SecioOutput<S> {
     fn remote_pub_key(&self) -> PublicKey {
         self.remote_pub_key.clone()
     }
+
+    fn session_id(&self) -> Option<[u8; 32]> {
+        Some(self.stream.session_id())
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Send + Unpin + 'static> SecioOutput<S> {
+    /// Finishes writing, while the stream can still be read from. See
+    /// [`SecureStream::close_write`].
+    pub async fn close_write(&mut self) -> io::Result<()> {
+        self.stream.close_write().await
+    }
 }
 
 impl<S: AsyncRead + AsyncWrite + Send + Unpin + 'static> AsyncRead for SecioOutput<S> {
@@ -271,7 +336,11 @@ impl<S: AsyncRead + AsyncWrite + Send + Unpin + 'static> AsyncWrite for SecioOut
 
 impl From<SecioError> for TransportError {
     fn from(e: SecioError) -> Self {
-        // TODO: make a security error catalog for secio
-        TransportError::SecurityError(Box::new(e))
+        let kind = match e {
+            SecioError::SignatureVerificationFailed => HandshakeErrorKind::SignatureVerificationFailed,
+            SecioError::NoSupportIntersection => HandshakeErrorKind::UnsupportedCipher,
+            _ => HandshakeErrorKind::Other,
+        };
+        TransportError::SecurityError(Box::new(HandshakeError::new(kind, e)))
     }
 }
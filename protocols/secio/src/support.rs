@@ -51,6 +51,28 @@ pub(crate) const DEFAULT_AGREEMENTS_PROPOSITION: &str = "P-256,P-384";
 pub(crate) const DEFAULT_CIPHERS_PROPOSITION: &str = "AES-128,AES-128-CTR,AES-128-GCM,AES-256-GCM,CHACHA20_POLY1305";
 pub(crate) const DEFAULT_DIGESTS_PROPOSITION: &str = "SHA256,SHA512";
 
+/// Returns the key agreement algorithms supported by this build, in the default order of preference.
+pub fn supported_agreements() -> Vec<KeyAgreement> {
+    vec![KeyAgreement::EcdhP256, KeyAgreement::EcdhP384]
+}
+
+/// Returns the ciphers supported by this build, in the default order of preference.
+///
+/// AES-128-CTR is implemented in pure Rust, so it is available on every platform.
+pub fn supported_ciphers() -> Vec<CipherType> {
+    vec![
+        CipherType::Aes128Ctr,
+        CipherType::Aes128Gcm,
+        CipherType::Aes256Gcm,
+        CipherType::ChaCha20Poly1305,
+    ]
+}
+
+/// Returns the digest algorithms supported by this build, in the default order of preference.
+pub fn supported_digests() -> Vec<Digest> {
+    vec![Digest::Sha256, Digest::Sha512]
+}
+
 /// Return a proposition string from the given sequence of `KeyAgreement` values.
 pub fn key_agreements_proposition<'a, I>(exchanges: I) -> String
 where
@@ -189,3 +211,32 @@ pub fn select_cipher(r: Ordering, ours: &str, theirs: &str) -> Result<CipherType
     }
     Err(SecioError::NoSupportIntersection)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn supported_algorithms_are_negotiable() {
+        let agreements = supported_agreements();
+        assert!(!agreements.is_empty());
+        for a in agreements {
+            let ours = key_agreements_proposition(&[a]);
+            assert_eq!(select_agreement(Ordering::Equal, &ours, DEFAULT_AGREEMENTS_PROPOSITION).unwrap(), a);
+        }
+
+        let ciphers = supported_ciphers();
+        assert!(!ciphers.is_empty());
+        for c in ciphers {
+            let ours = ciphers_proposition(&[c]);
+            assert_eq!(select_cipher(Ordering::Equal, &ours, DEFAULT_CIPHERS_PROPOSITION).unwrap(), c);
+        }
+
+        let digests = supported_digests();
+        assert!(!digests.is_empty());
+        for d in digests {
+            let ours = digests_proposition(&[d]);
+            assert_eq!(select_digest(Ordering::Equal, &ours, DEFAULT_DIGESTS_PROPOSITION).unwrap(), d);
+        }
+    }
+}
@@ -22,35 +22,434 @@
 //! Implements the Yamux multiplexing protocol for libp2p, see also the
 //! [specification](https://github.com/hashicorp/yamux/blob/master/spec.md).
 
+mod stats;
+
 use futures::channel::mpsc;
 use futures::future::BoxFuture;
+use futures::task::AtomicWaker;
 use futures::{prelude::*, stream::StreamExt, FutureExt, SinkExt};
+use futures_timer::Delay;
 use libp2prs_core::identity::{Keypair, PublicKey};
-use libp2prs_core::muxing::{IReadWrite, IStreamMuxer, ReadWriteEx, StreamInfo, StreamMuxer, StreamMuxerEx};
+use libp2prs_core::muxing::{FlowState, IReadWrite, IStreamMuxer, ReadWriteEx, StreamInfo, StreamMuxer, StreamMuxerEx};
 use libp2prs_core::secure_io::SecureInfo;
 use libp2prs_core::transport::{ConnectionInfo, TransportError};
 use libp2prs_core::upgrade::{UpgradeInfo, Upgrader};
 use libp2prs_core::{Multiaddr, PeerId};
 use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use std::{
     fmt, io,
     pin::Pin,
     task::{Context, Poll},
 };
 
-type YRet = Result<yamux::Stream, yamux::ConnectionError>;
+use stats::Counters;
+pub use stats::YamuxStats;
+
+/// The error of a yamux connection.
+///
+/// Errors returned by the `StreamMuxer` methods of `Yamux` wrap it in
+/// `TransportError::StreamMuxerError`. In particular, opening or accepting a
+/// substream on a connection which is gone fails with `ConnectionError::Closed`.
+///
+/// Frames are validated when their header is decoded, a frame carrying any version other
+/// than 0 fails the connection with `ConnectionError::Decode(FrameDecodeError::Header(
+/// HeaderDecodeError::Version(v)))`, instead of being misparsed.
+pub use yamux::ConnectionError;
+/// The errors of decoding yamux frames, carried by `ConnectionError::Decode`.
+pub use yamux::{FrameDecodeError, HeaderDecodeError};
+
+/// The error of both endpoints running in the same `Mode`.
+///
+/// A client opens the streams with odd IDs and a server with even ones, so the
+/// IDs would clash if both ends were clients or servers. The connection fails
+/// with `ConnectionError::Io` carrying this error as soon as the remote opens a
+/// stream with an ID of our parity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModeMismatch {
+    /// The ID of the stream opened by the remote.
+    pub stream_id: u32,
+}
+
+impl fmt::Display for ModeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "both ends in the same mode, remote opened stream {}", self.stream_id)
+    }
+}
+
+impl std::error::Error for ModeMismatch {}
+
+/// The size of a yamux frame header.
+const HEADER_SIZE: usize = 12;
+/// The yamux frame type carrying a body.
+const TYPE_DATA: u8 = 0;
+/// The yamux frame type granting more credit.
+const TYPE_WINDOW_UPDATE: u8 = 1;
+/// The yamux frame type terminating the connection.
+const TYPE_GO_AWAY: u8 = 3;
+/// The error code of a GoAway frame for a protocol error.
+const GO_AWAY_PROTOCOL_ERROR: u32 = 1;
+/// The yamux flag opening a new stream.
+const FLAG_SYN: u16 = 1;
+/// The yamux flag acknowledging a new stream.
+const FLAG_ACK: u16 = 2;
+/// The yamux flag closing the write side of a stream.
+const FLAG_FIN: u16 = 4;
+/// The yamux flag resetting a stream.
+const FLAG_RST: u16 = 8;
+/// The credit every stream starts with, as per the yamux spec.
+const DEFAULT_CREDIT: usize = 256 * 1024;
+/// A pong of the connection, which yamux takes without doing anything.
+const PONG: [u8; HEADER_SIZE] = [0, 2, 0, FLAG_ACK as u8, 0, 0, 0, 0, 0, 0, 0, 0];
+
+/// The flow control counters of a stream, shared by the stream and the connection socket,
+/// along with the FIN and RST flags seen from the remote.
+#[derive(Debug)]
+struct Flow {
+    /// The credit granted by the remote, including the initial one.
+    granted: AtomicUsize,
+    /// The bytes written to the stream.
+    sent: AtomicUsize,
+    /// The bytes of the data frames received.
+    received: AtomicUsize,
+    /// The bytes read from the stream.
+    read: AtomicUsize,
+    /// The remote has sent FIN, even if the data before it is not read yet.
+    remote_fin: AtomicBool,
+    /// The remote has reset the stream.
+    remote_rst: AtomicBool,
+}
+
+impl Default for Flow {
+    fn default() -> Self {
+        Flow {
+            granted: AtomicUsize::new(DEFAULT_CREDIT),
+            sent: AtomicUsize::new(0),
+            received: AtomicUsize::new(0),
+            read: AtomicUsize::new(0),
+            remote_fin: AtomicBool::new(false),
+            remote_rst: AtomicBool::new(false),
+        }
+    }
+}
+
+impl Flow {
+    fn state(&self) -> FlowState {
+        let granted = self.granted.load(Ordering::Relaxed);
+        let received = self.received.load(Ordering::Relaxed);
+        FlowState {
+            send_credit: granted.saturating_sub(self.sent.load(Ordering::Relaxed)),
+            recv_buffered: received.saturating_sub(self.read.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// The flow control counters of the streams of a connection, by stream ID.
+type Flows = Arc<Mutex<HashMap<u32, Arc<Flow>>>>;
+
+/// `Reaper` wakes the connection up when a stream is reset or dropped locally.
+///
+/// yamux finishes the streams gone this way, sending RST for the open ones, only when its
+/// connection has something else to do. `FrameCheck` gives it a pong to process then.
+#[derive(Debug, Default)]
+struct Reaper {
+    /// A stream is gone since the connection was last woken up.
+    pending: AtomicBool,
+    /// The waker of the connection reading the socket.
+    waker: AtomicWaker,
+}
+
+impl Reaper {
+    fn notify(&self) {
+        self.pending.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+}
+
+/// A chunk of the frames passing through the connection socket.
+enum Chunk {
+    /// A complete frame header.
+    Header([u8; HEADER_SIZE]),
+    /// The given number of body bytes.
+    Body(usize),
+}
+
+/// `Frames` splits the bytes passing through the connection socket into frame headers and
+/// bodies, the header being read is kept until it is complete.
+struct Frames {
+    /// The header being read.
+    header: [u8; HEADER_SIZE],
+    /// The number of bytes of the header read so far.
+    filled: usize,
+    /// The number of body bytes to pass before the next header.
+    skip: usize,
+}
+
+impl Frames {
+    fn new() -> Self {
+        Frames {
+            header: [0; HEADER_SIZE],
+            filled: 0,
+            skip: 0,
+        }
+    }
+
+    /// Takes the next chunk off `buf`, `None` if all of it is taken.
+    ///
+    /// The body of a data frame is expected to be given by `skip` once its header is taken.
+    fn next(&mut self, buf: &mut &[u8]) -> Option<Chunk> {
+        while !buf.is_empty() {
+            if self.skip > 0 {
+                let n = std::cmp::min(self.skip, buf.len());
+                self.skip -= n;
+                *buf = &buf[n..];
+                return Some(Chunk::Body(n));
+            }
+
+            let n = std::cmp::min(HEADER_SIZE - self.filled, buf.len());
+            self.header[self.filled..self.filled + n].copy_from_slice(&buf[..n]);
+            self.filled += n;
+            *buf = &buf[n..];
+            if self.filled == HEADER_SIZE {
+                self.filled = 0;
+                return Some(Chunk::Header(self.header));
+            }
+        }
+        None
+    }
+}
+
+/// The frame type, flags, stream ID and length of a frame header.
+fn parse_header(h: &[u8; HEADER_SIZE]) -> (u8, u16, u32, u32) {
+    let flags = u16::from_be_bytes([h[2], h[3]]);
+    let stream_id = u32::from_be_bytes([h[4], h[5], h[6], h[7]]);
+    let len = u32::from_be_bytes([h[8], h[9], h[10], h[11]]);
+    (h[1], flags, stream_id, len)
+}
+
+/// `FrameCheck` wraps the connection socket, following the headers of the
+/// inbound frames to detect the streams opened by the remote with our parity,
+/// and the data frames larger than allowed. It also accounts the data received
+/// and the credit granted for each stream, and notes the FIN and RST flags of the remote.
+///
+/// The headers of both directions are counted in the `Counters` of the connection.
+///
+/// When the `Reaper` is notified, a pong is read in between two inbound frames, waking the
+/// connection up to finish the streams gone. yamux flushes the socket at the end of each round,
+/// but sends the RST of those streams at the start of the next one, so the socket is flushed
+/// here before waiting for more frames, or the RST would stay buffered until something else happens.
+struct FrameCheck<T> {
+    inner: T,
+    /// The parity of the stream IDs we use, 1 for a client, 0 for a server.
+    parity: u32,
+    /// The maximum body size of a data frame.
+    max_message_size: Option<usize>,
+    /// The inbound frames.
+    received: Frames,
+    /// The outbound frames.
+    sent: Frames,
+    /// The flow control counters of the streams.
+    flows: Flows,
+    /// The flow control counters of the stream the body being passed belongs to.
+    body_flow: Option<Arc<Flow>>,
+    /// The counters of the frames dropping or failing something.
+    counters: Arc<Counters>,
+    /// The notice of the streams reset or dropped locally.
+    reaper: Arc<Reaper>,
+    /// The bytes of the pong read so far, if one is being read.
+    pong: Option<usize>,
+    /// Some bytes have been written since the last flush.
+    unflushed: bool,
+}
+
+impl<T> FrameCheck<T> {
+    fn new(
+        inner: T,
+        mode: yamux::Mode,
+        max_message_size: Option<usize>,
+        flows: Flows,
+        counters: Arc<Counters>,
+        reaper: Arc<Reaper>,
+    ) -> Self {
+        let parity = match mode {
+            yamux::Mode::Client => 1,
+            yamux::Mode::Server => 0,
+        };
+        FrameCheck {
+            inner,
+            parity,
+            max_message_size,
+            received: Frames::new(),
+            sent: Frames::new(),
+            flows,
+            body_flow: None,
+            counters,
+            reaper,
+            pong: None,
+            unflushed: false,
+        }
+    }
+
+    /// Returns the flow control counters of the stream. The ones of the streams just
+    /// opened or acknowledged are created here, if their `Stream` is not there yet.
+    fn flow_of(&self, stream_id: u32, flags: u16) -> Option<Arc<Flow>> {
+        if stream_id == 0 {
+            return None;
+        }
+        let mut flows = self.flows.lock();
+        if flags & (FLAG_SYN | FLAG_ACK) != 0 {
+            Some(flows.entry(stream_id).or_default().clone())
+        } else {
+            flows.get(&stream_id).cloned()
+        }
+    }
+
+    /// Follows the frames in the bytes just read.
+    fn inspect(&mut self, mut buf: &[u8]) -> io::Result<()> {
+        while let Some(chunk) = self.received.next(&mut buf) {
+            let h = match chunk {
+                Chunk::Body(n) => {
+                    if let Some(flow) = self.body_flow.as_ref() {
+                        flow.received.fetch_add(n, Ordering::Relaxed);
+                    }
+                    continue;
+                }
+                Chunk::Header(h) => h,
+            };
+
+            let (ty, flags, stream_id, len) = parse_header(&h);
+            if flags & FLAG_SYN != 0 && stream_id != 0 && stream_id % 2 == self.parity {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, ModeMismatch { stream_id }));
+            }
+            if flags & FLAG_RST != 0 {
+                Counters::incr(&self.counters.resets_received);
+            }
+            if ty == TYPE_GO_AWAY && len == GO_AWAY_PROTOCOL_ERROR {
+                Counters::incr(&self.counters.protocol_errors);
+            }
+            let flow = self.flow_of(stream_id, flags);
+            match flow.as_ref() {
+                Some(flow) => {
+                    if flags & FLAG_FIN != 0 {
+                        flow.remote_fin.store(true, Ordering::Relaxed);
+                    }
+                    if flags & FLAG_RST != 0 {
+                        flow.remote_rst.store(true, Ordering::Relaxed);
+                    }
+                }
+                // the stream is gone, so the frame goes nowhere
+                None if stream_id != 0 && (ty == TYPE_DATA || ty == TYPE_WINDOW_UPDATE) => {
+                    Counters::incr(&self.counters.frames_dropped);
+                }
+                None => {}
+            }
+            if ty == TYPE_DATA {
+                let len = len as usize;
+                if self.max_message_size.map_or(false, |max| len > max) {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, FrameDecodeError::FrameTooLarge(len)));
+                }
+                self.received.skip = len;
+                self.body_flow = flow;
+            } else if ty == TYPE_WINDOW_UPDATE {
+                if let Some(flow) = flow {
+                    flow.granted.fetch_add(len as usize, Ordering::Relaxed);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Follows the frames in the bytes just written.
+    fn inspect_sent(&mut self, mut buf: &[u8]) {
+        while let Some(chunk) = self.sent.next(&mut buf) {
+            if let Chunk::Header(h) = chunk {
+                let (ty, flags, _, len) = parse_header(&h);
+                if flags & FLAG_RST != 0 {
+                    Counters::incr(&self.counters.resets_sent);
+                }
+                if ty == TYPE_DATA {
+                    self.sent.skip = len as usize;
+                } else if ty == TYPE_GO_AWAY && len == GO_AWAY_PROTOCOL_ERROR {
+                    Counters::incr(&self.counters.protocol_errors);
+                }
+            }
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> AsyncRead for FrameCheck<T> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        if self.unflushed {
+            if let Poll::Ready(r) = Pin::new(&mut self.inner).poll_flush(cx) {
+                r?;
+                self.unflushed = false;
+            }
+        }
+        self.reaper.waker.register(cx.waker());
+        // the pong must not split an inbound frame
+        if self.pong.is_none()
+            && self.received.filled == 0
+            && self.received.skip == 0
+            && self.reaper.pending.swap(false, Ordering::Acquire)
+        {
+            self.pong = Some(0);
+        }
+        if let Some(offset) = self.pong {
+            let n = std::cmp::min(HEADER_SIZE - offset, buf.len());
+            buf[..n].copy_from_slice(&PONG[offset..offset + n]);
+            self.pong = Some(offset + n).filter(|&offset| offset < HEADER_SIZE);
+            return Poll::Ready(Ok(n));
+        }
+
+        let n = futures::ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        if let Err(e) = self.inspect(&buf[..n]) {
+            Counters::incr(&self.counters.protocol_errors);
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for FrameCheck<T> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let n = futures::ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        self.inspect_sent(&buf[..n]);
+        self.unflushed |= n > 0;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        futures::ready!(Pin::new(&mut self.inner).poll_flush(cx))?;
+        self.unflushed = false;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+type YRet = Result<yamux::Stream, ConnectionError>;
 
 /// A Yamux connection.
 // #[derive(Clone)]
 #[allow(clippy::type_complexity)]
 pub struct Yamux<T> {
     ///
-    incoming: Arc<Mutex<Option<(yamux::Connection<T>, mpsc::UnboundedSender<YRet>)>>>,
+    incoming: Arc<Mutex<Option<(yamux::Connection<FrameCheck<T>>, mpsc::UnboundedSender<YRet>)>>>,
     ///
     accepted: Arc<futures::lock::Mutex<mpsc::UnboundedReceiver<YRet>>>,
     /// Handle to control the connection.
     control: yamux::Control,
+    /// The flow control counters of the streams.
+    flows: Flows,
+    /// The counters of the frames dropping or failing something.
+    counters: Arc<Counters>,
+    /// The notice of the streams reset or dropped locally.
+    reaper: Arc<Reaper>,
     /// The local multiaddr of this connection
     pub la: Multiaddr,
     /// The remote multiaddr of this connection
@@ -63,6 +462,8 @@ pub struct Yamux<T> {
     pub remote_pub_key: PublicKey,
     /// For convenience, put a PeerId here, which is actually calculated from remote_key
     pub remote_peer_id: PeerId,
+    /// The id of the underlying secure session, if any
+    pub session_id: Option<[u8; 32]>,
 }
 
 impl<T> Clone for Yamux<T> {
@@ -71,12 +472,16 @@ impl<T> Clone for Yamux<T> {
             incoming: self.incoming.clone(),
             accepted: self.accepted.clone(),
             control: self.control.clone(),
+            flows: self.flows.clone(),
+            counters: self.counters.clone(),
+            reaper: self.reaper.clone(),
             la: self.la.clone(),
             ra: self.ra.clone(),
             local_priv_key: self.local_priv_key.clone(),
             local_peer_id: self.local_peer_id,
             remote_pub_key: self.remote_pub_key.clone(),
             remote_peer_id: self.remote_peer_id,
+            session_id: self.session_id,
         }
     }
 }
@@ -92,17 +497,22 @@ where
     T: ConnectionInfo + SecureInfo + AsyncRead + AsyncWrite + Send + Unpin + 'static,
 {
     /// Create a new Yamux connection.
-    fn new(io: T, mut cfg: yamux::Config, mode: yamux::Mode) -> Self {
+    fn new(io: T, mut cfg: yamux::Config, mode: yamux::Mode, max_message_size: Option<usize>) -> Self {
         cfg.set_read_after_close(false);
 
         let local_priv_key = io.local_priv_key();
         let remote_pub_key = io.remote_pub_key();
         let local_peer_id = io.local_peer();
         let remote_peer_id = io.remote_peer();
+        let session_id = io.session_id();
         let la = io.local_multiaddr();
         let ra = io.remote_multiaddr();
 
-        let conn = yamux::Connection::new(io, cfg, mode);
+        let flows = Flows::default();
+        let counters = Arc::new(Counters::default());
+        let reaper = Arc::new(Reaper::default());
+        let socket = FrameCheck::new(io, mode, max_message_size, flows.clone(), counters.clone(), reaper.clone());
+        let conn = yamux::Connection::new(socket, cfg, mode);
         let (sender, accepted) = mpsc::unbounded();
 
         let ctrl = conn.control();
@@ -110,16 +520,27 @@ where
             incoming: Arc::new(Mutex::new(Some((conn, sender)))),
             accepted: Arc::new(futures::lock::Mutex::new(accepted)),
             control: ctrl,
+            flows,
+            counters,
+            reaper,
             la,
             ra,
             local_priv_key,
             local_peer_id,
             remote_pub_key,
             remote_peer_id,
+            session_id,
         }
     }
 }
 
+impl<T> Yamux<T> {
+    /// Returns a snapshot of the resets, dropped frames and protocol errors of this connection so far.
+    pub fn stats(&self) -> YamuxStats {
+        self.counters.snapshot()
+    }
+}
+
 impl<T> SecureInfo for Yamux<T> {
     fn local_peer(&self) -> PeerId {
         self.local_peer_id
@@ -136,6 +557,10 @@ impl<T> SecureInfo for Yamux<T> {
     fn remote_pub_key(&self) -> PublicKey {
         self.remote_pub_key.clone()
     }
+
+    fn session_id(&self) -> Option<[u8; 32]> {
+        self.session_id
+    }
 }
 
 impl<T: Send> ConnectionInfo for Yamux<T> {
@@ -152,18 +577,24 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> StreamMuxerEx for Yamux
 #[async_trait::async_trait]
 impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> StreamMuxer for Yamux<T> {
     async fn open_stream(&mut self) -> Result<IReadWrite, TransportError> {
+        // the control channel is closed if the connection is gone, yamux maps it to `ConnectionError::Closed`
         let s = self.control.open_stream().await.map_err(map_yamux_err)?;
         log::trace!("a new outbound substream {:?} opened for yamux... ", s);
-        Ok(Box::new(Stream(s)))
+        Ok(Box::new(Stream::new(s, &self.flows, &self.reaper)))
     }
 
     async fn accept_stream(&mut self) -> Result<IReadWrite, TransportError> {
+        // yamux tells a GoAway, EOF or reset of the socket apart from a failure of the connection,
+        // reporting the former as `ConnectionError::Closed`
         if let Some(s) = self.accepted.lock().await.next().await {
-            let stream = s.map_err(map_yamux_err)?;
+            let stream = s.map_err(|e| match e {
+                ConnectionError::Closed => TransportError::ConnectionClosed,
+                e => map_yamux_err(e),
+            })?;
             log::trace!("a new inbound substream {:?} accepted for yamux...", stream);
-            return Ok(Box::new(Stream(stream)));
+            return Ok(Box::new(Stream::new(stream, &self.flows, &self.reaper)));
         }
-        Err(TransportError::StreamMuxerError(Box::new(yamux::ConnectionError::Closed)))
+        Err(TransportError::ConnectionClosed)
     }
 
     async fn close(&mut self) -> Result<(), TransportError> {
@@ -171,6 +602,7 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> StreamMuxer for Yamux<T
     }
 
     fn task(&mut self) -> Option<BoxFuture<'static, ()>> {
+        let counters = self.counters.clone();
         self.incoming.lock().take().map(|(mut conn, mut sender)| {
             async move {
                 loop {
@@ -185,7 +617,7 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> StreamMuxer for Yamux<T
                         }
                         Ok(None) => {
                             log::info!("{:?} background-task exiting...", conn);
-                            if let Err(e) = sender.send(Err(yamux::ConnectionError::Closed)).await {
+                            if let Err(e) = sender.send(Err(ConnectionError::Closed)).await {
                                 log::warn!("{:?}", e);
                             }
                             break;
@@ -198,6 +630,7 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> StreamMuxer for Yamux<T
                         }
                     }
                 }
+                log::debug!("{:?} closed, {:?}", conn, counters.snapshot());
             }
             .boxed()
         })
@@ -208,51 +641,256 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> StreamMuxer for Yamux<T
     }
 }
 
-#[pin_project::pin_project]
+/// The yamux substream.
+///
+/// It caches the latest known state of the substream, which can be queried via
+/// `is_read_closed` and `is_write_closed` without touching the stream itself.
+/// The state is updated as the I/O operations go on this substream.
+///
+/// Reads and writes can be given a deadline with `set_read_timeout` and
+/// `set_write_timeout`, they fail with `io::ErrorKind::TimedOut` when it expires.
+///
+/// The credit left for sending and the data buffered for reading are reported by `flow_state`.
+///
+/// A stream reset by the remote fails to read with `io::ErrorKind::ConnectionReset`, instead
+/// of reaching EOF.
+#[pin_project::pin_project(PinnedDrop)]
 #[derive(Debug)]
-pub struct Stream(#[pin] yamux::Stream);
+pub struct Stream {
+    /// The yamux stream, `None` once it is reset.
+    inner: Option<yamux::Stream>,
+    /// The ID of the stream.
+    id: u32,
+    /// Indicates if the remote has closed its write side, or the stream is broken.
+    read_closed: AtomicBool,
+    /// Indicates if the local side has closed its write side, or the stream is broken.
+    write_closed: AtomicBool,
+    /// The maximum time a read can wait for data.
+    read_timeout: Option<Duration>,
+    /// The maximum time a write can wait for credit.
+    write_timeout: Option<Duration>,
+    /// The timer of the pending read, if any.
+    read_delay: Option<Delay>,
+    /// The timer of the pending write, if any.
+    write_delay: Option<Delay>,
+    /// The flow control counters of the stream.
+    flow: Arc<Flow>,
+    /// The flow control counters of all streams of the connection.
+    flows: Flows,
+    /// The notice of the streams reset or dropped locally.
+    reaper: Arc<Reaper>,
+}
+
+/// Dropping a stream resets it, unless it has been closed before. The connection is woken up
+/// to finish the dropped stream, sending RST if it is still open, so that a pending read of the
+/// remote fails with `io::ErrorKind::ConnectionReset` instead of waiting forever or reaching EOF.
+#[pin_project::pinned_drop]
+impl PinnedDrop for Stream {
+    fn drop(self: Pin<&mut Self>) {
+        self.get_mut().reset();
+    }
+}
+
+impl Stream {
+    fn new(inner: yamux::Stream, flows: &Flows, reaper: &Arc<Reaper>) -> Self {
+        let id = inner.id().val();
+        let flow = flows.lock().entry(id).or_default().clone();
+        Stream {
+            inner: Some(inner),
+            id,
+            flow,
+            flows: flows.clone(),
+            reaper: reaper.clone(),
+            read_closed: AtomicBool::new(false),
+            write_closed: AtomicBool::new(false),
+            read_timeout: None,
+            write_timeout: None,
+            read_delay: None,
+            write_delay: None,
+        }
+    }
+
+    /// Sets the timeout of reading from the stream, `None` means to wait forever.
+    ///
+    /// The timeout applies to every read waiting for data, which then fails with
+    /// `io::ErrorKind::TimedOut`.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+        self.read_delay = None;
+    }
+
+    /// Sets the timeout of writing to the stream, `None` means to wait forever.
+    ///
+    /// The timeout applies to every write waiting for credit from the remote, which then
+    /// fails with `io::ErrorKind::TimedOut`.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        self.write_timeout = timeout;
+        self.write_delay = None;
+    }
+
+    /// Returns true if the remote has sent FIN or reset the stream, or the stream is broken.
+    ///
+    /// The FIN is seen as soon as the connection receives it, even if some data sent
+    /// before it is still buffered for reading.
+    pub fn is_read_closed(&self) -> bool {
+        self.read_closed.load(Ordering::Relaxed)
+            || self.flow.remote_fin.load(Ordering::Relaxed)
+            || self.flow.remote_rst.load(Ordering::Relaxed)
+    }
+
+    /// Returns true if nothing more could be written to the stream, i.e. the
+    /// stream has been closed locally, reset by the remote or it is broken.
+    pub fn is_write_closed(&self) -> bool {
+        self.write_closed.load(Ordering::Relaxed) || self.flow.remote_rst.load(Ordering::Relaxed)
+    }
+
+    /// Returns the credit left for sending and the data buffered for reading.
+    pub fn flow_state(&self) -> FlowState {
+        self.flow.state()
+    }
+
+    /// Resets the stream, the remote is sent RST and fails to read from the stream.
+    ///
+    /// The yamux stream is dropped while still open, which is what makes the connection send RST,
+    /// as soon as it is woken up. Reading or writing the stream fails afterwards.
+    pub fn reset(&mut self) {
+        if self.inner.take().is_some() {
+            self.read_closed.store(true, Ordering::Relaxed);
+            self.write_closed.store(true, Ordering::Relaxed);
+            self.flows.lock().remove(&self.id);
+            self.reaper.notify();
+        }
+    }
+
+    /// The stream has been reset locally.
+    fn reset_err(&self) -> io::Error {
+        io::Error::new(io::ErrorKind::ConnectionReset, format!("stream {} is reset", self.id))
+    }
+}
 
 #[async_trait::async_trait]
 impl ReadWriteEx for Stream {
     fn box_clone(&self) -> IReadWrite {
         unimplemented!()
     }
+
+    async fn reset(&mut self) -> io::Result<()> {
+        Stream::reset(self);
+        Ok(())
+    }
+
+    fn flow_state(&self) -> Option<FlowState> {
+        Some(self.flow.state())
+    }
 }
 
 impl StreamInfo for Stream {
     fn id(&self) -> usize {
-        self.0.id().val() as usize
+        self.id as usize
     }
 }
 
 impl AsyncRead for Stream {
-    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let inner = match self.inner.as_mut() {
+            Some(inner) => inner,
+            None => return Poll::Ready(Err(self.reset_err())),
+        };
+        let r = match Pin::new(inner).poll_read(cx, buf) {
+            Poll::Ready(r) => r,
+            Poll::Pending => {
+                let this = self.project();
+                return poll_timeout(*this.read_timeout, this.read_delay, cx);
+            }
+        };
+        let this = self.project();
+        *this.read_delay = None;
         // poll_read returns Poll:Ready(Ok(0)) means that the stream is closed,
-        // we converted to an Eof error return
-        match futures::ready!(self.project().0.poll_read(cx, buf)) {
+        // we converted to an Eof error return, or a reset error if the remote has reset it
+        match r {
             Ok(n) => {
                 if n == 0 {
+                    this.read_closed.store(true, Ordering::Relaxed);
+                    if this.flow.remote_rst.load(Ordering::Relaxed) {
+                        let msg = format!("stream {} is reset by remote", this.id);
+                        return Poll::Ready(Err(io::Error::new(io::ErrorKind::ConnectionReset, msg)));
+                    }
                     Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()))
                 } else {
+                    this.flow.read.fetch_add(n, Ordering::Relaxed);
                     Poll::Ready(Ok(n))
                 }
             }
-            Err(e) => Poll::Ready(Err(e)),
+            Err(e) => {
+                this.read_closed.store(true, Ordering::Relaxed);
+                Poll::Ready(Err(e))
+            }
         }
     }
 }
 
 impl AsyncWrite for Stream {
-    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
-        self.project().0.poll_write(cx, buf)
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        // The stream has been opened already, so don't send an empty data frame, nor wait
+        // for credit to send nothing.
+        if buf.is_empty() && !self.is_write_closed() {
+            return Poll::Ready(Ok(0));
+        }
+        let inner = match self.inner.as_mut() {
+            Some(inner) => inner,
+            None => return Poll::Ready(Err(self.reset_err())),
+        };
+        let r = match Pin::new(inner).poll_write(cx, buf) {
+            Poll::Ready(r) => r,
+            Poll::Pending => {
+                let this = self.project();
+                return poll_timeout(*this.write_timeout, this.write_delay, cx);
+            }
+        };
+        let this = self.project();
+        *this.write_delay = None;
+        match r {
+            Ok(n) => {
+                this.flow.sent.fetch_add(n, Ordering::Relaxed);
+            }
+            Err(_) => this.write_closed.store(true, Ordering::Relaxed),
+        }
+        Poll::Ready(r)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.inner.as_mut() {
+            Some(inner) => Pin::new(inner).poll_flush(cx),
+            None => Poll::Ready(Err(self.reset_err())),
+        }
     }
 
-    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        self.project().0.poll_flush(cx)
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // a reset stream is closed already
+        let r = match self.inner.as_mut() {
+            Some(inner) => futures::ready!(Pin::new(inner).poll_close(cx)),
+            None => Ok(()),
+        };
+        self.write_closed.store(true, Ordering::Relaxed);
+        Poll::Ready(r)
     }
+}
 
-    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        self.project().0.poll_close(cx)
+/// Polls the timer of a pending I/O operation, starting it if needed.
+///
+/// Returns `io::ErrorKind::TimedOut` if the timer has fired, `Poll::Pending` otherwise.
+fn poll_timeout<T>(timeout: Option<Duration>, delay: &mut Option<Delay>, cx: &mut Context<'_>) -> Poll<io::Result<T>> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return Poll::Pending,
+    };
+    let timer = delay.get_or_insert_with(|| Delay::new(timeout));
+    match timer.poll_unpin(cx) {
+        Poll::Ready(()) => {
+            *delay = None;
+            Poll::Ready(Err(io::ErrorKind::TimedOut.into()))
+        }
+        Poll::Pending => Poll::Pending,
     }
 }
 
@@ -261,6 +899,9 @@ impl AsyncWrite for Stream {
 pub struct Config {
     inner: yamux::Config,
     mode: Option<yamux::Mode>,
+    max_message_size: Option<usize>,
+    /// The smallest receive window allowed, 256 KiB unless lowered by `allow_small_window`.
+    min_receive_window: u32,
 }
 
 /// The window update mode determines when window updates are
@@ -327,8 +968,44 @@ impl Config {
     }
 
     /// Sets the size (in bytes) of the receive window per substream.
+    ///
+    /// A window smaller than 256 KiB, which can be allowed by `allow_small_window`, means
+    /// the remote is never granted any credit beyond the initial window every substream starts
+    /// with as of the yamux specification, which the remote assumes regardless.
+    ///
+    /// # Panics
+    ///
+    /// If `num_bytes` is less than the minimum, 256 KiB by default.
     pub fn set_receive_window_size(&mut self, num_bytes: u32) -> &mut Self {
-        self.inner.set_receive_window(num_bytes);
+        assert!(
+            num_bytes >= self.min_receive_window,
+            "receive window of {} bytes below the minimum of {}",
+            num_bytes,
+            self.min_receive_window
+        );
+        self.inner.set_receive_window(std::cmp::max(num_bytes, DEFAULT_CREDIT as u32));
+        self
+    }
+
+    /// Lowers the minimum of the receive window to `min` bytes, so that `set_receive_window_size`
+    /// accepts a window smaller than 256 KiB, e.g. for the peers running many substreams with
+    /// little memory.
+    ///
+    /// > **Note**: A small window limits the throughput of every substream, as the remote
+    /// > can't send more than a window before it is read. The initial 256 KiB window of
+    /// > the yamux specification still applies, the smaller one is what the remote is
+    /// > granted from then on, see `set_receive_window_size`.
+    ///
+    /// # Panics
+    ///
+    /// If `min` is 0 or greater than 256 KiB.
+    pub fn allow_small_window(&mut self, min: u32) -> &mut Self {
+        assert!(
+            min > 0 && min as usize <= DEFAULT_CREDIT,
+            "minimum receive window of {} bytes out of range",
+            min
+        );
+        self.min_receive_window = min;
         self
     }
 
@@ -344,6 +1021,16 @@ impl Config {
         self
     }
 
+    /// Sets the maximum body size (in bytes) of the inbound data frames.
+    ///
+    /// A larger data frame fails the connection with `ConnectionError::Io` carrying
+    /// `FrameDecodeError::FrameTooLarge`. By default, only the limit of the yamux
+    /// codec applies.
+    pub fn set_max_message_size(&mut self, num_bytes: usize) -> &mut Self {
+        self.max_message_size = Some(num_bytes);
+        self
+    }
+
     /// Sets the window update mode that determines when the remote
     /// is given new credit for sending more data.
     pub fn set_window_update_mode(&mut self, mode: WindowUpdateMode) -> &mut Self {
@@ -358,7 +1045,12 @@ impl Default for Config {
         // For conformity with mplex, read-after-close on a multiplexed
         // connection is never permitted and not configurable.
         inner.set_read_after_close(false);
-        Config { inner, mode: None }
+        Config {
+            inner,
+            mode: None,
+            max_message_size: None,
+            min_receive_window: DEFAULT_CREDIT as u32,
+        }
     }
 }
 
@@ -379,15 +1071,650 @@ where
 
     async fn upgrade_inbound(self, socket: C, _info: <Self as UpgradeInfo>::Info) -> Result<Self::Output, TransportError> {
         let mode = self.mode.unwrap_or(yamux::Mode::Server);
-        Ok(Yamux::new(socket, self.inner, mode))
+        Ok(Yamux::new(socket, self.inner, mode, self.max_message_size))
     }
 
     async fn upgrade_outbound(self, socket: C, _info: <Self as UpgradeInfo>::Info) -> Result<Self::Output, TransportError> {
         let mode = self.mode.unwrap_or(yamux::Mode::Client);
-        Ok(Yamux::new(socket, self.inner, mode))
+        Ok(Yamux::new(socket, self.inner, mode, self.max_message_size))
     }
 }
 
-fn map_yamux_err(e: yamux::ConnectionError) -> TransportError {
+fn map_yamux_err(e: ConnectionError) -> TransportError {
     TransportError::StreamMuxerError(Box::new(e))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2prs_core::transport::memory::MemoryTransport;
+    use libp2prs_core::transport::upgrade::TransportUpgrade;
+    use libp2prs_core::transport::ListenerEvent;
+    use libp2prs_core::upgrade::DummyUpgrader;
+    use libp2prs_core::Transport;
+    use libp2prs_runtime::task;
+
+    #[test]
+    fn stream_read_closed_on_remote_fin() {
+        task::block_on(async {
+            let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+            let mut listener = MemoryTransport.listen_on(addr.clone()).unwrap();
+
+            let server = task::spawn(async move {
+                let socket = match listener.accept().await.unwrap() {
+                    ListenerEvent::Accepted(s) => s,
+                    _ => panic!("unreachable"),
+                };
+                let socket = DummyUpgrader::new().upgrade_inbound(socket, b"").await.unwrap();
+                let mut conn = Config::new().upgrade_inbound(socket, b"").await.unwrap();
+                task::spawn(conn.task().unwrap());
+
+                let stream = conn.accepted.lock().await.next().await.unwrap().unwrap();
+                let mut stream = Stream::new(stream, &conn.flows, &conn.reaper);
+
+                // the FIN of the remote is seen before the data sent ahead of it is read
+                let deadline = std::time::Instant::now() + Duration::from_secs(5);
+                while !stream.is_read_closed() {
+                    assert!(std::time::Instant::now() < deadline, "the remote FIN is not seen");
+                    task::sleep(Duration::from_millis(10)).await;
+                }
+                assert!(!stream.is_write_closed());
+
+                let mut buf = [0u8; 5];
+                stream.read_exact(&mut buf).await.unwrap();
+                assert_eq!(&buf, b"hello");
+                assert!(stream.read(&mut buf).await.is_err());
+                assert!(stream.is_read_closed());
+                assert!(!stream.is_write_closed());
+            });
+
+            let socket = MemoryTransport.dial(addr).await.unwrap();
+            let socket = DummyUpgrader::new().upgrade_outbound(socket, b"").await.unwrap();
+            let mut conn = Config::new().upgrade_outbound(socket, b"").await.unwrap();
+            task::spawn(conn.task().unwrap());
+
+            let stream = conn.control.open_stream().await.unwrap();
+            let mut stream = Stream::new(stream, &conn.flows, &conn.reaper);
+            stream.write_all(b"hello").await.unwrap();
+            assert!(!stream.is_write_closed());
+            stream.close().await.unwrap();
+            assert!(stream.is_write_closed());
+
+            server.await;
+        });
+    }
+
+    #[test]
+    fn dropped_stream_fails_remote_read() {
+        task::block_on(async {
+            let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+            let mut listener = MemoryTransport.listen_on(addr.clone()).unwrap();
+
+            let (tx, rx) = futures::channel::oneshot::channel::<()>();
+            let server = task::spawn(async move {
+                let socket = match listener.accept().await.unwrap() {
+                    ListenerEvent::Accepted(s) => s,
+                    _ => panic!("unreachable"),
+                };
+                let socket = DummyUpgrader::new().upgrade_inbound(socket, b"").await.unwrap();
+                let mut conn = Config::new().upgrade_inbound(socket, b"").await.unwrap();
+                task::spawn(conn.task().unwrap());
+
+                let stream = conn.accepted.lock().await.next().await.unwrap().unwrap();
+                let mut stream = Stream::new(stream, &conn.flows, &conn.reaper);
+
+                let mut buf = [0u8; 5];
+                stream.read_exact(&mut buf).await.unwrap();
+                tx.send(()).unwrap();
+
+                // blocked until the remote drops its stream, which resets it
+                let r = task::timeout(Duration::from_secs(5), stream.read(&mut buf)).await;
+                let e = r.expect("the read is not woken up").unwrap_err();
+                assert_eq!(e.kind(), io::ErrorKind::ConnectionReset);
+                assert!(stream.is_read_closed());
+            });
+
+            let socket = MemoryTransport.dial(addr).await.unwrap();
+            let socket = DummyUpgrader::new().upgrade_outbound(socket, b"").await.unwrap();
+            let mut conn = Config::new().upgrade_outbound(socket, b"").await.unwrap();
+            task::spawn(conn.task().unwrap());
+
+            let stream = conn.control.open_stream().await.unwrap();
+            let mut stream = Stream::new(stream, &conn.flows, &conn.reaper);
+            stream.write_all(b"hello").await.unwrap();
+            rx.await.unwrap();
+            drop(stream);
+
+            server.await;
+            assert_eq!(conn.stats().resets_sent, 1);
+        });
+    }
+
+    #[test]
+    fn reset_stream_fails_remote_read() {
+        task::block_on(async {
+            let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+            let mut listener = MemoryTransport.listen_on(addr.clone()).unwrap();
+
+            let (tx, rx) = futures::channel::oneshot::channel::<()>();
+            let server = task::spawn(async move {
+                let socket = match listener.accept().await.unwrap() {
+                    ListenerEvent::Accepted(s) => s,
+                    _ => panic!("unreachable"),
+                };
+                let socket = DummyUpgrader::new().upgrade_inbound(socket, b"").await.unwrap();
+                let mut conn = Config::new().upgrade_inbound(socket, b"").await.unwrap();
+                task::spawn(conn.task().unwrap());
+
+                let stream = conn.accepted.lock().await.next().await.unwrap().unwrap();
+                let mut stream = Stream::new(stream, &conn.flows, &conn.reaper);
+
+                let mut buf = [0u8; 5];
+                stream.read_exact(&mut buf).await.unwrap();
+                tx.send(()).unwrap();
+
+                // blocked until the remote resets its stream
+                let r = task::timeout(Duration::from_secs(5), stream.read(&mut buf)).await;
+                let e = r.expect("the read is not woken up").unwrap_err();
+                assert_eq!(e.kind(), io::ErrorKind::ConnectionReset);
+                assert!(stream.is_read_closed());
+                assert!(stream.is_write_closed());
+            });
+
+            let socket = MemoryTransport.dial(addr).await.unwrap();
+            let socket = DummyUpgrader::new().upgrade_outbound(socket, b"").await.unwrap();
+            let mut conn = Config::new().upgrade_outbound(socket, b"").await.unwrap();
+            task::spawn(conn.task().unwrap());
+
+            let stream = conn.control.open_stream().await.unwrap();
+            let mut stream = Stream::new(stream, &conn.flows, &conn.reaper);
+            stream.write_all(b"hello").await.unwrap();
+            rx.await.unwrap();
+            ReadWriteEx::reset(&mut stream).await.unwrap();
+            assert!(stream.is_write_closed());
+            let e = stream.write_all(b"world").await.unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::ConnectionReset);
+
+            server.await;
+            assert_eq!(conn.stats().resets_sent, 1);
+        });
+    }
+
+    #[test]
+    fn reset_counted_on_both_sides() {
+        task::block_on(async {
+            let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+            let mut listener = MemoryTransport.listen_on(addr.clone()).unwrap();
+
+            let (tx, rx) = futures::channel::oneshot::channel::<()>();
+            let server = task::spawn(async move {
+                let socket = match listener.accept().await.unwrap() {
+                    ListenerEvent::Accepted(s) => s,
+                    _ => panic!("unreachable"),
+                };
+                let socket = DummyUpgrader::new().upgrade_inbound(socket, b"").await.unwrap();
+                // the stream is reset once more than 1 KiB is buffered
+                let mut cfg = Config::new();
+                cfg.set_max_buffer_size(1024);
+                let mut conn = cfg.upgrade_inbound(socket, b"").await.unwrap();
+                task::spawn(conn.task().unwrap());
+
+                // accept the stream, but never read from it
+                let _stream = conn.accepted.lock().await.next().await.unwrap().unwrap();
+                let _ = rx.await;
+                conn.stats()
+            });
+
+            let socket = MemoryTransport.dial(addr).await.unwrap();
+            let socket = DummyUpgrader::new().upgrade_outbound(socket, b"").await.unwrap();
+            let mut conn = Config::new().upgrade_outbound(socket, b"").await.unwrap();
+            task::spawn(conn.task().unwrap());
+
+            let stream = conn.control.open_stream().await.unwrap();
+            let mut stream = Stream::new(stream, &conn.flows, &conn.reaper);
+            let deadline = std::time::Instant::now() + Duration::from_secs(5);
+            while !stream.is_write_closed() {
+                assert!(std::time::Instant::now() < deadline, "the stream is not reset");
+                let _ = stream.write_all(&[0u8; 1024]).await;
+                task::sleep(Duration::from_millis(10)).await;
+            }
+
+            let stats = conn.stats();
+            assert_eq!(stats.resets_received, 1);
+            assert_eq!(stats.resets_sent, 0);
+            assert_eq!(stats.protocol_errors, 0);
+
+            let _ = tx.send(());
+            let stats = server.await.unwrap();
+            assert_eq!(stats.resets_sent, 1);
+            assert_eq!(stats.resets_received, 0);
+        });
+    }
+
+    #[test]
+    fn stream_read_timeout() {
+        task::block_on(async {
+            let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+            let mut listener = MemoryTransport.listen_on(addr.clone()).unwrap();
+
+            let (tx, rx) = futures::channel::oneshot::channel::<()>();
+            let server = task::spawn(async move {
+                let socket = match listener.accept().await.unwrap() {
+                    ListenerEvent::Accepted(s) => s,
+                    _ => panic!("unreachable"),
+                };
+                let socket = DummyUpgrader::new().upgrade_inbound(socket, b"").await.unwrap();
+                let mut conn = Config::new().upgrade_inbound(socket, b"").await.unwrap();
+                task::spawn(conn.task().unwrap());
+
+                // accept the stream, but never write anything
+                let stream = conn.accepted.lock().await.next().await.unwrap().unwrap();
+                let mut stream = Stream::new(stream, &conn.flows, &conn.reaper);
+                let mut buf = [0u8; 5];
+                stream.read_exact(&mut buf).await.unwrap();
+                let _ = rx.await;
+            });
+
+            let socket = MemoryTransport.dial(addr).await.unwrap();
+            let socket = DummyUpgrader::new().upgrade_outbound(socket, b"").await.unwrap();
+            let mut conn = Config::new().upgrade_outbound(socket, b"").await.unwrap();
+            task::spawn(conn.task().unwrap());
+
+            let stream = conn.control.open_stream().await.unwrap();
+            let mut stream = Stream::new(stream, &conn.flows, &conn.reaper);
+            stream.write_all(b"hello").await.unwrap();
+            stream.set_read_timeout(Some(Duration::from_millis(100)));
+
+            let mut buf = [0u8; 5];
+            let err = stream.read(&mut buf).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+            assert!(!stream.is_read_closed());
+
+            let _ = tx.send(());
+            server.await;
+        });
+    }
+
+    #[test]
+    fn stream_write_timeout() {
+        task::block_on(async {
+            let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+            let mut listener = MemoryTransport.listen_on(addr.clone()).unwrap();
+
+            let (tx, rx) = futures::channel::oneshot::channel::<()>();
+            let server = task::spawn(async move {
+                let socket = match listener.accept().await.unwrap() {
+                    ListenerEvent::Accepted(s) => s,
+                    _ => panic!("unreachable"),
+                };
+                let socket = DummyUpgrader::new().upgrade_inbound(socket, b"").await.unwrap();
+                // the remote gets new credit only when we read
+                let mut cfg = Config::new();
+                cfg.set_window_update_mode(WindowUpdateMode::on_read());
+                let mut conn = cfg.upgrade_inbound(socket, b"").await.unwrap();
+                task::spawn(conn.task().unwrap());
+
+                // accept the stream, but never read from it
+                let _stream = conn.accepted.lock().await.next().await.unwrap().unwrap();
+                let _ = rx.await;
+            });
+
+            let socket = MemoryTransport.dial(addr).await.unwrap();
+            let socket = DummyUpgrader::new().upgrade_outbound(socket, b"").await.unwrap();
+            let mut conn = Config::new().upgrade_outbound(socket, b"").await.unwrap();
+            task::spawn(conn.task().unwrap());
+
+            let stream = conn.control.open_stream().await.unwrap();
+            let mut stream = Stream::new(stream, &conn.flows, &conn.reaper);
+            stream.set_write_timeout(Some(Duration::from_millis(100)));
+
+            // the whole receive window of the remote is used up, then the write times out
+            let data = vec![0u8; 1024 * 1024];
+            let err = stream.write_all(&data).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+            assert!(!stream.is_write_closed());
+
+            let _ = tx.send(());
+            server.await;
+        });
+    }
+
+    #[test]
+    fn flow_state_follows_credit() {
+        task::block_on(async {
+            let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+            let mut listener = MemoryTransport.listen_on(addr.clone()).unwrap();
+
+            let (tx, rx) = futures::channel::oneshot::channel::<()>();
+            let server = task::spawn(async move {
+                let socket = match listener.accept().await.unwrap() {
+                    ListenerEvent::Accepted(s) => s,
+                    _ => panic!("unreachable"),
+                };
+                let socket = DummyUpgrader::new().upgrade_inbound(socket, b"").await.unwrap();
+                let mut cfg = Config::new();
+                cfg.set_window_update_mode(WindowUpdateMode::on_read());
+                let mut conn = cfg.upgrade_inbound(socket, b"").await.unwrap();
+                task::spawn(conn.task().unwrap());
+
+                // accept the stream, but never read from it
+                let _stream = conn.accepted.lock().await.next().await.unwrap().unwrap();
+                let _ = rx.await;
+            });
+
+            let socket = MemoryTransport.dial(addr).await.unwrap();
+            let socket = DummyUpgrader::new().upgrade_outbound(socket, b"").await.unwrap();
+            let mut conn = Config::new().upgrade_outbound(socket, b"").await.unwrap();
+            task::spawn(conn.task().unwrap());
+
+            let stream = conn.control.open_stream().await.unwrap();
+            let mut stream = Stream::new(stream, &conn.flows, &conn.reaper);
+            stream.set_write_timeout(Some(Duration::from_millis(100)));
+            assert_eq!(stream.flow_state().send_credit, DEFAULT_CREDIT);
+
+            stream.write_all(&[0u8; 1024]).await.unwrap();
+            assert_eq!(stream.flow_state().send_credit, DEFAULT_CREDIT - 1024);
+
+            // credit-starved, until the remote reads
+            let data = vec![0u8; 1024 * 1024];
+            assert!(stream.write_all(&data).await.is_err());
+            assert_eq!(stream.flow_state().send_credit, 0);
+
+            let _ = tx.send(());
+            server.await;
+        });
+    }
+
+    #[test]
+    fn empty_write_needs_no_credit() {
+        task::block_on(async {
+            let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+            let mut listener = MemoryTransport.listen_on(addr.clone()).unwrap();
+
+            let (tx, rx) = futures::channel::oneshot::channel::<()>();
+            let server = task::spawn(async move {
+                let socket = match listener.accept().await.unwrap() {
+                    ListenerEvent::Accepted(s) => s,
+                    _ => panic!("unreachable"),
+                };
+                let socket = DummyUpgrader::new().upgrade_inbound(socket, b"").await.unwrap();
+                let mut cfg = Config::new();
+                cfg.set_window_update_mode(WindowUpdateMode::on_read());
+                let mut conn = cfg.upgrade_inbound(socket, b"").await.unwrap();
+                task::spawn(conn.task().unwrap());
+
+                let _stream = conn.accepted.lock().await.next().await.unwrap().unwrap();
+                let _ = rx.await;
+            });
+
+            let socket = MemoryTransport.dial(addr).await.unwrap();
+            let socket = DummyUpgrader::new().upgrade_outbound(socket, b"").await.unwrap();
+            let mut conn = Config::new().upgrade_outbound(socket, b"").await.unwrap();
+            task::spawn(conn.task().unwrap());
+
+            let stream = conn.control.open_stream().await.unwrap();
+            let mut stream = Stream::new(stream, &conn.flows, &conn.reaper);
+            stream.set_write_timeout(Some(Duration::from_millis(100)));
+            assert_eq!(stream.write(&[]).await.unwrap(), 0);
+
+            // no data frame is sent for an empty write, so it doesn't wait for credit either
+            let data = vec![0u8; 1024 * 1024];
+            assert!(stream.write_all(&data).await.is_err());
+            assert_eq!(stream.write(&[]).await.unwrap(), 0);
+
+            // once closed, an empty write fails like any other
+            stream.close().await.unwrap();
+            assert!(stream.write(&[]).await.is_err());
+
+            let _ = tx.send(());
+            server.await;
+        });
+    }
+
+    #[test]
+    #[should_panic]
+    fn small_receive_window_rejected_by_default() {
+        Config::new().set_receive_window_size(16 * 1024);
+    }
+
+    #[test]
+    #[should_panic]
+    fn receive_window_below_allowed_minimum_rejected() {
+        Config::new().allow_small_window(16 * 1024).set_receive_window_size(8 * 1024);
+    }
+
+    #[test]
+    fn small_receive_window_transfers_data() {
+        let mut cfg = Config::new();
+        cfg.allow_small_window(16 * 1024).set_receive_window_size(16 * 1024);
+
+        task::block_on(async {
+            let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+            let mut listener = MemoryTransport.listen_on(addr.clone()).unwrap();
+
+            let srv_cfg = cfg.clone();
+            let server = task::spawn(async move {
+                let socket = match listener.accept().await.unwrap() {
+                    ListenerEvent::Accepted(s) => s,
+                    _ => panic!("unreachable"),
+                };
+                let socket = DummyUpgrader::new().upgrade_inbound(socket, b"").await.unwrap();
+                let mut conn = srv_cfg.upgrade_inbound(socket, b"").await.unwrap();
+                task::spawn(conn.task().unwrap());
+
+                let stream = conn.accepted.lock().await.next().await.unwrap().unwrap();
+                let mut stream = Stream::new(stream, &conn.flows, &conn.reaper);
+                let mut data = vec![0u8; 1024 * 1024];
+                stream.read_exact(&mut data).await.unwrap();
+                data
+            });
+
+            let socket = MemoryTransport.dial(addr).await.unwrap();
+            let socket = DummyUpgrader::new().upgrade_outbound(socket, b"").await.unwrap();
+            let mut conn = cfg.upgrade_outbound(socket, b"").await.unwrap();
+            task::spawn(conn.task().unwrap());
+
+            // more than the initial window, so the credit has to be granted again and again
+            let data = (0..1024 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+            let stream = conn.control.open_stream().await.unwrap();
+            let mut stream = Stream::new(stream, &conn.flows, &conn.reaper);
+            stream.write_all(&data).await.unwrap();
+
+            assert_eq!(server.await.unwrap(), data);
+        });
+    }
+
+    fn is_closed(e: TransportError) -> bool {
+        match e {
+            TransportError::StreamMuxerError(e) => matches!(e.downcast_ref::<ConnectionError>(), Some(ConnectionError::Closed)),
+            TransportError::ConnectionClosed => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn unknown_version_rejected() {
+        task::block_on(async {
+            let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+            let mut listener = MemoryTransport.listen_on(addr.clone()).unwrap();
+
+            let server = task::spawn(async move {
+                let socket = match listener.accept().await.unwrap() {
+                    ListenerEvent::Accepted(s) => s,
+                    _ => panic!("unreachable"),
+                };
+                let socket = DummyUpgrader::new().upgrade_inbound(socket, b"").await.unwrap();
+                let mut conn = Config::new().upgrade_inbound(socket, b"").await.unwrap();
+                task::spawn(conn.task().unwrap());
+
+                match conn.accept_stream().await.unwrap_err() {
+                    TransportError::StreamMuxerError(e) => assert!(matches!(
+                        e.downcast_ref::<ConnectionError>(),
+                        Some(ConnectionError::Decode(FrameDecodeError::Header(HeaderDecodeError::Version(1))))
+                    )),
+                    e => panic!("unexpected error {:?}", e),
+                }
+            });
+
+            // a raw SYN data frame of stream 1, with version 1 in its header
+            let socket = MemoryTransport.dial(addr).await.unwrap();
+            let mut socket = DummyUpgrader::new().upgrade_outbound(socket, b"").await.unwrap();
+            socket.write_all(&[1, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0]).await.unwrap();
+
+            server.await;
+        });
+    }
+
+    #[test]
+    fn open_stream_on_closed_connection() {
+        task::block_on(async {
+            let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+            let mut listener = MemoryTransport.listen_on(addr.clone()).unwrap();
+
+            let server = task::spawn(async move {
+                let socket = match listener.accept().await.unwrap() {
+                    ListenerEvent::Accepted(s) => s,
+                    _ => panic!("unreachable"),
+                };
+                let socket = DummyUpgrader::new().upgrade_inbound(socket, b"").await.unwrap();
+                let mut conn = Config::new().upgrade_inbound(socket, b"").await.unwrap();
+                task::spawn(conn.task().unwrap());
+
+                // the remote closes the connection, which is not a failure
+                assert!(matches!(conn.accept_stream().await, Err(TransportError::ConnectionClosed)));
+            });
+
+            let socket = MemoryTransport.dial(addr).await.unwrap();
+            let socket = DummyUpgrader::new().upgrade_outbound(socket, b"").await.unwrap();
+            let mut conn = Config::new().upgrade_outbound(socket, b"").await.unwrap();
+            task::spawn(conn.task().unwrap());
+
+            conn.close().await.unwrap();
+            assert!(is_closed(conn.open_stream().await.unwrap_err()));
+            assert!(is_closed(conn.accept_stream().await.unwrap_err()));
+
+            server.await;
+        });
+    }
+
+    #[test]
+    fn mode_mismatch_detected() {
+        task::block_on(async {
+            let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+            let mut listener = MemoryTransport.listen_on(addr.clone()).unwrap();
+
+            let server = task::spawn(async move {
+                let socket = match listener.accept().await.unwrap() {
+                    ListenerEvent::Accepted(s) => s,
+                    _ => panic!("unreachable"),
+                };
+                let socket = DummyUpgrader::new().upgrade_inbound(socket, b"").await.unwrap();
+                let mut conn = Config::client().upgrade_inbound(socket, b"").await.unwrap();
+                task::spawn(conn.task().unwrap());
+
+                match conn.accept_stream().await.unwrap_err() {
+                    TransportError::StreamMuxerError(e) => match e.downcast_ref::<ConnectionError>() {
+                        Some(ConnectionError::Io(e)) | Some(ConnectionError::Decode(FrameDecodeError::Io(e))) => {
+                            let e = e.get_ref().and_then(|e| e.downcast_ref::<ModeMismatch>());
+                            assert_eq!(e, Some(&ModeMismatch { stream_id: 1 }));
+                        }
+                        e => panic!("unexpected error {:?}", e),
+                    },
+                    e => panic!("unexpected error {:?}", e),
+                }
+                assert_eq!(conn.stats().protocol_errors, 1);
+            });
+
+            let socket = MemoryTransport.dial(addr).await.unwrap();
+            let socket = DummyUpgrader::new().upgrade_outbound(socket, b"").await.unwrap();
+            let mut conn = Config::client().upgrade_outbound(socket, b"").await.unwrap();
+            task::spawn(conn.task().unwrap());
+
+            let mut stream = conn.open_stream().await.unwrap();
+            let _ = stream.write_all(b"hello").await;
+
+            server.await;
+        });
+    }
+
+    #[test]
+    fn oversized_data_frame_rejected() {
+        task::block_on(async {
+            let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+            let mut listener = MemoryTransport.listen_on(addr.clone()).unwrap();
+
+            let server = task::spawn(async move {
+                let socket = match listener.accept().await.unwrap() {
+                    ListenerEvent::Accepted(s) => s,
+                    _ => panic!("unreachable"),
+                };
+                let socket = DummyUpgrader::new().upgrade_inbound(socket, b"").await.unwrap();
+                let mut cfg = Config::new();
+                cfg.set_max_message_size(512);
+                let mut conn = cfg.upgrade_inbound(socket, b"").await.unwrap();
+                task::spawn(conn.task().unwrap());
+
+                match conn.accept_stream().await.unwrap_err() {
+                    TransportError::StreamMuxerError(e) => match e.downcast_ref::<ConnectionError>() {
+                        Some(ConnectionError::Io(e)) | Some(ConnectionError::Decode(FrameDecodeError::Io(e))) => {
+                            let e = e.get_ref().and_then(|e| e.downcast_ref::<FrameDecodeError>());
+                            assert!(matches!(e, Some(FrameDecodeError::FrameTooLarge(1024))));
+                        }
+                        e => panic!("unexpected error {:?}", e),
+                    },
+                    e => panic!("unexpected error {:?}", e),
+                }
+            });
+
+            // a raw SYN data frame of stream 1, with a body of 1024 bytes
+            let socket = MemoryTransport.dial(addr).await.unwrap();
+            let mut socket = DummyUpgrader::new().upgrade_outbound(socket, b"").await.unwrap();
+            socket.write_all(&[0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 4, 0]).await.unwrap();
+            socket.write_all(&[0u8; 1024]).await.unwrap();
+
+            server.await;
+        });
+    }
+
+    #[test]
+    fn yamux_over_secio_over_memory() {
+        task::block_on(async {
+            let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().saturating_add(1)).parse().unwrap();
+            let srv_keys = Keypair::generate_ed25519();
+            let srv_peer_id = srv_keys.public().into_peer_id();
+            let mut transport = TransportUpgrade::new(MemoryTransport, Config::new(), libp2prs_secio::Config::new(srv_keys));
+            let mut listener = transport.listen_on(addr.clone()).unwrap();
+
+            let server = task::spawn(async move {
+                let mut stream_muxer = match listener.accept().await.unwrap() {
+                    ListenerEvent::Accepted(s) => s,
+                    _ => panic!("unreachable"),
+                };
+                task::spawn(stream_muxer.task().unwrap());
+
+                let mut stream = stream_muxer.accept_stream().await.unwrap();
+                let mut buf = [0u8; 5];
+                stream.read_exact(&mut buf).await.unwrap();
+                stream.write_all(&buf).await.unwrap();
+                stream.close().await.unwrap();
+                // handed over to keep the connection open, until the client has read everything
+                stream_muxer
+            });
+
+            let cli_keys = Keypair::generate_ed25519();
+            let cli_peer_id = cli_keys.public().into_peer_id();
+            let mut transport = TransportUpgrade::new(MemoryTransport, Config::new(), libp2prs_secio::Config::new(cli_keys));
+            let mut stream_muxer = transport.dial(addr).await.unwrap();
+            assert_eq!(stream_muxer.remote_peer(), srv_peer_id);
+            task::spawn(stream_muxer.task().unwrap());
+
+            let mut stream = stream_muxer.open_stream().await.unwrap();
+            stream.write_all(b"hello").await.unwrap();
+            let mut buf = vec![];
+            stream.read_to_end(&mut buf).await.unwrap();
+            assert_eq!(buf, b"hello");
+
+            assert_eq!(server.await.unwrap().remote_peer(), cli_peer_id);
+        });
+    }
+}
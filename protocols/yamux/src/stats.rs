@@ -0,0 +1,59 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A snapshot of the frames of a yamux connection which drop or fail something.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct YamuxStats {
+    /// The streams reset by us, i.e. RST frames sent.
+    pub resets_sent: u64,
+    /// The streams reset by the remote, i.e. RST frames received.
+    pub resets_received: u64,
+    /// The inbound frames of the streams no longer there, which are dropped.
+    pub frames_dropped: u64,
+    /// The protocol errors, either detected in the inbound frames or told by a GoAway
+    /// frame of either side.
+    pub protocol_errors: u64,
+}
+
+/// The live counters shared by the connection socket and the `Yamux` handles.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    pub(crate) resets_sent: AtomicU64,
+    pub(crate) resets_received: AtomicU64,
+    pub(crate) frames_dropped: AtomicU64,
+    pub(crate) protocol_errors: AtomicU64,
+}
+
+impl Counters {
+    pub(crate) fn incr(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> YamuxStats {
+        YamuxStats {
+            resets_sent: self.resets_sent.load(Ordering::Relaxed),
+            resets_received: self.resets_received.load(Ordering::Relaxed),
+            frames_dropped: self.frames_dropped.load(Ordering::Relaxed),
+            protocol_errors: self.protocol_errors.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -19,7 +19,7 @@
 // DEALINGS IN THE SOFTWARE.
 
 use std::io;
-use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr};
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
@@ -123,8 +123,10 @@ impl AsyncWrite for TcpStream {
     }
 
     // tokio, poll_close vs. poll_shutdown
+    // shut down writing explicitly, as the newer async-std only flushes when closing
     fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
-        AsyncWrite::poll_close(Pin::new(&mut self.0), cx)
+        futures::ready!(AsyncWrite::poll_flush(Pin::new(&mut self.0), cx))?;
+        Poll::Ready(self.0.shutdown(Shutdown::Write))
     }
 }
 
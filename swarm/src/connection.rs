@@ -25,34 +25,40 @@
 //! the Swarm [`Substream`] via multistream select procedure.
 //!
 
+use fnv::FnvHashMap;
 use smallvec::SmallVec;
-use std::fmt;
 use std::hash::Hash;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{fmt, io};
 
 use futures::channel::{mpsc, oneshot};
+use futures::future::BoxFuture;
 use futures::prelude::*;
 
 use libp2prs_runtime::task;
 
 use libp2prs_core::identity::Keypair;
-use libp2prs_core::multistream::Negotiator;
-use libp2prs_core::muxing::IStreamMuxer;
+use libp2prs_core::multistream::{NegotiationError, Negotiator, ProtocolError};
+use libp2prs_core::muxing::{IReadWrite, IStreamMuxer};
 use libp2prs_core::transport::TransportError;
+use libp2prs_core::util::TokenBucket;
 use libp2prs_core::PublicKey;
 
 use crate::connection::Direction::Outbound;
 use crate::control::SwarmControlCmd;
+use crate::disconnect::DISCONNECT_PROTOCOL;
 use crate::identify::{IDENTIFY_PROTOCOL, IDENTIFY_PUSH_PROTOCOL};
+use crate::metrics::bandwidth::{BandwidthCounters, BandwidthStats};
 use crate::metrics::metric::Metric;
 use crate::ping::PING_PROTOCOL;
 use crate::substream::{ConnectInfo, StreamId, Substream, SubstreamView};
-use crate::{identify, ping, Multiaddr, PeerId, ProtocolId, SwarmError, SwarmEvent};
+use crate::{disconnect, identify, ping, Multiaddr, PeerId, ProtocolId, SwarmError, SwarmEvent};
 
 /// The direction of a peer-to-peer communication channel.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Direction {
     /// The socket comes from a dialer.
     Outbound,
@@ -60,6 +66,16 @@ pub enum Direction {
     Inbound,
 }
 
+impl Direction {
+    /// Returns the opposite direction.
+    pub fn reverse(self) -> Direction {
+        match self {
+            Direction::Outbound => Direction::Inbound,
+            Direction::Inbound => Direction::Outbound,
+        }
+    }
+}
+
 impl fmt::Display for Direction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", if self == &Outbound { "Out" } else { "In " })
@@ -69,6 +85,123 @@ impl fmt::Display for Direction {
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct ConnectionId(usize);
 
+/// The state of a connection shared with all its substreams, so that the substreams can
+/// tell a failed connection from one closed by ourselves.
+#[derive(Debug, Default)]
+pub(crate) struct ConnectionState {
+    /// Set when the connection is being closed locally.
+    closing: AtomicBool,
+    /// Set when the connection went away without being closed locally.
+    failed: AtomicBool,
+    /// The bytes transferred by all substreams of the connection.
+    bandwidth: BandwidthCounters,
+    /// The maximum number of concurrent substreams of the limited protocols.
+    stream_limits: Arc<FnvHashMap<ProtocolId, usize>>,
+    /// The number of live substreams of the limited protocols.
+    stream_counts: Mutex<FnvHashMap<ProtocolId, usize>>,
+}
+
+impl ConnectionState {
+    pub(crate) fn with_stream_limits(stream_limits: Arc<FnvHashMap<ProtocolId, usize>>) -> Self {
+        ConnectionState {
+            stream_limits,
+            ..Default::default()
+        }
+    }
+    /// Marks the connection as closed by ourselves.
+    pub(crate) fn set_closing(&self) {
+        self.closing.store(true, Ordering::SeqCst);
+    }
+    /// Marks the connection as failed, unless it is being closed locally.
+    pub(crate) fn set_failed(&self) {
+        if !self.closing.load(Ordering::SeqCst) {
+            self.failed.store(true, Ordering::SeqCst);
+        }
+    }
+    /// Returns true if the connection has failed.
+    pub(crate) fn is_failed(&self) -> bool {
+        self.failed.load(Ordering::SeqCst)
+    }
+    /// Returns the bandwidth counters of the connection.
+    pub(crate) fn bandwidth(&self) -> &BandwidthCounters {
+        &self.bandwidth
+    }
+    /// Takes a slot for a new substream of the protocol. Returns false if the protocol
+    /// has reached its limit of concurrent substreams on the connection.
+    pub(crate) fn acquire_stream(&self, protocol: &ProtocolId) -> bool {
+        let max = match self.stream_limits.get(protocol) {
+            Some(max) => *max,
+            None => return true,
+        };
+        let mut counts = self.stream_counts.lock().unwrap();
+        let count = counts.entry(protocol.clone()).or_insert(0);
+        if *count >= max {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+    /// Releases the slot taken by a substream of the protocol.
+    pub(crate) fn release_stream(&self, protocol: &ProtocolId) {
+        if let Some(count) = self.stream_counts.lock().unwrap().get_mut(protocol) {
+            *count = count.saturating_sub(1);
+        }
+    }
+}
+
+/// The error of a substream rejected because of the limit of its protocol.
+pub(crate) fn too_many_streams(protocol: &ProtocolId) -> TransportError {
+    let msg = format!("too many concurrent substreams for protocol {}", protocol);
+    TransportError::IoError(io::Error::new(io::ErrorKind::Other, msg))
+}
+
+/// Limits the rate of the inbound substreams per remote peer, shared by all the connections,
+/// so that a peer can't get around it by opening more connections.
+pub(crate) struct StreamRateLimiter {
+    rate: u32,
+    burst: u32,
+    buckets: Mutex<FnvHashMap<PeerId, TokenBucket>>,
+}
+
+impl StreamRateLimiter {
+    pub(crate) fn new(rate: u32, burst: u32) -> Self {
+        StreamRateLimiter {
+            rate,
+            burst,
+            buckets: Default::default(),
+        }
+    }
+    /// Takes a token for an inbound substream of the peer, returns false if the peer exceeds the rate.
+    pub(crate) fn try_acquire(&self, peer_id: &PeerId) -> bool {
+        let (rate, burst) = (self.rate, self.burst);
+        self.buckets
+            .lock()
+            .unwrap()
+            .entry(*peer_id)
+            .or_insert_with(|| TokenBucket::new(rate, burst))
+            .try_take()
+    }
+    /// Drops the bucket of the peer, as soon as it is disconnected.
+    pub(crate) fn forget(&self, peer_id: &PeerId) {
+        self.buckets.lock().unwrap().remove(peer_id);
+    }
+}
+
+/// The error of a substream rejected because its peer exceeds the rate of the inbound substreams.
+pub(crate) fn stream_rate_exceeded(peer_id: &PeerId) -> TransportError {
+    let msg = format!("peer {} exceeds the rate of the inbound substreams", peer_id);
+    TransportError::IoError(io::Error::new(io::ErrorKind::Other, msg))
+}
+
+/// Accepts the next inbound substream, handing the stream muxer back along with the result.
+pub(crate) fn accept_next(mut stream_muxer: IStreamMuxer) -> BoxFuture<'static, (IStreamMuxer, Result<IReadWrite, TransportError>)> {
+    async move {
+        let r = stream_muxer.accept_stream().await;
+        (stream_muxer, r)
+    }
+    .boxed()
+}
+
 impl fmt::Display for ConnectionId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{:<5}", self.0)
@@ -96,6 +229,10 @@ pub struct Connection {
     ping_failures: u32,
     /// Identity service
     identity: Option<()>,
+    /// The agent and protocol version reported by the remote via Identify.
+    remote_versions: Option<(String, String)>,
+    /// The round-trip time measured by the last successful Ping.
+    rtt: Option<Duration>,
     /// The runtime handle of this connection, returned by runtime::Spawn
     /// handle.await() when closing a connection
     handle: Option<task::TaskHandle<()>>,
@@ -109,6 +246,12 @@ pub struct Connection {
     metric: Arc<Metric>,
     /// Flag, means that current connection is closed or not.
     closing: bool,
+    /// The state shared with the substreams of this connection.
+    state: Arc<ConnectionState>,
+    /// The timeout of the protocol negotiation of outbound substreams.
+    negotiation_timeout: Duration,
+    /// When the connection was established.
+    established: Instant,
 }
 
 impl PartialEq for Connection {
@@ -134,6 +277,7 @@ impl fmt::Debug for Connection {
 impl Connection {
     /// Builds a new `Connection` from the given substream multiplexer
     /// and a tx channel which will used to send events to Swarm.
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         id: usize,
         stream_muxer: IStreamMuxer,
@@ -141,6 +285,8 @@ impl Connection {
         tx: mpsc::UnboundedSender<SwarmEvent>,
         ctrl: mpsc::Sender<SwarmControlCmd>,
         metric: Arc<Metric>,
+        negotiation_timeout: Duration,
+        stream_limits: Arc<FnvHashMap<ProtocolId, usize>>,
     ) -> Self {
         Connection {
             id: ConnectionId(id),
@@ -154,10 +300,15 @@ impl Connection {
             ping_failures: 0,
             ping_handle: None,
             identity: None,
+            remote_versions: None,
+            rtt: None,
             identify_handle: None,
             identify_push_handle: None,
             metric,
             closing: false,
+            state: Arc::new(ConnectionState::with_stream_limits(stream_limits)),
+            negotiation_timeout,
+            established: Instant::now(),
         }
     }
 
@@ -184,6 +335,11 @@ impl Connection {
         &self.stream_muxer
     }
 
+    /// Returns the state shared with the substreams of this connection.
+    pub(crate) fn state(&self) -> Arc<ConnectionState> {
+        self.state.clone()
+    }
+
     /// Sets the runtime handle of the connection.
     pub(crate) fn set_handle(&mut self, handle: task::TaskHandle<()>) {
         self.handle = Some(handle);
@@ -200,9 +356,11 @@ impl Connection {
         let mut tx = self.tx.clone();
         let ctrl = self.ctrl.clone();
         let metric = self.metric.clone();
+        let state = self.state.clone();
+        let negotiation_timeout = self.negotiation_timeout;
 
         task::spawn(async move {
-            let result = open_stream_internal(cid, stream_muxer, pids, ctrl, metric).await;
+            let result = open_stream_internal(cid, stream_muxer, pids, ctrl, metric, state, negotiation_timeout).await;
 
             // TODO: how to extract the error from TransportError, ??? it doesn't implement 'Clone'
             // So, at this moment, make a new 'TransportError::Internal'
@@ -224,10 +382,20 @@ impl Connection {
         })
     }
 
+    /// Returns the direction of the connection.
+    pub(crate) fn dir(&self) -> Direction {
+        self.dir
+    }
+
     pub fn is_closing(&self) -> bool {
         self.closing
     }
 
+    /// Returns how long ago the connection was established.
+    pub(crate) fn age(&self) -> Duration {
+        self.established.elapsed()
+    }
+
     /// Closes the inner stream_muxer. Spawn a runtime to avoid blocking.
     pub fn close(&mut self) {
         log::debug!("closing {:?}", self);
@@ -236,11 +404,62 @@ impl Connection {
         // spawns a runtime to close the stream_muxer, later connection will cleaned up
         // in 'handle_connection_closed'
         self.closing = true;
+        self.state.set_closing();
         task::spawn(async move {
             let _ = stream_muxer.close().await;
         });
     }
 
+    /// Sends the reason to the remote on the reserved protocol, then closes the inner stream_muxer,
+    /// even if the reason can't be sent. `f` is called with the result of sending the reason.
+    pub(crate) fn close_with_reason<F>(&mut self, code: u32, message: String, f: F)
+    where
+        F: FnOnce(Result<(), TransportError>) + Send + 'static,
+    {
+        log::debug!("closing {:?} with reason {} {:?}", self, code, message);
+
+        let cid = self.id();
+        let mut stream_muxer = self.stream_muxer.clone();
+        let pids = vec![DISCONNECT_PROTOCOL.into()];
+        let ctrl = self.ctrl.clone();
+        let metric = self.metric.clone();
+        let state = self.state.clone();
+        let negotiation_timeout = self.negotiation_timeout;
+
+        self.closing = true;
+        task::spawn(async move {
+            let r = open_stream_internal(cid, stream_muxer.clone(), pids, ctrl, metric, state.clone(), negotiation_timeout).await;
+            // the remote is given as long as the protocol negotiation to take the reason
+            let r = match r {
+                Ok(stream) => task::timeout(negotiation_timeout, disconnect::send_reason(stream, code, &message))
+                    .await
+                    .map_err(|_| TransportError::Timeout)
+                    .and_then(|r| r.map_err(TransportError::from)),
+                Err(err) => Err(err),
+            };
+            if let Err(err) = &r {
+                log::info!("failed to send the reason of closing {:?}: {:?}", cid, err);
+            }
+
+            state.set_closing();
+            let _ = stream_muxer.close().await;
+            f(r);
+        });
+    }
+
+    /// Waits for the frames queued by all substreams to be written, on the inner stream_muxer.
+    /// Spawn a runtime to avoid blocking, `f` is called with the result.
+    pub(crate) fn flush_barrier<F>(&self, f: F)
+    where
+        F: FnOnce(Result<(), TransportError>) + Send + 'static,
+    {
+        let mut stream_muxer = self.stream_muxer.clone();
+        task::spawn(async move {
+            let r = stream_muxer.flush_barrier().await;
+            f(r);
+        });
+    }
+
     /// Waits for bg-runtime & accept-runtime.
     pub(crate) async fn wait(&mut self) -> Result<(), SwarmError> {
         // wait for accept-runtime and bg-runtime to exit
@@ -290,6 +509,11 @@ impl Connection {
         self.substreams.retain(|s| s.id != sid);
     }
 
+    /// Tells whether the substream is in the list.
+    pub(crate) fn has_stream(&self, sid: StreamId) -> bool {
+        self.substreams.iter().any(|s| s.id == sid)
+    }
+
     /// Returns how many substreams in the list.
     pub(crate) fn num_streams(&self) -> usize {
         self.substreams.len()
@@ -301,7 +525,7 @@ impl Connection {
     /// Note that we don't generate StreamOpened/Closed event for Ping/Identify outbound
     /// simply because it doesn't make much sense doing so for a transient outgoing
     /// stream.
-    pub(crate) fn start_ping(&mut self, timeout: Duration, interval: Duration, max_failures: u32) {
+    pub(crate) fn start_ping(&mut self, timeout: Duration, interval: Duration, jitter: f64, max_failures: u32) {
         self.ping_running.store(true, Ordering::Relaxed);
 
         let cid = self.id();
@@ -311,6 +535,8 @@ impl Connection {
         let pids = vec![PING_PROTOCOL.into()];
         let ctrl = self.ctrl.clone();
         let metric = self.metric.clone();
+        let state = self.state.clone();
+        let negotiation_timeout = self.negotiation_timeout;
 
         let handle = task::spawn(async move {
             let mut fail_cnt: u32 = 0;
@@ -319,7 +545,8 @@ impl Connection {
                     break;
                 }
 
-                // sleep for the interval
+                // sleep for the interval, randomly varied by the jitter
+                let interval = ping::jittered(interval, jitter, &mut rand::thread_rng());
                 task::sleep(interval).await;
 
                 //recheck, in case ping service has been terminated already
@@ -331,7 +558,7 @@ impl Connection {
                 let pids = pids.clone();
 
                 let ctrl2 = ctrl.clone();
-                let r = open_stream_internal(cid, stream_muxer, pids, ctrl2, metric.clone()).await;
+                let r = open_stream_internal(cid, stream_muxer, pids, ctrl2, metric.clone(), state.clone(), negotiation_timeout).await;
                 let r = match r {
                     Ok(stream) => {
                         let view = stream.to_view();
@@ -359,6 +586,11 @@ impl Connection {
                     }
                 };
 
+                // the round-trip time is recorded on the connection
+                if let Ok(rtt) = &r {
+                    let _ = tx.send(SwarmEvent::PingResult { cid, result: Ok(*rtt) }).await;
+                }
+
                 if fail_cnt >= max_failures {
                     let _ = tx
                         .send(SwarmEvent::PingResult {
@@ -395,9 +627,11 @@ impl Connection {
         let ctrl = self.ctrl.clone();
         let pids = vec![IDENTIFY_PROTOCOL.into()];
         let metric = self.metric.clone();
+        let state = self.state.clone();
+        let negotiation_timeout = self.negotiation_timeout;
 
         let handle = task::spawn(async move {
-            let r = open_stream_internal(cid, stream_muxer, pids, ctrl, metric).await;
+            let r = open_stream_internal(cid, stream_muxer, pids, ctrl, metric, state, negotiation_timeout).await;
             let r = match r {
                 Ok(stream) => {
                     let view = stream.to_view();
@@ -444,6 +678,8 @@ impl Connection {
         let stream_muxer = self.stream_muxer.clone();
         let pids = vec![IDENTIFY_PUSH_PROTOCOL.into()];
         let metric = self.metric.clone();
+        let state = self.state.clone();
+        let negotiation_timeout = self.negotiation_timeout;
 
         let mut ctrl = self.ctrl.clone();
 
@@ -457,7 +693,7 @@ impl Connection {
             }
             let info = swrm_rx.await.expect("get identify info");
 
-            let r = open_stream_internal(cid, stream_muxer, pids, ctrl, metric).await;
+            let r = open_stream_internal(cid, stream_muxer, pids, ctrl, metric, state, negotiation_timeout).await;
             match r {
                 Ok(stream) => {
                     let view = stream.to_view();
@@ -490,6 +726,31 @@ impl Connection {
         }
     }
 
+    /// Records the agent and protocol version the remote reported via Identify.
+    pub(crate) fn set_remote_versions(&mut self, agent_version: String, protocol_version: String) {
+        self.remote_versions = Some((agent_version, protocol_version));
+    }
+
+    /// Returns the agent and protocol version of the remote, if identified.
+    pub(crate) fn remote_versions(&self) -> Option<&(String, String)> {
+        self.remote_versions.as_ref()
+    }
+
+    /// Returns whether the remote has been identified via Identify.
+    pub(crate) fn is_identified(&self) -> bool {
+        self.remote_versions.is_some()
+    }
+
+    /// Records the round-trip time measured by Ping.
+    pub(crate) fn set_rtt(&mut self, rtt: Duration) {
+        self.rtt = Some(rtt);
+    }
+
+    /// Returns the round-trip time measured by the last successful Ping.
+    pub(crate) fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
     pub(crate) fn info(&self) -> ConnectionInfo {
         // calculate inbound
         let num_inbound_streams = self.substreams.iter().fold(0usize, |mut acc, s| {
@@ -506,6 +767,33 @@ impl Connection {
             remote_peer_id: self.remote_peer(),
             num_inbound_streams,
             num_outbound_streams,
+            bandwidth: self.state.bandwidth().stats(),
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // nothing left when the connection is cleaned up by Swarm, otherwise the tasks
+        // would be leaked, as dropping a task handle doesn't terminate the task
+        self.ping_running.store(false, Ordering::Relaxed);
+        let handles = vec![
+            self.ping_handle.take(),
+            self.identify_handle.take(),
+            self.identify_push_handle.take(),
+        ];
+        let handles = handles.into_iter().flatten().collect::<Vec<_>>();
+        if !handles.is_empty() {
+            log::debug!("cancelling {} tasks of dropped {:?}", handles.len(), self.id);
+            task::spawn(async move {
+                for h in handles {
+                    h.cancel().await;
+                }
+            });
+        }
+        // closing the stream_muxer stops the bg-task
+        if self.handle.is_some() && !self.closing {
+            self.close();
         }
     }
 }
@@ -539,6 +827,8 @@ async fn open_stream_internal(
     pids: Vec<ProtocolId>,
     ctrl: mpsc::Sender<SwarmControlCmd>,
     metric: Arc<Metric>,
+    state: Arc<ConnectionState>,
+    negotiation_timeout: Duration,
 ) -> Result<Substream, TransportError> {
     log::debug!("opening substream on {:?} {:?}", cid, pids);
 
@@ -549,16 +839,29 @@ async fn open_stream_internal(
 
     // now it's time to do protocol multiplexing for sub stream
     let negotiator = Negotiator::new_with_protocols(pids);
-    let result = negotiator.select_one(raw_stream).await;
+    let result = task::timeout(negotiation_timeout, negotiator.select_one(raw_stream))
+        .await
+        .map_err(|_| TransportError::Timeout)?;
 
     match result {
-        Ok((proto, raw_stream)) => {
+        Ok((proto, mut raw_stream)) => {
             log::debug!("selected outbound {:?} {:?}", cid, proto);
 
+            if !state.acquire_stream(&proto) {
+                log::debug!("too many concurrent substreams for {} on {:?}", proto, cid);
+                let _ = raw_stream.close().await;
+                return Err(too_many_streams(&proto));
+            }
+
             let ci = ConnectInfo { la, ra, rpid };
-            let stream = Substream::new(raw_stream, metric.clone(), Direction::Outbound, proto, cid, ci, ctrl);
+            let stream = Substream::new(raw_stream, metric.clone(), Direction::Outbound, proto, cid, ci, ctrl, state);
             Ok(stream)
         }
+        // EOF before any reply, the remote has closed the substream instead of negotiating
+        Err(NegotiationError::ProtocolError(ProtocolError::IoError(err))) if err.kind() == io::ErrorKind::UnexpectedEof => {
+            log::debug!("remote closed outbound substream during protocol selection {:?}", cid);
+            Err(TransportError::RemoteClosedDuringNegotiation)
+        }
         Err(err) => {
             log::debug!("failed outbound protocol selection {:?} {:?}", cid, err);
             Err(TransportError::NegotiationError(err))
@@ -568,19 +871,101 @@ async fn open_stream_internal(
 
 /// Information about the network obtained by [`Network::info()`].
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct ConnectionInfo {
     /// The local multiaddr of this connection.
     pub la: Multiaddr,
     /// The remote multiaddr of this connection.
     pub ra: Multiaddr,
     /// The local peer ID.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serialize::display"))]
     pub local_peer_id: PeerId,
     /// The remote peer ID.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serialize::display"))]
     pub remote_peer_id: PeerId,
     /// The total number of inbound sub streams.
     pub num_inbound_streams: usize,
     /// The total number of outbound sub streams.
     pub num_outbound_streams: usize,
+    /// The bytes transferred over this connection.
+    pub bandwidth: BandwidthStats,
     // /// The Sub-streams.
     // pub streams: Vec<StreamStats>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2prs_core::multiaddr::protocol::Protocol;
+    use libp2prs_core::transport::memory::MemoryTransport;
+    use libp2prs_core::transport::upgrade::TransportUpgrade;
+    use libp2prs_core::{Multiaddr, Transport};
+    use libp2prs_secio as secio;
+    use libp2prs_yamux as yamux;
+    use std::time::Instant;
+
+    /// Sets the flag when the task owning it terminates.
+    struct Guard(Arc<AtomicBool>);
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn dropped_connection_stops_tasks() {
+        task::block_on(async {
+            let addr: Multiaddr = Protocol::Memory(1 + rand::random::<u64>()).into();
+            let srv_keys = Keypair::generate_ed25519();
+            let mut srv_tu = TransportUpgrade::new(MemoryTransport::default(), yamux::Config::new(), secio::Config::new(srv_keys));
+            let mut listener = srv_tu.listen_on(addr.clone()).unwrap();
+            task::spawn(async move {
+                let mut stream_muxer = listener.accept_output().await.unwrap();
+                if let Some(bg) = stream_muxer.task() {
+                    bg.await;
+                }
+            });
+
+            let cli_keys = Keypair::generate_ed25519();
+            let mut cli_tu = TransportUpgrade::new(MemoryTransport::default(), yamux::Config::new(), secio::Config::new(cli_keys));
+            let mut stream_muxer = cli_tu.dial(addr).await.unwrap();
+            let bg = stream_muxer.task().unwrap();
+
+            let (tx, _rx) = mpsc::unbounded();
+            let (ctrl, _ctrl_rx) = mpsc::channel(0);
+            let mut connection = Connection::new(
+                0,
+                stream_muxer,
+                Direction::Outbound,
+                tx,
+                ctrl,
+                Arc::new(Metric::new()),
+                Duration::from_secs(10),
+                Default::default(),
+            );
+
+            let bg_stopped = Arc::new(AtomicBool::new(false));
+            let guard = Guard(bg_stopped.clone());
+            connection.set_handle(task::spawn(async move {
+                let _guard = guard;
+                bg.await;
+            }));
+            let ping_stopped = Arc::new(AtomicBool::new(false));
+            let guard = Guard(ping_stopped.clone());
+            connection.ping_handle = Some(task::spawn(async move {
+                let _guard = guard;
+                future::pending::<()>().await;
+            }));
+
+            drop(connection);
+
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while !(bg_stopped.load(Ordering::SeqCst) && ping_stopped.load(Ordering::SeqCst)) && Instant::now() < deadline {
+                task::sleep(Duration::from_millis(10)).await;
+            }
+            assert!(bg_stopped.load(Ordering::SeqCst));
+            assert!(ping_stopped.load(Ordering::SeqCst));
+        });
+    }
+}
@@ -29,12 +29,18 @@ use std::time::Duration;
 
 use crate::connection::{ConnectionId, ConnectionView};
 use crate::identify::IdentifyInfo;
+use crate::metrics::bandwidth::BandwidthStats;
 use crate::metrics::metric::Metric;
-use crate::network::NetworkInfo;
+use crate::network::{NetworkInfo, PeerInfo};
+use crate::notifier::ConnectionEvent;
+use crate::protocol_handler::{Overflow, StreamHandler, StreamHandlerFn};
 use crate::substream::{StreamId, Substream, SubstreamView};
 use crate::{SwarmError, SwarmStats, SWARM_EXIT_FLAG};
+use libp2prs_runtime::task;
 use std::collections::hash_map::IntoIter;
+use std::pin::Pin;
 use std::sync::atomic::Ordering;
+use std::task::{Context, Poll};
 
 type Result<T> = std::result::Result<T, SwarmError>;
 
@@ -55,14 +61,34 @@ pub enum SwarmControlCmd {
     /// Parameter 'bool' means using routing interface(if available) to
     /// lookup multiaddr of the remote peer.
     NewStream(PeerId, Vec<ProtocolId>, bool, oneshot::Sender<Result<Substream>>),
+    /// Open a new stream specified with protocol Ids on the connection specified.
+    NewStreamOn(ConnectionId, Vec<ProtocolId>, oneshot::Sender<Result<Substream>>),
+    /// Close the connection specified, telling the remote the reason with a code and a message.
+    CloseConnectionWithReason(ConnectionId, u32, String, oneshot::Sender<Result<()>>),
+    /// Wait for the frames queued by all streams of the connection specified to be written.
+    FlushBarrier(ConnectionId, oneshot::Sender<Result<()>>),
     /// Close a stream specified.
     CloseStream(ConnectionId, StreamId),
     /// Retrieve the self multi addresses of Swarm.
     SelfAddresses(oneshot::Sender<Vec<Multiaddr>>),
+    /// Retrieve the addresses of the active listeners of Swarm.
+    Listeners(oneshot::Sender<Vec<Multiaddr>>),
     /// Retrieve network information of Swarm.
     NetworkInfo(oneshot::Sender<NetworkInfo>),
     /// Retrieve network information of Swarm.
     IdentifyInfo(oneshot::Sender<IdentifyInfo>),
+    /// Retrieve the aggregated information about a remote peer.
+    PeerInfo(PeerId, oneshot::Sender<Option<PeerInfo>>),
+    /// Drain Swarm, the reply is sent when all connections are gone or the deadline passes.
+    Drain(Duration, oneshot::Sender<()>),
+    /// Register a stream handler for the inbound substreams.
+    SetStreamHandler(StreamHandler, oneshot::Sender<()>),
+    /// Remove the stream handler registered for the protocol.
+    RemoveStreamHandler(ProtocolId, oneshot::Sender<()>),
+    /// Retrieve the protocols Swarm serves.
+    Protocols(oneshot::Sender<Vec<String>>),
+    /// Subscribe to the connection events.
+    Subscribe(oneshot::Sender<mpsc::Receiver<ConnectionEvent>>),
     ///
     Dump(DumpCommand),
 }
@@ -77,6 +103,8 @@ pub enum DumpCommand {
     Streams(PeerId, oneshot::Sender<Result<Vec<SubstreamView>>>),
     /// Dump all statistics of Swarm.
     Statistics(oneshot::Sender<Result<SwarmStats>>),
+    /// Find the connection a substream belongs to.
+    FindConnection(StreamId, oneshot::Sender<Option<ConnectionId>>),
 }
 
 /// The `Swarm` controller.
@@ -139,6 +167,12 @@ impl Control {
         self.metric.get_sent_count_and_size()
     }
 
+    /// Get the node-wide bandwidth totals and rolling rates. The figures of a single
+    /// connection are part of its `ConnectionInfo`.
+    pub fn bandwidth(&self) -> BandwidthStats {
+        self.metric.get_bandwidth()
+    }
+
     /// Get recv&send bytes by protocol_id
     pub fn get_protocol_in_and_out(&self, protocol_id: &str) -> (Option<usize>, Option<usize>) {
         self.metric.get_protocol_in_and_out(protocol_id)
@@ -172,7 +206,11 @@ impl Control {
         self.sender.send(SwarmControlCmd::NewConnection(peer_id, false, tx)).await?;
         rx.await?
     }
-    /// Close connection towards the remote peer.
+    /// Close all connections towards the remote peer.
+    ///
+    /// It returns when all the connections are closed and cleaned up, after the
+    /// `ConnectionEvent::Closed` events have been fired. An error is returned if there
+    /// is no connection to the peer.
     pub async fn disconnect(&mut self, peer_id: PeerId) -> Result<()> {
         let (tx, rx) = oneshot::channel();
         self.sender.send(SwarmControlCmd::CloseConnection(peer_id, tx)).await?;
@@ -198,6 +236,42 @@ impl Control {
         rx.await?
     }
 
+    /// Closes the connection specified, after telling the remote the reason, an application
+    /// defined `code` and `message`, which is surfaced as `ConnectionEvent::DisconnectedWithReason`
+    /// on the remote side.
+    ///
+    /// The connection is closed even if the reason can't be sent, in which case the error
+    /// is returned, e.g. when the remote is not built `with_disconnect_reason`.
+    pub async fn close_connection_with_reason(&mut self, cid: ConnectionId, code: u32, message: &str) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(SwarmControlCmd::CloseConnectionWithReason(cid, code, message.to_string(), tx))
+            .await?;
+        rx.await?
+    }
+
+    /// Waits until all the data written so far on the streams of the connection specified
+    /// has been written to the socket, so that a control message sent on one stream reaches
+    /// the remote before the data sent on other streams afterwards.
+    ///
+    /// An error is returned if the connection is gone, or its stream muxer is not capable
+    /// of it.
+    pub async fn flush_barrier(&mut self, cid: ConnectionId) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.sender.send(SwarmControlCmd::FlushBarrier(cid, tx)).await?;
+        rx.await?
+    }
+
+    /// Open a new outbound stream on the connection specified, instead of the one
+    /// picked by Swarm, when there are multiple connections towards the remote peer.
+    ///
+    /// An error is returned if the connection is gone or being closed.
+    pub async fn new_stream_on(&mut self, cid: ConnectionId, pids: Vec<ProtocolId>) -> Result<Substream> {
+        let (tx, rx) = oneshot::channel();
+        self.sender.send(SwarmControlCmd::NewStreamOn(cid, pids, tx)).await?;
+        rx.await?
+    }
+
     /// Retrieve the all listened addresses from Swarm.
     ///
     /// All listened addresses on interface and the observed addresses
@@ -208,6 +282,16 @@ impl Control {
         Ok(rx.await?)
     }
 
+    /// Retrieve the addresses the active listeners of Swarm are listening on.
+    ///
+    /// Unlike `self_addrs`, the observed addresses are not included. The
+    /// concrete addresses are reported for the wildcard ones, e.g. port 0.
+    pub async fn listeners(&mut self) -> Result<Vec<Multiaddr>> {
+        let (tx, rx) = oneshot::channel();
+        self.sender.send(SwarmControlCmd::Listeners(tx)).await?;
+        Ok(rx.await?)
+    }
+
     /// Retrieve network information from Swarm.
     pub async fn retrieve_networkinfo(&mut self) -> Result<NetworkInfo> {
         let (tx, rx) = oneshot::channel();
@@ -222,6 +306,115 @@ impl Control {
         Ok(rx.await?)
     }
 
+    /// Retrieve the information Swarm knows about the remote peer.
+    ///
+    /// The addresses and protocols come from the peer store, the versions and
+    /// the round-trip time from the Identify and Ping run on the active
+    /// connections. Returns `None` if the peer is unknown.
+    pub async fn peer_info(&mut self, peer_id: PeerId) -> Result<Option<PeerInfo>> {
+        let (tx, rx) = oneshot::channel();
+        self.sender.send(SwarmControlCmd::PeerInfo(peer_id, tx)).await?;
+        Ok(rx.await?)
+    }
+
+    /// Puts Swarm into the drain state, before shutting it down.
+    ///
+    /// No new connection is made any more, neither inbound nor outbound, the
+    /// dials fail with `SwarmError::Draining`. The existing connections keep
+    /// serving, each of them is closed as soon as it has no stream left. The
+    /// ones still in use are closed when `deadline` passes.
+    ///
+    /// Returns when all connections are closed.
+    pub async fn drain(&mut self, deadline: Duration) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.sender.send(SwarmControlCmd::Drain(deadline, tx)).await?;
+        Ok(rx.await?)
+    }
+
+    /// Sets the handler for the inbound substreams negotiated for the protocol.
+    ///
+    /// The handler is an async closure which will be invoked with each accepted
+    /// `Substream` in a newly spawned runtime. It replaces the handler previously
+    /// set for the same protocol, and takes effect on the existing connections
+    /// as well. Note that the protocol handlers added via `Swarm::with_protocol`
+    /// always take precedence.
+    pub async fn set_stream_handler<F, Fut>(&mut self, protocol: ProtocolId, handler: F) -> Result<()>
+    where
+        F: Fn(Substream) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let f: StreamHandlerFn = Arc::new(move |stream: Substream| handler(stream).boxed());
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(SwarmControlCmd::SetStreamHandler(StreamHandler::new(protocol, f), tx))
+            .await?;
+        Ok(rx.await?)
+    }
+
+    /// Like `set_stream_handler`, but at most `max` invocations of the handler run at the
+    /// same time, on all connections. The inbound substreams beyond are either queued up
+    /// until a running invocation finishes, or reset, as specified by `overflow`.
+    pub async fn set_stream_handler_with_limit<F, Fut>(
+        &mut self,
+        protocol: ProtocolId,
+        max: usize,
+        overflow: Overflow,
+        handler: F,
+    ) -> Result<()>
+    where
+        F: Fn(Substream) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let f: StreamHandlerFn = Arc::new(move |stream: Substream| handler(stream).boxed());
+        let h = StreamHandler::new(protocol, f).with_limit(max, overflow);
+        let (tx, rx) = oneshot::channel();
+        self.sender.send(SwarmControlCmd::SetStreamHandler(h, tx)).await?;
+        Ok(rx.await?)
+    }
+
+    /// Removes the stream handler set for the protocol, which is no longer negotiated for
+    /// the inbound substreams afterwards. The protocol handlers added via `Swarm::with_protocol`
+    /// can't be removed.
+    pub async fn remove_stream_handler(&mut self, protocol: ProtocolId) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.sender.send(SwarmControlCmd::RemoveStreamHandler(protocol, tx)).await?;
+        Ok(rx.await?)
+    }
+
+    /// Retrieve the protocols Swarm currently serves, i.e. the ones of the protocol
+    /// handlers and the stream handlers. These are the protocols Identify reports.
+    pub async fn protocols(&mut self) -> Result<Vec<String>> {
+        let (tx, rx) = oneshot::channel();
+        self.sender.send(SwarmControlCmd::Protocols(tx)).await?;
+        Ok(rx.await?)
+    }
+
+    /// Sets a stream handler for the protocol which queues up the inbound substreams, so
+    /// that they can be pulled from the returned `InboundStreams`, instead of being handled
+    /// by a callback.
+    ///
+    /// Like `set_stream_handler`, it replaces the handler previously set for the protocol.
+    pub async fn inbound_streams(&mut self, protocol: ProtocolId) -> Result<InboundStreams> {
+        let (tx, rx) = mpsc::unbounded();
+        self.set_stream_handler(protocol, move |stream| {
+            let _ = tx.unbounded_send(stream);
+            future::ready(())
+        })
+        .await?;
+        Ok(InboundStreams { rx })
+    }
+
+    /// Subscribes to the connection events of `Swarm`.
+    ///
+    /// Every subscriber receives its own copy of the events, which are established,
+    /// closed and identified connections. A subscriber falling behind gets
+    /// `ConnectionEvent::Lagged` instead of the events it has missed.
+    pub async fn subscribe(&mut self) -> Result<impl Stream<Item = ConnectionEvent> + Unpin> {
+        let (tx, rx) = oneshot::channel();
+        self.sender.send(SwarmControlCmd::Subscribe(tx)).await?;
+        Ok(rx.await?)
+    }
+
     pub async fn dump_connections(&mut self, peer_id: Option<PeerId>) -> Result<Vec<ConnectionView>> {
         let (tx, rx) = oneshot::channel();
         self.sender
@@ -242,6 +435,30 @@ impl Control {
         rx.await?
     }
 
+    /// Finds the connection the substream belongs to, e.g. for a stream id from a log line.
+    ///
+    /// The stream ids are allocated by the stream muxer of each connection, so the same id
+    /// might be in use on several connections, the first one found is returned then.
+    pub async fn find_connection(&mut self, sid: StreamId) -> Result<Option<ConnectionId>> {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(SwarmControlCmd::Dump(DumpCommand::FindConnection(sid, tx)))
+            .await?;
+        Ok(rx.await?)
+    }
+
+    /// Returns a stream of the statistics of `Swarm`, sampled every `interval`.
+    ///
+    /// The stream ends when `Swarm` exits.
+    pub fn stats_stream(&self, interval: Duration) -> impl Stream<Item = SwarmStats> + Unpin {
+        let samples = stream::unfold(self.clone(), move |mut control| async move {
+            task::sleep(interval).await;
+            let stats = control.dump_statistics().await.ok()?;
+            Some((stats, control))
+        });
+        Box::pin(samples)
+    }
+
     /// Close the swarm.
     pub fn close(&mut self) {
         // simply close the tx, then exit the main loop
@@ -278,6 +495,11 @@ impl Control {
         self.peer_store.get_addrs(peer_id)
     }
 
+    /// Gets all multiaddr of a peer, the ones succeeded recently in dialing first.
+    pub fn addrs_prioritized(&self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        self.peer_store.addrs_prioritized(peer_id)
+    }
+
     /// Adds a address to address_book by peer_id, if exists, update rtt.
     pub fn add_addr(&self, peer_id: &PeerId, addr: Multiaddr, ttl: Duration) {
         self.peer_store.add_addr(peer_id, addr, ttl)
@@ -323,3 +545,26 @@ impl Control {
         self.peer_store.support_protocols(peer_id, protos)
     }
 }
+
+/// The inbound substreams negotiated for a protocol, returned by `Control::inbound_streams`.
+pub struct InboundStreams {
+    rx: mpsc::UnboundedReceiver<Substream>,
+}
+
+impl InboundStreams {
+    /// Waits for the next inbound substream, for at most `timeout`.
+    ///
+    /// Returns `None` if no substream arrives in time, which allows a server loop to do
+    /// some periodic maintenance in between, or if `Swarm` has exited.
+    pub async fn next_inbound_stream_timeout(&mut self, timeout: Duration) -> Option<Substream> {
+        task::timeout(timeout, self.rx.next()).await.ok().flatten()
+    }
+}
+
+impl Stream for InboundStreams {
+    type Item = Substream;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_next_unpin(cx)
+    }
+}
@@ -31,7 +31,10 @@ use futures::lock::Mutex;
 use futures::prelude::*;
 
 use libp2prs_core::muxing::IStreamMuxer;
+use libp2prs_core::peerstore::PeerStore;
 use libp2prs_core::transport::upgrade::ITransportEx;
+use libp2prs_core::transport::TransportError;
+use libp2prs_core::util::Semaphore;
 use libp2prs_core::{
     multiaddr::{protocol, Multiaddr},
     PeerId,
@@ -39,7 +42,7 @@ use libp2prs_core::{
 use libp2prs_runtime::task;
 
 use crate::connection::Direction;
-use crate::{SwarmError, SwarmEvent, TransactionId, Transports};
+use crate::{DialErrorKind, SwarmError, SwarmEvent, TransactionId, Transports};
 use libp2prs_core::routing::IRouting;
 
 type Result<T> = std::result::Result<T, SwarmError>;
@@ -48,7 +51,7 @@ type Result<T> = std::result::Result<T, SwarmError>;
 const CONCURRENT_DIALS_LIMIT: u32 = 500;
 
 /// DIAL_TIMEOUT is the maximum duration a Dial is allowed to take.This includes the time between dialing the raw network connection,protocol selection as well the handshake, if applicable.
-const DIAL_TIMEOUT: Duration = Duration::from_secs(20);
+pub(crate) const DIAL_TIMEOUT: Duration = Duration::from_secs(20);
 
 /// DIAL_TIMEOUT_LOCAL is the maximum duration a Dial to local network address is allowed to take.This includes the time between dialing the raw network connection,protocol selection as well the handshake, if applicable.
 const DIAL_TIMEOUT_LOCAL: Duration = Duration::from_secs(2);
@@ -82,6 +85,7 @@ struct DialerStats {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct DialerStatsView {
     /// Dialing in progress.
     pub in_progress: usize,
@@ -124,6 +128,9 @@ struct DialJob {
 struct DialLimiter {
     dial_consuming: Arc<AtomicU32>,
     dial_limit: u32,
+    /// Queues the dials beyond the concurrency factor, if any. A dial in flight holds a
+    /// permit, given back once it is done.
+    slots: Option<Semaphore>,
 }
 
 impl DialLimiter {
@@ -137,6 +144,7 @@ impl DialLimiter {
         DialLimiter {
             dial_consuming: Arc::new(AtomicU32::new(0)),
             dial_limit,
+            slots: None,
         }
     }
 
@@ -152,6 +160,15 @@ impl DialLimiter {
     async fn do_dial_job(&self, mut dj: DialJob) {
         log::debug!("[DialLimiter] executing job through limiter, {} {}", dj.peer, dj.addr);
 
+        // given back when dropped, even if the dial job is cancelled
+        let _slot = match &self.slots {
+            Some(slots) => {
+                log::trace!("[DialLimiter] waiting for a dial slot: peer: {}; addr: {}", dj.peer, dj.addr);
+                Some(slots.acquire().await)
+            }
+            None => None,
+        };
+
         if self.dial_consuming.load(Ordering::SeqCst) >= self.dial_limit {
             log::debug!(
                 "[DialLimiter] Terminate while waiting on dial token; peer: {}; addr: {}; consuming: {:?}; limit: {:?};",
@@ -188,7 +205,12 @@ impl DialLimiter {
             } else {
                 dj.stats.dialing_success.fetch_add(1, Ordering::SeqCst);
             }
-            let _ = dj.tx.send((r.map_err(|e| e.into()), dj.addr)).await;
+            // the peer denied by the gater is not a transport failure of the address
+            let r = r.map_err(|e| match e {
+                TransportError::SecuredDenied(addr) => SwarmError::ConnectionGated(addr),
+                e => e.into(),
+            });
+            let _ = dj.tx.send((r, dj.addr)).await;
         } else {
             dj.stats.dialing_timeout.fetch_add(1, Ordering::SeqCst);
             let _ = dj
@@ -347,6 +369,8 @@ pub(crate) struct AsyncDialer {
     stats: Arc<DialerStats>,
     handle: mpsc::Sender<()>,
     attempts: u32,
+    /// The peer store, where the dial history of the addresses is kept.
+    peer_store: PeerStore,
 }
 
 #[derive(Clone)]
@@ -359,6 +383,9 @@ struct DialParam {
     backoff: DialBackoff,
     stats: Arc<DialerStats>,
     attempts: u32,
+    peer_store: PeerStore,
+    /// Reports the failed security handshakes to Swarm.
+    event_sender: mpsc::UnboundedSender<SwarmEvent>,
 }
 
 impl Drop for AsyncDialer {
@@ -369,7 +396,7 @@ impl Drop for AsyncDialer {
 }
 
 impl AsyncDialer {
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(peer_store: PeerStore) -> Self {
         let mut attempts = DIAL_ATTEMPTS;
         for (key, value) in std::env::vars() {
             if key == "LIBP2P_SWARM_DIAL_ATTEMPTS" {
@@ -389,9 +416,15 @@ impl AsyncDialer {
             attempts,
             handle,
             stats: Arc::new(Default::default()),
+            peer_store,
         }
     }
 
+    /// Limits the outbound dials in flight to `factor`, the dials beyond it wait for a slot.
+    pub(crate) fn set_concurrency_factor(&mut self, factor: usize) {
+        self.limiter.slots = Some(Semaphore::new(factor));
+    }
+
     pub(crate) fn dial(
         &self,
         peer_id: PeerId,
@@ -409,6 +442,8 @@ impl AsyncDialer {
             backoff: self.backoff.clone(),
             stats: self.stats.clone(),
             attempts: self.attempts,
+            peer_store: self.peer_store.clone(),
+            event_sender: event_sender.clone(),
         };
 
         self.stats.total_attempts.fetch_add(1, Ordering::SeqCst);
@@ -458,6 +493,9 @@ impl AsyncDialer {
 
     async fn start_dialing(dial_param: DialParam) -> Result<IStreamMuxer> {
         let mut dial_count: u32 = 0;
+        // the classification of the last attempt, the addresses it failed are in backoff
+        // for the next attempts, which must not hide why they failed
+        let mut kind = None;
         loop {
             dial_count += 1;
 
@@ -465,6 +503,14 @@ impl AsyncDialer {
             let r = AsyncDialer::dial_addrs(active_param).await;
             if let Err(e) = r {
                 log::debug!("[Dialer] dialer failed at attempt={} error={:?}", dial_count, e);
+                if let SwarmError::ConnectionGated(_) = e {
+                    // no point in trying again, the gater would deny it again
+                    break Err(e);
+                }
+                match e {
+                    SwarmError::DialBackoff if kind.is_some() => {}
+                    _ => kind = e.dial_error_kind(),
+                }
                 if dial_count < dial_param.attempts {
                     log::debug!(
                         "[Dialer] All addresses of {:?} cannot be dialed to. Now try dialing again, attempts={}",
@@ -474,7 +520,8 @@ impl AsyncDialer {
                     //TODO:
                     task::sleep(BACKOFF_BASE).await;
                 } else if dial_param.attempts > 1 {
-                    break Err(SwarmError::MaxDialAttempts(dial_param.attempts));
+                    let kind = kind.unwrap_or(DialErrorKind::Unreachable);
+                    break Err(SwarmError::MaxDialAttempts(dial_param.attempts, kind));
                 } else {
                     break Err(e);
                 }
@@ -521,8 +568,8 @@ impl AsyncDialer {
             return Err(SwarmError::DialBackoff);
         }
 
-        // ranking all addresses
-        let addrs_rank = AsyncDialer::rank_addrs(addrs);
+        // ranking all addresses, the ones succeeded recently go first regardless of the type
+        let addrs_rank = param.peer_store.prioritize_addrs(&peer_id, AsyncDialer::rank_addrs(addrs));
 
         // dialing all addresses
         let (tx, rx) = mpsc::unbounded::<(Result<IStreamMuxer>, Multiaddr)>();
@@ -569,6 +616,8 @@ impl AsyncDialer {
         jobs: usize,
         param: DialParam,
     ) -> Result<IStreamMuxer> {
+        // the classification of the last failed job
+        let mut kind = DialErrorKind::Unreachable;
         for i in 0..jobs {
             let peer_id = param.peer_id;
             let r = rx.next().await;
@@ -582,6 +631,7 @@ impl AsyncDialer {
                     // it is a bad outgoing connection
                     if peer_id == reported_pid {
                         log::debug!("[Dialer] job for {:?} succeeded, {:?}", peer_id, stream_muxer);
+                        param.peer_store.record_dial_success(&peer_id, &addr);
                         // return here, ignore the rest of jobs
                         return Ok(stream_muxer);
                     } else {
@@ -592,12 +642,27 @@ impl AsyncDialer {
                             reported_pid
                         );
                         param.stats.mismatch_peer_id.fetch_add(1, Ordering::SeqCst);
+                        param.peer_store.record_dial_failure(&peer_id, &addr);
                         param.backoff.add_peer(peer_id, addr).await;
+                        kind = DialErrorKind::HandshakeFailed;
                     }
                 }
+                Some((Err(SwarmError::ConnectionGated(addr)), _)) => {
+                    // the gater denies the peer rather than the address, the rest would be denied as well
+                    log::debug!("[Dialer] job for {:?} denied by the gater: addr={:?}", peer_id, addr);
+                    return Err(SwarmError::ConnectionGated(addr));
+                }
                 Some((Err(err), addr)) => {
                     log::debug!("[Dialer] job for {:?} failed: addr={:?},error={:?}", peer_id, addr.clone(), err);
-                    if let SwarmError::Transport(_) = err {
+                    kind = err.dial_error_kind().unwrap_or(DialErrorKind::Unreachable);
+                    param.peer_store.record_dial_failure(&peer_id, &addr);
+                    if let SwarmError::Transport(e) = err {
+                        if let Some(kind) = e.handshake_error_kind() {
+                            let _ = param.event_sender.unbounded_send(SwarmEvent::HandshakeFailed {
+                                remote_addr: addr.clone(),
+                                kind,
+                            });
+                        }
                         // add to backoff list if transport error reported
                         param.backoff.add_peer(peer_id, addr).await;
                     }
@@ -608,7 +673,7 @@ impl AsyncDialer {
             }
         }
 
-        Err(SwarmError::AllDialsFailed)
+        Err(SwarmError::AllDialsFailed(kind))
     }
 
     /// ranks addresses in descending order of preference for dialing   Private UDP > Public UDP > Private TCP > Public TCP > UDP Relay server > TCP Relay server
@@ -658,10 +723,46 @@ impl AsyncDialer {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use libp2prs_core::{Multiaddr, PeerId};
+    use libp2prs_core::identity::Keypair;
+    use libp2prs_core::transport::memory::MemoryTransport;
+    use libp2prs_core::transport::upgrade::TransportUpgrade;
+    use libp2prs_core::transport::ListenerEvent;
+    use libp2prs_core::{Multiaddr, PeerId, Transport};
+    use libp2prs_dns::{DnsConfig, DnsMode};
+    use libp2prs_tcp::TcpConfig;
+    use std::collections::HashMap;
     use std::str::FromStr;
     use std::time::Duration;
 
+    /// Makes the parameters of dialing the address in the given number of attempts.
+    fn dial_param(transport: ITransportEx, addr: Multiaddr, attempts: u32) -> DialParam {
+        let mut transports = Transports::default();
+        for id in transport.protocols() {
+            transports.add(id, transport.box_clone());
+        }
+
+        let dialer = AsyncDialer::new(PeerStore::default());
+        DialParam {
+            transports,
+            addrs: EitherDialAddr::Addresses(vec![addr]),
+            peer_id: PeerId::random(),
+            tid: 0,
+            limiter: dialer.limiter.clone(),
+            backoff: dialer.backoff.clone(),
+            stats: dialer.stats.clone(),
+            attempts,
+            peer_store: PeerStore::default(),
+            event_sender: mpsc::unbounded().0,
+        }
+    }
+
+    /// Returns a TCP address nobody is listening on.
+    fn refused_addr() -> Multiaddr {
+        // grab a free port, then close the listener so that nobody is listening on it
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        Multiaddr::from_str(&format!("/ip4/127.0.0.1/tcp/{}", port)).unwrap()
+    }
+
     #[test]
     fn test_dial_find_peer() {
         let ab = DialBackoff::new();
@@ -738,4 +839,70 @@ mod tests {
         assert_eq!(r1, true);
         assert_eq!(r2, false);
     }
+
+    #[test]
+    fn test_dial_refused_classified() {
+        let sec = libp2prs_secio::Config::new(Keypair::generate_ed25519());
+        let transport = TransportUpgrade::new(TcpConfig::new(), libp2prs_yamux::Config::new(), sec);
+
+        let r = task::block_on(AsyncDialer::start_dialing(dial_param(Box::new(transport), refused_addr(), 1)));
+        match r {
+            Err(SwarmError::AllDialsFailed(kind)) => assert_eq!(kind, DialErrorKind::ConnectionRefused),
+            _ => panic!("unexpected result"),
+        }
+    }
+
+    #[test]
+    fn test_dial_dns_failure_classified() {
+        // nothing resolves with an empty hosts table
+        let dial_addr = Multiaddr::from_str("/dns4/libp2p.test/tcp/4001").unwrap();
+        let dns = DnsConfig::new(TcpConfig::new()).dns_mode(DnsMode::Hosts(HashMap::new()));
+        let sec = libp2prs_secio::Config::new(Keypair::generate_ed25519());
+        let transport = TransportUpgrade::new(dns, libp2prs_yamux::Config::new(), sec);
+
+        let r = task::block_on(AsyncDialer::start_dialing(dial_param(Box::new(transport), dial_addr, 1)));
+        match r {
+            Err(e) => assert_eq!(e.dial_error_kind(), Some(DialErrorKind::DnsFailure)),
+            _ => panic!("unexpected result"),
+        }
+    }
+
+    #[test]
+    fn test_dial_attempts_keep_kind() {
+        let sec = libp2prs_secio::Config::new(Keypair::generate_ed25519());
+        let transport = TransportUpgrade::new(TcpConfig::new(), libp2prs_yamux::Config::new(), sec);
+
+        // the second attempt finds the address in backoff, but the failure is still a refused connection
+        let r = task::block_on(AsyncDialer::start_dialing(dial_param(Box::new(transport), refused_addr(), 2)));
+        match r {
+            Err(SwarmError::MaxDialAttempts(2, kind)) => assert_eq!(kind, DialErrorKind::ConnectionRefused),
+            _ => panic!("unexpected result"),
+        }
+    }
+
+    #[test]
+    fn test_dial_gated_not_retried() {
+        let dial_addr = Multiaddr::from_str(&format!("/memory/{}", 1 + rand::random::<u64>())).unwrap();
+        let sec = libp2prs_secio::Config::new(Keypair::generate_ed25519());
+        let mut listener = TransportUpgrade::new(MemoryTransport::default(), libp2prs_yamux::Config::new(), sec)
+            .listen_on(dial_addr.clone())
+            .unwrap();
+        task::spawn(async move {
+            let mut muxers = vec![];
+            while let Ok(ListenerEvent::Accepted(muxer)) = listener.accept().await {
+                muxers.push(muxer);
+            }
+        });
+
+        let sec = libp2prs_secio::Config::new(Keypair::generate_ed25519());
+        let mut transport = TransportUpgrade::new(MemoryTransport::default(), libp2prs_yamux::Config::new(), sec);
+        transport.set_secured_filter(Arc::new(|_: &PeerId| false));
+
+        // denied once secured, without trying again
+        let r = task::block_on(AsyncDialer::start_dialing(dial_param(Box::new(transport), dial_addr.clone(), 2)));
+        match r {
+            Err(SwarmError::ConnectionGated(addr)) => assert_eq!(addr, dial_addr),
+            _ => panic!("unexpected result"),
+        }
+    }
 }
@@ -0,0 +1,119 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Closing a connection with a reason.
+//!
+//! Before closing the connection, the reason is sent to the remote on a sub stream of the
+//! reserved protocol, as an application defined code and message. The remote surfaces it as
+//! `ConnectionEvent::DisconnectedWithReason`, then closes the sub stream, which lets the
+//! connection be closed. The remote takes the reason only if it is built with
+//! `Swarm::with_disconnect_reason`.
+
+use async_trait::async_trait;
+use futures::channel::mpsc;
+use futures::SinkExt;
+use std::{error::Error, io};
+
+use libp2prs_core::upgrade::UpgradeInfo;
+use libp2prs_core::{ProtocolId, ReadEx, WriteEx};
+
+use crate::protocol_handler::{IProtocolHandler, Notifiee, ProtocolHandler};
+use crate::substream::Substream;
+use crate::SwarmEvent;
+
+/// The reserved protocol for telling the remote why the connection is closed.
+pub const DISCONNECT_PROTOCOL: &[u8] = b"/libp2p-rs/disconnect/1.0.0";
+
+/// The maximum length of the message of a reason.
+const MAX_MESSAGE_LEN: usize = 1024;
+
+/// Sends the reason, then waits for the remote to close the sub stream once it got it.
+pub(crate) async fn send_reason(mut stream: Substream, code: u32, message: &str) -> io::Result<()> {
+    stream.write_fixed_u32(code as usize).await?;
+    stream.write_one(message.as_bytes()).await?;
+    stream.close_write().await?;
+
+    // the muxers report the end of the sub stream as UnexpectedEof
+    let mut buf = [0u8; 1];
+    loop {
+        match stream.read2(&mut buf).await {
+            Ok(0) => return Ok(()),
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Receives the reason, a code and a message, the message is cut at `MAX_MESSAGE_LEN`.
+pub(crate) async fn recv_reason(stream: &mut Substream) -> io::Result<(u32, String)> {
+    let code = stream.read_fixed_u32().await? as u32;
+    let message = stream.read_one(MAX_MESSAGE_LEN).await?;
+    Ok((code, String::from_utf8_lossy(&message).into_owned()))
+}
+
+/// Handles the reason sent by the remote, closing the connection.
+#[derive(Clone)]
+pub(crate) struct DisconnectHandler {
+    tx: mpsc::UnboundedSender<SwarmEvent>,
+}
+
+impl DisconnectHandler {
+    pub(crate) fn new(tx: mpsc::UnboundedSender<SwarmEvent>) -> Self {
+        Self { tx }
+    }
+}
+
+impl UpgradeInfo for DisconnectHandler {
+    type Info = ProtocolId;
+    fn protocol_info(&self) -> Vec<Self::Info> {
+        vec![DISCONNECT_PROTOCOL.into()]
+    }
+}
+
+impl Notifiee for DisconnectHandler {}
+
+#[async_trait]
+impl ProtocolHandler for DisconnectHandler {
+    async fn handle(&mut self, mut stream: Substream, _info: <Self as UpgradeInfo>::Info) -> Result<(), Box<dyn Error>> {
+        let cid = stream.cid();
+        let peer_id = stream.remote_peer();
+        let (code, message) = recv_reason(&mut stream).await?;
+        log::debug!("{:?} of {:?} disconnected with reason {} {:?}", cid, peer_id, code, message);
+
+        let _ = self
+            .tx
+            .send(SwarmEvent::DisconnectedWithReason {
+                cid,
+                peer_id,
+                code,
+                message,
+            })
+            .await;
+
+        // tells the remote the reason is received
+        stream.close2().await?;
+        Ok(())
+    }
+
+    fn box_clone(&self) -> IProtocolHandler {
+        Box::new(self.clone())
+    }
+}
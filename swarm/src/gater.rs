@@ -0,0 +1,132 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Connection gating.
+//!
+//! A [`ConnectionGater`] can be installed into [`Swarm`] to allow or deny a connection
+//! at the various stages of its lifecycle, so that the unwanted peers or addresses can be
+//! blocked as early as possible. The connection will be dropped immediately once denied
+//! by any of the hooks.
+//!
+//! - `intercept_accept` is consulted when an inbound connection is accepted by a listener,
+//!   before the security handshake is started on it.
+//! - `intercept_secured` is consulted when the remote peer Id of a connection is known,
+//!   for both inbound and outbound connections, before the stream muxer is selected.
+//! - `intercept_upgraded` is consulted when the connection is fully upgraded, right before
+//!   it is taken by `Swarm`.
+//!
+//! Every denied connection is reported to the subscribers as `ConnectionEvent::Gated`.
+
+use libp2prs_core::transport::SecuredFilter;
+use libp2prs_core::{Multiaddr, PeerId};
+use std::sync::Arc;
+
+use crate::connection::{ConnectionInfo, Direction};
+
+/// The connection gater, all hooks allow the connection by default.
+pub trait ConnectionGater {
+    /// Tests whether an inbound connection from the remote address is allowed.
+    fn intercept_accept(&self, _remote_addr: &Multiaddr) -> bool {
+        true
+    }
+    /// Tests whether a connection with the remote peer is allowed, after the security
+    /// handshake is done.
+    fn intercept_secured(&self, _remote_peer: &PeerId, _dir: Direction) -> bool {
+        true
+    }
+    /// Tests whether a fully upgraded connection is allowed.
+    fn intercept_upgraded(&self, _info: &ConnectionInfo) -> bool {
+        true
+    }
+}
+
+/// The trait object of ConnectionGater, shared by Swarm and its listeners.
+pub type IConnectionGater = Arc<dyn ConnectionGater + Send + Sync>;
+
+/// Makes the filter consulting the gater, once the outbound connections are secured.
+pub(crate) fn outbound_secured_filter(gater: IConnectionGater) -> SecuredFilter {
+    Arc::new(move |remote_peer: &PeerId| gater.intercept_secured(remote_peer, Direction::Outbound))
+}
+
+/// Makes the filter consulting the gater, once the inbound connections are secured.
+pub(crate) fn inbound_secured_filter(gater: IConnectionGater) -> SecuredFilter {
+    Arc::new(move |remote_peer: &PeerId| gater.intercept_secured(remote_peer, Direction::Inbound))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2prs_core::identity::Keypair;
+    use libp2prs_core::transport::memory::MemoryTransport;
+    use libp2prs_core::transport::upgrade::TransportUpgrade;
+    use libp2prs_core::transport::{TransportError, TransportListener};
+    use libp2prs_core::Transport;
+    use libp2prs_runtime::task;
+    use libp2prs_secio as secio;
+    use libp2prs_yamux as yamux;
+    use std::sync::Mutex;
+
+    /// Denies the secured connections of the given direction, recording the peers it is asked about.
+    struct DenySecured(Direction, Mutex<Vec<(PeerId, Direction)>>);
+
+    impl ConnectionGater for DenySecured {
+        fn intercept_secured(&self, remote_peer: &PeerId, dir: Direction) -> bool {
+            self.1.lock().unwrap().push((*remote_peer, dir));
+            self.0 != dir
+        }
+    }
+
+    /// Dials from a transport with the outbound filter to a listener with the inbound filter,
+    /// returning the results of both ends.
+    fn secured_by(gater: IConnectionGater, dialer: Keypair) -> (bool, Option<TransportError>) {
+        let addr: Multiaddr = format!("/memory/{}", 1 + rand::random::<u64>()).parse().unwrap();
+        let sec = secio::Config::new(Keypair::generate_ed25519());
+        let mut listener = TransportUpgrade::new(MemoryTransport::default(), yamux::Config::new(), sec)
+            .listen_on(addr.clone())
+            .unwrap();
+        listener.set_secured_filter(inbound_secured_filter(gater.clone()));
+
+        let mut transport = TransportUpgrade::new(MemoryTransport::default(), yamux::Config::new(), secio::Config::new(dialer));
+        transport.set_secured_filter(outbound_secured_filter(gater));
+
+        task::block_on(async move {
+            let (dialed, accepted) = futures::join!(transport.dial(addr), listener.accept());
+            let denied = matches!(dialed, Err(TransportError::SecuredDenied(_)));
+            (denied, accepted.err())
+        })
+    }
+
+    #[test]
+    fn secured_filters_tell_the_direction() {
+        let dialer = Keypair::generate_ed25519();
+        let dialer_id = dialer.public().into_peer_id();
+        let gater = Arc::new(DenySecured(Direction::Inbound, Mutex::new(vec![])));
+        let (denied, accepted) = secured_by(gater.clone(), dialer);
+        assert!(!denied);
+        assert!(matches!(accepted, Some(TransportError::SecuredDenied(_))));
+        assert!(gater.1.lock().unwrap().contains(&(dialer_id, Direction::Inbound)));
+
+        let gater = Arc::new(DenySecured(Direction::Outbound, Mutex::new(vec![])));
+        let (denied, accepted) = secured_by(gater.clone(), Keypair::generate_ed25519());
+        assert!(denied);
+        assert!(!matches!(accepted, Some(TransportError::SecuredDenied(_))));
+        assert!(gater.1.lock().unwrap().iter().any(|(_, dir)| *dir == Direction::Outbound));
+    }
+}
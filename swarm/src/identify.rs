@@ -44,6 +44,7 @@ use prost::Message;
 use std::convert::TryFrom;
 use std::{error::Error, io};
 
+use libp2prs_core::multiaddr::protocol::Protocol;
 use libp2prs_core::transport::TransportError;
 use libp2prs_core::upgrade::UpgradeInfo;
 use libp2prs_core::{Multiaddr, ProtocolId, PublicKey, ReadEx, WriteEx};
@@ -61,19 +62,86 @@ mod structs_proto {
 pub const IDENTIFY_PROTOCOL: &[u8] = b"/ipfs/id/1.0.0";
 pub const IDENTIFY_PUSH_PROTOCOL: &[u8] = b"/ipfs/id/push/1.0.0";
 
+/// The maximum size of a chunk of the identify message, which is also the size of the
+/// single frame the peers not supporting chunks can read.
+const MAX_CHUNK_SIZE: usize = 4096;
+/// The maximum size of the whole identify message.
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+/// The policy of keeping the private addresses advertised by the peers, the
+/// loopback, link-local and private network ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PrivateAddrPolicy {
+    /// Keeps the private addresses.
+    Keep,
+    /// Drops the private addresses of the peers connected over a public address.
+    DropIfPublic,
+    /// Drops the private addresses.
+    Drop,
+}
+
+impl Default for PrivateAddrPolicy {
+    fn default() -> Self {
+        PrivateAddrPolicy::DropIfPublic
+    }
+}
+
 /// The configuration for identify.
 #[derive(Clone, Debug, Default)]
 pub struct IdentifyConfig {
     /// Starts the Push service.
     pub(crate) push: bool,
+    /// The policy of keeping the private addresses advertised by the peers.
+    pub(crate) private_addrs: PrivateAddrPolicy,
 }
 
 impl IdentifyConfig {
     pub fn new(push: bool) -> Self {
-        Self { push }
+        Self {
+            push,
+            private_addrs: Default::default(),
+        }
+    }
+
+    /// Sets the policy of keeping the private addresses advertised by the peers,
+    /// `PrivateAddrPolicy::DropIfPublic` by default.
+    pub fn with_private_addrs(mut self, policy: PrivateAddrPolicy) -> Self {
+        self.private_addrs = policy;
+        self
+    }
+
+    /// Filters the addresses advertised by the peer connected over `remote_addr`,
+    /// dropping the invalid ones and the private ones according to the policy.
+    pub(crate) fn filter_listen_addrs(&self, addrs: Vec<Multiaddr>, remote_addr: &Multiaddr) -> Vec<Multiaddr> {
+        let drop_private = match self.private_addrs {
+            PrivateAddrPolicy::Keep => false,
+            PrivateAddrPolicy::DropIfPublic => {
+                let is_ip = remote_addr.iter().any(|p| matches!(p, Protocol::Ip4(_) | Protocol::Ip6(_)));
+                is_ip && !remote_addr.is_private_addr()
+            }
+            PrivateAddrPolicy::Drop => true,
+        };
+
+        addrs
+            .into_iter()
+            .filter(|addr| is_valid_listen_addr(addr) && !(drop_private && addr.is_private_addr()))
+            .collect()
     }
 }
 
+/// Returns false if nobody could dial `addr`, e.g. an unspecified IP address or port 0.
+fn is_valid_listen_addr(addr: &Multiaddr) -> bool {
+    if addr.iter().next().is_none() {
+        return false;
+    }
+    addr.iter().all(|p| match p {
+        Protocol::Ip4(ip) => !(ip.is_unspecified() || ip.is_broadcast() || ip.is_multicast()),
+        Protocol::Ip6(ip) => !(ip.is_unspecified() || ip.is_multicast()),
+        Protocol::Tcp(port) | Protocol::Udp(port) => port != 0,
+        _ => true,
+    })
+}
+
 /// Information of a peer sent in `Identify` protocol responses.
 #[derive(Debug, Clone)]
 pub struct IdentifyInfo {
@@ -130,8 +198,26 @@ fn parse_proto_msg(msg: impl AsRef<[u8]>) -> Result<(IdentifyInfo, Multiaddr), i
 }
 
 pub(crate) async fn process_message(mut stream: Substream) -> Result<(IdentifyInfo, Multiaddr), TransportError> {
-    let buf = stream.read_one(4096).await?;
-    stream.close().await?;
+    let mut buf = stream.read_one(MAX_CHUNK_SIZE).await?;
+    // A large message comes in several chunks, until the remote closes the stream. The
+    // concatenation of protobuf messages is the merge of them.
+    loop {
+        match stream.read_one(MAX_CHUNK_SIZE).await {
+            Ok(chunk) if chunk.is_empty() => break,
+            Ok(chunk) => {
+                if buf.len() + chunk.len() > MAX_MESSAGE_SIZE {
+                    let msg = format!("identify message over maximum size: {}", MAX_MESSAGE_SIZE);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, msg).into());
+                }
+                buf.extend_from_slice(&chunk);
+            }
+            // the end of the stream, which a raw memory socket reports as BrokenPipe
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof || e.kind() == io::ErrorKind::BrokenPipe => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    // the remote has closed the stream after the last chunk, and might be gone already
+    let _ = stream.close().await;
 
     parse_proto_msg(&buf).map_err(io::Error::into)
 }
@@ -152,14 +238,51 @@ pub(crate) async fn produce_message(mut stream: Substream, info: IdentifyInfo) -
         protocols: info.protocols,
     };
 
-    let mut bytes = Vec::with_capacity(message.encoded_len());
-    message.encode(&mut bytes).expect("Vec<u8> provides capacity as needed");
-
-    stream.write_one(&bytes).await?;
+    for chunk in into_chunks(message) {
+        let mut bytes = Vec::with_capacity(chunk.encoded_len());
+        chunk.encode(&mut bytes).expect("Vec<u8> provides capacity as needed");
+        stream.write_one(&bytes).await?;
+    }
 
     stream.close().await.map_err(io::Error::into)
 }
 
+// Splits the message into chunks of at most MAX_CHUNK_SIZE, by spreading the addresses and the
+// protocols over them. A message small enough is sent as a single chunk, as it has always been.
+fn into_chunks(mut message: structs_proto::Identify) -> Vec<structs_proto::Identify> {
+    if message.encoded_len() <= MAX_CHUNK_SIZE {
+        return vec![message];
+    }
+
+    let listen_addrs = std::mem::take(&mut message.listen_addrs);
+    let protocols = std::mem::take(&mut message.protocols);
+    let mut chunks = vec![message];
+
+    for addr in listen_addrs {
+        let last = chunks.last_mut().expect("never empty");
+        last.listen_addrs.push(addr);
+        if last.encoded_len() > MAX_CHUNK_SIZE {
+            let addr = last.listen_addrs.pop().expect("just pushed");
+            chunks.push(structs_proto::Identify {
+                listen_addrs: vec![addr],
+                ..Default::default()
+            });
+        }
+    }
+    for protocol in protocols {
+        let last = chunks.last_mut().expect("never empty");
+        last.protocols.push(protocol);
+        if last.encoded_len() > MAX_CHUNK_SIZE {
+            let protocol = last.protocols.pop().expect("just pushed");
+            chunks.push(structs_proto::Identify {
+                protocols: vec![protocol],
+                ..Default::default()
+            });
+        }
+    }
+    chunks
+}
+
 /// Represents a prototype for the identify protocol.
 ///
 /// The protocol works the following way:
@@ -288,7 +411,7 @@ impl ProtocolHandler for IdentifyPushHandler {
 mod tests {
     use super::IdentifyHandler;
     use crate::control::SwarmControlCmd;
-    use crate::identify::{IdentifyConfig, IdentifyInfo, IdentifyPushHandler};
+    use crate::identify::{IdentifyConfig, IdentifyInfo, IdentifyPushHandler, PrivateAddrPolicy};
     use crate::protocol_handler::ProtocolHandler;
     use crate::substream::Substream;
     use crate::{identify, SwarmEvent};
@@ -297,11 +420,13 @@ mod tests {
     use libp2prs_core::identity::Keypair;
     use libp2prs_core::transport::ListenerEvent;
     use libp2prs_core::upgrade::UpgradeInfo;
+    use libp2prs_core::Multiaddr;
     use libp2prs_core::{
         multiaddr::multiaddr,
         transport::{memory::MemoryTransport, Transport},
     };
     use libp2prs_runtime::task;
+    use prost::Message;
     use rand::{thread_rng, Rng};
 
     #[test]
@@ -350,6 +475,55 @@ mod tests {
         });
     }
 
+    #[test]
+    fn produce_and_consume_chunked() {
+        let mem_addr = multiaddr![Memory(thread_rng().gen::<u64>())];
+        let listener_addr = mem_addr.clone();
+        let mut listener = MemoryTransport.listen_on(mem_addr).unwrap();
+
+        let pubkey = Keypair::generate_ed25519_fixed().public();
+        let listen_addrs: Vec<Multiaddr> = (0..200u16).map(|i| multiaddr![Ip4([10, 0, 0, 1]), Tcp(i)]).collect();
+        let protocols: Vec<String> = (0..300).map(|i| format!("/test/protocol/{}/1.0.0", i)).collect();
+        let info = IdentifyInfo {
+            public_key: pubkey.clone(),
+            protocol_version: "ipfs/1.0.0".to_string(),
+            agent_version: "abc".to_string(),
+            listen_addrs: listen_addrs.clone(),
+            protocols: protocols.clone(),
+        };
+
+        // the message doesn't fit in a single frame
+        let message = identify::structs_proto::Identify {
+            listen_addrs: listen_addrs.iter().map(|a| a.to_vec()).collect(),
+            protocols: protocols.clone(),
+            ..Default::default()
+        };
+        let chunks = identify::into_chunks(message);
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| c.encoded_len() <= identify::MAX_CHUNK_SIZE));
+
+        task::spawn(async move {
+            let socket = match listener.accept().await.unwrap() {
+                ListenerEvent::Accepted(socket) => socket,
+                _ => panic!("unreachable"),
+            };
+            let socket = Substream::new_with_default(Box::new(socket));
+            identify::produce_message(socket, info).await.unwrap();
+        });
+
+        task::block_on(async move {
+            let socket = MemoryTransport.dial(listener_addr).await.unwrap();
+            let socket = Substream::new_with_default(Box::new(socket));
+
+            let (ri, _addr) = identify::process_message(socket).await.unwrap();
+            assert_eq!(ri.public_key, pubkey);
+            assert_eq!(ri.agent_version, "abc");
+            assert_eq!(ri.protocol_version, "ipfs/1.0.0");
+            assert_eq!(ri.listen_addrs, listen_addrs);
+            assert_eq!(ri.protocols, protocols);
+        });
+    }
+
     #[test]
     fn produce_and_consume_push() {
         let mem_addr = multiaddr![Memory(thread_rng().gen::<u64>())];
@@ -395,4 +569,33 @@ mod tests {
             }
         });
     }
+
+    #[test]
+    fn listen_addrs_filtered() {
+        let addrs = vec![
+            multiaddr![Ip4([1, 2, 3, 4]), Tcp(4001u16)],
+            multiaddr![Ip4([0, 0, 0, 0]), Tcp(4001u16)],
+            multiaddr![Ip4([1, 2, 3, 4]), Tcp(0u16)],
+            multiaddr![Ip4([224, 0, 0, 1]), Tcp(4001u16)],
+            multiaddr![Ip6([0, 0, 0, 0, 0, 0, 0, 0]), Tcp(4001u16)],
+            multiaddr![Ip4([192, 168, 1, 2]), Tcp(4001u16)],
+            multiaddr![Ip4([127, 0, 0, 1]), Tcp(4001u16)],
+            multiaddr![Ip6([0x2001, 0xdb8, 0, 0, 0, 0, 0, 1]), Tcp(4001u16)],
+        ];
+        let public = multiaddr![Ip4([1, 2, 3, 4]), Tcp(50000u16)];
+        let private = multiaddr![Ip4([192, 168, 1, 2]), Tcp(50000u16)];
+
+        let routable = vec![addrs[0].clone(), addrs[7].clone()];
+        let with_private = vec![addrs[0].clone(), addrs[5].clone(), addrs[6].clone(), addrs[7].clone()];
+
+        let config = IdentifyConfig::new(false);
+        assert_eq!(config.filter_listen_addrs(addrs.clone(), &public), routable);
+        assert_eq!(config.filter_listen_addrs(addrs.clone(), &private), with_private);
+
+        let config = IdentifyConfig::new(false).with_private_addrs(PrivateAddrPolicy::Drop);
+        assert_eq!(config.filter_listen_addrs(addrs.clone(), &private), routable);
+
+        let config = IdentifyConfig::new(false).with_private_addrs(PrivateAddrPolicy::Keep);
+        assert_eq!(config.filter_listen_addrs(addrs, &public), with_private);
+    }
 }
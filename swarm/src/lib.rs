@@ -47,24 +47,32 @@
 pub mod connection;
 mod control;
 mod dial;
+pub mod disconnect;
+pub mod gater;
 mod muxer;
 pub mod network;
+mod notifier;
 mod registry;
+#[cfg(feature = "serde")]
+mod serialize;
 
 pub mod cli;
 pub mod identify;
 pub mod metrics;
 pub mod ping;
 pub mod protocol_handler;
+pub mod reconnect;
 pub mod substream;
 
-pub use control::Control;
+pub use control::{Control, InboundStreams};
+pub use notifier::ConnectionEvent;
 pub use protocol_handler::DummyProtocol;
 
 use fnv::FnvHashMap;
 use futures::channel::{mpsc, oneshot};
 use futures::future::Either;
 use futures::prelude::*;
+use futures::stream::FuturesUnordered;
 use smallvec::SmallVec;
 use std::collections::HashSet;
 use std::sync::atomic::{AtomicBool, AtomicUsize};
@@ -76,31 +84,41 @@ use libp2prs_core::peerstore::{PeerStore, ADDRESS_TTL};
 use libp2prs_core::{
     multiaddr::{protocol, Multiaddr},
     muxing::IStreamMuxer,
+    secure_io::HandshakeErrorKind,
     transport::{upgrade::ITransportEx, TransportError},
     PeerId, ProtocolId, PublicKey,
 };
 use libp2prs_runtime::task;
 
-use crate::connection::{Connection, ConnectionId, ConnectionView, Direction};
+use crate::connection::{Connection, ConnectionId, ConnectionView, Direction, StreamRateLimiter};
 use crate::control::{DumpCommand, SwarmControlCmd};
 use crate::dial::{DialerStatsView, EitherDialAddr};
+use crate::disconnect::DisconnectHandler;
+use crate::gater::{inbound_secured_filter, outbound_secured_filter, ConnectionGater, IConnectionGater};
 use crate::identify::{IdentifyConfig, IdentifyHandler, IdentifyInfo, IdentifyPushHandler};
+use crate::metrics::bandwidth::BandwidthStats;
 use crate::metrics::metric::Metric;
 use crate::muxer::Muxer;
-use crate::network::NetworkInfo;
+use crate::network::{NetworkInfo, PeerInfo};
+use crate::notifier::Notifier;
 use crate::ping::{PingConfig, PingHandler};
 use crate::protocol_handler::ProtocolImpl;
+use crate::reconnect::ReconnectPolicy;
 use crate::registry::Addresses;
 use crate::substream::{ConnectInfo, StreamId, Substream, SubstreamView};
 use libp2prs_core::routing::IRouting;
 use libp2prs_core::translation::address_translation;
-use libp2prs_core::transport::ListenerEvent;
+use libp2prs_core::transport::{ListenerEvent, OverflowPolicy, SecuredFilter};
 use std::error::Error;
 use std::sync::atomic::Ordering;
 
 type Result<T> = std::result::Result<T, SwarmError>;
 
 const PEERSTORE_GC_PURGE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// The default timeout of the protocol negotiation of substreams.
+const NEGOTIATION_TIMEOUT: Duration = Duration::from_secs(10);
+/// The default number of the inbound substreams negotiated concurrently on each connection.
+const MAX_INBOUND_NEGOTIATIONS: usize = 16;
 
 const LIBP2P_RS_PROTOCOL_VERSION: &str = "ipfs/0.1.0";
 const LIBP2P_RS_AGENT_VERSION: &str = "libp2p-rs/0.1.0";
@@ -160,6 +178,30 @@ pub enum SwarmEvent {
         // The dial transaction id
         tid: TransactionId,
     },
+    /// An inbound connection was dropped, because the accept queue of the listener is full.
+    IncomingConnectionDropped {
+        /// The remote multiaddr.
+        remote_addr: Multiaddr,
+    },
+    /// A connection was denied by the connection gater.
+    ConnectionGated {
+        /// The remote multiaddr.
+        remote_addr: Multiaddr,
+        /// Direction of the connection.
+        dir: Direction,
+    },
+    /// The security handshake of an outbound connection failed.
+    HandshakeFailed {
+        /// The remote multiaddr.
+        remote_addr: Multiaddr,
+        /// The reason of the failure.
+        kind: HandshakeErrorKind,
+    },
+    /// An inbound connection was closed, because its application version is incompatible.
+    IncompatibleVersion {
+        /// The remote multiaddr.
+        remote_addr: Multiaddr,
+    },
     /// One of our listeners has a new address added.
     ListenAddressAdded(Multiaddr),
     /// One of our listeners has an address deleted.
@@ -191,6 +233,43 @@ pub enum SwarmEvent {
         /// Duration means the TTL when succeeded, or SwarmError for failed.
         result: Result<(IdentifyInfo, Multiaddr)>,
     },
+    /// The remote closed the connection, telling the reason.
+    DisconnectedWithReason {
+        /// The connection Id.
+        cid: ConnectionId,
+        /// The remote Peer Id.
+        peer_id: PeerId,
+        /// The application defined code of the reason.
+        code: u32,
+        /// The message of the reason.
+        message: String,
+    },
+    /// The deadline of draining Swarm has passed.
+    DrainDeadline {
+        /// The generation of the drain request the deadline belongs to.
+        generation: u64,
+    },
+    /// The grace period of an inbound connection to get identified has passed.
+    IdentifyDeadline {
+        /// The connection Id.
+        cid: ConnectionId,
+    },
+    /// The backoff before reconnecting to the peer has passed.
+    Reconnect {
+        /// The remote Peer Id.
+        peer_id: PeerId,
+        /// The attempt, counting from 0.
+        attempt: u32,
+    },
+    /// The result of reconnecting to the peer.
+    Reconnected {
+        /// The remote Peer Id.
+        peer_id: PeerId,
+        /// The attempt, counting from 0.
+        attempt: u32,
+        /// The result.
+        result: Result<()>,
+    },
 }
 
 /// The Transports helper.
@@ -217,16 +296,27 @@ impl Transports {
     pub(crate) fn add(&mut self, id: u32, transport: ITransportEx) -> Option<ITransportEx> {
         self.inner.insert(id, transport)
     }
+    pub(crate) fn set_secured_filter(&mut self, filter: SecuredFilter) {
+        for transport in self.inner.values_mut() {
+            transport.set_secured_filter(filter.clone());
+        }
+    }
 }
 
 /// Statistics of Swarm connection and stream.
 #[derive(Default, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct SwarmBaseStats {
     connection_incoming_opened: usize,
     connection_outgoing_opened: usize,
     connection_closed: usize,
     incoming_connection_error: usize,
     outgoing_connection_error: usize,
+    connection_gated: usize,
+    incoming_connection_dropped: usize,
+    incompatible_version: usize,
+    unidentified_closed: usize,
+    reconnect_attempts: usize,
     substream_inbound_opened: usize,
     substream_outbound_opened: usize,
     substream_closed: usize,
@@ -236,10 +326,36 @@ pub struct SwarmBaseStats {
 
 /// Statistics of Swarm.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct SwarmStats {
     base: SwarmBaseStats,
     dialer: DialerStatsView,
     listener: ListenerStatsView,
+    connections: usize,
+    substreams: usize,
+    bandwidth: BandwidthStats,
+}
+
+impl SwarmStats {
+    /// Returns the number of the connections.
+    pub fn connections(&self) -> usize {
+        self.connections
+    }
+
+    /// Returns the number of the active substreams, over all the connections.
+    pub fn substreams(&self) -> usize {
+        self.substreams
+    }
+
+    /// Returns the node-wide bandwidth totals and rolling rates.
+    pub fn bandwidth(&self) -> BandwidthStats {
+        self.bandwidth
+    }
+
+    /// Returns the statistics of the outbound dials.
+    pub fn dialer(&self) -> &DialerStatsView {
+        &self.dialer
+    }
 }
 
 /// Statistics of listener.
@@ -250,6 +366,7 @@ struct ListenerStats {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct ListenerStatsView {
     total_connecton: usize,
     total_error: usize,
@@ -317,6 +434,36 @@ pub struct Swarm {
     /// List of nodes for which are forbidden.
     banned_peers: HashSet<PeerId>,
 
+    /// The connection gater, if any.
+    gater: Option<IConnectionGater>,
+
+    /// The policy of reconnecting to the peers on connection failures, if any.
+    reconnect: Option<ReconnectPolicy>,
+
+    /// The depth and overflow policy of the accept queue of listeners, if any.
+    accept_queue: Option<(usize, OverflowPolicy)>,
+
+    /// The rate and burst of the upgrades of inbound connections, if limited.
+    accept_rate_limit: Option<(u32, u32)>,
+
+    /// The configuration of Identify, filtering the addresses advertised by the peers.
+    identify_config: IdentifyConfig,
+
+    /// The grace period of the inbound connections to get identified, if required.
+    identify_grace: Option<Duration>,
+
+    /// The timeout of the protocol negotiation of substreams.
+    negotiation_timeout: Duration,
+
+    /// The maximum number of the inbound substreams negotiated concurrently on each connection.
+    max_inbound_negotiations: usize,
+
+    /// The maximum number of concurrent substreams per protocol on each connection.
+    stream_limits: Arc<FnvHashMap<ProtocolId, usize>>,
+
+    /// The rate limiter of the inbound substreams per remote peer, if any.
+    stream_rate_limiter: Option<Arc<StreamRateLimiter>>,
+
     /// The all connections_by_peer connections organized by their Ids
     connections_by_id: FnvHashMap<ConnectionId, Connection>,
     /// The all connections_by_peer connections by  peer Id
@@ -340,6 +487,21 @@ pub struct Swarm {
     next_tid: TransactionId,
     /// The dialer post-processing hashmap.
     dial_transactions: FnvHashMap<TransactionId, DialCallback>,
+
+    /// The subscribers of connection events.
+    notifier: Notifier,
+
+    /// The replies to disconnect requests, sent when all connections of the peer are gone.
+    pending_disconnects: FnvHashMap<PeerId, Vec<oneshot::Sender<Result<()>>>>,
+
+    /// The replies to drain requests along with their generations, sent when all connections
+    /// are gone. Swarm is draining if it is not `None`, and stays so once all connections are gone.
+    draining: Option<Vec<(u64, oneshot::Sender<()>)>>,
+    /// The generation of the latest drain request.
+    drain_generation: u64,
+    /// Set once Swarm is draining, shared with the listeners to deny the inbound connections
+    /// before they are upgraded.
+    drain_started: Arc<AtomicBool>,
 }
 
 #[allow(dead_code)]
@@ -363,6 +525,7 @@ impl Swarm {
         }
 
         let metric = Metric::new();
+        let dialer = dial::AsyncDialer::new(peer_store.clone());
 
         Swarm {
             peer_store,
@@ -378,6 +541,16 @@ impl Swarm {
             listened_addrs: Default::default(),
             external_addrs: Default::default(),
             banned_peers: Default::default(),
+            gater: None,
+            reconnect: None,
+            accept_queue: None,
+            accept_rate_limit: None,
+            identify_config: Default::default(),
+            identify_grace: None,
+            negotiation_timeout: NEGOTIATION_TIMEOUT,
+            max_inbound_negotiations: MAX_INBOUND_NEGOTIATIONS,
+            stream_limits: Default::default(),
+            stream_rate_limiter: None,
             connections_by_id: Default::default(),
             connections_by_peer: Default::default(),
             metric: Arc::new(metric),
@@ -385,9 +558,14 @@ impl Swarm {
             event_sender: event_tx,
             ctrl_receiver: ctrl_rx,
             ctrl_sender: ctrl_tx,
-            dialer: dial::AsyncDialer::new(),
+            dialer,
             next_tid: 0,
             dial_transactions: Default::default(),
+            notifier: Default::default(),
+            pending_disconnects: Default::default(),
+            draining: None,
+            drain_generation: 0,
+            drain_started: Arc::new(AtomicBool::new(false)),
         }
     }
     fn assign_cid(&mut self) -> usize {
@@ -400,7 +578,10 @@ impl Swarm {
     }
 
     /// Adds transport to Swarm.
-    pub fn with_transport(mut self, transport: ITransportEx) -> Self {
+    pub fn with_transport(mut self, mut transport: ITransportEx) -> Self {
+        if let Some(gater) = self.gater.clone() {
+            transport.set_secured_filter(outbound_secured_filter(gater));
+        }
         let protocols = transport.protocols();
         if protocols.is_empty() {
             panic!("Shouldn't happen: no protocols found in Transport");
@@ -432,6 +613,14 @@ impl Swarm {
         }
         self
     }
+    /// Modifies Swarm to take the reason of closing a connection told by the remote, on the
+    /// reserved protocol `/libp2p-rs/disconnect/1.0.0`, and surface it as
+    /// `ConnectionEvent::DisconnectedWithReason`. Not taken by default.
+    pub fn with_disconnect_reason(mut self) -> Self {
+        let handler = DisconnectHandler::new(self.event_sender.clone());
+        self.muxer.add_protocol_handler(Box::new(handler));
+        self
+    }
     /// Modifies Swarm with Ping service.
     pub fn with_ping(mut self, ping: PingConfig) -> Self {
         self.muxer.add_protocol_handler(Box::new(PingHandler::new(ping)));
@@ -447,14 +636,98 @@ impl Swarm {
         self.metric = Arc::new(metric);
         self
     }
+    /// Modifies Swarm with a connection gater.
+    ///
+    /// Must be done before starting any listener, otherwise the listener wouldn't
+    /// consult the gater at all.
+    pub fn with_gater<G: ConnectionGater + Send + Sync + 'static>(mut self, gater: G) -> Self {
+        let gater: IConnectionGater = Arc::new(gater);
+        self.transports.set_secured_filter(outbound_secured_filter(gater.clone()));
+        self.gater = Some(gater);
+        self
+    }
+    /// Modifies Swarm with a policy of reconnecting to the peers, whose connection went
+    /// away without being closed by ourselves.
+    pub fn with_reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = Some(policy);
+        self
+    }
+    /// Modifies Swarm with the maximum number of the outbound dials in flight, across all the
+    /// peers, so that churn can't exhaust the file descriptors. The dials beyond it are queued
+    /// until a slot frees up. Unlimited by default.
+    pub fn with_dial_concurrency_factor(mut self, factor: usize) -> Self {
+        self.dialer.set_concurrency_factor(factor);
+        self
+    }
+    /// Modifies Swarm with the timeout of the protocol negotiation of substreams, both
+    /// inbound and outbound. It defaults to 10 seconds.
+    pub fn with_negotiation_timeout(mut self, timeout: Duration) -> Self {
+        self.negotiation_timeout = timeout;
+        self
+    }
+    /// Modifies Swarm with the maximum number of the inbound substreams negotiated concurrently
+    /// on each connection. It defaults to 16.
+    ///
+    /// No more substream is accepted while the limit is reached, until one of the negotiations
+    /// completes or times out.
+    pub fn with_max_inbound_negotiations(mut self, max: usize) -> Self {
+        self.max_inbound_negotiations = max.max(1);
+        self
+    }
+    /// Modifies Swarm with a limit of the rate of the inbound substreams of each remote peer,
+    /// `rate` substreams per second with bursts of up to `burst` substreams, counted across
+    /// all the connections to the peer.
+    ///
+    /// The inbound substreams beyond the rate are reset before the protocol negotiation.
+    pub fn with_inbound_stream_rate_limit(mut self, rate: u32, burst: u32) -> Self {
+        self.stream_rate_limiter = Some(Arc::new(StreamRateLimiter::new(rate, burst)));
+        self
+    }
+    /// Modifies Swarm with a limit of the concurrent substreams of the protocol on each
+    /// connection, so that a protocol can't monopolize the connection resources.
+    ///
+    /// The inbound substreams beyond the limit are reset, while opening an outbound one fails.
+    pub fn with_max_concurrent_streams(mut self, protocol: ProtocolId, max: usize) -> Self {
+        Arc::make_mut(&mut self.stream_limits).insert(protocol, max);
+        self
+    }
+    /// Modifies Swarm with a bounded accept queue for the listeners.
+    ///
+    /// The inbound connections waiting for the upgrade are queued up to `depth`, beyond which
+    /// the connections are dropped according to the `policy`. Must be done before starting
+    /// any listener.
+    pub fn with_accept_queue(mut self, depth: usize, policy: OverflowPolicy) -> Self {
+        self.accept_queue = Some((depth, policy));
+        self
+    }
+    /// Modifies Swarm with a rate limit on the inbound connections of the listeners.
+    ///
+    /// At most `rate` connections per second are upgraded, with bursts of up to `burst`
+    /// connections. The others wait in the accept queue if any, where the overflow is
+    /// dropped and reported as `IncomingConnectionDropped`. Must be done before starting
+    /// any listener.
+    pub fn with_accept_rate_limit(mut self, rate: u32, burst: u32) -> Self {
+        self.accept_rate_limit = Some((rate, burst));
+        self
+    }
     /// Modifies Swarm with Identify service.
     pub fn with_identify(mut self, config: IdentifyConfig) -> Self {
+        self.identify_config = config.clone();
         let handler = IdentifyHandler::new(self.ctrl_sender.clone());
         self.muxer.add_protocol_handler(Box::new(handler));
         let handler = IdentifyPushHandler::new(config, self.event_sender.clone());
         self.muxer.add_protocol_handler(Box::new(handler));
         self
     }
+    /// Modifies Swarm to require the inbound connections to get identified within the grace
+    /// period, or they are closed. Not required by default.
+    ///
+    /// The connections are identified by the Identify service, which must be installed with
+    /// `with_identify`, otherwise all the inbound connections are closed after the grace period.
+    pub fn require_identify_within(mut self, grace: Option<Duration>) -> Self {
+        self.identify_grace = grace;
+        self
+    }
 
     /// Get an API controller for Swarm.
     pub fn control(&self) -> Control {
@@ -520,8 +793,8 @@ impl Swarm {
             } => {
                 let _ = self.handle_connection_opened(stream_muxer, direction, tid);
             }
-            SwarmEvent::ConnectionClosed { cid, error: _ } => {
-                let _ = self.handle_connection_closed(cid);
+            SwarmEvent::ConnectionClosed { cid, error } => {
+                let _ = self.handle_connection_closed(cid, error);
             }
             SwarmEvent::OutgoingConnectionError { tid, peer_id, error } => {
                 let _ = self.handle_outgoing_connection_error(peer_id, error, tid);
@@ -529,6 +802,18 @@ impl Swarm {
             SwarmEvent::IncomingConnectionError { remote_addr, error } => {
                 let _ = self.handle_incoming_connection_error(remote_addr, error);
             }
+            SwarmEvent::IncomingConnectionDropped { remote_addr } => {
+                let _ = self.handle_incoming_connection_dropped(remote_addr);
+            }
+            SwarmEvent::IncompatibleVersion { remote_addr } => {
+                let _ = self.handle_incompatible_version(remote_addr);
+            }
+            SwarmEvent::ConnectionGated { remote_addr, dir } => {
+                let _ = self.handle_connection_gated(remote_addr, dir);
+            }
+            SwarmEvent::HandshakeFailed { remote_addr, kind } => {
+                let _ = self.handle_handshake_failed(remote_addr, Direction::Outbound, kind);
+            }
             SwarmEvent::StreamError { cid, dir, .. } => {
                 let _ = self.handle_stream_error(cid, dir);
             }
@@ -541,6 +826,26 @@ impl Swarm {
             SwarmEvent::IdentifyResult { cid, result } => {
                 let _ = self.handle_identify_result(cid, result);
             }
+            SwarmEvent::DisconnectedWithReason {
+                cid,
+                peer_id,
+                code,
+                message,
+            } => {
+                let _ = self.handle_disconnected_with_reason(cid, peer_id, code, message);
+            }
+            SwarmEvent::DrainDeadline { generation } => {
+                let _ = self.handle_drain_deadline(generation);
+            }
+            SwarmEvent::IdentifyDeadline { cid } => {
+                let _ = self.handle_identify_deadline(cid);
+            }
+            SwarmEvent::Reconnect { peer_id, attempt } => {
+                let _ = self.handle_reconnect(peer_id, attempt);
+            }
+            SwarmEvent::Reconnected { peer_id, attempt, result } => {
+                let _ = self.handle_reconnected(peer_id, attempt, result);
+            }
         }
     }
 
@@ -564,6 +869,18 @@ impl Swarm {
                 // got the peer_id, try opening a new sub stream
                 let _ = self.on_new_stream(peer_id, pids, use_routing, reply);
             }
+            SwarmControlCmd::CloseConnectionWithReason(cid, code, message, reply) => {
+                // got the connection_id, tell the remote the reason then close the connection
+                let _ = self.on_close_connection_with_reason(cid, code, message, reply);
+            }
+            SwarmControlCmd::NewStreamOn(cid, pids, reply) => {
+                // got the connection_id, try opening a new sub stream on it
+                let _ = self.on_new_stream_on(cid, pids, reply);
+            }
+            SwarmControlCmd::FlushBarrier(cid, reply) => {
+                // got the connection_id, wait for its queued frames to be written
+                let _ = self.on_flush_barrier(cid, reply);
+            }
             SwarmControlCmd::CloseStream(cid, sid) => {
                 // got the connection_id, try closing a new sub stream
                 let _ = self.on_close_stream(cid, sid);
@@ -574,6 +891,11 @@ impl Swarm {
                     let _ = reply.send(r);
                 });
             }
+            SwarmControlCmd::Listeners(reply) => {
+                let _ = self.on_retrieve_listen_addresses(|r| {
+                    let _ = reply.send(r);
+                });
+            }
             SwarmControlCmd::NetworkInfo(reply) => {
                 // Received from channel, try retrieving network info
                 let _ = self.on_retrieve_network_info(|r| {
@@ -586,6 +908,28 @@ impl Swarm {
                     let _ = reply.send(r);
                 });
             }
+            SwarmControlCmd::PeerInfo(peer_id, reply) => {
+                let _ = self.on_retrieve_peer_info(peer_id, |r| {
+                    let _ = reply.send(r);
+                });
+            }
+            SwarmControlCmd::SetStreamHandler(handler, reply) => {
+                self.muxer.add_stream_handler(handler);
+                let _ = reply.send(());
+            }
+            SwarmControlCmd::RemoveStreamHandler(protocol, reply) => {
+                self.muxer.remove_stream_handler(&protocol);
+                let _ = reply.send(());
+            }
+            SwarmControlCmd::Protocols(reply) => {
+                let _ = reply.send(self.get_supported_protocols());
+            }
+            SwarmControlCmd::Subscribe(reply) => {
+                let _ = reply.send(self.notifier.subscribe());
+            }
+            SwarmControlCmd::Drain(deadline, reply) => {
+                let _ = self.on_drain(deadline, reply);
+            }
             SwarmControlCmd::Dump(cmd) => match cmd {
                 DumpCommand::Connections(peer_id, reply) => {
                     let _ = self.on_retrieve_connection_views(peer_id, |r| {
@@ -602,6 +946,9 @@ impl Swarm {
                         let _ = reply.send(r);
                     });
                 }
+                DumpCommand::FindConnection(sid, reply) => {
+                    let _ = reply.send(self.find_connection_by_stream(sid));
+                }
             },
         }
         Ok(())
@@ -634,19 +981,21 @@ impl Swarm {
     }
 
     fn on_close_connection(&mut self, peer_id: PeerId, reply: oneshot::Sender<Result<()>>) -> Result<()> {
-        let r = if let Some(ids) = self.connections_by_peer.get(&peer_id) {
-            // close all connections related to the peer_id
+        if let Some(ids) = self.connections_by_peer.get(&peer_id) {
+            // close all connections related to the peer_id, the reply is sent when all of
+            // them are cleaned up in 'handle_connection_closed'
             for id in ids {
                 if let Some(c) = self.connections_by_id.get_mut(&id) {
-                    c.close()
+                    if !c.is_closing() {
+                        c.close()
+                    }
                 }
             }
-            Ok(())
+            self.pending_disconnects.entry(peer_id).or_default().push(reply);
         } else {
-            Err(SwarmError::NoConnection(peer_id))
-        };
+            let _ = reply.send(Err(SwarmError::NoConnection(peer_id)));
+        }
 
-        let _ = reply.send(r);
         Ok(())
     }
 
@@ -684,16 +1033,96 @@ impl Swarm {
         Ok(())
     }
 
+    fn on_new_stream_on(&mut self, cid: ConnectionId, pids: Vec<ProtocolId>, reply: oneshot::Sender<Result<Substream>>) -> Result<()> {
+        match self.connections_by_id.get_mut(&cid) {
+            Some(connection) if !connection.is_closing() => {
+                log::debug!("open a stream using the connection {:?}", cid);
+                connection.open_stream(pids, move |r| {
+                    let _ = reply.send(r.map_err(|e| e.into()));
+                });
+            }
+            _ => {
+                let _ = reply.send(Err(SwarmError::NoSuchConnection(cid)));
+            }
+        }
+        Ok(())
+    }
+
+    fn on_close_connection_with_reason(
+        &mut self,
+        cid: ConnectionId,
+        code: u32,
+        message: String,
+        reply: oneshot::Sender<Result<()>>,
+    ) -> Result<()> {
+        match self.connections_by_id.get_mut(&cid) {
+            Some(connection) if !connection.is_closing() => {
+                connection.close_with_reason(code, message, move |r| {
+                    let _ = reply.send(r.map_err(|e| e.into()));
+                });
+            }
+            _ => {
+                let _ = reply.send(Err(SwarmError::NoSuchConnection(cid)));
+            }
+        }
+        Ok(())
+    }
+
+    fn on_flush_barrier(&mut self, cid: ConnectionId, reply: oneshot::Sender<Result<()>>) -> Result<()> {
+        match self.connections_by_id.get(&cid) {
+            Some(connection) if !connection.is_closing() => {
+                connection.flush_barrier(move |r| {
+                    let _ = reply.send(r.map_err(|e| e.into()));
+                });
+            }
+            _ => {
+                let _ = reply.send(Err(SwarmError::NoSuchConnection(cid)));
+            }
+        }
+        Ok(())
+    }
+
     fn on_close_stream(&mut self, cid: ConnectionId, sid: StreamId) -> Result<()> {
         self.handle_stream_closed(cid, sid)
     }
 
+    fn on_drain(&mut self, deadline: Duration, reply: oneshot::Sender<()>) -> Result<()> {
+        log::info!("draining, deadline={:?}", deadline);
+
+        // the listeners refuse the inbound connections from now on
+        self.drain_started.store(true, Ordering::SeqCst);
+        self.drain_generation += 1;
+        let generation = self.drain_generation;
+        self.draining.get_or_insert_with(Vec::new).push((generation, reply));
+
+        // the connections without any stream are done already
+        for c in self.connections_by_id.values_mut() {
+            if c.num_streams() == 0 && !c.is_closing() {
+                c.close();
+            }
+        }
+        self.check_drained();
+
+        let mut tx = self.event_sender.clone();
+        task::spawn(async move {
+            task::sleep(deadline).await;
+            let _ = tx.send(SwarmEvent::DrainDeadline { generation }).await;
+        });
+        Ok(())
+    }
+
     ///
     fn on_retrieve_own_addresses(&mut self, f: impl FnOnce(Vec<Multiaddr>)) -> Result<()> {
         f(self.get_self_addrs());
         Ok(())
     }
 
+    /// Retrieves the addresses of the active listeners.
+    fn on_retrieve_listen_addresses(&mut self, f: impl FnOnce(Vec<Multiaddr>)) -> Result<()> {
+        f(self.listened_addrs.to_vec());
+        Ok(())
+    }
+
     /// Retrieves the network information.
     fn on_retrieve_network_info(&mut self, f: impl FnOnce(NetworkInfo)) -> Result<()> {
         f(self.get_network_info());
@@ -704,6 +1133,11 @@ impl Swarm {
         f(self.get_identify_info());
         Ok(())
     }
+    /// Retrieves the information about the remote peer.
+    fn on_retrieve_peer_info(&mut self, peer_id: PeerId, f: impl FnOnce(Option<PeerInfo>)) -> Result<()> {
+        f(self.get_peer_info(&peer_id));
+        Ok(())
+    }
     /// Retrieves the connection views.
     fn on_retrieve_connection_views(&mut self, pid: Option<PeerId>, f: impl FnOnce(Vec<ConnectionView>)) -> Result<()> {
         f(self.get_connection_views(pid));
@@ -788,12 +1222,26 @@ impl Swarm {
             Err(SwarmError::NoConnection(peer_id))
         }
     }
+    /// Returns the connection having the substream, if any.
+    fn find_connection_by_stream(&self, sid: StreamId) -> Option<ConnectionId> {
+        self.connections_by_id.values().find(|c| c.has_stream(sid)).map(|c| c.id())
+    }
     /// Returns the statistics of the `Swarm`.
     fn get_stats(&self) -> Result<SwarmStats> {
         let dialer = self.dialer.stats();
         let listener = self.listener_stats.as_ref().into();
         let base = self.base_stats.clone();
-        Ok(SwarmStats { base, dialer, listener })
+        let connections = self.connections_by_id.len();
+        let substreams = self.connections_by_id.values().map(|c| c.num_streams()).sum();
+        let bandwidth = self.metric.get_bandwidth();
+        Ok(SwarmStats {
+            base,
+            dialer,
+            listener,
+            connections,
+            substreams,
+            bandwidth,
+        })
     }
     /// Returns network information about the `Swarm`.
     fn get_network_info(&self) -> NetworkInfo {
@@ -811,9 +1259,13 @@ impl Swarm {
             num_active_streams,
         }
     }
+    /// Returns the protocols served by the `Swarm`.
+    fn get_supported_protocols(&self) -> Vec<String> {
+        self.muxer.supported_protocols().into_iter().map(|p| p.to_string()).collect()
+    }
     /// Returns identify information about the `Swarm`.
     fn get_identify_info(&self) -> IdentifyInfo {
-        let protocols = self.muxer.supported_protocols().into_iter().map(|p| p.to_string()).collect();
+        let protocols = self.get_supported_protocols();
 
         let public_key = self.public_key.clone();
         let listen_addrs = self.get_self_addrs();
@@ -826,6 +1278,34 @@ impl Swarm {
             protocols,
         }
     }
+    /// Returns the information about the remote peer, aggregated from the
+    /// peer store and the active connections.
+    fn get_peer_info(&self, peer_id: &PeerId) -> Option<PeerInfo> {
+        let addrs = self.peer_store.get_addrs(peer_id);
+        let protocols = self.peer_store.get_protocols(peer_id);
+        let connections = self
+            .connections_by_id
+            .values()
+            .filter(|c| &c.remote_peer() == peer_id)
+            .collect::<Vec<_>>();
+
+        if addrs.is_none() && protocols.is_none() && connections.is_empty() {
+            return None;
+        }
+
+        let versions = connections.iter().find_map(|c| c.remote_versions());
+        let rtt = connections.iter().filter_map(|c| c.rtt()).min();
+
+        Some(PeerInfo {
+            id: *peer_id,
+            addrs: addrs.unwrap_or_default(),
+            protocols: protocols.unwrap_or_default(),
+            agent_version: versions.map(|v| v.0.clone()),
+            protocol_version: versions.map(|v| v.1.clone()),
+            num_connections: connections.len(),
+            rtt,
+        })
+    }
 
     /// Starts listening on the given address.
     ///
@@ -869,6 +1349,22 @@ impl Swarm {
         log::info!("starting a listener on {:?}", addr);
         let mut transport = self.transports.lookup_by_addr(addr.clone())?;
         let mut listener = transport.listen_on(addr)?;
+        if let Some((depth, policy)) = self.accept_queue {
+            listener.set_accept_queue(depth, policy);
+        }
+        if let Some((rate, burst)) = self.accept_rate_limit {
+            listener.set_accept_rate_limit(rate, burst);
+        }
+        // refuse the connection if draining, or consult the gater, before spending any handshake on it
+        let gater = self.gater.clone();
+        let drain_started = self.drain_started.clone();
+        listener.set_accept_filter(Arc::new(move |remote_addr: &Multiaddr| {
+            !drain_started.load(Ordering::SeqCst) && gater.as_ref().map_or(true, |gater| gater.intercept_accept(remote_addr))
+        }));
+        if let Some(gater) = self.gater.clone() {
+            // consult the gater as soon as the remote peer is known, before selecting the stream muxer
+            listener.set_secured_filter(inbound_secured_filter(gater));
+        }
         if let Some(addr) = listener.multi_addr() {
             log::info!("adding an actual listening address {}", addr);
             self.listened_addrs.push(addr.clone());
@@ -878,6 +1374,7 @@ impl Swarm {
         // start a runtime for this listener
         // TODO: remember the runtime handle of this listener, so that we can 'cancel' it when exiting
         let stats = self.listener_stats.clone();
+        let drain_started = self.drain_started.clone();
         task::spawn(async move {
             loop {
                 let r = listener.accept().await;
@@ -890,7 +1387,7 @@ impl Swarm {
                     }
                     Ok(ListenerEvent::Accepted(muxer)) => {
                         // don't have to verify if remote peer id matches its public key
-                        // always accept any incoming connection
+                        // always accept any incoming connection, the gater has been consulted by the listener
                         // send muxer back to Swarm main runtime
                         stats.total_connecton.fetch_add(1, Ordering::SeqCst);
                         let _ = tx
@@ -901,6 +1398,23 @@ impl Swarm {
                             })
                             .await;
                     }
+                    Err(TransportError::AcceptQueueOverflow(remote_addr)) => {
+                        let _ = tx.send(SwarmEvent::IncomingConnectionDropped { remote_addr }).await;
+                    }
+                    Err(TransportError::AcceptDenied(remote_addr)) if drain_started.load(Ordering::SeqCst) => {
+                        log::info!("connection {} refused, draining", remote_addr);
+                    }
+                    Err(TransportError::AcceptDenied(remote_addr)) | Err(TransportError::SecuredDenied(remote_addr)) => {
+                        let _ = tx
+                            .send(SwarmEvent::ConnectionGated {
+                                remote_addr,
+                                dir: Direction::Inbound,
+                            })
+                            .await;
+                    }
+                    Err(TransportError::IncompatibleVersion(remote_addr)) => {
+                        let _ = tx.send(SwarmEvent::IncompatibleVersion { remote_addr }).await;
+                    }
                     Err(err) => {
                         stats.total_error.fetch_add(1, Ordering::SeqCst);
                         let _ = tx
@@ -919,6 +1433,10 @@ impl Swarm {
     //
     fn dial_addr<F: FnOnce(Result<&mut Connection>) + Send + 'static>(&mut self, peer_id: PeerId, addrs: Vec<Multiaddr>, f: F) {
         log::debug!("dialing {:?} with addrs={:?}", peer_id, addrs);
+        if self.draining.is_some() {
+            f(Err(SwarmError::Draining));
+            return;
+        }
         // if dialing to itself...
         if self.local_peer_id().eq(&peer_id) {
             f(Err(SwarmError::DialToSelf));
@@ -943,14 +1461,18 @@ impl Swarm {
     // routing interface to find out if routing is available
     fn dial_peer<F: FnOnce(Result<&mut Connection>) + Send + 'static>(&mut self, peer_id: PeerId, use_routing: bool, f: F) {
         log::debug!("dialing {:?}, with routing={}", peer_id, use_routing);
+        if self.draining.is_some() {
+            f(Err(SwarmError::Draining));
+            return;
+        }
         // if dialing to itself...
         if self.local_peer_id().eq(&peer_id) {
             f(Err(SwarmError::DialToSelf));
             return;
         }
-        // then check addrs, return error if None while routing is not available
-        let addrs = match self.peer_store.get_addrs(&peer_id) {
-            Some(list) if !list.is_empty() => dial::EitherDialAddr::Addresses(list),
+        // then check addrs, the ones succeeded recently first, return error if None while routing is not available
+        let addrs = match self.peer_store.addrs_prioritized(&peer_id) {
+            list if !list.is_empty() => dial::EitherDialAddr::Addresses(list),
             _ => {
                 if use_routing && self.routing.is_some() {
                     // ok, clone the routing interface into EitherDialAddr
@@ -1065,15 +1587,27 @@ impl Swarm {
     fn handle_connection_opened(&mut self, stream_muxer: IStreamMuxer, dir: Direction, tid: Option<TransactionId>) -> Result<()> {
         log::debug!("handle_connection_opened: {:?} {:?}", stream_muxer, dir);
 
-        // update base statistics
-        if dir == Direction::Inbound {
-            self.base_stats.connection_incoming_opened += 1;
-        } else {
-            self.base_stats.connection_outgoing_opened += 1;
+        if self.draining.is_some() {
+            log::info!("connection {} {} refused, draining", dir, stream_muxer.remote_multiaddr());
+            drop(stream_muxer);
+            if let Some(id) = tid {
+                let callback = self.dial_transactions.remove(&id).expect("no match tid found");
+                callback(Err(SwarmError::Draining));
+            }
+            return Ok(());
         }
 
-        // add local pubkey to keybook
-        // self.peer_store.add_key(&self.local_peer_id, stream_muxer.local_priv_key().public());
+        // Both peers might have dialed each other simultaneously, ending up with two connections
+        // in opposite directions. They both keep the one initiated by the peer with the lower
+        // PeerId, so that the decision is the same on each side.
+        let remote_peer_id = stream_muxer.remote_peer();
+        let crossed = self.crossed_connection(&remote_peer_id, dir);
+        if let Some(kept) = crossed {
+            let initiated_by_us = dir == Direction::Outbound;
+            if initiated_by_us != (self.local_peer_id < remote_peer_id) {
+                return self.drop_crossed_connection(stream_muxer, dir, tid, kept);
+            }
+        }
 
         // clone the stream_muxer, and then wrap into Connection, task_handle will be assigned later
         let mut connection = Connection::new(
@@ -1083,7 +1617,26 @@ impl Swarm {
             self.event_sender.clone(),
             self.ctrl_sender.clone(),
             self.metric.clone(),
+            self.negotiation_timeout,
+            self.stream_limits.clone(),
         );
+
+        if let Some(gater) = self.gater.as_ref() {
+            if !gater.intercept_upgraded(&connection.info()) {
+                drop(connection);
+                return self.reject_connection(stream_muxer, dir, tid);
+            }
+        }
+
+        // update base statistics
+        if dir == Direction::Inbound {
+            self.base_stats.connection_incoming_opened += 1;
+        } else {
+            self.base_stats.connection_outgoing_opened += 1;
+        }
+
+        // add local pubkey to keybook
+        // self.peer_store.add_key(&self.local_peer_id, stream_muxer.local_priv_key().public());
         // TODO: filtering the multiaddr, Err = AddrFiltered(addr)
 
         /*        raddr := tc.RemoteMultiaddr()
@@ -1103,10 +1656,15 @@ impl Swarm {
 
         let mut tx = self.event_sender.clone();
         let cid = connection.id();
+        let state = connection.state();
         let metric = self.metric.clone();
-        // clone muxer and move it into the runtime
-        let mut muxer = self.muxer.clone();
+        // clone muxer and move it into the runtime, the stream handlers are shared with the clone,
+        // so that the ones registered after the connection is up can be negotiated as well
+        let muxer = self.muxer.clone();
         let ctrl = self.ctrl_sender.clone();
+        let negotiation_timeout = self.negotiation_timeout;
+        let max_negotiations = self.max_inbound_negotiations;
+        let rate_limiter = self.stream_rate_limiter.clone();
 
         // Note we have to use the original copy of the stream muxer to start the runtime,
         // instead of the cloned one which doesn't have the runtime handle at all
@@ -1114,45 +1672,101 @@ impl Swarm {
             let mut stream_muxer = stream_muxer;
             // start the background runtime of the stream_muxer, the handle can be await'ed by us
             let task_handle = stream_muxer.task().map(task::spawn);
+            let la = stream_muxer.local_multiaddr();
+            let ra = stream_muxer.remote_multiaddr();
+            let rpid = stream_muxer.remote_peer();
+
+            // the protocol selection of the inbound streams runs concurrently, up to `max_negotiations`
+            // at a time, so that a stalled one doesn't hold up the others. The pending accept is kept
+            // across the iterations, as dropping it might lose a stream
+            let mut accept = connection::accept_next(stream_muxer.clone());
+            let mut negotiations = FuturesUnordered::new();
             loop {
-                let metric = metric.clone();
-                let ctrl = ctrl.clone();
-                let r = stream_muxer.accept_stream().await;
+                let accepted = if negotiations.is_empty() {
+                    Some((&mut accept).await)
+                } else if negotiations.len() >= max_negotiations {
+                    negotiations.next().await;
+                    None
+                } else {
+                    match future::select(&mut accept, negotiations.next()).await {
+                        Either::Left((accepted, _)) => Some(accepted),
+                        Either::Right(_) => None,
+                    }
+                };
+                let (acceptor, r) = match accepted {
+                    Some(accepted) => accepted,
+                    None => continue,
+                };
 
-                // TODO: probably we should spawn a new runtime for protocol selection
-                // As a fact, in go-libp2p, the protocol selection is done in a blocking way...
                 match r {
-                    Ok(raw_stream) => {
+                    Ok(mut raw_stream) => {
+                        accept = connection::accept_next(acceptor);
+
+                        if rate_limiter.as_ref().map_or(false, |l| !l.try_acquire(&rpid)) {
+                            log::info!("{:?} exceeds the rate of the inbound substreams, resetting on {:?}", rpid, cid);
+                            if raw_stream.reset().await.is_err() {
+                                let _ = raw_stream.close().await;
+                            }
+                            let error = connection::stream_rate_exceeded(&rpid);
+                            let _ = tx.send(SwarmEvent::StreamError { cid, dir, error }).await;
+                            continue;
+                        }
+
                         // well, got the raw stream, now do protocol selection
                         log::debug!("run protocol selection for inbound stream={:?}", raw_stream);
 
-                        // now it's time to do multistream multiplexing for inbound stream
-                        let result = muxer.select_inbound(raw_stream).await;
-                        match result {
-                            Ok((mut handler, raw_stream, proto)) => {
-                                let la = stream_muxer.local_multiaddr();
-                                let ra = stream_muxer.remote_multiaddr();
-                                let rpid = stream_muxer.remote_peer();
-                                let ci = ConnectInfo { la, ra, rpid };
-                                let stream = Substream::new(raw_stream, metric, Direction::Inbound, proto.clone(), cid, ci, ctrl);
-                                let view = stream.to_view();
-                                let _ = tx.send(SwarmEvent::StreamOpened { view }).await;
-
-                                // anyway, start handler runtime
-                                task::spawn(async move {
-                                    let _ = handler.handle(stream, proto).await;
-                                });
-
-                                // TODO: hook the runtime handle to the Substream, so that it can wait for exiting the runtime
-                            }
-                            Err(error) => {
-                                log::debug!("failed inbound protocol selection {:?} {:?}", cid, error);
-                                let _ = tx.send(SwarmEvent::StreamError { cid, dir, error }).await;
+                        let mut muxer = muxer.clone();
+                        let mut tx = tx.clone();
+                        let metric = metric.clone();
+                        let ctrl = ctrl.clone();
+                        let state = state.clone();
+                        let ci = ConnectInfo {
+                            la: la.clone(),
+                            ra: ra.clone(),
+                            rpid,
+                        };
+                        negotiations.push(async move {
+                            // now it's time to do multistream multiplexing for inbound stream
+                            let result = task::timeout(negotiation_timeout, muxer.select_inbound(raw_stream))
+                                .await
+                                .unwrap_or(Err(TransportError::Timeout));
+                            match result {
+                                Ok((_, mut raw_stream, proto)) if !state.acquire_stream(&proto) => {
+                                    log::info!(
+                                        "too many concurrent substreams for {} on {:?}, resetting the inbound one",
+                                        proto,
+                                        cid
+                                    );
+                                    if raw_stream.reset().await.is_err() {
+                                        let _ = raw_stream.close().await;
+                                    }
+                                    let error = connection::too_many_streams(&proto);
+                                    let _ = tx.send(SwarmEvent::StreamError { cid, dir, error }).await;
+                                }
+                                Ok((mut handler, raw_stream, proto)) => {
+                                    let stream =
+                                        Substream::new(raw_stream, metric, Direction::Inbound, proto.clone(), cid, ci, ctrl, state);
+                                    let view = stream.to_view();
+                                    let _ = tx.send(SwarmEvent::StreamOpened { view }).await;
+
+                                    // anyway, start handler runtime
+                                    task::spawn(async move {
+                                        let _ = handler.handle(stream, proto).await;
+                                    });
+
+                                    // TODO: hook the runtime handle to the Substream, so that it can wait for exiting the runtime
+                                }
+                                Err(error) => {
+                                    log::debug!("failed inbound protocol selection {:?} {:?}", cid, error);
+                                    let _ = tx.send(SwarmEvent::StreamError { cid, dir, error }).await;
+                                }
                             }
-                        }
+                        });
                     }
                     Err(error) => {
                         log::debug!("connection closed {:?} {:?}", cid, error);
+                        // let the live substreams know their connection is gone, unless we closed it
+                        state.set_failed();
                         let _ = tx.send(SwarmEvent::ConnectionClosed { cid, error }).await;
                         // something happened, break the loop then exit the Task
                         break;
@@ -1189,10 +1803,130 @@ impl Swarm {
         // the spawned connection runtime might have exited for some reason, before we insert connection
         // into the hashmap. No problem, the connection will be cleaned up in 'handle_connection_closed'
         // event.
+        let event = ConnectionEvent::Established {
+            cid: connection.id(),
+            peer_id: connection.remote_peer(),
+            dir,
+        };
+        let kept = connection.id();
         self.add_connection(connection);
+        self.notifier.notify(event);
+
+        // the inbound connection has to get identified before the grace period passes
+        if let Some(grace) = self.identify_grace.filter(|_| dir == Direction::Inbound) {
+            let mut tx = self.event_sender.clone();
+            task::spawn(async move {
+                task::sleep(grace).await;
+                let _ = tx.send(SwarmEvent::IdentifyDeadline { cid: kept }).await;
+            });
+        }
+
+        // the new connection wins over the crossed one, close the latter
+        if let Some(cid) = crossed {
+            if let Some(connection) = self.connections_by_id.get_mut(&cid) {
+                log::debug!("simultaneous connect to {:?}, closing {:?}", remote_peer_id, cid);
+                connection.close();
+            }
+            self.notifier.notify(ConnectionEvent::Deduplicated {
+                kept,
+                peer_id: remote_peer_id,
+                dir: dir.reverse(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Returns the connection to the peer in the opposite direction, if there is any, which
+    /// might have been established by a simultaneous connect.
+    ///
+    /// Only a connection established within the dial timeout, thus racing with the dial of the
+    /// new one, and not used by any substream yet is taken as crossed. An older or busy one is
+    /// not a duplicate to get rid of, closing it would break its substreams.
+    fn crossed_connection(&self, peer_id: &PeerId, dir: Direction) -> Option<ConnectionId> {
+        self.connections_by_peer.get(peer_id)?.iter().copied().find(|cid| {
+            self.connections_by_id.get(cid).map_or(false, |c| {
+                c.dir() != dir && !c.is_closing() && c.num_streams() == 0 && c.age() < dial::DIAL_TIMEOUT
+            })
+        })
+    }
+
+    /// Drops a new connection which lost the simultaneous connect against the `kept` one.
+    ///
+    /// Like a rejected connection, the muxer task is not started yet. The dial transaction,
+    /// if there is any, is completed with the kept connection.
+    fn drop_crossed_connection(
+        &mut self,
+        stream_muxer: IStreamMuxer,
+        dir: Direction,
+        tid: Option<TransactionId>,
+        kept: ConnectionId,
+    ) -> Result<()> {
+        let peer_id = stream_muxer.remote_peer();
+        log::debug!("simultaneous connect to {:?}, dropping the {} connection", peer_id, dir);
+        drop(stream_muxer);
+
+        if let Some(id) = tid {
+            log::debug!("invoking dial transaction {:?}", tid);
+            let callback = self.dial_transactions.remove(&id).expect("no match tid found");
+            match self.connections_by_id.get_mut(&kept) {
+                Some(connection) => callback(Ok(connection)),
+                None => callback(Err(SwarmError::NoConnection(peer_id))),
+            }
+        }
+
+        self.notifier.notify(ConnectionEvent::Deduplicated { kept, peer_id, dir });
+        Ok(())
+    }
+    /// Rejects a connection denied by the gater.
+    ///
+    /// The muxer task is not started yet, so dropping the stream muxer is enough to close
+    /// the connection. Besides, the dial transaction must be cleaned up if there is any.
+    fn reject_connection(&mut self, stream_muxer: IStreamMuxer, dir: Direction, tid: Option<TransactionId>) -> Result<()> {
+        let remote_addr = stream_muxer.remote_multiaddr();
+        drop(stream_muxer);
+
+        if let Some(id) = tid {
+            log::debug!("invoking dial transaction {:?}", tid);
+            let callback = self.dial_transactions.remove(&id).expect("no match tid found");
+            callback(Err(SwarmError::ConnectionGated(remote_addr.clone())));
+        }
+
+        self.handle_connection_gated(remote_addr, dir)
+    }
+
+    /// Handles connection denied by the gater.
+    fn handle_connection_gated(&mut self, remote_addr: Multiaddr, dir: Direction) -> Result<()> {
+        log::info!("connection {} {} denied by gater", dir, remote_addr);
+
+        // update base statistics
+        self.base_stats.connection_gated += 1;
+
+        self.notifier.notify(ConnectionEvent::Gated { remote_addr, dir });
+
+        Ok(())
+    }
+
+    /// Handles an inbound connection dropped by the accept queue.
+    fn handle_incoming_connection_dropped(&mut self, remote_addr: Multiaddr) -> Result<()> {
+        log::info!("incoming connection {} dropped, accept queue is full", remote_addr);
+
+        // update base statistics
+        self.base_stats.incoming_connection_dropped += 1;
+
+        Ok(())
+    }
+
+    /// Handles an inbound connection closed by the version handshake.
+    fn handle_incompatible_version(&mut self, remote_addr: Multiaddr) -> Result<()> {
+        log::info!("incoming connection {} closed, incompatible version", remote_addr);
+
+        // update base statistics
+        self.base_stats.incompatible_version += 1;
 
         Ok(())
     }
+
     /// Handles outgoing connection error.
     fn handle_incoming_connection_error(&mut self, remote_addr: Multiaddr, error: TransportError) -> Result<()> {
         log::debug!("incoming connection error for {:?} {:?}", remote_addr, error);
@@ -1200,6 +1934,19 @@ impl Swarm {
         // update base statistics
         self.base_stats.incoming_connection_error += 1;
 
+        if let Some(kind) = error.handshake_error_kind() {
+            self.handle_handshake_failed(remote_addr, Direction::Inbound, kind)?;
+        }
+
+        Ok(())
+    }
+
+    /// Handles a failed security handshake, telling the subscribers why it failed.
+    fn handle_handshake_failed(&mut self, remote_addr: Multiaddr, dir: Direction, kind: HandshakeErrorKind) -> Result<()> {
+        log::info!("security handshake of connection {} {} failed: {:?}", dir, remote_addr, kind);
+
+        self.notifier.notify(ConnectionEvent::HandshakeFailed { remote_addr, dir, kind });
+
         Ok(())
     }
 
@@ -1207,8 +1954,13 @@ impl Swarm {
     fn handle_outgoing_connection_error(&mut self, peer_id: PeerId, error: SwarmError, tid: TransactionId) -> Result<()> {
         log::debug!("outgoing connection error: {:?} {:?} tid={:?}", peer_id, error, tid);
 
-        // update base statistics
-        self.base_stats.outgoing_connection_error += 1;
+        if let SwarmError::ConnectionGated(remote_addr) = &error {
+            // the connection has been denied by the gater, once it was secured
+            let _ = self.handle_connection_gated(remote_addr.clone(), Direction::Outbound);
+        } else {
+            // update base statistics
+            self.base_stats.outgoing_connection_error += 1;
+        }
 
         //execute dial callback for post processing
         let callback = self.dial_transactions.remove(&tid).expect("no match tid found");
@@ -1246,7 +1998,11 @@ impl Swarm {
 
         // delete sub-stream from the connection substream list
         if let Some(c) = self.connections_by_id.get_mut(&cid) {
-            c.del_stream(sid)
+            c.del_stream(sid);
+            // the connection is done if draining
+            if self.draining.is_some() && c.num_streams() == 0 && !c.is_closing() {
+                c.close();
+            }
         }
         Ok(())
     }
@@ -1268,8 +2024,8 @@ impl Swarm {
     /// Handles closing a connection
     ///
     /// start a Task for accepting new sub-stream from the connection
-    fn handle_connection_closed(&mut self, cid: ConnectionId) -> Result<()> {
-        log::debug!("handle_connection_closed: {:?}", cid);
+    fn handle_connection_closed(&mut self, cid: ConnectionId, error: TransportError) -> Result<()> {
+        log::debug!("handle_connection_closed: {:?} {:?}", cid, error);
 
         // update base statistics
         self.base_stats.connection_closed += 1;
@@ -1281,11 +2037,24 @@ impl Swarm {
             }
 
             let remote_peer_id = connection.remote_peer();
+            // a clean close by the remote, as told by the stream muxer, is not a failure
+            let failed = connection.state().is_failed() && !matches!(error, TransportError::ConnectionClosed);
+            self.notifier.notify(ConnectionEvent::Closed {
+                cid,
+                peer_id: remote_peer_id,
+            });
             if let Some(ids) = self.connections_by_peer.get_mut(&remote_peer_id) {
                 ids.retain(|id| id != &cid);
                 // remove the peer if all the connections of the peer are closed
                 if ids.is_empty() {
                     self.connections_by_peer.remove(&remote_peer_id);
+                    if let Some(limiter) = self.stream_rate_limiter.as_ref() {
+                        limiter.forget(&remote_peer_id);
+                    }
+                    // the peer is disconnected now
+                    for reply in self.pending_disconnects.remove(&remote_peer_id).unwrap_or_default() {
+                        let _ = reply.send(Ok(()));
+                    }
                 }
             } else {
                 log::warn!("shouldn't happen, PeerId={:?}", remote_peer_id);
@@ -1298,6 +2067,13 @@ impl Swarm {
                 let _ = connection.stop_identify().await;
                 let _ = connection.stop_identify_push().await;
             });
+
+            // reconnect if the last connection to the peer failed
+            if failed && !self.connections_by_peer.contains_key(&remote_peer_id) {
+                self.schedule_reconnect(remote_peer_id, 0);
+            }
+
+            self.check_drained();
         } else {
             log::info!("shouldn't happen, wired connection {:?}", cid);
         }
@@ -1305,18 +2081,144 @@ impl Swarm {
         Ok(())
     }
 
-    /// Received ping result, and then updates addrbook in peerstore
+    /// Schedules reconnecting to the peer after the backoff of the attempt, if the
+    /// reconnect policy is installed.
+    fn schedule_reconnect(&mut self, peer_id: PeerId, attempt: u32) {
+        if self.draining.is_some() {
+            return;
+        }
+        let policy = match self.reconnect.as_ref() {
+            Some(policy) => policy,
+            None => return,
+        };
+        if attempt >= policy.max_attempts() {
+            log::info!("giving up reconnecting to {:?} after {} attempts", peer_id, attempt);
+            return;
+        }
+
+        let backoff = policy.backoff(attempt);
+        log::debug!("reconnecting to {:?} in {:?}, attempt={}", peer_id, backoff, attempt);
+        let mut tx = self.event_sender.clone();
+        task::spawn(async move {
+            task::sleep(backoff).await;
+            let _ = tx.send(SwarmEvent::Reconnect { peer_id, attempt }).await;
+        });
+    }
+
+    /// Handles the backoff of reconnecting passed, re-dialing the peer if it is still needed.
+    fn handle_reconnect(&mut self, peer_id: PeerId, attempt: u32) -> Result<()> {
+        let needed = self
+            .reconnect
+            .as_mut()
+            .map_or(false, |policy| policy.reinitializer().is_needed(&peer_id));
+        if !needed {
+            log::debug!("{:?} is not needed any more, stop reconnecting", peer_id);
+            return Ok(());
+        }
+        // connected already, by the remote or the application
+        if self.get_best_conn(&peer_id).is_some() {
+            return self.handle_reconnected(peer_id, attempt, Ok(()));
+        }
+
+        // update base statistics
+        self.base_stats.reconnect_attempts += 1;
+
+        let tx = self.event_sender.clone();
+        self.dial_peer(peer_id, false, move |r: Result<&mut Connection>| {
+            let result = r.map(|_| ());
+            let _ = tx.unbounded_send(SwarmEvent::Reconnected { peer_id, attempt, result });
+        });
+        Ok(())
+    }
+
+    /// Handles the result of reconnecting, reinitializing the peer or trying again.
+    fn handle_reconnected(&mut self, peer_id: PeerId, attempt: u32, result: Result<()>) -> Result<()> {
+        match result {
+            Ok(()) => {
+                log::info!("reconnected to {:?}, attempt={}", peer_id, attempt);
+                let control = self.control();
+                if let Some(policy) = self.reconnect.as_mut() {
+                    policy.reinitializer().reinitialize(peer_id, control);
+                }
+            }
+            Err(e) => {
+                log::info!("failed to reconnect to {:?}, attempt={}: {}", peer_id, attempt, e);
+                self.schedule_reconnect(peer_id, attempt + 1);
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles the deadline of draining, closing the connections still in use.
+    fn handle_drain_deadline(&mut self, generation: u64) -> Result<()> {
+        // nothing to do if the drain request of the deadline is complete already, the
+        // deadline is stale then
+        let pending = self
+            .draining
+            .as_ref()
+            .map_or(false, |replies| replies.iter().any(|(g, _)| *g == generation));
+        if !pending {
+            return Ok(());
+        }
+
+        log::info!("drain deadline passed, closing {} connections", self.connections_by_id.len());
+        for c in self.connections_by_id.values_mut() {
+            if !c.is_closing() {
+                c.close();
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles the reason of closing the connection told by the remote.
+    fn handle_disconnected_with_reason(&mut self, cid: ConnectionId, peer_id: PeerId, code: u32, message: String) -> Result<()> {
+        log::info!("{:?} of {:?} closed by the remote, reason {} {:?}", cid, peer_id, code, message);
+        self.notifier.notify(ConnectionEvent::DisconnectedWithReason {
+            cid,
+            peer_id,
+            code,
+            message,
+        });
+        Ok(())
+    }
+
+    /// Handles the grace period of the inbound connection passed, closing it if it is not
+    /// identified yet.
+    fn handle_identify_deadline(&mut self, cid: ConnectionId) -> Result<()> {
+        if let Some(connection) = self.connections_by_id.get_mut(&cid) {
+            if !connection.is_identified() && !connection.is_closing() {
+                log::info!("{:?} not identified within the grace period, closing", connection);
+
+                // update base statistics
+                self.base_stats.unidentified_closed += 1;
+
+                connection.close();
+            }
+        }
+        Ok(())
+    }
+
+    /// Replies to the drain requests if all connections are gone.
+    fn check_drained(&mut self) {
+        if self.connections_by_id.is_empty() {
+            if let Some(replies) = self.draining.as_mut() {
+                for (_, reply) in replies.drain(..) {
+                    let _ = reply.send(());
+                }
+            }
+        }
+    }
+
+    /// Received ping result, and then records the round trip time of the connection
     fn handle_ping_result(&mut self, cid: ConnectionId, result: Result<Duration>) -> Result<()> {
         log::trace!("handle_ping_result: {:?} {:?}", cid, result);
 
         if let Some(connection) = self.connections_by_id.get_mut(&cid) {
             match result {
-                Ok(ttl) => {
-                    //let remote_peer_id = c.remote_peer();
-                    log::trace!("ping TTL={:?} for {:?}", ttl, connection);
-                    // update peer store with the TTL
-                    let peer_id = connection.stream_muxer().remote_peer();
-                    self.peer_store.update_addr(&peer_id, ttl);
+                Ok(rtt) => {
+                    // the RTT has nothing to do with how long the addresses of the peer are valid
+                    log::trace!("ping RTT={:?} for {:?}", rtt, connection);
+                    connection.set_rtt(rtt);
                 }
                 Err(_) => {
                     log::info!("reach the max ping failure count, closing {:?}", connection);
@@ -1348,20 +2250,23 @@ impl Swarm {
                 Ok((info, observed_addr)) => {
                     let remote_pubkey = connection.remote_pub_key();
                     let peer_id = connection.remote_peer();
+                    connection.set_remote_versions(info.agent_version, info.protocol_version);
+                    let listen_addrs = self
+                        .identify_config
+                        .filter_listen_addrs(info.listen_addrs, &connection.remote_addr());
 
                     self.handle_observed_address(observed_addr, cid);
 
                     log::debug!(
                         "identified peer addresses {:?} protocols {:?} for {}",
-                        info.listen_addrs,
+                        listen_addrs,
                         info.protocols,
                         peer_id
                     );
                     // update peerstore with the listening addresses and protocols of the remote peer
                     // Note, we don't use connection.remote_addr(), because it might be a NATed address/port which
                     // changed very frequently. Instead, using info.listen_addrs is a better solution.
-                    // TODO: to handle info.protocol_version .agent_version
-                    self.peer_store.add_addrs(&peer_id, info.listen_addrs, ADDRESS_TTL);
+                    self.peer_store.add_addrs(&peer_id, listen_addrs, ADDRESS_TTL);
                     self.peer_store.add_key(&peer_id, remote_pubkey);
                     self.peer_store.add_protocols(&peer_id, info.protocols);
 
@@ -1369,6 +2274,7 @@ impl Swarm {
                     for handler in self.muxer.protocol_handlers.values_mut() {
                         handler.identified(peer_id);
                     }
+                    self.notifier.notify(ConnectionEvent::Identified { cid, peer_id });
                 }
                 Err(err) => {
                     log::debug!("identify failed {:?} for {:?}", err, connection);
@@ -1419,6 +2325,9 @@ pub enum SwarmError {
     /// No connection yet, unable to open a sub stream.
     NoConnection(PeerId),
 
+    /// The connection specified is gone, unable to open a sub stream.
+    NoSuchConnection(ConnectionId),
+
     /// The peer identity obtained on the connection did not
     /// match the one that was expected.
     InvalidPeerId(PeerId),
@@ -1451,16 +2360,26 @@ pub enum SwarmError {
     DialNoTransport(PeerId),
 
     /// AllDialsFailed is returned when connecting to a peer has ultimately failed
-    AllDialsFailed,
+    ///
+    /// Contains the classification of the last failed dial.
+    AllDialsFailed(DialErrorKind),
 
     ///dial timeout
     DialTimeout(Multiaddr, u64),
 
     ///max dial attempts exceeded
-    MaxDialAttempts(u32),
+    ///
+    /// Contains the number of attempts and the classification of the last one.
+    MaxDialAttempts(u32, DialErrorKind),
 
     ///max concurrent dial  exceeded
     ConcurrentDialLimit(u32),
+
+    /// The connection is denied by the connection gater.
+    ConnectionGated(Multiaddr),
+
+    /// Swarm is draining, no new connection is allowed.
+    Draining,
 }
 
 #[rustfmt::skip]
@@ -1469,6 +2388,7 @@ impl fmt::Display for SwarmError {
         match self {
             SwarmError::NoAddresses(peer_id) => write!(f, "Swarm Dial error: no addresses for peer{:?}.", peer_id),
             SwarmError::NoConnection(peer_id) => write!(f, "Swarm Stream error: no connections for peer{:?}.", peer_id),
+            SwarmError::NoSuchConnection(cid) => write!(f, "Swarm Stream error: no such connection {:?}.", cid),
             SwarmError::InvalidPeerId(peer_id) => write!(f, "Swarm Dial error: invalid peer id{:?}.", peer_id),
             SwarmError::Transport(err) => write!(f, "Swarm Transport error: {}.", err),
             SwarmError::Internal => write!(f, "Swarm internal error."),
@@ -1478,10 +2398,12 @@ impl fmt::Display for SwarmError {
             SwarmError::DialToSelf => write!(f, "Swarm Dial error: dial to self attempted"),
             SwarmError::DialNoTransport(peer_id) => write!(f, "Swarm Dial error: No suitable transport found for dialing {}", peer_id),
             SwarmError::DialBackoff => write!(f, "Swarm Dial error: dial backoff"),
-            SwarmError::AllDialsFailed => write!(f, "Swarm Dial error: all dials failed"),
+            SwarmError::AllDialsFailed(kind) => write!(f, "Swarm Dial error: all dials failed, kind={:?}", kind),
             SwarmError::DialTimeout(ma, t) => write!(f, "Swarm Dial error:dial timeout, addr={:?},timeout={:?}", ma, Duration::from_secs(*t)),
-            SwarmError::MaxDialAttempts(c) => write!(f, "Swarm Dial error:max dial attempts exceeded, count={}", c),
+            SwarmError::MaxDialAttempts(c, kind) => write!(f, "Swarm Dial error:max dial attempts exceeded, count={}, kind={:?}", c, kind),
             SwarmError::ConcurrentDialLimit(c) => write!(f, "Swarm Dial error:max concurrent dial exceeded, count={}", c),
+            SwarmError::ConnectionGated(ma) => write!(f, "Swarm connection denied by gater, addr={}", ma),
+            SwarmError::Draining => write!(f, "Swarm is draining"),
         }
     }
 }
@@ -1491,6 +2413,7 @@ impl error::Error for SwarmError {
         match self {
             SwarmError::NoAddresses(_) => None,
             SwarmError::NoConnection(_) => None,
+            SwarmError::NoSuchConnection(_) => None,
             SwarmError::InvalidPeerId(_) => None,
             SwarmError::Transport(err) => Some(err),
             SwarmError::Internal => None,
@@ -1500,10 +2423,73 @@ impl error::Error for SwarmError {
             SwarmError::DialToSelf => None,
             SwarmError::DialNoTransport(_) => None,
             SwarmError::DialBackoff => None,
-            SwarmError::AllDialsFailed => None,
+            SwarmError::AllDialsFailed(_) => None,
             SwarmError::DialTimeout(_, _) => None,
-            SwarmError::MaxDialAttempts(_) => None,
+            SwarmError::MaxDialAttempts(_, _) => None,
             SwarmError::ConcurrentDialLimit(_) => None,
+            SwarmError::ConnectionGated(_) => None,
+            SwarmError::Draining => None,
+        }
+    }
+}
+
+impl SwarmError {
+    /// Classifies a dialing failure, so that the application can decide whether
+    /// and when to retry.
+    ///
+    /// Returns `None` if the error is not caused by dialing.
+    pub fn dial_error_kind(&self) -> Option<DialErrorKind> {
+        match self {
+            SwarmError::Transport(err) => Some(DialErrorKind::from(err)),
+            SwarmError::NoAddresses(_) | SwarmError::DialNoTransport(_) => Some(DialErrorKind::Unreachable),
+            SwarmError::InvalidPeerId(_) => Some(DialErrorKind::HandshakeFailed),
+            SwarmError::DialBackoff | SwarmError::ConcurrentDialLimit(_) => Some(DialErrorKind::Backoff),
+            SwarmError::AllDialsFailed(kind) | SwarmError::MaxDialAttempts(_, kind) => Some(*kind),
+            SwarmError::DialTimeout(_, _) => Some(DialErrorKind::Timeout),
+            SwarmError::ConnectionGated(_) => Some(DialErrorKind::Banned),
+            _ => None,
+        }
+    }
+}
+
+/// The classification of a dialing failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DialErrorKind {
+    /// The domain name of the address could not be resolved.
+    DnsFailure,
+    /// The remote actively refused the connection.
+    ConnectionRefused,
+    /// The connection could not be established in time.
+    Timeout,
+    /// The connection was established, but the security or multiplexing
+    /// upgrade failed, or the remote reported an unexpected peer ID.
+    HandshakeFailed,
+    /// The remote could not be reached at all.
+    Unreachable,
+    /// The connection was denied locally, by the address filter or the connection gater.
+    Banned,
+    /// The dial was not attempted, because of the dial backoff or the concurrent
+    /// dialing limit.
+    Backoff,
+}
+
+impl From<&TransportError> for DialErrorKind {
+    fn from(err: &TransportError) -> Self {
+        match err {
+            TransportError::ResolveFail(_) => DialErrorKind::DnsFailure,
+            TransportError::Timeout => DialErrorKind::Timeout,
+            TransportError::IoError(e) => match e.kind() {
+                std::io::ErrorKind::ConnectionRefused => DialErrorKind::ConnectionRefused,
+                std::io::ErrorKind::TimedOut => DialErrorKind::Timeout,
+                _ => DialErrorKind::Unreachable,
+            },
+            TransportError::NegotiationError(_)
+            | TransportError::ProtectorError(_)
+            | TransportError::SecurityError(_)
+            | TransportError::StreamMuxerError(_)
+            | TransportError::IncompatibleVersion(_) => DialErrorKind::HandshakeFailed,
+            TransportError::AddressNotAllowed(_) | TransportError::SecuredDenied(_) => DialErrorKind::Banned,
+            _ => DialErrorKind::Unreachable,
         }
     }
 }
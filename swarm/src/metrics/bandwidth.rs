@@ -0,0 +1,169 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// The time constants, in seconds, of the rolling rate estimates.
+const WINDOWS: [f64; 3] = [1.0, 60.0, 900.0];
+
+/// Rolling estimates of a transfer rate, in bytes per second.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Rates {
+    /// The rate averaged over about the last second.
+    pub one_sec: f64,
+    /// The rate averaged over about the last minute.
+    pub one_min: f64,
+    /// The rate averaged over about the last 15 minutes.
+    pub fifteen_min: f64,
+}
+
+/// A snapshot of the bandwidth counters.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BandwidthStats {
+    /// The total number of bytes received.
+    pub total_in: u64,
+    /// The total number of bytes sent.
+    pub total_out: u64,
+    /// The inbound transfer rates.
+    pub rate_in: Rates,
+    /// The outbound transfer rates.
+    pub rate_out: Rates,
+}
+
+/// Keeps the total of transferred bytes in one direction, as well as exponentially
+/// decaying rate estimates for each of the windows.
+struct Meter {
+    total: AtomicU64,
+    rates: Mutex<(Instant, [f64; 3])>,
+}
+
+impl Default for Meter {
+    fn default() -> Self {
+        Meter {
+            total: AtomicU64::new(0),
+            rates: Mutex::new((Instant::now(), [0.0; 3])),
+        }
+    }
+}
+
+impl Meter {
+    fn mark(&self, n: usize) {
+        self.total.fetch_add(n as u64, Ordering::SeqCst);
+        let mut rates = self.rates.lock().unwrap();
+        let now = Instant::now();
+        Self::decay(&mut rates, now);
+        for (rate, window) in rates.1.iter_mut().zip(WINDOWS.iter()) {
+            *rate += n as f64 / window;
+        }
+    }
+
+    fn total(&self) -> u64 {
+        self.total.load(Ordering::SeqCst)
+    }
+
+    fn rates(&self) -> Rates {
+        let mut rates = self.rates.lock().unwrap();
+        Self::decay(&mut rates, Instant::now());
+        Rates {
+            one_sec: rates.1[0],
+            one_min: rates.1[1],
+            fifteen_min: rates.1[2],
+        }
+    }
+
+    /// Ages the estimates to `now`.
+    fn decay(rates: &mut (Instant, [f64; 3]), now: Instant) {
+        let elapsed = now.saturating_duration_since(rates.0).as_secs_f64();
+        for (rate, window) in rates.1.iter_mut().zip(WINDOWS.iter()) {
+            *rate *= (-elapsed / window).exp();
+        }
+        rates.0 = now;
+    }
+}
+
+/// Counts the bytes transferred in both directions, either by a single connection or
+/// by the whole swarm.
+#[derive(Default)]
+pub struct BandwidthCounters {
+    inbound: Meter,
+    outbound: Meter,
+}
+
+impl fmt::Debug for BandwidthCounters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BandwidthCounters")
+            .field("total_in", &self.inbound.total())
+            .field("total_out", &self.outbound.total())
+            .finish()
+    }
+}
+
+impl BandwidthCounters {
+    /// Create new bandwidth counters.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    pub(crate) fn log_recv(&self, n: usize) {
+        self.inbound.mark(n);
+    }
+
+    #[inline]
+    pub(crate) fn log_sent(&self, n: usize) {
+        self.outbound.mark(n);
+    }
+
+    /// Get a snapshot of the totals and the current rate estimates.
+    pub fn stats(&self) -> BandwidthStats {
+        BandwidthStats {
+            total_in: self.inbound.total(),
+            total_out: self.outbound.total(),
+            rate_in: self.inbound.rates(),
+            rate_out: self.outbound.rates(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BandwidthCounters;
+
+    #[test]
+    fn test_bandwidth_counters() {
+        let counters = BandwidthCounters::new();
+        counters.log_recv(1000);
+        counters.log_recv(24);
+        counters.log_sent(512);
+
+        let stats = counters.stats();
+        assert_eq!(stats.total_in, 1024);
+        assert_eq!(stats.total_out, 512);
+        assert!(stats.rate_in.one_sec > 0.0 && stats.rate_in.one_sec <= 1024.0);
+        assert!(stats.rate_in.one_sec > stats.rate_in.one_min);
+        assert!(stats.rate_in.one_min > stats.rate_in.fifteen_min);
+        assert!(stats.rate_out.one_sec > 0.0);
+    }
+}
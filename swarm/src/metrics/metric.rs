@@ -18,6 +18,7 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
+use crate::metrics::bandwidth::{BandwidthCounters, BandwidthStats};
 use crate::metrics::metricmap::MetricMap;
 use crate::ProtocolId;
 use libp2prs_core::PeerId;
@@ -46,6 +47,9 @@ pub struct Metric {
     peer_in: MetricMap<PeerId, usize>,
     /// A hashmap that key is peer_id and value is a counter of bytes sent.
     peer_out: MetricMap<PeerId, usize>,
+
+    /// The node-wide bandwidth counters.
+    bandwidth: BandwidthCounters,
 }
 
 impl fmt::Debug for Metric {
@@ -59,6 +63,7 @@ impl fmt::Debug for Metric {
             .field("protocol_out", &self.protocol_out)
             .field("peer_in", &self.peer_in)
             .field("peer_out", &self.peer_out)
+            .field("bandwidth", &self.bandwidth)
             .finish()
     }
 }
@@ -81,6 +86,7 @@ impl Metric {
             protocol_out: MetricMap::new(),
             peer_in: MetricMap::new(),
             peer_out: MetricMap::new(),
+            bandwidth: BandwidthCounters::new(),
         }
     }
 
@@ -88,12 +94,14 @@ impl Metric {
     pub(crate) fn log_recv_msg(&self, n: usize) {
         self.pkt_recv.fetch_add(1, Ordering::SeqCst);
         self.byte_recv.fetch_add(n, Ordering::SeqCst);
+        self.bandwidth.log_recv(n);
     }
 
     #[inline]
     pub(crate) fn log_sent_msg(&self, n: usize) {
         self.pkt_sent.fetch_add(1, Ordering::SeqCst);
         self.byte_sent.fetch_add(n, Ordering::SeqCst);
+        self.bandwidth.log_sent(n);
     }
 
     #[inline]
@@ -120,6 +128,11 @@ impl Metric {
         (self.pkt_sent.load(SeqCst), self.byte_sent.load(SeqCst))
     }
 
+    /// Get the node-wide bandwidth totals and rates
+    pub fn get_bandwidth(&self) -> BandwidthStats {
+        self.bandwidth.stats()
+    }
+
     /// Get in&out bytes by protocol_id
     pub fn get_protocol_in_and_out(&self, protocol_id: &str) -> (Option<usize>, Option<usize>) {
         let protocol_in = self.protocol_in.load(&protocol_id.to_string());
@@ -1,2 +1,3 @@
+pub mod bandwidth;
 pub mod metric;
 pub mod metricmap;
@@ -22,8 +22,9 @@ use fnv::FnvHashMap;
 use libp2prs_core::multistream::Negotiator;
 use libp2prs_core::muxing::IReadWrite;
 use libp2prs_core::transport::TransportError;
+use std::sync::{Arc, Mutex};
 
-use crate::protocol_handler::IProtocolHandler;
+use crate::protocol_handler::{IProtocolHandler, StreamHandler};
 use crate::ProtocolId;
 
 /// [`Muxer`] is used by [`Swarm`] to select and handle protocols upgrading for inbound substream.
@@ -32,14 +33,19 @@ use crate::ProtocolId;
 ///
 /// IProtocolHandler is the tait object of ProtocolHandler.
 ///
+/// The stream handlers registered at runtime are shared by all clones of the `Muxer`,
+/// so that they take effect on the existing connections as well.
+///
 pub(crate) struct Muxer {
     pub(crate) protocol_handlers: FnvHashMap<ProtocolId, IProtocolHandler>,
+    stream_handlers: Arc<Mutex<FnvHashMap<ProtocolId, StreamHandler>>>,
 }
 
 impl Clone for Muxer {
     fn clone(&self) -> Self {
         Muxer {
             protocol_handlers: self.protocol_handlers.clone(),
+            stream_handlers: self.stream_handlers.clone(),
         }
     }
 }
@@ -57,6 +63,7 @@ impl Muxer {
     pub fn new() -> Self {
         Self {
             protocol_handlers: Default::default(),
+            stream_handlers: Default::default(),
         }
     }
 }
@@ -69,8 +76,23 @@ impl Muxer {
         });
     }
 
-    pub(crate) fn supported_protocols(&self) -> impl IntoIterator<Item = ProtocolId> + '_ {
-        self.protocol_handlers.keys().cloned()
+    /// Adds or replaces the stream handler for its protocol.
+    pub(crate) fn add_stream_handler(&mut self, h: StreamHandler) {
+        log::debug!("adding stream handler: {:?}", h.protocol());
+        self.stream_handlers.lock().unwrap().insert(h.protocol().clone(), h);
+    }
+
+    /// Removes the stream handler for the protocol, if any.
+    pub(crate) fn remove_stream_handler(&mut self, protocol: &ProtocolId) {
+        log::debug!("removing stream handler: {:?}", protocol);
+        self.stream_handlers.lock().unwrap().remove(protocol);
+    }
+
+    pub(crate) fn supported_protocols(&self) -> Vec<ProtocolId> {
+        let mut protocols = self.protocol_handlers.keys().cloned().collect::<Vec<_>>();
+        let stream_handlers = self.stream_handlers.lock().unwrap();
+        protocols.extend(stream_handlers.keys().filter(|p| !self.protocol_handlers.contains_key(p)).cloned());
+        protocols
     }
 
     pub(crate) async fn select_inbound(
@@ -81,7 +103,14 @@ impl Muxer {
         let negotiator = Negotiator::new_with_protocols(protocols);
 
         let (proto, socket) = negotiator.negotiate(socket).await?;
-        let handler = self.protocol_handlers.get_mut(&proto).unwrap().clone();
+        let handler = match self.protocol_handlers.get(&proto) {
+            Some(h) => h.clone(),
+            None => {
+                let stream_handlers = self.stream_handlers.lock().unwrap();
+                let h = stream_handlers.get(&proto).ok_or(TransportError::Internal)?;
+                Box::new(h.clone()) as IProtocolHandler
+            }
+        };
 
         log::debug!("muxer select inbound {:?}", proto);
 
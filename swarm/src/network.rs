@@ -19,7 +19,8 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-use libp2prs_core::PeerId;
+use libp2prs_core::{Multiaddr, PeerId};
+use std::time::Duration;
 
 /// Information about the network obtained by [`Network::info()`].
 #[derive(Debug)]
@@ -38,6 +39,28 @@ pub struct NetworkInfo {
     pub num_active_streams: usize,
 }
 
+/// Information about a remote peer obtained by [`Control::peer_info()`].
+///
+/// It aggregates the data found in the peer store with what the active
+/// connections learned from Identify and Ping.
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    /// The Peer Id of the remote peer.
+    pub id: PeerId,
+    /// The known addresses of the remote peer.
+    pub addrs: Vec<Multiaddr>,
+    /// The protocols supported by the remote peer.
+    pub protocols: Vec<String>,
+    /// The agent version reported via Identify, if any.
+    pub agent_version: Option<String>,
+    /// The protocol version reported via Identify, if any.
+    pub protocol_version: Option<String>,
+    /// The number of active connections to the remote peer.
+    pub num_connections: usize,
+    /// The best round-trip time measured by Ping over the active connections.
+    pub rtt: Option<Duration>,
+}
+
 /// The (optional) configuration for a [`Network`].
 ///
 /// The default configuration specifies no dedicated runtime executor, no
@@ -0,0 +1,188 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Connection event notification.
+//!
+//! Applications may subscribe to the connection level events of [`Swarm`] via
+//! `Control::subscribe`, instead of polling `Swarm` for the connections. Each subscriber
+//! gets its own bounded channel. When a subscriber can't keep up with the events, the
+//! events are dropped for it and a `ConnectionEvent::Lagged` is delivered as soon as
+//! the channel has room again, reporting how many events have been missed.
+
+use futures::channel::mpsc;
+use libp2prs_core::secure_io::HandshakeErrorKind;
+use libp2prs_core::{Multiaddr, PeerId};
+
+use crate::connection::{ConnectionId, Direction};
+
+/// The capacity of the channel of every subscriber.
+const SUBSCRIBER_CAPACITY: usize = 64;
+
+/// The connection level events delivered to subscribers.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionEvent {
+    /// A connection to the remote peer has been established.
+    Established {
+        /// The connection Id.
+        cid: ConnectionId,
+        /// The remote peer Id.
+        peer_id: PeerId,
+        /// Direction of the connection.
+        dir: Direction,
+    },
+    /// A connection to the remote peer has been closed.
+    Closed {
+        /// The connection Id.
+        cid: ConnectionId,
+        /// The remote peer Id.
+        peer_id: PeerId,
+    },
+    /// One of two connections to the remote peer, opened by a simultaneous connect, has been
+    /// dropped in favor of the other.
+    Deduplicated {
+        /// The Id of the connection kept.
+        kept: ConnectionId,
+        /// The remote peer Id.
+        peer_id: PeerId,
+        /// Direction of the connection dropped.
+        dir: Direction,
+    },
+    /// The remote peer has been identified by the Identify protocol.
+    Identified {
+        /// The connection Id.
+        cid: ConnectionId,
+        /// The remote peer Id.
+        peer_id: PeerId,
+    },
+    /// The remote peer closed the connection, telling the reason.
+    DisconnectedWithReason {
+        /// The connection Id.
+        cid: ConnectionId,
+        /// The remote peer Id.
+        peer_id: PeerId,
+        /// The application defined code of the reason.
+        code: u32,
+        /// The message of the reason.
+        message: String,
+    },
+    /// A connection has been denied by the connection gater.
+    Gated {
+        /// The remote address of the connection.
+        remote_addr: Multiaddr,
+        /// Direction of the connection.
+        dir: Direction,
+    },
+    /// The security handshake of a connection has failed.
+    HandshakeFailed {
+        /// The remote address of the connection, empty for an inbound one as the listener
+        /// doesn't report it.
+        remote_addr: Multiaddr,
+        /// Direction of the connection.
+        dir: Direction,
+        /// The reason of the failure.
+        kind: HandshakeErrorKind,
+    },
+    /// The subscriber was too slow, the given number of events have been dropped.
+    Lagged(usize),
+}
+
+struct Subscriber {
+    tx: mpsc::Sender<ConnectionEvent>,
+    /// The number of events dropped since the last delivery.
+    missed: usize,
+}
+
+impl Subscriber {
+    fn send(&mut self, event: ConnectionEvent) {
+        if self.missed > 0 {
+            if self.tx.try_send(ConnectionEvent::Lagged(self.missed)).is_err() {
+                self.missed += 1;
+                return;
+            }
+            self.missed = 0;
+        }
+        if self.tx.try_send(event).is_err() {
+            self.missed += 1;
+        }
+    }
+}
+
+/// Fans out the connection events to all subscribers.
+#[derive(Default)]
+pub(crate) struct Notifier {
+    subscribers: Vec<Subscriber>,
+}
+
+impl Notifier {
+    /// Adds a new subscriber, returns the receiving end of its channel.
+    pub(crate) fn subscribe(&mut self) -> mpsc::Receiver<ConnectionEvent> {
+        let (tx, rx) = mpsc::channel(SUBSCRIBER_CAPACITY);
+        self.subscribers.push(Subscriber { tx, missed: 0 });
+        rx
+    }
+
+    /// Delivers the event to all subscribers, the subscribers gone away are removed.
+    pub(crate) fn notify(&mut self, event: ConnectionEvent) {
+        self.subscribers.retain(|s| !s.tx.is_closed());
+        for s in self.subscribers.iter_mut() {
+            s.send(event.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use libp2prs_runtime::task;
+
+    fn closed(peer_id: PeerId) -> ConnectionEvent {
+        ConnectionEvent::Closed {
+            cid: ConnectionId::default(),
+            peer_id,
+        }
+    }
+
+    #[test]
+    fn lagged_subscriber() {
+        task::block_on(async {
+            let mut notifier = Notifier::default();
+            let mut rx = notifier.subscribe();
+            let peers = (0..SUBSCRIBER_CAPACITY + 4).map(|_| PeerId::random()).collect::<Vec<_>>();
+
+            // the channel capacity is SUBSCRIBER_CAPACITY plus one slot for the sender
+            for peer_id in &peers[..SUBSCRIBER_CAPACITY + 3] {
+                notifier.notify(closed(*peer_id));
+            }
+            for peer_id in &peers[..=SUBSCRIBER_CAPACITY] {
+                assert_eq!(rx.next().await, Some(closed(*peer_id)));
+            }
+
+            let last = peers[SUBSCRIBER_CAPACITY + 3];
+            notifier.notify(closed(last));
+            assert_eq!(rx.next().await, Some(ConnectionEvent::Lagged(2)));
+            assert_eq!(rx.next().await, Some(closed(last)));
+
+            drop(rx);
+            notifier.notify(closed(last));
+            assert!(notifier.subscribers.is_empty());
+        });
+    }
+}
@@ -66,6 +66,9 @@ pub struct PingConfig {
     /// The duration between the last successful outbound or inbound ping
     /// and the next outbound ping.
     interval: Duration,
+    /// The random jitter applied to each interval, as a fraction of it, i.e.
+    /// 0.1 means the interval varies within ±10%.
+    jitter: f64,
     /// The maximum number of failed outbound pings before the associated
     /// connection is deemed unhealthy, indicating to the `Swarm` that it
     /// should be closed.
@@ -89,6 +92,7 @@ impl PingConfig {
     ///
     ///   * [`PingConfig::with_interval`] 15s
     ///   * [`PingConfig::with_timeout`] 20s
+    ///   * [`PingConfig::with_jitter`] 0
     ///   * [`PingConfig::with_max_failures`] 1
     ///   * [`PingConfig::with_keep_alive`] false
     ///
@@ -106,6 +110,7 @@ impl PingConfig {
         Self {
             timeout: Duration::from_secs(20),
             interval: Duration::from_secs(15),
+            jitter: 0.0,
             max_failures: NonZeroU32::new(1).expect("1 != 0"),
             unsolicited: false,
             keep_alive: false,
@@ -121,6 +126,11 @@ impl PingConfig {
         self.interval
     }
 
+    /// Gets the ping interval jitter.
+    pub fn jitter(&self) -> f64 {
+        self.jitter
+    }
+
     /// Sets the maximum number of consecutive ping failures upon which the remote
     /// peer is considered unreachable and the connection closed.
     pub fn max_failures(&self) -> u32 {
@@ -143,6 +153,15 @@ impl PingConfig {
         self
     }
 
+    /// Sets the random jitter applied to each ping interval, as a fraction of
+    /// the interval. The value is clamped to `[0, 1]`.
+    ///
+    /// Connections established at the same time would otherwise ping in lockstep.
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.max(0.0).min(1.0);
+        self
+    }
+
     /// Sets the maximum number of consecutive ping failures upon which the remote
     /// peer is considered unreachable and the connection closed.
     pub fn with_max_failures(mut self, n: NonZeroU32) -> Self {
@@ -198,6 +217,14 @@ pub async fn ping<T: AsyncRead + AsyncWrite + Send + Unpin + std::fmt::Debug>(
         .map_or(Err(TransportError::Timeout), |r| r.map_err(|e| e.into()))
 }
 
+/// Returns `interval` randomly varied within `±jitter` of itself.
+pub(crate) fn jittered<R: Rng>(interval: Duration, jitter: f64, rng: &mut R) -> Duration {
+    if jitter <= 0.0 {
+        return interval;
+    }
+    interval.mul_f64(1.0 + rng.gen_range(-jitter, jitter))
+}
+
 /// Protocol handler that handles pinging the remote at a regular period
 /// and answering ping queries.
 ///
@@ -244,7 +271,7 @@ impl Notifiee for PingHandler {
         let config = self.config.clone();
         if config.unsolicited() {
             log::trace!("starting Ping service for {:?}", connection);
-            connection.start_ping(config.timeout(), config.interval(), config.max_failures());
+            connection.start_ping(config.timeout(), config.interval(), config.jitter(), config.max_failures());
         }
     }
 }
@@ -272,7 +299,7 @@ impl ProtocolHandler for PingHandler {
 #[cfg(test)]
 mod tests {
     use super::PingHandler;
-    use crate::ping::{ping, PingConfig};
+    use crate::ping::{jittered, ping, PingConfig};
     use crate::protocol_handler::ProtocolHandler;
     use crate::substream::Substream;
     use libp2prs_core::transport::ListenerEvent;
@@ -282,7 +309,7 @@ mod tests {
         transport::{memory::MemoryTransport, Transport},
     };
     use libp2prs_runtime::task;
-    use rand::{thread_rng, Rng};
+    use rand::{rngs::StdRng, thread_rng, Rng, SeedableRng};
     use std::time::Duration;
 
     #[test]
@@ -309,4 +336,22 @@ mod tests {
             assert!(rtt > Duration::from_secs(0));
         });
     }
+
+    #[test]
+    fn interval_jitter() {
+        let interval = Duration::from_secs(10);
+        let config = PingConfig::new().with_interval(interval).with_jitter(0.2);
+        let (min, max) = (Duration::from_secs(8), Duration::from_secs(12));
+
+        let mut rng = StdRng::seed_from_u64(42);
+        let intervals = (0..16)
+            .map(|_| jittered(config.interval(), config.jitter(), &mut rng))
+            .collect::<Vec<_>>();
+
+        assert!(intervals.iter().all(|d| *d >= min && *d <= max));
+        assert!(intervals.windows(2).any(|w| w[0] != w[1]));
+
+        // no jitter configured, the interval is kept as is
+        assert_eq!(jittered(interval, PingConfig::new().jitter(), &mut rng), interval);
+    }
 }
@@ -49,10 +49,14 @@
 //!
 
 use async_trait::async_trait;
+use futures::future::BoxFuture;
 use libp2prs_core::upgrade::UpgradeInfo;
+use libp2prs_core::util::Semaphore;
 use libp2prs_core::{Multiaddr, PeerId};
 use libp2prs_runtime::task;
 use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
 
 use crate::connection::Connection;
 use crate::substream::Substream;
@@ -112,6 +116,93 @@ impl Clone for IProtocolHandler {
     }
 }
 
+/// The async closure invoked with each inbound substream negotiated for a protocol.
+pub type StreamHandlerFn = Arc<dyn Fn(Substream) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// What a stream handler with a concurrency limit does with the inbound substreams
+/// accepted while the limit is reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Waits for one of the running invocations to finish.
+    Queue,
+    /// Resets the substream at once.
+    Reset,
+}
+
+/// Protocol handler built from an async closure, which is registered via
+/// `Control::set_stream_handler`.
+///
+/// Swarm spawns a new task for each accepted substream, in which the closure
+/// is then invoked. The invocations running at the same time can be limited with
+/// `Control::set_stream_handler_with_limit`.
+#[derive(Clone)]
+pub struct StreamHandler {
+    protocol: ProtocolId,
+    f: StreamHandlerFn,
+    /// The permits of the invocations, shared by the clones, and the policy of the excess.
+    limit: Option<(Semaphore, Overflow)>,
+}
+
+impl StreamHandler {
+    pub(crate) fn new(protocol: ProtocolId, f: StreamHandlerFn) -> Self {
+        StreamHandler { protocol, f, limit: None }
+    }
+
+    /// Limits the invocations running at the same time to `max`, at least 1.
+    pub(crate) fn with_limit(mut self, max: usize, overflow: Overflow) -> Self {
+        self.limit = Some((Semaphore::new(max), overflow));
+        self
+    }
+
+    /// Returns the protocol handled by this stream handler.
+    pub fn protocol(&self) -> &ProtocolId {
+        &self.protocol
+    }
+}
+
+impl fmt::Debug for StreamHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamHandler").field("protocol", &self.protocol).finish()
+    }
+}
+
+impl UpgradeInfo for StreamHandler {
+    type Info = ProtocolId;
+    fn protocol_info(&self) -> Vec<Self::Info> {
+        vec![self.protocol.clone()]
+    }
+}
+
+impl Notifiee for StreamHandler {}
+
+#[async_trait]
+impl ProtocolHandler for StreamHandler {
+    async fn handle(&mut self, stream: Substream, _info: <Self as UpgradeInfo>::Info) -> Result<(), Box<dyn Error>> {
+        log::trace!("stream handler handling inbound {:?}", stream);
+        // the permit is held until the invocation finishes
+        let _permit = match &self.limit {
+            None => None,
+            Some((semaphore, Overflow::Queue)) => Some(semaphore.acquire().await),
+            Some((semaphore, Overflow::Reset)) => match semaphore.try_acquire() {
+                Some(permit) => Some(permit),
+                None => {
+                    log::debug!("stream handler of {:?} is busy, resetting inbound {:?}", self.protocol, stream);
+                    let mut stream = stream;
+                    if let Err(e) = stream.reset().await {
+                        log::debug!("failed to reset inbound {:?}, closed instead: {}", stream, e);
+                    }
+                    return Ok(());
+                }
+            },
+        };
+        (self.f)(stream).await;
+        Ok(())
+    }
+    fn box_clone(&self) -> IProtocolHandler {
+        Box::new(self.clone())
+    }
+}
+
 /// Dummy protocol handler, test purpose
 ///
 /// Implementation of `ProtocolHandler` that doesn't handle anything.
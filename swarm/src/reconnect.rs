@@ -0,0 +1,124 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Reconnecting to the peers on connection failures.
+//!
+//! A [`ReconnectPolicy`] can be installed into [`Swarm`] to re-establish the connection to
+//! a peer still needed, when its connection fails, i.e. because of a stream muxer or socket
+//! error. The peer is re-dialed using the known addresses after a backoff, which doubles on
+//! every failed attempt. Once the connection is back, the [`Reinitializer`] re-opens the
+//! protocol streams the application needs.
+//!
+//! The connections closed by ourselves, or closed cleanly by the remote as told by the stream
+//! muxer with [`TransportError::ConnectionClosed`], are not reconnected.
+//!
+//! [`TransportError::ConnectionClosed`]: libp2prs_core::transport::TransportError::ConnectionClosed
+
+use libp2prs_core::PeerId;
+use std::time::Duration;
+
+use crate::Control;
+
+/// Re-establishes the state of a reconnected peer, typically by re-opening the protocol
+/// streams.
+pub trait Reinitializer {
+    /// Tests whether the peer is still needed, thus to be reconnected.
+    fn is_needed(&self, _peer_id: &PeerId) -> bool {
+        true
+    }
+    /// Called when the peer is reconnected. Note it runs in the context of `Swarm`, so the
+    /// streams should be opened in a spawned task using `control`.
+    fn reinitialize(&mut self, peer_id: PeerId, control: Control);
+}
+
+/// The policy of reconnecting to the peers on connection failures.
+pub struct ReconnectPolicy {
+    /// The backoff before the first attempt.
+    initial_backoff: Duration,
+    /// The maximum backoff between the attempts.
+    max_backoff: Duration,
+    /// The maximum number of attempts before giving up.
+    max_attempts: u32,
+    /// The reinitializer of the reconnected peers.
+    reinitializer: Box<dyn Reinitializer + Send>,
+}
+
+impl ReconnectPolicy {
+    /// Creates a new `ReconnectPolicy` with the reinitializer, backing off 1s initially
+    /// and 60s at most, for at most 5 attempts.
+    pub fn new<R: Reinitializer + Send + 'static>(reinitializer: R) -> Self {
+        ReconnectPolicy {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            max_attempts: 5,
+            reinitializer: Box::new(reinitializer),
+        }
+    }
+
+    /// Sets the backoff before the first attempt and the maximum backoff.
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Sets the maximum number of attempts.
+    pub fn with_max_attempts(mut self, n: u32) -> Self {
+        self.max_attempts = n;
+        self
+    }
+
+    /// Returns the backoff before the attempt, counting from 0.
+    pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self.initial_backoff.checked_mul(1 << attempt.min(16)).unwrap_or(self.max_backoff);
+        backoff.min(self.max_backoff)
+    }
+
+    /// Returns the maximum number of attempts.
+    pub(crate) fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Returns the reinitializer.
+    pub(crate) fn reinitializer(&mut self) -> &mut (dyn Reinitializer + Send) {
+        self.reinitializer.as_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Dummy;
+
+    impl Reinitializer for Dummy {
+        fn reinitialize(&mut self, _peer_id: PeerId, _control: Control) {}
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let policy = ReconnectPolicy::new(Dummy).with_backoff(Duration::from_millis(100), Duration::from_millis(500));
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(1), Duration::from_millis(200));
+        assert_eq!(policy.backoff(2), Duration::from_millis(400));
+        assert_eq!(policy.backoff(3), Duration::from_millis(500));
+        assert_eq!(policy.backoff(100), Duration::from_millis(500));
+    }
+}
@@ -0,0 +1,65 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Helpers to serialize the information and statistics of Swarm, enabled by the `serde` feature.
+//!
+//! Peer IDs and protocols are serialized as their canonical string forms.
+
+use serde::Serializer;
+use std::fmt;
+
+/// Serializes the value as its `Display` string.
+pub(crate) fn display<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: fmt::Display,
+    S: Serializer,
+{
+    serializer.collect_str(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::connection::ConnectionInfo;
+    use crate::{Multiaddr, PeerId};
+
+    #[test]
+    fn connection_info_to_json() {
+        let local_peer_id = PeerId::random();
+        let remote_peer_id = PeerId::random();
+        let info = ConnectionInfo {
+            la: "/ip4/127.0.0.1/tcp/8086".parse().unwrap(),
+            ra: "/ip4/127.0.0.1/tcp/8087".parse().unwrap(),
+            local_peer_id,
+            remote_peer_id,
+            num_inbound_streams: 1,
+            num_outbound_streams: 2,
+            bandwidth: Default::default(),
+        };
+
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["la"], "/ip4/127.0.0.1/tcp/8086");
+        assert_eq!(json["ra"], "/ip4/127.0.0.1/tcp/8087");
+        assert_eq!(json["local_peer_id"], local_peer_id.to_base58());
+        assert_eq!(json["remote_peer_id"], remote_peer_id.to_base58());
+        assert_eq!(json["num_inbound_streams"], 1);
+        assert_eq!(json["num_outbound_streams"], 2);
+        assert!(serde_json::from_value::<Multiaddr>(json["ra"].clone()).is_ok());
+    }
+}
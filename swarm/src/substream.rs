@@ -27,30 +27,64 @@
 use futures::channel::mpsc;
 use futures::SinkExt;
 use std::sync::Arc;
-use std::{fmt, io};
+use std::{error, fmt, io};
 
 use futures::{AsyncRead, AsyncWrite, AsyncWriteExt};
-use libp2prs_core::muxing::IReadWrite;
+use libp2prs_core::muxing::{FlowState, IReadWrite};
+use libp2prs_core::upgrade::ProtocolName;
 use libp2prs_core::{Multiaddr, PeerId};
 use libp2prs_runtime::task;
 
-use crate::connection::{ConnectionId, Direction};
+use crate::connection::{ConnectionId, ConnectionState, Direction};
 use crate::control::SwarmControlCmd;
 use crate::metrics::metric::Metric;
 use crate::ProtocolId;
 use futures::task::{Context, Poll};
 use std::pin::Pin;
 
+/// The error carried by the `io::Error` a [`Substream`] returns, once its parent connection
+/// has failed, as opposed to being closed locally.
+#[derive(Debug)]
+pub struct ConnectionFailed;
+
+impl fmt::Display for ConnectionFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("parent connection failed")
+    }
+}
+
+impl error::Error for ConnectionFailed {}
+
+/// Returns true if the I/O error is caused by the failure of the parent connection.
+pub fn is_connection_failed(err: &io::Error) -> bool {
+    err.get_ref().map_or(false, |e| e.is::<ConnectionFailed>())
+}
+
+fn connection_failed() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionAborted, ConnectionFailed)
+}
+
+/// Returns the inner stream, or the error of the given kind once it is closed or reset.
+fn opened(inner: &mut Option<IReadWrite>, kind: io::ErrorKind) -> io::Result<&mut IReadWrite> {
+    inner.as_mut().ok_or_else(|| io::Error::new(kind, "sub stream closed"))
+}
+
 /// The Id of sub stream
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct StreamId(usize);
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct SubstreamInfo {
     /// The protocol of the sub stream.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serialize::display"))]
     protocol: ProtocolId,
     /// The direction of the sub stream.
     dir: Direction,
+    /// Whether reading has been closed by `close_read`.
+    read_closed: bool,
+    /// Whether writing has been closed by `close_write`.
+    write_closed: bool,
 }
 
 #[derive(Debug)]
@@ -79,6 +113,9 @@ struct SubstreamMeta {
 /// Substream is the logical channel for the p2p connection.
 /// SubstreamMeta contains the meta information of the substream and IReadWrite
 /// provides the I/O operation to Substream.
+///
+/// The data written might be buffered by the stream muxer or the secure layer underneath,
+/// so it is recommended to finish writing with `WriteEx::flush_and_close2`.
 // #[derive(Clone)]
 pub struct Substream {
     /// The inner sub stream, created by the StreamMuxer
@@ -89,6 +126,16 @@ pub struct Substream {
     ctrl: mpsc::Sender<SwarmControlCmd>,
     /// The statistics of the substream
     metric: Arc<Metric>,
+    /// The state of the parent connection
+    state: Arc<ConnectionState>,
+    /// Set when reading is closed by ourselves.
+    read_closed: bool,
+    /// Set when writing is closed by ourselves.
+    write_closed: bool,
+    /// Set when every write should be flushed through at once.
+    flush_eagerly: bool,
+    /// Set when the eager flush of the last write could not complete at once.
+    unflushed: bool,
 }
 
 impl fmt::Debug for Substream {
@@ -105,7 +152,7 @@ impl fmt::Debug for Substream {
 // Note that we spawn a runtime to close Substream, since Rust doesn't support Async Destructor yet
 impl Drop for Substream {
     fn drop(&mut self) {
-        let inner = self.inner.take();
+        let inner = self.take_inner();
         if let Some(mut inner) = inner {
             let cid = self.cid();
             let sid = StreamId(inner.id());
@@ -121,6 +168,7 @@ impl Drop for Substream {
 }
 
 impl Substream {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         inner: IReadWrite,
         metric: Arc<Metric>,
@@ -129,12 +177,18 @@ impl Substream {
         cid: ConnectionId,
         ci: ConnectInfo,
         ctrl: mpsc::Sender<SwarmControlCmd>,
+        state: Arc<ConnectionState>,
     ) -> Self {
         Self {
             inner: Some(inner),
             info: Arc::new(SubstreamMeta { protocol, dir, cid, ci }),
             ctrl,
             metric,
+            state,
+            read_closed: false,
+            write_closed: false,
+            flush_eagerly: false,
+            unflushed: false,
         }
     }
     /// For internal test only
@@ -155,6 +209,11 @@ impl Substream {
             info: Arc::new(SubstreamMeta { protocol, dir, cid, ci }),
             ctrl,
             metric,
+            state: Default::default(),
+            read_closed: false,
+            write_closed: false,
+            flush_eagerly: false,
+            unflushed: false,
         }
     }
     /// Builds a SubstreamView struct.
@@ -166,10 +225,80 @@ impl Substream {
             dir: self.dir(),
         }
     }
+    /// Resets the sub stream abruptly, as opposed to the graceful close.
+    ///
+    /// The remote end will get an error when reading from the sub stream, rather than EOF.
+    /// An error is returned if the underlying stream muxer doesn't support resetting, the
+    /// sub stream is closed gracefully then. The sub stream must not be used any more after being reset.
+    pub async fn reset(&mut self) -> io::Result<()> {
+        if let Some(mut inner) = self.take_inner() {
+            // to ask Swarm to remove myself
+            let cid = self.cid();
+            let sid = StreamId(inner.id());
+            let _ = self.ctrl.send(SwarmControlCmd::CloseStream(cid, sid)).await;
+            let r = inner.reset().await;
+            if r.is_err() {
+                // the remote gets EOF at least, instead of waiting for nothing
+                let _ = inner.close().await;
+            }
+            r
+        } else {
+            Ok(())
+        }
+    }
+    /// Closes the writing half of the sub stream, while reading is still possible.
+    ///
+    /// The remote end reaches EOF once it has read all the data sent before.
+    pub async fn close_write(&mut self) -> io::Result<()> {
+        if self.write_closed {
+            return Ok(());
+        }
+        if let Some(inner) = self.inner.as_mut() {
+            inner.close().await?;
+        }
+        self.write_closed = true;
+        self.release_if_closed().await;
+        Ok(())
+    }
+    /// Closes the reading half of the sub stream, while writing is still possible.
+    ///
+    /// Reading returns EOF from now on. Note the remote end is not notified, as the stream
+    /// muxers have no means to do so.
+    pub async fn close_read(&mut self) -> io::Result<()> {
+        self.read_closed = true;
+        self.release_if_closed().await;
+        Ok(())
+    }
+    /// Asks Swarm to remove the sub stream once both halves are closed.
+    async fn release_if_closed(&mut self) {
+        if self.read_closed && self.write_closed {
+            if let Some(inner) = self.take_inner() {
+                let cid = self.cid();
+                let sid = StreamId(inner.id());
+                let _ = self.ctrl.send(SwarmControlCmd::CloseStream(cid, sid)).await;
+            }
+        }
+    }
+    /// Takes the inner stream out, releasing the slot taken for the protocol.
+    fn take_inner(&mut self) -> Option<IReadWrite> {
+        let inner = self.inner.take();
+        if inner.is_some() {
+            self.state.release_stream(&self.info.protocol);
+        }
+        inner
+    }
     /// Returns the protocol of the sub stream.
     pub fn protocol(&self) -> &ProtocolId {
         &self.info.protocol
     }
+    /// Returns the protocol name of the sub stream.
+    pub fn protocol_name(&self) -> &str {
+        self.info.protocol.protocol_name_str()
+    }
+    /// Returns true if the sub stream is negotiated with the given protocol.
+    pub fn matches(&self, protocol: &str) -> bool {
+        self.protocol_name() == protocol
+    }
     /// Returns the direction of the sub stream.
     pub fn dir(&self) -> Direction {
         self.info.dir
@@ -182,6 +311,51 @@ impl Substream {
     pub fn id(&self) -> StreamId {
         StreamId(self.inner.as_ref().expect("already closed?").id())
     }
+    /// Returns a one-line summary of the sub stream, e.g. `conn=1 sid=3 proto=/echo/1.0.0 dir=Outbound`,
+    /// for debugging.
+    ///
+    /// The sub stream Id is `-` once the sub stream is closed.
+    pub fn describe(&self) -> String {
+        let sid = self.inner.as_ref().map_or_else(|| "-".to_string(), |s| s.id().to_string());
+        format!(
+            "conn={} sid={} proto={} dir={:?}",
+            self.cid().to_string().trim_end(),
+            sid,
+            self.protocol_name(),
+            self.dir()
+        )
+    }
+    /// Returns the flow control state of the sub stream, i.e. the credit left for sending
+    /// and the data buffered for reading, so that the application can pace its sends.
+    ///
+    /// Returns `None` if the stream muxer doesn't have flow control.
+    pub fn flow_state(&self) -> Option<FlowState> {
+        self.inner.as_ref().and_then(|s| s.flow_state())
+    }
+    /// Hints that every write should reach the remote at once, like `TCP_NODELAY`, for
+    /// latency-sensitive request/response protocols.
+    ///
+    /// When set, every write is flushed through to the stream muxer at once, instead of
+    /// staying in any buffer until the next explicit flush. A flush which can't complete at
+    /// once is finished before the next write is accepted.
+    pub fn set_flush_eagerly(&mut self, eager: bool) {
+        self.flush_eagerly = eager;
+    }
+    /// Takes the data received but not read yet, without waiting for more, e.g. the
+    /// trailing data the remote sent before closing, after `close_write`.
+    ///
+    /// Returns nothing once the sub stream is closed or reset, or if the stream muxer
+    /// doesn't expose its receive buffer.
+    pub fn drain_buffer(&mut self) -> Vec<u8> {
+        let buf = self.inner.as_mut().map(|s| s.drain_buffer()).unwrap_or_default();
+        if !buf.is_empty() {
+            let n = buf.len();
+            self.metric.log_recv_msg(n);
+            self.state.bandwidth().log_recv(n);
+            self.metric.log_recv_stream(self.protocol(), n, &self.info.ci.rpid);
+        }
+        buf
+    }
     /// Returns the remote multiaddr of the sub stream.
     pub fn remote_multiaddr(&self) -> Multiaddr {
         self.info.ci.ra.clone()
@@ -199,6 +373,8 @@ impl Substream {
         SubstreamInfo {
             protocol: self.protocol().clone(),
             dir: self.dir(),
+            read_closed: self.read_closed,
+            write_closed: self.write_closed,
         }
     }
 }
@@ -206,48 +382,98 @@ impl Substream {
 impl AsyncRead for Substream {
     fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
         let this = &mut *self;
-        let inner = this.inner.as_mut().expect("already closed?");
-        Poll::Ready(futures::ready!(AsyncRead::poll_read(Pin::new(inner), cx, buf)).map(|n| {
-            this.metric.log_recv_msg(n);
-            this.metric.log_recv_stream(this.protocol(), n, &this.info.ci.rpid);
-            n
-        }))
+        if this.read_closed {
+            return Poll::Ready(Ok(0));
+        }
+        let inner = opened(&mut this.inner, io::ErrorKind::NotConnected)?;
+        match futures::ready!(AsyncRead::poll_read(Pin::new(inner), cx, buf)) {
+            // EOF or error caused by the parent connection going away
+            Ok(0) | Err(_) if !buf.is_empty() && this.state.is_failed() => Poll::Ready(Err(connection_failed())),
+            r => Poll::Ready(r.map(|n| {
+                this.metric.log_recv_msg(n);
+                this.state.bandwidth().log_recv(n);
+                this.metric.log_recv_stream(this.protocol(), n, &this.info.ci.rpid);
+                n
+            })),
+        }
     }
 }
 
 impl AsyncWrite for Substream {
     fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
         let this = &mut *self;
-        let inner = this.inner.as_mut().expect("already closed?");
-        Poll::Ready(futures::ready!(AsyncWrite::poll_write(Pin::new(inner), cx, buf)).map(|n| {
-            this.metric.log_sent_msg(n);
-            this.metric.log_sent_stream(this.protocol(), n, &this.info.ci.rpid);
-            n
-        }))
+        // finish the eager flush left over by the last write, before taking more data
+        if this.unflushed {
+            futures::ready!(Pin::new(&mut *this).poll_flush(cx))?;
+        }
+
+        let inner = opened(&mut this.inner, io::ErrorKind::BrokenPipe)?;
+        let n = match futures::ready!(AsyncWrite::poll_write(Pin::new(inner), cx, buf)) {
+            Err(_) if this.state.is_failed() => return Poll::Ready(Err(connection_failed())),
+            Err(e) => return Poll::Ready(Err(e)),
+            Ok(n) => n,
+        };
+        this.metric.log_sent_msg(n);
+        this.state.bandwidth().log_sent(n);
+        this.metric.log_sent_stream(this.protocol(), n, &this.info.ci.rpid);
+
+        // the data has been taken, so the write must not be pending any more
+        if this.flush_eagerly && n > 0 {
+            let inner = opened(&mut this.inner, io::ErrorKind::BrokenPipe)?;
+            match Pin::new(inner).poll_flush(cx) {
+                Poll::Ready(Err(_)) if this.state.is_failed() => return Poll::Ready(Err(connection_failed())),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Ready(Ok(())) => {}
+                Poll::Pending => this.unflushed = true,
+            }
+        }
+        Poll::Ready(Ok(n))
     }
 
     fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        Pin::new(self.inner.as_mut().expect("already closed?")).poll_flush(cx)
+        let this = &mut *self;
+        let inner = opened(&mut this.inner, io::ErrorKind::BrokenPipe)?;
+        let r = futures::ready!(Pin::new(inner).poll_flush(cx));
+        this.unflushed = false;
+        match r {
+            Err(_) if this.state.is_failed() => Poll::Ready(Err(connection_failed())),
+            r => Poll::Ready(r),
+        }
     }
 
     fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
         let this = &mut *self;
 
-        if let Some(mut inner) = this.inner.take() {
-            match this.ctrl.poll_ready(cx) {
-                Poll::Pending => {
-                    this.inner = Some(inner);
-                    return Poll::Pending;
-                }
-                Poll::Ready(_) => {}
-            }
-            // to ask Swarm to remove myself
-            let cid = this.cid();
-            let sid = StreamId(inner.id());
-            let _ = this.ctrl.start_send(SwarmControlCmd::CloseStream(cid, sid));
-            Pin::new(&mut inner).poll_close(cx)
+        if this.inner.is_none() {
+            return Poll::Ready(Ok(()));
+        }
+        // make sure the close command can be sent as soon as the sub stream is closed
+        if this.ctrl.poll_ready(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        let inner = this.inner.as_mut().expect("checked above");
+        let r = if this.write_closed {
+            // the closing has been done already by close_write
+            Ok(())
         } else {
-            Poll::Ready(Ok(()))
+            // flush the buffered data to the muxer first, otherwise the trailing data might be lost
+            match futures::ready!(Pin::new(&mut *inner).poll_flush(cx)) {
+                Ok(()) => futures::ready!(Pin::new(&mut *inner).poll_close(cx)),
+                Err(e) => Err(e),
+            }
+        };
+        this.write_closed = true;
+
+        // to ask Swarm to remove myself
+        let inner = this.take_inner().expect("checked above");
+        let cid = this.cid();
+        let sid = StreamId(inner.id());
+        let _ = this.ctrl.start_send(SwarmControlCmd::CloseStream(cid, sid));
+
+        match r {
+            Err(_) if this.state.is_failed() => Poll::Ready(Err(connection_failed())),
+            r => Poll::Ready(r),
         }
     }
 }
@@ -270,3 +496,142 @@ impl fmt::Display for SubstreamView {
         write!(f, "{} Sid({}) {} {}", self.cid, self.id.0, self.dir, self.protocol)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::AsyncReadExt;
+    use libp2prs_core::muxing::{ReadWriteEx, StreamInfo};
+    use std::sync::Mutex;
+
+    /// A sub stream which holds the written data until flushed, the flushed data is what
+    /// the remote gets. The first `stalls` flushes are pending.
+    #[derive(Debug, Clone, Default)]
+    struct Buffering {
+        buf: Vec<u8>,
+        wire: Arc<Mutex<Vec<u8>>>,
+        stalls: usize,
+    }
+
+    impl AsyncRead for Buffering {
+        fn poll_read(self: Pin<&mut Self>, _cx: &mut Context<'_>, _buf: &mut [u8]) -> Poll<io::Result<usize>> {
+            Poll::Ready(Ok(0))
+        }
+    }
+
+    impl AsyncWrite for Buffering {
+        fn poll_write(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            self.buf.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            if self.stalls > 0 {
+                self.stalls -= 1;
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let mut data = std::mem::take(&mut self.buf);
+            self.wire.lock().unwrap().append(&mut data);
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            self.poll_flush(cx)
+        }
+    }
+
+    impl StreamInfo for Buffering {
+        fn id(&self) -> usize {
+            1
+        }
+    }
+
+    impl ReadWriteEx for Buffering {
+        fn box_clone(&self) -> IReadWrite {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    fn protocol_name_and_summary() {
+        task::block_on(async {
+            let mut stream = Substream::new_with_default(Box::new(Buffering::default()));
+            assert_eq!(stream.protocol_name(), "/test");
+            assert!(stream.matches("/test"));
+            assert!(!stream.matches("/test/1.0.0"));
+
+            let summary = stream.describe();
+            assert!(summary.starts_with("conn="), "{}", summary);
+            assert!(summary.ends_with(" sid=1 proto=/test dir=Outbound"), "{}", summary);
+            assert!(!summary.contains('\n'));
+
+            stream.close().await.unwrap();
+            assert!(stream.describe().contains(" sid=- "));
+        });
+    }
+
+    #[test]
+    fn half_close_flags() {
+        task::block_on(async {
+            let inner = Buffering::default();
+            let wire = inner.wire.clone();
+            let mut stream = Substream::new_with_default(Box::new(inner));
+
+            // closing writing flushes the buffered data
+            stream.write_all(b"request").await.unwrap();
+            stream.close_write().await.unwrap();
+            assert_eq!(&wire.lock().unwrap()[..], b"request");
+            let info = stream.info();
+            assert!(info.write_closed && !info.read_closed);
+
+            stream.close_read().await.unwrap();
+            assert!(stream.info().read_closed);
+            let mut buf = [0u8; 4];
+            assert_eq!(stream.read(&mut buf).await.unwrap(), 0);
+
+            // both halves are closed, the sub stream is released
+            let r = stream.write_all(b"more").await.map_err(|e| e.kind());
+            assert_eq!(r, Err(io::ErrorKind::BrokenPipe));
+            assert_eq!(stream.flush().await.map_err(|e| e.kind()), Err(io::ErrorKind::BrokenPipe));
+            stream.close().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn eager_write_reaches_remote() {
+        task::block_on(async {
+            let inner = Buffering::default();
+            let wire = inner.wire.clone();
+            let mut stream = Substream::new_with_default(Box::new(inner));
+
+            // held back until the next flush by default
+            stream.write_all(b"batched").await.unwrap();
+            assert!(wire.lock().unwrap().is_empty());
+
+            stream.set_flush_eagerly(true);
+            stream.write_all(b"ping").await.unwrap();
+            assert_eq!(&wire.lock().unwrap()[..], b"batchedping");
+        });
+    }
+
+    #[test]
+    fn stalled_eager_flush_keeps_data() {
+        task::block_on(async {
+            let inner = Buffering {
+                stalls: 1,
+                ..Default::default()
+            };
+            let wire = inner.wire.clone();
+            let mut stream = Substream::new_with_default(Box::new(inner));
+            stream.set_flush_eagerly(true);
+
+            // the write is done even though its flush stalls, dropping it loses nothing
+            assert!(matches!(futures::poll!(stream.write(b"ping")), Poll::Ready(Ok(4))));
+            assert!(wire.lock().unwrap().is_empty());
+
+            stream.write_all(b"pong").await.unwrap();
+            assert_eq!(&wire.lock().unwrap()[..], b"pingpong");
+        });
+    }
+}
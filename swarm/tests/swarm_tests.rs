@@ -0,0 +1,1811 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use futures::channel::mpsc;
+use futures::{AsyncReadExt, AsyncWriteExt, StreamExt};
+use libp2prs_core::identity::Keypair;
+use libp2prs_core::multiaddr::protocol::Protocol;
+use libp2prs_core::multistream::Negotiator;
+use libp2prs_core::secure_io::HandshakeErrorKind;
+use libp2prs_core::transport::memory::MemoryTransport;
+use libp2prs_core::transport::upgrade::TransportUpgrade;
+use libp2prs_core::transport::{ListenerEvent, TransportError};
+use libp2prs_core::{Multiaddr, PeerId, ProtocolId, Transport};
+use libp2prs_dns::DnsConfig;
+use libp2prs_mplex as mplex;
+use libp2prs_runtime::task;
+use libp2prs_secio as secio;
+use libp2prs_swarm::connection::{ConnectionInfo, Direction};
+use libp2prs_swarm::gater::ConnectionGater;
+use libp2prs_swarm::identify::IdentifyConfig;
+use libp2prs_swarm::ping::PingConfig;
+use libp2prs_swarm::protocol_handler::Overflow;
+use libp2prs_swarm::reconnect::{ReconnectPolicy, Reinitializer};
+use libp2prs_swarm::substream::{is_connection_failed, Substream};
+use libp2prs_swarm::{ConnectionEvent, Control, DialErrorKind, Swarm, SwarmError};
+use libp2prs_tcp::TcpConfig;
+use libp2prs_yamux as yamux;
+use rand::random;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+const ECHO_PROTOCOL: &[u8] = b"/echo/1.0.0";
+const OTHER_ECHO_PROTOCOL: &[u8] = b"/echo/2.0.0";
+
+fn setup_swarm(keys: Keypair) -> Swarm {
+    let sec = secio::Config::new(keys.clone());
+    let mux = yamux::Config::new();
+    let tu = TransportUpgrade::new(MemoryTransport::default(), mux, sec);
+
+    Swarm::new(keys.public()).with_transport(Box::new(tu))
+}
+
+fn setup_mplex_swarm(keys: Keypair) -> Swarm {
+    let sec = secio::Config::new(keys.clone());
+    let mux = mplex::Config::new();
+    let tu = TransportUpgrade::new(MemoryTransport::default(), mux, sec);
+
+    Swarm::new(keys.public()).with_transport(Box::new(tu))
+}
+
+fn setup_tcp_swarm(keys: Keypair) -> Swarm {
+    let sec = secio::Config::new(keys.clone());
+    let mux = yamux::Config::new();
+    let tu = TransportUpgrade::new(DnsConfig::new(TcpConfig::new()), mux, sec);
+
+    Swarm::new(keys.public()).with_transport(Box::new(tu))
+}
+
+/// A server swarm listening on a memory address and a client swarm, both started but not
+/// connected to each other yet.
+struct SwarmPair {
+    srv_peer_id: PeerId,
+    srv_ctrl: Control,
+    cli_peer_id: PeerId,
+    cli_ctrl: Control,
+    addr: Multiaddr,
+}
+
+impl SwarmPair {
+    /// Builds both swarms with `setup`.
+    fn new(setup: fn(Keypair) -> Swarm) -> Self {
+        Self::with(setup, setup)
+    }
+
+    /// Builds the server swarm with `srv` and the client swarm with `cli`.
+    fn with(srv: impl FnOnce(Keypair) -> Swarm, cli: impl FnOnce(Keypair) -> Swarm) -> Self {
+        let srv_keys = Keypair::generate_ed25519();
+        let srv_peer_id = srv_keys.public().into_peer_id();
+        let mut srv_swarm = srv(srv_keys);
+        let srv_ctrl = srv_swarm.control();
+
+        let addr: Multiaddr = Protocol::Memory(1 + random::<u64>()).into();
+        srv_swarm.listen_on(vec![addr.clone()]).unwrap();
+        srv_swarm.start();
+
+        let cli_keys = Keypair::generate_ed25519();
+        let cli_peer_id = cli_keys.public().into_peer_id();
+        let cli_swarm = cli(cli_keys);
+        let cli_ctrl = cli_swarm.control();
+        cli_swarm.start();
+
+        SwarmPair {
+            srv_peer_id,
+            srv_ctrl,
+            cli_peer_id,
+            cli_ctrl,
+            addr,
+        }
+    }
+}
+
+async fn echo(mut stream: Substream) {
+    let mut buf = [0u8; 1024];
+    loop {
+        let n = match stream.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        if stream.write_all(&buf[..n]).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[test]
+fn set_stream_handler_serves_inbound() {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::new(setup_swarm);
+
+        srv_ctrl.set_stream_handler(ProtocolId::from(ECHO_PROTOCOL), echo).await.unwrap();
+
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+        let mut stream = cli_ctrl
+            .new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+            .await
+            .unwrap();
+
+        stream.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+    });
+}
+
+#[test]
+fn stream_handler_registered_after_connected() {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::new(setup_swarm);
+
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+        let r = cli_ctrl.new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)]).await;
+        assert!(r.is_err());
+
+        // the connection is up already when the protocol gets registered
+        srv_ctrl.set_stream_handler(ProtocolId::from(ECHO_PROTOCOL), echo).await.unwrap();
+
+        let mut stream = cli_ctrl
+            .new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+            .await
+            .unwrap();
+        assert_eq!(cli_ctrl.dump_connections(Some(srv_peer_id)).await.unwrap().len(), 1);
+
+        stream.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+    });
+}
+
+#[test]
+fn inbound_streams_with_timeout() {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::new(setup_swarm);
+
+        let mut inbound = srv_ctrl.inbound_streams(ProtocolId::from(ECHO_PROTOCOL)).await.unwrap();
+        assert!(inbound.next_inbound_stream_timeout(Duration::from_millis(100)).await.is_none());
+
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+        let mut stream = cli_ctrl
+            .new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+            .await
+            .unwrap();
+        stream.write_all(b"hello").await.unwrap();
+
+        let mut accepted = inbound.next_inbound_stream_timeout(Duration::from_secs(5)).await.unwrap();
+        assert_eq!(accepted.protocol_name(), "/echo/1.0.0");
+        let mut buf = [0u8; 5];
+        accepted.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+    });
+}
+
+#[test]
+fn substream_protocol_name() {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::new(setup_swarm);
+
+        srv_ctrl.set_stream_handler(ProtocolId::from(ECHO_PROTOCOL), echo).await.unwrap();
+
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+        let stream = cli_ctrl
+            .new_stream(
+                srv_peer_id,
+                vec![ProtocolId::from(b"/foo/1.0.0" as &[u8]), ProtocolId::from(ECHO_PROTOCOL)],
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(stream.protocol_name(), "/echo/1.0.0");
+    });
+}
+
+#[test]
+fn substream_reset() {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::new(setup_mplex_swarm);
+
+        // reports how the read after the data fails
+        let (tx, mut rx) = mpsc::unbounded();
+        srv_ctrl
+            .set_stream_handler(ProtocolId::from(ECHO_PROTOCOL), move |mut stream: Substream| {
+                let tx = tx.clone();
+                async move {
+                    let mut buf = [0u8; 5];
+                    stream.read_exact(&mut buf).await.unwrap();
+                    let r = stream.read(&mut buf).await.map_err(|e| e.kind());
+                    let _ = tx.unbounded_send(r);
+                }
+            })
+            .await
+            .unwrap();
+
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+
+        // graceful close, the remote sees EOF
+        let mut stream = cli_ctrl
+            .new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+            .await
+            .unwrap();
+        stream.write_all(b"hello").await.unwrap();
+        stream.close().await.unwrap();
+        assert_eq!(rx.next().await.unwrap(), Err(io::ErrorKind::UnexpectedEof));
+
+        // reset, the remote gets an error
+        let mut stream = cli_ctrl
+            .new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+            .await
+            .unwrap();
+        stream.write_all(b"hello").await.unwrap();
+        stream.reset().await.unwrap();
+        assert_eq!(rx.next().await.unwrap(), Err(io::ErrorKind::ConnectionReset));
+    });
+}
+
+#[test]
+fn flush_barrier_covers_all_streams() {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::with(|keys| setup_mplex_swarm(keys).with_disconnect_reason(), setup_mplex_swarm);
+
+        let (tx, mut rx) = mpsc::unbounded();
+        srv_ctrl
+            .set_stream_handler(ProtocolId::from(ECHO_PROTOCOL), move |mut stream: Substream| {
+                let tx = tx.clone();
+                async move {
+                    let mut buf = [0u8; 5];
+                    stream.read_exact(&mut buf).await.unwrap();
+                    let _ = tx.unbounded_send(buf);
+                }
+            })
+            .await
+            .unwrap();
+
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+
+        let mut streams = Vec::new();
+        for data in &[b"hello", b"world"] {
+            let mut stream = cli_ctrl
+                .new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+                .await
+                .unwrap();
+            stream.write_all(*data).await.unwrap();
+            streams.push(stream);
+        }
+        let cid = streams[0].cid();
+        assert_eq!(streams[1].cid(), cid);
+
+        cli_ctrl.flush_barrier(cid).await.unwrap();
+
+        let mut received = vec![rx.next().await.unwrap(), rx.next().await.unwrap()];
+        received.sort();
+        assert_eq!(received, vec![*b"hello", *b"world"]);
+
+        cli_ctrl.close_connection_with_reason(cid, 0, "done").await.unwrap();
+        let r = cli_ctrl.flush_barrier(cid).await;
+        assert!(matches!(r, Err(SwarmError::NoSuchConnection(_))));
+    });
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Stage {
+    Accept,
+    Secured,
+    Upgraded,
+}
+
+/// Denies any connection at the given stage.
+struct DenyAt(Stage);
+
+impl ConnectionGater for DenyAt {
+    fn intercept_accept(&self, _remote_addr: &Multiaddr) -> bool {
+        self.0 != Stage::Accept
+    }
+    fn intercept_secured(&self, _remote_peer: &PeerId, _dir: Direction) -> bool {
+        self.0 != Stage::Secured
+    }
+    fn intercept_upgraded(&self, _info: &ConnectionInfo) -> bool {
+        self.0 != Stage::Upgraded
+    }
+}
+
+fn deny_inbound_at(stage: Stage) {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::with(|keys| setup_swarm(keys).with_gater(DenyAt(stage)), setup_swarm);
+
+        let mut sub = srv_ctrl.subscribe().await.unwrap();
+
+        // the outbound connection might be established before it is closed by the remote
+        let _ = cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await;
+
+        match sub.next().await {
+            Some(ConnectionEvent::Gated { dir, .. }) => assert_eq!(dir, Direction::Inbound),
+            other => panic!("unexpected event {:?}", other),
+        }
+        assert!(srv_ctrl.dump_connections(None).await.unwrap().is_empty());
+    });
+}
+
+fn deny_outbound_at(stage: Stage) {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::with(setup_swarm, |keys| setup_swarm(keys).with_gater(DenyAt(stage)));
+
+        let r = cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await;
+        assert!(matches!(r, Err(SwarmError::ConnectionGated(_))));
+        assert!(cli_ctrl.dump_connections(None).await.unwrap().is_empty());
+    });
+}
+
+#[test]
+fn gater_deny_on_accept() {
+    deny_inbound_at(Stage::Accept);
+}
+
+#[test]
+fn gater_deny_on_secured() {
+    // the outbound denial is covered by the unit tests of the gater and the dialer
+    deny_inbound_at(Stage::Secured);
+}
+
+#[test]
+fn gater_deny_on_upgraded() {
+    deny_inbound_at(Stage::Upgraded);
+    deny_outbound_at(Stage::Upgraded);
+}
+
+#[test]
+fn handshake_failure_reported() {
+    task::block_on(async {
+        use secio::crypto::cipher::CipherType;
+
+        // the peers have no cipher in common
+        let setup = |keys: Keypair, cipher: CipherType| {
+            let sec = secio::Config::new(keys.clone()).ciphers(&[cipher]);
+            let tu = TransportUpgrade::new(MemoryTransport::default(), yamux::Config::new(), sec);
+            Swarm::new(keys.public()).with_transport(Box::new(tu))
+        };
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::with(
+            |keys| setup(keys, CipherType::Aes128Gcm),
+            |keys| setup(keys, CipherType::ChaCha20Poly1305),
+        );
+
+        let mut srv_sub = srv_ctrl.subscribe().await.unwrap();
+        let mut cli_sub = cli_ctrl.subscribe().await.unwrap();
+
+        let r = cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr.clone()]).await;
+        assert!(matches!(r, Err(SwarmError::AllDialsFailed(DialErrorKind::HandshakeFailed))));
+
+        match cli_sub.next().await {
+            Some(ConnectionEvent::HandshakeFailed { remote_addr, dir, kind }) => {
+                assert_eq!(remote_addr, addr);
+                assert_eq!(dir, Direction::Outbound);
+                assert_eq!(kind, HandshakeErrorKind::UnsupportedCipher);
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+        match srv_sub.next().await {
+            Some(ConnectionEvent::HandshakeFailed { dir, kind, .. }) => {
+                assert_eq!(dir, Direction::Inbound);
+                assert_eq!(kind, HandshakeErrorKind::UnsupportedCipher);
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    });
+}
+
+#[test]
+fn substream_reports_connection_failure() {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            cli_peer_id,
+            mut cli_ctrl,
+            addr,
+        } = SwarmPair::new(setup_swarm);
+
+        srv_ctrl.set_stream_handler(ProtocolId::from(ECHO_PROTOCOL), echo).await.unwrap();
+
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+        let mut stream = cli_ctrl
+            .new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+            .await
+            .unwrap();
+
+        stream.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+
+        // the remote kills the connection, which fails from our point of view
+        srv_ctrl.disconnect(cli_peer_id).await.unwrap();
+        while !cli_ctrl.dump_connections(None).await.unwrap().is_empty() {
+            task::sleep(Duration::from_millis(10)).await;
+        }
+
+        let err = stream.read(&mut buf).await.unwrap_err();
+        assert!(is_connection_failed(&err));
+    });
+}
+
+#[test]
+fn substream_half_close() {
+    async fn count_then_reply(mut stream: Substream) {
+        // drain everything until the remote closes writing, then reply with the byte count
+        let mut buf = [0u8; 1024];
+        let mut count = 0;
+        loop {
+            match stream.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => count += n,
+            }
+        }
+        stream.write_all(&(count as u32).to_be_bytes()).await.unwrap();
+        stream.close().await.unwrap();
+    }
+
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::new(setup_swarm);
+
+        srv_ctrl
+            .set_stream_handler(ProtocolId::from(ECHO_PROTOCOL), count_then_reply)
+            .await
+            .unwrap();
+
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+        let mut stream = cli_ctrl
+            .new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+            .await
+            .unwrap();
+
+        stream.write_all(&[1u8; 300]).await.unwrap();
+        stream.write_all(&[2u8; 200]).await.unwrap();
+        stream.close_write().await.unwrap();
+        assert!(stream.write_all(b"more").await.is_err());
+
+        let mut reply = [0u8; 4];
+        stream.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply, 500u32.to_be_bytes());
+        // the remote has closed writing as well
+        let r = stream.read(&mut reply).await.map_err(|e| e.kind());
+        assert_eq!(r, Err(io::ErrorKind::UnexpectedEof));
+        stream.close().await.unwrap();
+    });
+}
+
+#[test]
+fn stalled_negotiation_times_out() {
+    task::block_on(async {
+        let srv_keys = Keypair::generate_ed25519();
+        let srv_peer_id = srv_keys.public().into_peer_id();
+
+        // a bare peer which accepts the substream, but never negotiates its protocol
+        let sec = secio::Config::new(srv_keys);
+        let mut transport = TransportUpgrade::new(MemoryTransport::default(), yamux::Config::new(), sec);
+        let mut listener = transport.listen_on("/memory/0".parse().unwrap()).unwrap();
+        let addr = listener.multi_addr().cloned().unwrap();
+        task::spawn(async move {
+            let mut stream_muxer = match listener.accept().await.unwrap() {
+                ListenerEvent::Accepted(stream_muxer) => stream_muxer,
+                _ => panic!("unreachable"),
+            };
+            if let Some(t) = stream_muxer.task() {
+                task::spawn(t);
+            }
+            let _stream = stream_muxer.accept_stream().await.unwrap();
+            task::sleep(Duration::from_secs(30)).await;
+        });
+
+        let cli_swarm = setup_swarm(Keypair::generate_ed25519()).with_negotiation_timeout(Duration::from_millis(200));
+        let mut cli_ctrl = cli_swarm.control();
+        cli_swarm.start();
+
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+        let start = Instant::now();
+        let r = cli_ctrl.new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)]).await;
+        assert!(matches!(r, Err(SwarmError::Transport(TransportError::Timeout))));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    });
+}
+
+#[test]
+fn substream_closed_during_negotiation() {
+    task::block_on(async {
+        let srv_keys = Keypair::generate_ed25519();
+        let srv_peer_id = srv_keys.public().into_peer_id();
+
+        // a bare peer which accepts the substream, then closes it at once
+        let sec = secio::Config::new(srv_keys);
+        let mut transport = TransportUpgrade::new(MemoryTransport::default(), yamux::Config::new(), sec);
+        let mut listener = transport.listen_on("/memory/0".parse().unwrap()).unwrap();
+        let addr = listener.multi_addr().cloned().unwrap();
+        task::spawn(async move {
+            let mut stream_muxer = match listener.accept().await.unwrap() {
+                ListenerEvent::Accepted(stream_muxer) => stream_muxer,
+                _ => panic!("unreachable"),
+            };
+            if let Some(t) = stream_muxer.task() {
+                task::spawn(t);
+            }
+            let mut stream = stream_muxer.accept_stream().await.unwrap();
+            stream.close().await.unwrap();
+            task::sleep(Duration::from_secs(30)).await;
+        });
+
+        let cli_swarm = setup_swarm(Keypair::generate_ed25519());
+        let mut cli_ctrl = cli_swarm.control();
+        cli_swarm.start();
+
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+        let r = cli_ctrl.new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)]).await;
+        assert!(matches!(
+            r,
+            Err(SwarmError::Transport(TransportError::RemoteClosedDuringNegotiation))
+        ));
+    });
+}
+
+#[test]
+fn bandwidth_counts_transferred_bytes() {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::new(setup_swarm);
+
+        srv_ctrl.set_stream_handler(ProtocolId::from(ECHO_PROTOCOL), echo).await.unwrap();
+
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+        let mut stream = cli_ctrl
+            .new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+            .await
+            .unwrap();
+
+        let data = vec![0x42u8; 10000];
+        let mut buf = vec![0u8; data.len()];
+        stream.write_all(&data).await.unwrap();
+        stream.read_exact(&mut buf).await.unwrap();
+
+        let bandwidth = cli_ctrl.bandwidth();
+        assert_eq!(bandwidth.total_out, 10000);
+        assert_eq!(bandwidth.total_in, 10000);
+        assert!(bandwidth.rate_out.one_sec > 0.0);
+        assert!(bandwidth.rate_in.one_sec > 0.0);
+
+        let views = cli_ctrl.dump_connections(Some(srv_peer_id)).await.unwrap();
+        assert_eq!(views[0].info.bandwidth.total_out, 10000);
+        assert_eq!(views[0].info.bandwidth.total_in, 10000);
+    });
+}
+
+#[test]
+fn disconnect_closes_all_connections() {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::new(setup_swarm);
+
+        let mut sub = cli_ctrl.subscribe().await.unwrap();
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+        assert!(matches!(sub.next().await, Some(ConnectionEvent::Established { .. })));
+
+        cli_ctrl.disconnect(srv_peer_id).await.unwrap();
+        assert!(cli_ctrl.dump_connections(Some(srv_peer_id)).await.unwrap().is_empty());
+        match sub.next().await {
+            Some(ConnectionEvent::Closed { peer_id, .. }) => assert_eq!(peer_id, srv_peer_id),
+            other => panic!("unexpected event {:?}", other),
+        }
+
+        let r = cli_ctrl.disconnect(srv_peer_id).await;
+        assert!(matches!(r, Err(SwarmError::NoConnection(_))));
+    });
+}
+
+#[test]
+fn disconnect_ends_substreams_cleanly() {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::new(setup_mplex_swarm);
+
+        let mut inbound = srv_ctrl.inbound_streams(ProtocolId::from(ECHO_PROTOCOL)).await.unwrap();
+
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+        let mut stream = cli_ctrl
+            .new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+            .await
+            .unwrap();
+        stream.write_all(b"hello").await.unwrap();
+
+        let mut accepted = inbound.next_inbound_stream_timeout(Duration::from_secs(5)).await.unwrap();
+        let reader = task::spawn(async move {
+            let mut buf = [0u8; 5];
+            accepted.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"hello");
+            accepted.read(&mut buf).await.unwrap_err()
+        });
+
+        // the sub stream still open is closed by the muxer rather than reset
+        cli_ctrl.disconnect(srv_peer_id).await.unwrap();
+        let err = reader.await.unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    });
+}
+
+#[test]
+fn close_connection_with_reason_observed_by_remote() {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::with(setup_swarm, |keys| setup_swarm(keys).with_disconnect_reason());
+
+        let mut srv_sub = srv_ctrl.subscribe().await.unwrap();
+        let mut cli_sub = cli_ctrl.subscribe().await.unwrap();
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+        let cid = match srv_sub.next().await {
+            Some(ConnectionEvent::Established { cid, .. }) => cid,
+            other => panic!("unexpected event {:?}", other),
+        };
+        assert!(matches!(cli_sub.next().await, Some(ConnectionEvent::Established { .. })));
+
+        srv_ctrl.close_connection_with_reason(cid, 42, "banned").await.unwrap();
+
+        match cli_sub.next().await {
+            Some(ConnectionEvent::DisconnectedWithReason {
+                peer_id, code, message, ..
+            }) => {
+                assert_eq!(peer_id, srv_peer_id);
+                assert_eq!(code, 42);
+                assert_eq!(message, "banned");
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+        assert!(matches!(cli_sub.next().await, Some(ConnectionEvent::Closed { .. })));
+
+        let r = srv_ctrl.close_connection_with_reason(cid, 42, "banned").await;
+        assert!(matches!(r, Err(SwarmError::NoSuchConnection(_))));
+    });
+}
+
+#[test]
+fn close_connection_with_reason_not_taken_by_default() {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::new(setup_swarm);
+
+        let mut srv_sub = srv_ctrl.subscribe().await.unwrap();
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+        let cid = match srv_sub.next().await {
+            Some(ConnectionEvent::Established { cid, .. }) => cid,
+            other => panic!("unexpected event {:?}", other),
+        };
+
+        // the remote doesn't take the reason, the connection is closed anyway
+        assert!(srv_ctrl.close_connection_with_reason(cid, 42, "banned").await.is_err());
+        assert!(matches!(srv_sub.next().await, Some(ConnectionEvent::Closed { .. })));
+    });
+}
+
+#[test]
+fn subscribers_observe_connection_established() {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::new(setup_swarm);
+
+        let sub1 = cli_ctrl.subscribe().await.unwrap();
+        let sub2 = cli_ctrl.subscribe().await.unwrap();
+
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+
+        for mut sub in vec![sub1, sub2] {
+            match sub.next().await {
+                Some(ConnectionEvent::Established { peer_id, dir, .. }) => {
+                    assert_eq!(peer_id, srv_peer_id);
+                    assert_eq!(dir, Direction::Outbound);
+                }
+                other => panic!("unexpected event {:?}", other),
+            }
+        }
+    });
+}
+
+#[test]
+fn simultaneous_connect_keeps_one_connection() {
+    task::block_on(async {
+        let a_keys = Keypair::generate_ed25519();
+        let a_peer_id = a_keys.public().into_peer_id();
+        let mut a_swarm = setup_swarm(a_keys);
+        let a_addr: Multiaddr = Protocol::Memory(1 + random::<u64>()).into();
+        a_swarm.listen_on(vec![a_addr.clone()]).unwrap();
+        let mut a_ctrl = a_swarm.control();
+        a_swarm.start();
+
+        let b_keys = Keypair::generate_ed25519();
+        let b_peer_id = b_keys.public().into_peer_id();
+        let mut b_swarm = setup_swarm(b_keys);
+        let b_addr: Multiaddr = Protocol::Memory(1 + random::<u64>()).into();
+        b_swarm.listen_on(vec![b_addr.clone()]).unwrap();
+        let mut b_ctrl = b_swarm.control();
+        b_swarm.start();
+
+        // cross-dial
+        let (ra, rb) = futures::join!(
+            a_ctrl.connect_with_addrs(b_peer_id, vec![b_addr]),
+            b_ctrl.connect_with_addrs(a_peer_id, vec![a_addr])
+        );
+        ra.unwrap();
+        rb.unwrap();
+
+        // wait for the crossed connection to be closed on both sides
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let (a_views, b_views) = loop {
+            let a_views = a_ctrl.dump_connections(Some(b_peer_id)).await.unwrap();
+            let b_views = b_ctrl.dump_connections(Some(a_peer_id)).await.unwrap();
+            if (a_views.len() == 1 && b_views.len() == 1) || Instant::now() > deadline {
+                break (a_views, b_views);
+            }
+            task::sleep(Duration::from_millis(50)).await;
+        };
+        assert_eq!(a_views.len(), 1);
+        assert_eq!(b_views.len(), 1);
+
+        // the connection initiated by the peer with the lower PeerId is kept
+        let a_dir = if a_peer_id < b_peer_id {
+            Direction::Outbound
+        } else {
+            Direction::Inbound
+        };
+        assert_eq!(a_views[0].dir, a_dir);
+        assert_eq!(b_views[0].dir, a_dir.reverse());
+    });
+}
+
+#[test]
+fn crossed_connection_with_live_stream_survives() {
+    task::block_on(async {
+        // the new inbound connection of 'a' would win a simultaneous connect, as it is initiated
+        // by the peer with the lower PeerId
+        let (a_keys, b_keys) = loop {
+            let (a_keys, b_keys) = (Keypair::generate_ed25519(), Keypair::generate_ed25519());
+            if b_keys.public().into_peer_id() < a_keys.public().into_peer_id() {
+                break (a_keys, b_keys);
+            }
+        };
+        let b_peer_id = b_keys.public().into_peer_id();
+
+        let mut a_swarm = setup_swarm(a_keys);
+        let a_addr: Multiaddr = Protocol::Memory(1 + random::<u64>()).into();
+        a_swarm.listen_on(vec![a_addr.clone()]).unwrap();
+        let mut a_ctrl = a_swarm.control();
+        a_swarm.start();
+
+        let mut b_swarm = setup_swarm(b_keys.clone());
+        let b_addr: Multiaddr = Protocol::Memory(1 + random::<u64>()).into();
+        b_swarm.listen_on(vec![b_addr.clone()]).unwrap();
+        let mut b_ctrl = b_swarm.control();
+        b_swarm.start();
+        b_ctrl.set_stream_handler(ProtocolId::from(ECHO_PROTOCOL), echo).await.unwrap();
+
+        a_ctrl.connect_with_addrs(b_peer_id, vec![b_addr]).await.unwrap();
+        let mut stream = a_ctrl.new_stream(b_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)]).await.unwrap();
+        echo_roundtrip(&mut stream).await.unwrap();
+
+        // 'b' connects to 'a' once more, the older connection is in use
+        let mut tu = TransportUpgrade::new(MemoryTransport::default(), yamux::Config::new(), secio::Config::new(b_keys));
+        let _muxer = tu.dial(a_addr).await.unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let views = loop {
+            let views = a_ctrl.dump_connections(Some(b_peer_id)).await.unwrap();
+            if views.len() == 2 || Instant::now() > deadline {
+                break views;
+            }
+            task::sleep(Duration::from_millis(50)).await;
+        };
+        assert_eq!(views.len(), 2);
+
+        // the stream on the older connection keeps working
+        echo_roundtrip(&mut stream).await.unwrap();
+    });
+}
+
+#[test]
+fn substream_close_flushes_trailing_data() {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::new(setup_swarm);
+
+        let mut inbound = srv_ctrl.inbound_streams(ProtocolId::from(ECHO_PROTOCOL)).await.unwrap();
+
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+        let mut stream = cli_ctrl
+            .new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+            .await
+            .unwrap();
+
+        // the final message is written right before closing
+        let data: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+        stream.write_all(&data).await.unwrap();
+        stream.close().await.unwrap();
+        drop(stream);
+
+        let mut accepted = inbound.next_inbound_stream_timeout(Duration::from_secs(5)).await.unwrap();
+        let mut buf = vec![0u8; data.len()];
+        accepted.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, data);
+    });
+}
+
+#[test]
+fn eager_write_reaches_remote_without_flush() {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::new(setup_mplex_swarm);
+
+        let mut inbound = srv_ctrl.inbound_streams(ProtocolId::from(ECHO_PROTOCOL)).await.unwrap();
+
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+        let mut stream = cli_ctrl
+            .new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+            .await
+            .unwrap();
+        stream.set_flush_eagerly(true);
+
+        // neither flushed nor closed, the stream stays open while the remote reads
+        stream.write_all(b"ping").await.unwrap();
+
+        let mut accepted = inbound.next_inbound_stream_timeout(Duration::from_secs(5)).await.unwrap();
+        let mut buf = [0u8; 4];
+        task::timeout(Duration::from_secs(5), accepted.read_exact(&mut buf))
+            .await
+            .expect("the write is not delivered")
+            .unwrap();
+        assert_eq!(&buf, b"ping");
+        drop(stream);
+    });
+}
+
+#[test]
+fn dial_prefers_address_succeeded_before() {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::new(setup_swarm);
+
+        // nobody listens on it
+        let dead: Multiaddr = Protocol::Memory(1 + random::<u64>()).into();
+
+        cli_ctrl
+            .connect_with_addrs(srv_peer_id, vec![dead.clone(), addr.clone()])
+            .await
+            .unwrap();
+        assert_eq!(cli_ctrl.get_addrs(&srv_peer_id).unwrap(), vec![dead, addr.clone()]);
+        assert_eq!(cli_ctrl.addrs_prioritized(&srv_peer_id)[0], addr);
+
+        // the next dial, with the addresses from the peer store, tries the working one first
+        cli_ctrl.disconnect(srv_peer_id).await.unwrap();
+        cli_ctrl.new_connection_no_routing(srv_peer_id).await.unwrap();
+        assert_eq!(cli_ctrl.dump_connections(Some(srv_peer_id)).await.unwrap().len(), 1);
+    });
+}
+
+#[test]
+fn listeners_report_concrete_addresses() {
+    task::block_on(async {
+        let mut swarm = setup_tcp_swarm(Keypair::generate_ed25519());
+        let mut ctrl = swarm.control();
+
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+        swarm.listen_on(vec![addr.clone(), addr]).unwrap();
+        swarm.start();
+
+        let listeners = ctrl.listeners().await.unwrap();
+        assert_eq!(listeners.len(), 2);
+        assert_ne!(listeners[0], listeners[1]);
+        for addr in listeners {
+            assert!(addr.iter().any(|p| matches!(p, Protocol::Tcp(port) if port != 0)));
+        }
+    });
+}
+
+async fn echo_roundtrip(stream: &mut Substream) -> io::Result<()> {
+    stream.write_all(b"hello").await?;
+    let mut buf = [0u8; 5];
+    stream.read_exact(&mut buf).await?;
+    assert_eq!(&buf, b"hello");
+    Ok(())
+}
+
+#[test]
+fn outbound_streams_limited_per_protocol() {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::with(setup_swarm, |keys| {
+            setup_swarm(keys).with_max_concurrent_streams(ProtocolId::from(ECHO_PROTOCOL), 2)
+        });
+
+        srv_ctrl.set_stream_handler(ProtocolId::from(ECHO_PROTOCOL), echo).await.unwrap();
+        srv_ctrl
+            .set_stream_handler(ProtocolId::from(OTHER_ECHO_PROTOCOL), echo)
+            .await
+            .unwrap();
+
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+
+        let mut s1 = cli_ctrl
+            .new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+            .await
+            .unwrap();
+        let _s2 = cli_ctrl
+            .new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+            .await
+            .unwrap();
+        assert!(cli_ctrl
+            .new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+            .await
+            .is_err());
+
+        // the other protocol is unaffected
+        let mut other = cli_ctrl
+            .new_stream(srv_peer_id, vec![ProtocolId::from(OTHER_ECHO_PROTOCOL)])
+            .await
+            .unwrap();
+        echo_roundtrip(&mut other).await.unwrap();
+
+        // closing a stream frees a slot
+        s1.close().await.unwrap();
+        let mut s3 = cli_ctrl
+            .new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+            .await
+            .unwrap();
+        echo_roundtrip(&mut s3).await.unwrap();
+    });
+}
+
+#[test]
+fn inbound_streams_limited_per_protocol() {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::with(
+            |keys| setup_swarm(keys).with_max_concurrent_streams(ProtocolId::from(ECHO_PROTOCOL), 1),
+            setup_swarm,
+        );
+
+        srv_ctrl.set_stream_handler(ProtocolId::from(ECHO_PROTOCOL), echo).await.unwrap();
+        srv_ctrl
+            .set_stream_handler(ProtocolId::from(OTHER_ECHO_PROTOCOL), echo)
+            .await
+            .unwrap();
+
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+
+        let mut s1 = cli_ctrl
+            .new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+            .await
+            .unwrap();
+        echo_roundtrip(&mut s1).await.unwrap();
+
+        // the excess stream is negotiated, then dropped by the remote
+        let mut s2 = cli_ctrl
+            .new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+            .await
+            .unwrap();
+        assert!(echo_roundtrip(&mut s2).await.is_err());
+
+        // the other protocol is unaffected
+        let mut other = cli_ctrl
+            .new_stream(srv_peer_id, vec![ProtocolId::from(OTHER_ECHO_PROTOCOL)])
+            .await
+            .unwrap();
+        echo_roundtrip(&mut other).await.unwrap();
+        echo_roundtrip(&mut s1).await.unwrap();
+    });
+}
+
+#[test]
+fn stream_handler_with_limit_serves_serially() {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::new(setup_swarm);
+
+        // tracks the invocations running at the same time
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let (r, p) = (running.clone(), peak.clone());
+        srv_ctrl
+            .set_stream_handler_with_limit(ProtocolId::from(ECHO_PROTOCOL), 1, Overflow::Queue, move |stream| {
+                let (running, peak) = (r.clone(), p.clone());
+                async move {
+                    let n = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(n, Ordering::SeqCst);
+                    task::sleep(Duration::from_millis(50)).await;
+                    echo(stream).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                }
+            })
+            .await
+            .unwrap();
+
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+
+        let clients = (0..3)
+            .map(|_| {
+                let mut ctrl = cli_ctrl.clone();
+                task::spawn(async move {
+                    let mut stream = ctrl.new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)]).await.unwrap();
+                    echo_roundtrip(&mut stream).await.unwrap();
+                    stream.close().await.unwrap();
+                })
+            })
+            .collect::<Vec<_>>();
+        for client in clients {
+            client.await;
+        }
+
+        assert_eq!(peak.load(Ordering::SeqCst), 1);
+    });
+}
+
+#[test]
+fn stream_handler_with_limit_resets_excess() {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::new(setup_mplex_swarm);
+
+        srv_ctrl
+            .set_stream_handler_with_limit(ProtocolId::from(ECHO_PROTOCOL), 1, Overflow::Reset, echo)
+            .await
+            .unwrap();
+
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+
+        let mut s1 = cli_ctrl
+            .new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+            .await
+            .unwrap();
+        echo_roundtrip(&mut s1).await.unwrap();
+
+        // the handler is busy with the first stream
+        let mut s2 = cli_ctrl
+            .new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+            .await
+            .unwrap();
+        assert!(echo_roundtrip(&mut s2).await.is_err());
+        echo_roundtrip(&mut s1).await.unwrap();
+    });
+}
+
+#[test]
+fn stream_handler_with_limit_resets_excess_on_yamux() {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::new(setup_swarm);
+
+        srv_ctrl
+            .set_stream_handler_with_limit(ProtocolId::from(ECHO_PROTOCOL), 1, Overflow::Reset, echo)
+            .await
+            .unwrap();
+
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+
+        let mut s1 = cli_ctrl
+            .new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+            .await
+            .unwrap();
+        echo_roundtrip(&mut s1).await.unwrap();
+
+        // the handler is busy with the first stream, the second one is reset rather than closed
+        let mut s2 = cli_ctrl
+            .new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+            .await
+            .unwrap();
+        let mut buf = [0u8; 5];
+        let e = s2.read(&mut buf).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::ConnectionReset);
+        echo_roundtrip(&mut s1).await.unwrap();
+    });
+}
+
+#[test]
+fn peer_info_aggregates_identify_and_ping() {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::with(
+            |keys| {
+                setup_swarm(keys)
+                    .with_ping(PingConfig::new())
+                    .with_identify(IdentifyConfig::new(false))
+            },
+            |keys| {
+                setup_swarm(keys)
+                    .with_ping(PingConfig::new().with_unsolicited(true).with_interval(Duration::from_millis(100)))
+                    .with_identify(IdentifyConfig::new(false))
+            },
+        );
+
+        assert!(cli_ctrl.peer_info(srv_peer_id).await.unwrap().is_none());
+
+        let mut sub = cli_ctrl.subscribe().await.unwrap();
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+        while let Some(event) = sub.next().await {
+            if matches!(event, ConnectionEvent::Identified { .. }) {
+                break;
+            }
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let info = loop {
+            let info = cli_ctrl.peer_info(srv_peer_id).await.unwrap().expect("known peer");
+            if info.rtt.is_some() || Instant::now() > deadline {
+                break info;
+            }
+            task::sleep(Duration::from_millis(50)).await;
+        };
+
+        assert_eq!(info.id, srv_peer_id);
+        assert_eq!(info.num_connections, 1);
+        assert!(!info.addrs.is_empty());
+        assert!(info.protocols.iter().any(|p| p.starts_with("/ipfs/ping")));
+        assert!(info.agent_version.is_some());
+        assert!(info.protocol_version.is_some());
+        assert!(info.rtt.is_some());
+    });
+}
+
+#[test]
+fn unidentified_inbound_connection_closed_after_grace() {
+    task::block_on(async {
+        let grace = Duration::from_millis(300);
+        // the peer omitting Identify gets closed by the server
+        let SwarmPair {
+            srv_peer_id,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::with(
+            |keys| {
+                setup_swarm(keys)
+                    .with_identify(IdentifyConfig::new(false))
+                    .require_identify_within(Some(grace))
+            },
+            setup_swarm,
+        );
+
+        let mut sub = cli_ctrl.subscribe().await.unwrap();
+        let start = Instant::now();
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr.clone()]).await.unwrap();
+        assert!(matches!(sub.next().await, Some(ConnectionEvent::Established { .. })));
+        let closed = task::timeout(Duration::from_secs(5), sub.next()).await;
+        assert!(matches!(closed, Ok(Some(ConnectionEvent::Closed { .. }))));
+        assert!(start.elapsed() >= grace);
+
+        // the one identified in time is kept
+        let cli_swarm = setup_swarm(Keypair::generate_ed25519()).with_identify(IdentifyConfig::new(false));
+        let mut cli_ctrl = cli_swarm.control();
+        cli_swarm.start();
+
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+        task::sleep(grace * 2).await;
+        assert_eq!(cli_ctrl.dump_connections(Some(srv_peer_id)).await.unwrap().len(), 1);
+    });
+}
+
+#[test]
+fn protocols_reflect_stream_handlers() {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::with(
+            |keys| setup_swarm(keys).with_identify(IdentifyConfig::new(false)),
+            |keys| setup_swarm(keys).with_identify(IdentifyConfig::new(false)),
+        );
+
+        let echo_protocol = String::from_utf8(ECHO_PROTOCOL.to_vec()).unwrap();
+        assert!(!srv_ctrl.protocols().await.unwrap().contains(&echo_protocol));
+        srv_ctrl.set_stream_handler(ProtocolId::from(ECHO_PROTOCOL), echo).await.unwrap();
+        assert!(srv_ctrl.protocols().await.unwrap().contains(&echo_protocol));
+
+        let mut sub = cli_ctrl.subscribe().await.unwrap();
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+        while let Some(event) = sub.next().await {
+            if matches!(event, ConnectionEvent::Identified { .. }) {
+                break;
+            }
+        }
+        let identified = cli_ctrl.get_protocols(&srv_peer_id).unwrap();
+        assert!(identified.contains(&echo_protocol));
+
+        srv_ctrl.remove_stream_handler(ProtocolId::from(ECHO_PROTOCOL)).await.unwrap();
+        assert!(!srv_ctrl.protocols().await.unwrap().contains(&echo_protocol));
+        let info = srv_ctrl.retrieve_identify_info().await.unwrap();
+        assert!(!info.protocols.contains(&echo_protocol));
+    });
+}
+
+#[test]
+fn drain_refuses_dials_and_waits_for_streams() {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::new(setup_swarm);
+
+        srv_ctrl.set_stream_handler(ProtocolId::from(ECHO_PROTOCOL), echo).await.unwrap();
+
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+        let mut stream = cli_ctrl
+            .new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+            .await
+            .unwrap();
+
+        let deadline = Duration::from_secs(10);
+        let start = Instant::now();
+        let mut ctrl = cli_ctrl.clone();
+        let drain = task::spawn(async move { ctrl.drain(deadline).await });
+        task::sleep(Duration::from_millis(100)).await;
+
+        // no new connection while draining
+        let other_peer_id = Keypair::generate_ed25519().public().into_peer_id();
+        let other_addr: Multiaddr = Protocol::Memory(1 + random::<u64>()).into();
+        let r = cli_ctrl.connect_with_addrs(other_peer_id, vec![other_addr]).await;
+        assert!(matches!(r, Err(SwarmError::Draining)));
+
+        // the stream in flight keeps working, the connection is closed once it is done
+        echo_roundtrip(&mut stream).await.unwrap();
+        stream.close().await.unwrap();
+
+        drain.await.unwrap().unwrap();
+        assert!(start.elapsed() < deadline);
+        assert!(cli_ctrl.dump_connections(None).await.unwrap().is_empty());
+    });
+}
+
+#[test]
+fn drain_refuses_inbound_before_upgrade() {
+    task::block_on(async {
+        let mut srv_swarm = setup_swarm(Keypair::generate_ed25519());
+        let mut srv_ctrl = srv_swarm.control();
+
+        let addr: Multiaddr = Protocol::Memory(1 + random::<u64>()).into();
+        srv_swarm.listen_on(vec![addr.clone()]).unwrap();
+        srv_swarm.start();
+        srv_ctrl.drain(Duration::from_secs(1)).await.unwrap();
+
+        // the listener drops the socket at once, so the handshake of the dialer fails
+        let sec = secio::Config::new(Keypair::generate_ed25519());
+        let mut transport = TransportUpgrade::new(MemoryTransport::default(), yamux::Config::new(), sec);
+        assert!(transport.dial(addr).await.is_err());
+        assert!(srv_ctrl.dump_connections(None).await.unwrap().is_empty());
+    });
+}
+
+#[test]
+fn new_stream_on_specified_connection() {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::new(setup_swarm);
+
+        srv_ctrl.set_stream_handler(ProtocolId::from(ECHO_PROTOCOL), echo).await.unwrap();
+
+        // two dials in flight at the same time end up with two connections
+        let mut cli_ctrl2 = cli_ctrl.clone();
+        let (r1, r2) = futures::join!(
+            cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr.clone()]),
+            cli_ctrl2.connect_with_addrs(srv_peer_id, vec![addr])
+        );
+        r1.unwrap();
+        r2.unwrap();
+
+        let views = cli_ctrl.dump_connections(Some(srv_peer_id)).await.unwrap();
+        assert_eq!(views.len(), 2);
+
+        for view in views.iter() {
+            let mut stream = cli_ctrl
+                .new_stream_on(view.id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+                .await
+                .unwrap();
+            assert_eq!(stream.cid(), view.id);
+
+            stream.write_all(b"hello").await.unwrap();
+            let mut buf = [0u8; 5];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"hello");
+            stream.close().await.unwrap();
+        }
+
+        // the connection is gone
+        cli_ctrl.disconnect(srv_peer_id).await.unwrap();
+        let r = cli_ctrl.new_stream_on(views[0].id, vec![ProtocolId::from(ECHO_PROTOCOL)]).await;
+        assert!(matches!(r, Err(SwarmError::NoSuchConnection(cid)) if cid == views[0].id));
+    });
+}
+
+#[test]
+fn find_connection_by_stream_id() {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::new(setup_swarm);
+
+        srv_ctrl.set_stream_handler(ProtocolId::from(ECHO_PROTOCOL), echo).await.unwrap();
+
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+        let mut stream = cli_ctrl
+            .new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+            .await
+            .unwrap();
+        let (sid, cid) = (stream.id(), stream.cid());
+
+        // the stream is registered to its connection asynchronously
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let found = loop {
+            let found = cli_ctrl.find_connection(sid).await.unwrap();
+            if found.is_some() || Instant::now() > deadline {
+                break found;
+            }
+            task::sleep(Duration::from_millis(10)).await;
+        };
+        assert_eq!(found, Some(cid));
+
+        // gone with the stream
+        stream.close().await.unwrap();
+        task::sleep(Duration::from_millis(100)).await;
+        assert_eq!(cli_ctrl.find_connection(sid).await.unwrap(), None);
+    });
+}
+
+struct ReconnectRecorder(mpsc::UnboundedSender<(PeerId, Instant)>);
+
+impl Reinitializer for ReconnectRecorder {
+    fn reinitialize(&mut self, peer_id: PeerId, _control: Control) {
+        let _ = self.0.unbounded_send((peer_id, Instant::now()));
+    }
+}
+
+/// The switch of a `Breakable` socket, waking up the pending read when it breaks the socket.
+#[derive(Default)]
+struct Breaker {
+    broken: AtomicBool,
+    waker: futures::task::AtomicWaker,
+}
+
+impl Breaker {
+    fn break_socket(&self) {
+        self.broken.store(true, Ordering::SeqCst);
+        self.waker.wake();
+    }
+}
+
+/// A memory socket whose reads fail once it is broken, like a socket going bad.
+struct Breakable {
+    inner: libp2prs_core::transport::memory::Channel,
+    broken: Arc<Breaker>,
+}
+
+impl futures::AsyncRead for Breakable {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        // an idle connection would never read again otherwise
+        self.broken.waker.register(cx.waker());
+        if self.broken.broken.load(Ordering::SeqCst) {
+            return Poll::Ready(Err(io::ErrorKind::InvalidData.into()));
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl futures::AsyncWrite for Breakable {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+impl libp2prs_core::transport::ConnectionInfo for Breakable {
+    fn local_multiaddr(&self) -> Multiaddr {
+        self.inner.local_multiaddr()
+    }
+
+    fn remote_multiaddr(&self) -> Multiaddr {
+        self.inner.remote_multiaddr()
+    }
+}
+
+#[test]
+fn reconnect_after_connection_failure() {
+    task::block_on(async {
+        // the client dials through sockets which can be broken on demand
+        let sockets = Arc::new(Mutex::new(Vec::<Arc<Breaker>>::new()));
+        let registry = sockets.clone();
+        let breakable = MemoryTransport::default().map(move |inner, _addr| {
+            let broken = Arc::new(Breaker::default());
+            registry.lock().unwrap().push(broken.clone());
+            Breakable { inner, broken }
+        });
+
+        let backoff = Duration::from_millis(300);
+        let (tx, mut rx) = mpsc::unbounded();
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            cli_peer_id,
+            mut cli_ctrl,
+            addr,
+        } = SwarmPair::with(setup_swarm, |keys| {
+            let tu = TransportUpgrade::new(breakable, yamux::Config::new(), secio::Config::new(keys.clone()));
+            Swarm::new(keys.public())
+                .with_transport(Box::new(tu))
+                .with_reconnect(ReconnectPolicy::new(ReconnectRecorder(tx)).with_backoff(backoff, Duration::from_secs(1)))
+        });
+
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+
+        // the socket of the client fails, so does the stream muxer on top of it
+        let failed_at = Instant::now();
+        for broken in sockets.lock().unwrap().drain(..) {
+            broken.break_socket();
+        }
+
+        let (peer_id, reconnected_at) = rx.next().await.unwrap();
+        assert_eq!(peer_id, srv_peer_id);
+        assert!(reconnected_at - failed_at >= backoff);
+        assert_eq!(cli_ctrl.dump_connections(Some(srv_peer_id)).await.unwrap().len(), 1);
+
+        // the server closing the connection cleanly is no failure, hence no reconnect
+        srv_ctrl.disconnect(cli_peer_id).await.unwrap();
+        assert!(task::timeout(Duration::from_secs(2), rx.next()).await.is_err());
+        assert!(cli_ctrl.dump_connections(Some(srv_peer_id)).await.unwrap().is_empty());
+    });
+}
+
+#[test]
+fn substream_flow_state_reflects_credit() {
+    task::block_on(async {
+        // the client gets new credit only when the server reads
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::with(
+            |keys| {
+                let mut mux = yamux::Config::new();
+                mux.set_window_update_mode(yamux::WindowUpdateMode::on_read());
+                let tu = TransportUpgrade::new(MemoryTransport::default(), mux, secio::Config::new(keys.clone()));
+                Swarm::new(keys.public()).with_transport(Box::new(tu))
+            },
+            setup_swarm,
+        );
+        // hold the stream without reading from it
+        srv_ctrl
+            .set_stream_handler(ProtocolId::from(ECHO_PROTOCOL), |stream: Substream| async move {
+                task::sleep(Duration::from_secs(5)).await;
+                drop(stream);
+            })
+            .await
+            .unwrap();
+
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+        let mut stream = cli_ctrl
+            .new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+            .await
+            .unwrap();
+        assert!(stream.flow_state().unwrap().send_credit > 0);
+
+        let data = vec![0u8; 1024 * 1024];
+        assert!(task::timeout(Duration::from_millis(500), stream.write_all(&data)).await.is_err());
+        assert_eq!(stream.flow_state().unwrap().send_credit, 0);
+    });
+}
+
+#[test]
+fn stalled_inbound_negotiation_does_not_block_others() {
+    task::block_on(async {
+        let mut srv_swarm = setup_swarm(Keypair::generate_ed25519()).with_max_inbound_negotiations(2);
+        let mut srv_ctrl = srv_swarm.control();
+
+        let addr: Multiaddr = Protocol::Memory(1 + random::<u64>()).into();
+        srv_swarm.listen_on(vec![addr.clone()]).unwrap();
+        srv_swarm.start();
+        srv_ctrl.set_stream_handler(ProtocolId::from(ECHO_PROTOCOL), echo).await.unwrap();
+
+        // a bare peer which opens a substream, but never completes its protocol negotiation
+        let cli_keys = Keypair::generate_ed25519();
+        let sec = secio::Config::new(cli_keys);
+        let mut transport = TransportUpgrade::new(MemoryTransport::default(), yamux::Config::new(), sec);
+        let mut stream_muxer = transport.dial(addr).await.unwrap();
+        if let Some(t) = stream_muxer.task() {
+            task::spawn(t);
+        }
+        let mut stalled = stream_muxer.open_stream().await.unwrap();
+        // only the length prefix of the multistream header
+        stalled.write_all(&[0x13]).await.unwrap();
+
+        let start = Instant::now();
+        let stream = stream_muxer.open_stream().await.unwrap();
+        let negotiator = Negotiator::new_with_protocols(vec![ProtocolId::from(ECHO_PROTOCOL)]);
+        let (_, mut stream) = negotiator.select_one(stream).await.unwrap();
+        stream.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+        // well within the negotiation timeout of the stalled substream
+        assert!(start.elapsed() < Duration::from_secs(5));
+    });
+}
+
+#[test]
+fn stats_stream_samples_periodically() {
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            mut cli_ctrl,
+            addr,
+            ..
+        } = SwarmPair::new(setup_swarm);
+
+        srv_ctrl.set_stream_handler(ProtocolId::from(ECHO_PROTOCOL), echo).await.unwrap();
+
+        cli_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+        let mut stream = cli_ctrl
+            .new_stream(srv_peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)])
+            .await
+            .unwrap();
+        echo_roundtrip(&mut stream).await.unwrap();
+
+        let start = Instant::now();
+        let samples = cli_ctrl.stats_stream(Duration::from_millis(100)).take(2).collect::<Vec<_>>().await;
+        assert_eq!(samples.len(), 2);
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        for stats in samples {
+            assert_eq!(stats.connections(), 1);
+            assert!(stats.substreams() >= 1);
+            assert!(stats.bandwidth().total_out > 0);
+            assert!(stats.bandwidth().total_in > 0);
+        }
+    });
+}
+
+#[test]
+fn inbound_streams_rate_limited_per_peer() {
+    async fn try_echo(ctrl: &mut Control, peer_id: PeerId) -> bool {
+        match ctrl.new_stream(peer_id, vec![ProtocolId::from(ECHO_PROTOCOL)]).await {
+            Ok(mut stream) => echo_roundtrip(&mut stream).await.is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    task::block_on(async {
+        let SwarmPair {
+            srv_peer_id,
+            mut srv_ctrl,
+            cli_ctrl: mut greedy_ctrl,
+            addr,
+            ..
+        } = SwarmPair::with(|keys| setup_swarm(keys).with_inbound_stream_rate_limit(1, 2), setup_swarm);
+        srv_ctrl.set_stream_handler(ProtocolId::from(ECHO_PROTOCOL), echo).await.unwrap();
+        greedy_ctrl.connect_with_addrs(srv_peer_id, vec![addr.clone()]).await.unwrap();
+
+        let polite_swarm = setup_swarm(Keypair::generate_ed25519());
+        let mut polite_ctrl = polite_swarm.control();
+        polite_swarm.start();
+        polite_ctrl.connect_with_addrs(srv_peer_id, vec![addr]).await.unwrap();
+
+        // the burst goes through, the excess streams are reset
+        assert!(try_echo(&mut greedy_ctrl, srv_peer_id).await);
+        assert!(try_echo(&mut greedy_ctrl, srv_peer_id).await);
+        assert!(!try_echo(&mut greedy_ctrl, srv_peer_id).await);
+        assert!(!try_echo(&mut greedy_ctrl, srv_peer_id).await);
+
+        // the other peer has a bucket of its own
+        assert!(try_echo(&mut polite_ctrl, srv_peer_id).await);
+        assert!(try_echo(&mut polite_ctrl, srv_peer_id).await);
+
+        // the bucket refills over time
+        task::sleep(Duration::from_millis(1100)).await;
+        assert!(try_echo(&mut greedy_ctrl, srv_peer_id).await);
+    });
+}
+
+#[test]
+fn outbound_dials_limited_by_concurrency_factor() {
+    task::block_on(async {
+        // a black hole, accepting the connections but never answering the handshake
+        let addr: Multiaddr = Protocol::Memory(1 + random::<u64>()).into();
+        let mut listener = MemoryTransport::default().listen_on(addr.clone()).unwrap();
+        task::spawn(async move {
+            let mut sockets = vec![];
+            while let Ok(socket) = listener.accept().await {
+                sockets.push(socket);
+            }
+        });
+
+        let swarm = setup_swarm(Keypair::generate_ed25519()).with_dial_concurrency_factor(2);
+        let mut ctrl = swarm.control();
+        swarm.start();
+
+        for _ in 0..5 {
+            let mut ctrl = ctrl.clone();
+            let peer_id = Keypair::generate_ed25519().public().into_peer_id();
+            let addr = addr.clone();
+            task::spawn(async move {
+                let _ = ctrl.connect_with_addrs(peer_id, vec![addr]).await;
+            });
+        }
+
+        let mut max_in_flight = 0;
+        for _ in 0..20 {
+            task::sleep(Duration::from_millis(25)).await;
+            let stats = ctrl.dump_statistics().await.unwrap();
+            max_in_flight = max_in_flight.max(stats.dialer().in_progress);
+        }
+        assert_eq!(max_in_flight, 2);
+    });
+}
@@ -0,0 +1,102 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use futures::future;
+use libp2prs_core::identity::Keypair;
+use libp2prs_core::multiaddr::protocol::Protocol;
+use libp2prs_core::muxing::throughput::{self, Config};
+use libp2prs_core::muxing::IStreamMuxer;
+use libp2prs_core::transport::memory::MemoryTransport;
+use libp2prs_core::transport::upgrade::TransportUpgrade;
+use libp2prs_core::transport::ListenerEvent;
+use libp2prs_core::{Multiaddr, Transport};
+use libp2prs_mplex as mplex;
+use libp2prs_runtime::task;
+use libp2prs_secio as secio;
+use libp2prs_yamux as yamux;
+use rand::random;
+use std::time::Duration;
+
+async fn connect<T>(mut dialer: T, mut listener: T) -> (IStreamMuxer, IStreamMuxer)
+where
+    T: Transport<Output = IStreamMuxer>,
+{
+    let addr: Multiaddr = Protocol::Memory(1 + random::<u64>()).into();
+    let mut listener = listener.listen_on(addr.clone()).unwrap();
+    let accept = async move {
+        loop {
+            if let ListenerEvent::Accepted(muxer) = listener.accept().await.unwrap() {
+                break muxer;
+            }
+        }
+    };
+    let (dialer, listener) = future::join(dialer.dial(addr), accept).await;
+    (dialer.unwrap(), listener)
+}
+
+fn check(dialer: IStreamMuxer, listener: IStreamMuxer) {
+    task::block_on(async {
+        let config = Config::new().streams(10).payload(256 * 1024);
+        let report = task::timeout(Duration::from_secs(30), throughput::run(dialer, listener, config))
+            .await
+            .expect("throughput run timed out")
+            .unwrap();
+
+        assert_eq!(report.bytes, 10 * 256 * 1024);
+        assert_eq!(report.per_stream.len(), 10);
+        assert!(report.throughput() > 0.0);
+        // no stream should be starved by the others
+        assert!(report.fairness() > 0.3, "fairness {}", report.fairness());
+    });
+}
+
+#[test]
+fn yamux_throughput() {
+    let (dialer, listener) = task::block_on(connect(
+        TransportUpgrade::new(
+            MemoryTransport::default(),
+            yamux::Config::client(),
+            secio::Config::new(Keypair::generate_ed25519()),
+        ),
+        TransportUpgrade::new(
+            MemoryTransport::default(),
+            yamux::Config::server(),
+            secio::Config::new(Keypair::generate_ed25519()),
+        ),
+    ));
+    check(dialer, listener);
+}
+
+#[test]
+fn mplex_throughput() {
+    let (dialer, listener) = task::block_on(connect(
+        TransportUpgrade::new(
+            MemoryTransport::default(),
+            mplex::Config::new(),
+            secio::Config::new(Keypair::generate_ed25519()),
+        ),
+        TransportUpgrade::new(
+            MemoryTransport::default(),
+            mplex::Config::new(),
+            secio::Config::new(Keypair::generate_ed25519()),
+        ),
+    ));
+    check(dialer, listener);
+}
@@ -0,0 +1,242 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! A minimal DNS-over-HTTPS (RFC 8484) client.
+//!
+//! Queries are sent with the GET method, the DNS message being base64url encoded into the
+//! `dns` parameter. Only A and AAAA records are looked up.
+
+use async_tls::TlsConnector;
+use dns_parser::{Builder, Packet, QueryClass, QueryType, RData, ResponseCode};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2prs_runtime::net::TcpStream;
+use std::{io, net::IpAddr, str};
+
+/// Maximum size of a DoH response we are willing to read.
+const MAX_RESPONSE_SIZE: u64 = 64 * 1024;
+
+/// The resolver URL, split into the parts needed to send a request.
+#[derive(Debug, PartialEq)]
+struct DohUrl {
+    tls: bool,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl DohUrl {
+    /// Parses `https://host[:port][/path]`. The plain `http` scheme is accepted as well,
+    /// for resolvers on a trusted network.
+    fn parse(url: &str) -> io::Result<Self> {
+        let (tls, rest) = if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            return Err(invalid_input("unsupported DoH url scheme"));
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/dns-query"),
+        };
+
+        let default_port = if tls { 443 } else { 80 };
+        let (host, port) = if let Some(v6) = authority.strip_prefix('[') {
+            // [v6 address]:port
+            let end = v6.find(']').ok_or_else(|| invalid_input("invalid DoH url host"))?;
+            let port = match &v6[end + 1..] {
+                "" => default_port,
+                p => parse_port(p.strip_prefix(':').ok_or_else(|| invalid_input("invalid DoH url port"))?)?,
+            };
+            (&v6[..end], port)
+        } else {
+            match authority.rfind(':') {
+                Some(i) => (&authority[..i], parse_port(&authority[i + 1..])?),
+                None => (authority, default_port),
+            }
+        };
+        if host.is_empty() {
+            return Err(invalid_input("invalid DoH url host"));
+        }
+
+        Ok(DohUrl {
+            tls,
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+/// Resolves `name` via the DoH resolver at `url`, looking up A records if `ipv4` and AAAA
+/// records if `ipv6` is set.
+pub(crate) async fn resolve(url: &str, name: &str, ipv4: bool, ipv6: bool) -> io::Result<Vec<IpAddr>> {
+    let url = DohUrl::parse(url)?;
+
+    let mut addrs = Vec::new();
+    for (wanted, qtype) in vec![(ipv4, QueryType::A), (ipv6, QueryType::AAAA)] {
+        if !wanted {
+            continue;
+        }
+        let query = build_query(name, qtype)?;
+        let response = query_resolver(&url, &query).await?;
+        addrs.extend(parse_response(&response)?);
+    }
+    Ok(addrs)
+}
+
+/// Builds the DNS message of the query. The ID is 0 as recommended by RFC 8484, for the sake
+/// of HTTP caching.
+fn build_query(name: &str, qtype: QueryType) -> io::Result<Vec<u8>> {
+    let mut builder = Builder::new_query(0, true);
+    builder.add_question(name, false, qtype, QueryClass::IN);
+    builder.build().map_err(|_| invalid_input("DNS query too large"))
+}
+
+/// Extracts the addresses of the A/AAAA records from the DNS message of the response.
+fn parse_response(buf: &[u8]) -> io::Result<Vec<IpAddr>> {
+    let packet = Packet::parse(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if packet.header.query {
+        return Err(invalid_data("not a DNS response"));
+    }
+    if packet.header.response_code != ResponseCode::NoError {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("DNS response code: {:?}", packet.header.response_code),
+        ));
+    }
+
+    let addrs = packet
+        .answers
+        .iter()
+        .filter_map(|answer| match answer.data {
+            RData::A(ref a) => Some(IpAddr::from(a.0)),
+            RData::AAAA(ref aaaa) => Some(IpAddr::from(aaaa.0)),
+            _ => None,
+        })
+        .collect();
+    Ok(addrs)
+}
+
+/// Sends the query to the resolver and returns the DNS message of the response.
+async fn query_resolver(url: &DohUrl, query: &[u8]) -> io::Result<Vec<u8>> {
+    let request = format!(
+        "GET {}?dns={} HTTP/1.0\r\nHost: {}\r\nAccept: application/dns-message\r\n\r\n",
+        url.path,
+        base64::encode_config(query, base64::URL_SAFE_NO_PAD),
+        url.host
+    );
+
+    let stream = TcpStream::connect((url.host.as_str(), url.port)).await?;
+    if url.tls {
+        let stream = TlsConnector::default().connect(&url.host, stream).await?;
+        exchange(stream, request.as_bytes()).await
+    } else {
+        exchange(stream, request.as_bytes()).await
+    }
+}
+
+/// Writes the HTTP request and reads the body of the HTTP response.
+///
+/// HTTP/1.0 is used, so that the response is neither chunked nor kept alive: the body is
+/// whatever comes after the header, up to the content length if any.
+async fn exchange<S>(mut stream: S, request: &[u8]) -> io::Result<Vec<u8>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(request).await?;
+    stream.flush().await?;
+
+    let mut response = Vec::new();
+    stream.take(MAX_RESPONSE_SIZE).read_to_end(&mut response).await?;
+
+    let end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| invalid_data("incomplete HTTP response"))?;
+    let header = str::from_utf8(&response[..end]).map_err(|_| invalid_data("invalid HTTP response"))?;
+
+    let mut lines = header.split("\r\n");
+    let status = lines.next().and_then(|l| l.split(' ').nth(1)).unwrap_or_default();
+    if status != "200" {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("DoH resolver returned status {}", status),
+        ));
+    }
+
+    let content_length = lines.find_map(|l| {
+        let mut kv = l.splitn(2, ':');
+        match (kv.next(), kv.next()) {
+            (Some(k), Some(v)) if k.trim().eq_ignore_ascii_case("content-length") => v.trim().parse::<usize>().ok(),
+            _ => None,
+        }
+    });
+    let mut body = response.split_off(end + 4);
+    if let Some(len) = content_length {
+        if body.len() < len {
+            return Err(invalid_data("truncated HTTP response"));
+        }
+        body.truncate(len);
+    }
+    Ok(body)
+}
+
+fn parse_port(s: &str) -> io::Result<u16> {
+    s.parse().map_err(|_| invalid_input("invalid DoH url port"))
+}
+
+fn invalid_input(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_url() {
+        assert_eq!(
+            DohUrl::parse("https://1.1.1.1/dns-query").unwrap(),
+            DohUrl {
+                tls: true,
+                host: "1.1.1.1".to_string(),
+                port: 443,
+                path: "/dns-query".to_string()
+            }
+        );
+        assert_eq!(
+            DohUrl::parse("http://[::1]:8053").unwrap(),
+            DohUrl {
+                tls: false,
+                host: "::1".to_string(),
+                port: 8053,
+                path: "/dns-query".to_string()
+            }
+        );
+        assert!(DohUrl::parse("ftp://1.1.1.1/dns-query").is_err());
+        assert!(DohUrl::parse("https://:443/dns-query").is_err());
+    }
+}
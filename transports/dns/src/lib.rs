@@ -32,6 +32,11 @@
 //! Whenever we want to dial an address through the `DnsConfig` and that address contains a
 //! `/dns/`, `/dns4/`, or `/dns6/` component, a DNS resolve will be performed and the component
 //! will be replaced with `/ip4/` and/or `/ip6/` components.
+//!
+//! The resolution is done by the resolver of the operating system, unless DNS-over-HTTPS is
+//! selected with [`DnsConfig::dns_mode`].
+
+mod doh;
 
 use async_trait::async_trait;
 use libp2prs_core::transport::{IListener, ITransport};
@@ -40,10 +45,32 @@ use libp2prs_core::{
     transport::TransportError,
     Transport,
 };
-use libp2prs_runtime::net;
+use libp2prs_runtime::{net, task};
 use log::{error, trace};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Duration;
 use std::{error, fmt, io};
 
+/// The way domain names are resolved.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DnsMode {
+    /// Use the resolver of the operating system.
+    System,
+    /// Use DNS-over-HTTPS (RFC 8484) with the resolver at the given URL,
+    /// e.g. `https://1.1.1.1/dns-query`.
+    Https(String),
+    /// Use a fixed table of names, like a hosts file, e.g. for tests. The names missing from
+    /// the table fail to resolve.
+    Hosts(HashMap<String, Vec<IpAddr>>),
+}
+
+impl Default for DnsMode {
+    fn default() -> Self {
+        DnsMode::System
+    }
+}
+
 /// Represents the configuration for a DNS transport capability of libp2p.
 ///
 /// This struct implements the `Transport` trait and holds an underlying transport. Any call to
@@ -55,12 +82,77 @@ use std::{error, fmt, io};
 pub struct DnsConfig<T> {
     /// Underlying transport to use once the DNS addresses have been resolved.
     inner: T,
+    /// How to resolve the domain names.
+    mode: DnsMode,
+    /// How long a single resolution may take.
+    timeout: Option<Duration>,
+    /// How many times a failed resolution is retried.
+    retries: u32,
 }
 
 impl<T> DnsConfig<T> {
     /// Creates a new configuration object for DNS.
     pub fn new(inner: T) -> Self {
-        DnsConfig { inner }
+        DnsConfig {
+            inner,
+            mode: DnsMode::default(),
+            timeout: None,
+            retries: 0,
+        }
+    }
+
+    /// Sets the way domain names are resolved, the system resolver by default.
+    pub fn dns_mode(mut self, mode: DnsMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets how long a single resolution may take before it is considered failed. There is no
+    /// timeout by default, i.e. the one of the resolver applies.
+    pub fn dns_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how many times a failed or timed out resolution is retried, none by default.
+    pub fn dns_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Resolves the domain name, applying the timeout to each attempt and retrying the failed ones.
+    async fn resolve_with_retries(&mut self, name: &str, dns4: bool, dns6: bool) -> io::Result<Vec<IpAddr>> {
+        let mut attempt = 0;
+        loop {
+            let result = match self.timeout {
+                Some(timeout) => task::timeout(timeout, resolve(&self.mode, name, dns4, dns6))
+                    .await
+                    .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into())),
+                None => resolve(&self.mode, name, dns4, dns6).await,
+            };
+            match result {
+                Err(e) if attempt < self.retries => {
+                    attempt += 1;
+                    trace!("resolving {} failed: {}, retry {}/{}", name, e, attempt, self.retries);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Resolves the domain name to the IPv4 and/or IPv6 addresses.
+async fn resolve(mode: &DnsMode, name: &str, dns4: bool, dns6: bool) -> io::Result<Vec<IpAddr>> {
+    match mode {
+        DnsMode::System => {
+            let list = net::resolve_host(format!("{}:0", name)).await?;
+            Ok(list.map(|s| s.ip()).collect())
+        }
+        DnsMode::Https(url) => doh::resolve(url, name, dns4, dns6).await,
+        DnsMode::Hosts(hosts) => hosts
+            .get(name)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} not in the hosts table", name))),
     }
 }
 
@@ -69,7 +161,7 @@ where
     T: fmt::Debug,
 {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt.debug_tuple("DnsConfig").field(&self.inner).finish()
+        fmt.debug_tuple("DnsConfig").field(&self.inner).field(&self.mode).finish()
     }
 }
 
@@ -107,13 +199,11 @@ where
         };
 
         let name = name.to_string();
-        let to_resolve = format!("{}:0", name);
 
-        let list = net::resolve_host(to_resolve).await.map_err(|_| {
-            error!("DNS resolver crashed");
+        let list = self.resolve_with_retries(&name, dns4, dns6).await.map_err(|e| {
+            error!("DNS resolver crashed: {}", e);
             TransportError::ResolveFail(name.clone())
         })?;
-        let list = list.map(|s| s.ip()).collect::<Vec<_>>();
 
         let outcome = list
             .into_iter()
@@ -187,13 +277,16 @@ impl error::Error for DnsErr {
 
 #[cfg(test)]
 mod tests {
-    use super::DnsConfig;
+    use super::{DnsConfig, DnsMode};
     use futures::{AsyncReadExt, AsyncWriteExt};
-    use libp2prs_core::transport::ListenerEvent;
+    use libp2prs_core::transport::{ListenerEvent, TransportError};
     use libp2prs_core::Transport;
     use libp2prs_multiaddr::Multiaddr;
+    use libp2prs_runtime::net::TcpListener;
     use libp2prs_runtime::task;
     use libp2prs_tcp::TcpConfig;
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
 
     #[test]
     fn basic_resolve_v4() {
@@ -260,4 +353,133 @@ mod tests {
             handle.await;
         });
     }
+
+    /// Serves a single DoH query, answering with an A record of 127.0.0.1.
+    async fn mock_doh_server(listener: TcpListener) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !request.ends_with(b"\r\n\r\n") {
+            let n = stream.read(&mut buf).await.unwrap();
+            assert!(n > 0);
+            request.extend_from_slice(&buf[..n]);
+        }
+        let request = String::from_utf8(request).unwrap();
+        assert!(request.contains("Accept: application/dns-message"));
+
+        // GET /dns-query?dns=... HTTP/1.0
+        let target = request.split(' ').nth(1).unwrap();
+        let (path, query) = target.split_at(target.find('?').unwrap());
+        assert_eq!(path, "/dns-query");
+        let mut message = base64::decode_config(query.trim_start_matches("?dns="), base64::URL_SAFE_NO_PAD).unwrap();
+
+        // turn the query into a response with one answer
+        message[2] = 0x81;
+        message[3] = 0x80;
+        message[7] = 1;
+        message.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 127, 0, 0, 1]);
+
+        let header = format!(
+            "HTTP/1.0 200 OK\r\nContent-Type: application/dns-message\r\nContent-Length: {}\r\n\r\n",
+            message.len()
+        );
+        stream.write_all(header.as_bytes()).await.unwrap();
+        stream.write_all(&message).await.unwrap();
+        stream.close().await.unwrap();
+    }
+
+    #[test]
+    fn doh_resolve_v4() {
+        task::block_on(async move {
+            let doh_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let doh_url = format!("http://{}/dns-query", doh_listener.local_addr().unwrap());
+            let doh_server = task::spawn(mock_doh_server(doh_listener));
+
+            let listen_addr: Multiaddr = "/ip4/127.0.0.1/tcp/8385".parse().unwrap();
+            let addr: Multiaddr = "/dns4/libp2p.test/tcp/8385".parse().unwrap();
+            let mut transport = DnsConfig::new(TcpConfig::default()).dns_mode(DnsMode::Https(doh_url));
+            let mut client = transport.clone();
+
+            let msg = b"Hello World";
+
+            let mut listener = transport.listen_on(listen_addr).unwrap();
+            let handle = task::spawn(async move {
+                let mut conn = match listener.accept().await.unwrap() {
+                    ListenerEvent::Accepted(s) => s,
+                    _ => panic!("unreachable"),
+                };
+
+                let mut buf = vec![0; msg.len()];
+                conn.read_exact(&mut buf).await.expect("server read exact");
+
+                assert_eq!(&msg[..], &buf[..]);
+            });
+
+            let mut conn = client.dial(addr).await.expect("client dial");
+            conn.write_all(&msg[..]).await.expect("client write all");
+            conn.close().await.expect("client close connection");
+
+            handle.await;
+            doh_server.await;
+        });
+    }
+
+    #[test]
+    fn doh_resolve_times_out_after_retries() {
+        task::block_on(async move {
+            // accepts the queries, never answers them
+            let doh_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let doh_url = format!("http://{}/dns-query", doh_listener.local_addr().unwrap());
+            let doh_server = task::spawn(async move {
+                let mut queries = vec![];
+                while let Ok((stream, _)) = doh_listener.accept().await {
+                    queries.push(stream);
+                }
+            });
+
+            let addr: Multiaddr = "/dns4/libp2p.test/tcp/8386".parse().unwrap();
+            let mut transport = DnsConfig::new(TcpConfig::default())
+                .dns_mode(DnsMode::Https(doh_url))
+                .dns_timeout(Duration::from_millis(200))
+                .dns_retries(2);
+
+            let start = Instant::now();
+            let r = transport.dial(addr).await;
+            assert!(matches!(r, Err(TransportError::ResolveFail(name)) if name == "libp2p.test"));
+            assert!(start.elapsed() >= Duration::from_millis(600));
+            assert!(start.elapsed() < Duration::from_secs(5));
+
+            doh_server.cancel().await;
+        });
+    }
+
+    #[test]
+    fn hosts_resolve_v4() {
+        task::block_on(async move {
+            let mut hosts = HashMap::new();
+            hosts.insert("libp2p.test".to_string(), vec!["127.0.0.1".parse().unwrap()]);
+
+            let listen_addr: Multiaddr = "/ip4/127.0.0.1/tcp/8387".parse().unwrap();
+            let addr: Multiaddr = "/dns4/libp2p.test/tcp/8387".parse().unwrap();
+            let mut transport = DnsConfig::new(TcpConfig::default()).dns_mode(DnsMode::Hosts(hosts));
+            let mut client = transport.clone();
+
+            let mut listener = transport.listen_on(listen_addr).unwrap();
+            let handle = task::spawn(async move {
+                match listener.accept().await.unwrap() {
+                    ListenerEvent::Accepted(s) => s,
+                    _ => panic!("unreachable"),
+                }
+            });
+
+            let _conn = client.dial(addr).await.expect("client dial");
+            handle.await;
+
+            // not in the table
+            let addr: Multiaddr = "/dns4/unknown.test/tcp/8387".parse().unwrap();
+            let r = client.dial(addr).await;
+            assert!(matches!(r, Err(TransportError::ResolveFail(name)) if name == "unknown.test"));
+        });
+    }
 }
@@ -28,6 +28,11 @@
 //! The `TcpConfig` struct implements the `Transport` trait of the
 //! `core` library. See the documentation of `core` and of libp2p in general to learn how to
 //! use the `Transport` trait.
+//!
+//! `ProxyTransport` wraps a `TcpConfig` to dial through an HTTP `CONNECT` proxy.
+
+mod proxy;
+pub use proxy::ProxyTransport;
 
 use async_trait::async_trait;
 use futures::future::Either;
@@ -40,9 +45,10 @@ use socket2::{Domain, Socket, Type};
 use std::{
     convert::TryFrom,
     io,
-    net::{IpAddr, SocketAddr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6},
     pin::Pin,
     task::{Context, Poll},
+    time::Duration,
 };
 
 use libp2prs_core::transport::{ConnectionInfo, IListener, ITransport, ListenerEvent};
@@ -53,6 +59,9 @@ use libp2prs_core::{
 };
 use libp2prs_runtime::net::{TcpListener, TcpStream};
 
+/// How long connecting a bound socket may take, before it is given up.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// Represents the configuration for a TCP/IP transport capability for libp2p.
 ///
 #[derive(Debug, Clone, Default)]
@@ -61,12 +70,22 @@ pub struct TcpConfig {
     ttl: Option<u32>,
     /// `TCP_NODELAY` to set for opened sockets, or `None` to keep default.
     nodelay: Option<bool>,
+    /// Whether the listening port can be shared by the outgoing connections.
+    port_reuse: bool,
+    /// The local IP address the outgoing connections are bound to, or `None` to let
+    /// the OS pick one.
+    bind_source: Option<IpAddr>,
 }
 
 impl TcpConfig {
     /// Creates a new configuration object for TCP/IP.
     pub fn new() -> TcpConfig {
-        TcpConfig { ttl: None, nodelay: None }
+        TcpConfig {
+            ttl: None,
+            nodelay: None,
+            port_reuse: false,
+            bind_source: None,
+        }
     }
 
     /// Sets the TTL to set for opened sockets.
@@ -80,6 +99,72 @@ impl TcpConfig {
         self.nodelay = Some(value);
         self
     }
+
+    /// Sets `SO_REUSEPORT` on the listening sockets, so that [`TcpConfig::dial_from`] can
+    /// dial from a port we are listening on. Only available on unix.
+    pub fn port_reuse(mut self, value: bool) -> Self {
+        self.port_reuse = value;
+        self
+    }
+
+    /// Sets the local IP address the outgoing connections are bound to, which selects
+    /// the interface used on a multi-homed host.
+    ///
+    /// It only applies to the addresses of the same family, e.g. an IPv4 source is
+    /// ignored when dialing an IPv6 address.
+    pub fn bind_source(mut self, value: Option<IpAddr>) -> Self {
+        self.bind_source = value;
+        self
+    }
+
+    /// Returns the source IP address to bind to when dialing `socket_addr`.
+    fn source_ip(&self, socket_addr: &SocketAddr) -> Option<IpAddr> {
+        self.bind_source.filter(|ip| ip.is_ipv4() == socket_addr.is_ipv4())
+    }
+
+    /// Dials `addr` from the given local port, which may be a port we are listening on
+    /// if `port_reuse` is enabled.
+    ///
+    /// If the remote dials us the same way at the same time, the two connection attempts
+    /// end up in a single connection (TCP simultaneous open), which is how NAT hole punching
+    /// works.
+    pub async fn dial_from(&mut self, addr: Multiaddr, local_port: u16) -> Result<TcpTransStream, TransportError> {
+        let socket_addr = match multiaddr_to_socketaddr(&addr) {
+            Ok(sa) if sa.port() != 0 && !sa.ip().is_unspecified() => sa,
+            Ok(_) => {
+                log::debug!("Instantly refusing dialing {}, as it is invalid", addr);
+                return Err(TransportError::IoError(io::ErrorKind::ConnectionRefused.into()));
+            }
+            Err(_) => return Err(TransportError::MultiaddrNotSupported(addr)),
+        };
+
+        let socket = new_socket(&socket_addr)?;
+        let ip = self.source_ip(&socket_addr).unwrap_or_else(|| {
+            if socket_addr.is_ipv4() {
+                Ipv4Addr::UNSPECIFIED.into()
+            } else {
+                Ipv6Addr::UNSPECIFIED.into()
+            }
+        });
+        socket.set_reuse_address(true)?;
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        socket.bind(&SocketAddr::new(ip, local_port).into())?;
+
+        log::debug!("Dialing {} from port {}", addr, local_port);
+
+        let stream = connect(socket, socket_addr).await?;
+        self.make_stream(stream, addr)
+    }
+
+    fn make_stream(&self, stream: TcpStream, ra: Multiaddr) -> Result<TcpTransStream, TransportError> {
+        apply_config(self, &stream)?;
+
+        // figure out the local sock address
+        let local_addr = stream.local_addr()?;
+        let la = sock_to_multiaddr(local_addr);
+        Ok(TcpTransStream { inner: stream, la, ra })
+    }
 }
 
 #[async_trait]
@@ -92,16 +177,16 @@ impl Transport for TcpConfig {
             return Err(TransportError::MultiaddrNotSupported(addr));
         };
 
-        let socket = if socket_addr.is_ipv4() {
-            Socket::new(Domain::ipv4(), Type::stream(), Some(socket2::Protocol::tcp()))?
-        } else {
-            let s = Socket::new(Domain::ipv6(), Type::stream(), Some(socket2::Protocol::tcp()))?;
-            s.set_only_v6(true)?;
-            s
-        };
+        let socket = new_socket(&socket_addr)?;
         if cfg!(target_family = "unix") {
             socket.set_reuse_address(true)?;
         }
+        #[cfg(unix)]
+        {
+            if self.port_reuse {
+                socket.set_reuse_port(true)?;
+            }
+        }
         socket.bind(&socket_addr.into())?;
         socket.listen(1024)?; // we may want to make this configurable
 
@@ -151,19 +236,18 @@ impl Transport for TcpConfig {
             return Err(TransportError::MultiaddrNotSupported(addr));
         };
 
-        log::debug!("Dialing {}", addr);
+        let stream = if let Some(ip) = self.source_ip(&socket_addr) {
+            log::debug!("Dialing {} from {}", addr, ip);
 
-        let stream = TcpStream::connect(&socket_addr).await?;
-        apply_config(&self, &stream)?;
+            let socket = new_socket(&socket_addr)?;
+            socket.bind(&SocketAddr::new(ip, 0).into())?;
+            connect(socket, socket_addr).await?
+        } else {
+            log::debug!("Dialing {}", addr);
 
-        // figure out the local sock address
-        let local_addr = stream.local_addr()?;
-        let la = sock_to_multiaddr(local_addr);
-        Ok(TcpTransStream {
-            inner: stream,
-            la,
-            ra: addr,
-        })
+            TcpStream::connect(&socket_addr).await?
+        };
+        self.make_stream(stream, addr)
     }
 
     fn box_clone(&self) -> ITransport<Self::Output> {
@@ -269,6 +353,32 @@ impl Drop for TcpTransStream {
     }
 }
 
+/// Creates a TCP socket of the same family as `socket_addr`.
+fn new_socket(socket_addr: &SocketAddr) -> io::Result<Socket> {
+    if socket_addr.is_ipv4() {
+        Socket::new(Domain::ipv4(), Type::stream(), Some(socket2::Protocol::tcp()))
+    } else {
+        let s = Socket::new(Domain::ipv6(), Type::stream(), Some(socket2::Protocol::tcp()))?;
+        s.set_only_v6(true)?;
+        Ok(s)
+    }
+}
+
+/// Connects the (bound) socket to `socket_addr`, failing with `TimedOut` after `CONNECT_TIMEOUT`.
+async fn connect(socket: Socket, socket_addr: SocketAddr) -> io::Result<TcpStream> {
+    // socket2 only provides a blocking connect, run it off the executor. The thread is bounded
+    // by the timeout, even if the dialing is cancelled in the meantime
+    let (tx, rx) = futures::channel::oneshot::channel();
+    std::thread::spawn(move || {
+        let r = socket.connect_timeout(&socket_addr.into(), CONNECT_TIMEOUT).map(|_| socket);
+        let _ = tx.send(r);
+    });
+    let socket = rx.await.map_err(|_| io::Error::new(io::ErrorKind::Other, "connect aborted"))??;
+    socket.set_nonblocking(true)?;
+
+    Ok(TcpStream::from(socket.into_tcp_stream()))
+}
+
 /// Applies the socket configuration parameters to a socket.
 fn apply_config(config: &TcpConfig, socket: &TcpStream) -> Result<(), io::Error> {
     if let Some(ttl) = config.ttl {
@@ -282,30 +392,45 @@ fn apply_config(config: &TcpConfig, socket: &TcpStream) -> Result<(), io::Error>
     Ok(())
 }
 
+/// Polls the socket operation again as long as it is interrupted, which is transient, instead
+/// of failing the stream.
+fn retry_interrupted<T>(mut poll: impl FnMut() -> Poll<Result<T, io::Error>>) -> Poll<Result<T, io::Error>> {
+    loop {
+        match poll() {
+            Poll::Ready(Err(e)) if e.kind() == io::ErrorKind::Interrupted => log::trace!("socket operation interrupted, retrying"),
+            r => return r,
+        }
+    }
+}
+
 impl AsyncRead for TcpTransStream {
     fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<Result<usize, io::Error>> {
-        AsyncRead::poll_read(Pin::new(&mut self.inner), cx, buf)
+        retry_interrupted(|| AsyncRead::poll_read(Pin::new(&mut self.inner), cx, buf))
     }
 }
 
 impl AsyncWrite for TcpTransStream {
     fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<Result<usize, io::Error>> {
-        AsyncWrite::poll_write(Pin::new(&mut self.inner), cx, buf)
+        retry_interrupted(|| AsyncWrite::poll_write(Pin::new(&mut self.inner), cx, buf))
     }
 
     fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
-        AsyncWrite::poll_flush(Pin::new(&mut self.inner), cx)
+        retry_interrupted(|| AsyncWrite::poll_flush(Pin::new(&mut self.inner), cx))
     }
 
     fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
-        AsyncWrite::poll_close(Pin::new(&mut self.inner), cx)
+        retry_interrupted(|| AsyncWrite::poll_close(Pin::new(&mut self.inner), cx))
     }
 }
 
 // This type of logic should probably be moved into the multiaddr package
 fn multiaddr_to_socketaddr(addr: &Multiaddr) -> Result<SocketAddr, ()> {
     let mut iter = addr.iter();
-    let proto1 = iter.next().ok_or(())?;
+    // an optional `/ip6zone` may precede the `/ip6` component
+    let (zone, proto1) = match iter.next().ok_or(())? {
+        Protocol::Ip6zone(zone) => (Some(zone), iter.next().ok_or(())?),
+        proto => (None, proto),
+    };
     let proto2 = iter.next().ok_or(())?;
 
     if iter.next().is_some() {
@@ -313,12 +438,43 @@ fn multiaddr_to_socketaddr(addr: &Multiaddr) -> Result<SocketAddr, ()> {
     }
 
     match (proto1, proto2) {
-        (Protocol::Ip4(ip), Protocol::Tcp(port)) => Ok(SocketAddr::new(ip.into(), port)),
-        (Protocol::Ip6(ip), Protocol::Tcp(port)) => Ok(SocketAddr::new(ip.into(), port)),
+        (Protocol::Ip4(ip), Protocol::Tcp(port)) if zone.is_none() => Ok(SocketAddr::new(ip.into(), port)),
+        (Protocol::Ip6(ip), Protocol::Tcp(port)) => {
+            let scope_id = match zone {
+                Some(zone) => zone_to_scope_id(&zone)?,
+                None => 0,
+            };
+            Ok(SocketAddr::V6(SocketAddrV6::new(ip, port, 0, scope_id)))
+        }
         _ => Err(()),
     }
 }
 
+// Translate an IPv6 zone into a scope id, the zone is either a numeric id or an interface name.
+fn zone_to_scope_id(zone: &str) -> Result<u32, ()> {
+    if let Ok(id) = zone.parse::<u32>() {
+        return Ok(id);
+    }
+    interface_index(zone).ok_or(())
+}
+
+#[cfg(unix)]
+fn interface_index(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    // Safety: `name` is a valid NUL-terminated string that outlives the call.
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    if index == 0 {
+        None
+    } else {
+        Some(index)
+    }
+}
+
+#[cfg(not(unix))]
+fn interface_index(_name: &str) -> Option<u32> {
+    None
+}
+
 // Create a [`Multiaddr`] from the given IP address and port number.
 fn ip_to_multiaddr(ip: IpAddr, port: u16) -> Multiaddr {
     let mut addr = Multiaddr::from(ip);
@@ -326,12 +482,28 @@ fn ip_to_multiaddr(ip: IpAddr, port: u16) -> Multiaddr {
     addr
 }
 
+// Create a [`Multiaddr`] from the given socket address. IPv4-mapped IPv6 addresses, as seen by
+// IPv6 sockets for IPv4 peers, are turned into their `/ip4` form.
 fn sock_to_multiaddr(sock: SocketAddr) -> Multiaddr {
-    let mut addr = Multiaddr::from(sock.ip());
+    let ip = match sock.ip() {
+        IpAddr::V6(ip) => ipv4_mapped(&ip).map_or(IpAddr::V6(ip), IpAddr::V4),
+        ip => ip,
+    };
+    let mut addr = Multiaddr::from(ip);
     addr.push(Protocol::Tcp(sock.port()));
     addr
 }
 
+// Returns the IPv4 address if the IPv6 address is IPv4-mapped, i.e. `::ffff:a.b.c.d`.
+//
+// Unlike `Ipv6Addr::to_ipv4`, the deprecated IPv4-compatible addresses such as `::1` are left alone.
+fn ipv4_mapped(ip: &Ipv6Addr) -> Option<Ipv4Addr> {
+    match ip.segments() {
+        [0, 0, 0, 0, 0, 0xffff, ..] => ip.to_ipv4(),
+        _ => None,
+    }
+}
+
 // Collect all local host addresses and use the provided port number as listen port.
 #[allow(dead_code)]
 fn host_addresses(port: u16) -> io::Result<Vec<(IpAddr, IpNet, Multiaddr)>> {
@@ -362,13 +534,16 @@ fn host_addresses(port: u16) -> io::Result<Vec<(IpAddr, IpNet, Multiaddr)>> {
 #[cfg(test)]
 mod tests {
     use super::multiaddr_to_socketaddr;
-    use super::TcpConfig;
-    use futures::{AsyncReadExt, AsyncWriteExt};
+    use super::{retry_interrupted, sock_to_multiaddr, TcpConfig};
+    use futures::{future, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
     use libp2prs_core::multiaddr::Multiaddr;
-    use libp2prs_core::transport::ListenerEvent;
+    use libp2prs_core::transport::{ConnectionInfo, ListenerEvent};
     use libp2prs_core::Transport;
     use libp2prs_runtime::task;
+    use std::io;
     use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
     use std::time::Duration;
 
     #[test]
@@ -402,6 +577,51 @@ mod tests {
         );
     }
 
+    #[test]
+    fn socketaddr_to_multiaddr_conversion() {
+        use std::net::Ipv6Addr;
+
+        let mapped = SocketAddr::new(IpAddr::V6(Ipv4Addr::new(1, 2, 3, 4).to_ipv6_mapped()), 8080);
+        assert_eq!(sock_to_multiaddr(mapped), "/ip4/1.2.3.4/tcp/8080".parse().unwrap());
+
+        let v6 = SocketAddr::new(IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)), 8080);
+        assert_eq!(sock_to_multiaddr(v6), "/ip6/2001:db8::1/tcp/8080".parse().unwrap());
+
+        let loopback = SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 8080);
+        assert_eq!(sock_to_multiaddr(loopback), "/ip6/::1/tcp/8080".parse().unwrap());
+
+        let v4 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
+        assert_eq!(sock_to_multiaddr(v4), "/ip4/127.0.0.1/tcp/8080".parse().unwrap());
+    }
+
+    #[test]
+    fn multiaddr_with_ip6zone_to_tcp_conversion() {
+        use std::net::{Ipv6Addr, SocketAddrV6};
+
+        assert_eq!(
+            multiaddr_to_socketaddr(&"/ip6zone/3/ip6/fe80::1/tcp/1".parse::<Multiaddr>().unwrap()),
+            Ok(SocketAddr::V6(SocketAddrV6::new(
+                Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1),
+                1,
+                0,
+                3
+            )))
+        );
+        assert!(multiaddr_to_socketaddr(&"/ip6zone/no-such-if0/ip6/fe80::1/tcp/1".parse::<Multiaddr>().unwrap()).is_err());
+        assert!(multiaddr_to_socketaddr(&"/ip6zone/3/ip4/127.0.0.1/tcp/1".parse::<Multiaddr>().unwrap()).is_err());
+
+        #[cfg(target_os = "linux")]
+        assert_eq!(
+            multiaddr_to_socketaddr(&"/ip6zone/lo/ip6/fe80::1/tcp/1".parse::<Multiaddr>().unwrap()),
+            Ok(SocketAddr::V6(SocketAddrV6::new(
+                Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1),
+                1,
+                0,
+                1
+            )))
+        );
+    }
+
     #[test]
     fn dialer_and_listener_timeout() {
         fn test1(addr: Multiaddr) {
@@ -457,4 +677,153 @@ mod tests {
         test("/ip4/127.0.0.1/tcp/1110".parse().unwrap());
         test("/ip6/::1/tcp/1110".parse().unwrap());
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn dial_from_listening_port() {
+        task::block_on(async {
+            let mut tcp = TcpConfig::new().port_reuse(true);
+            let listener = tcp.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+            let port = multiaddr_to_socketaddr(listener.multi_addr().unwrap()).unwrap().port();
+
+            let mut remote = TcpConfig::new().listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+            let remote_addr = remote.multi_addr().cloned().unwrap();
+            let server = task::spawn(async move {
+                let mut socket = match remote.accept().await.unwrap() {
+                    ListenerEvent::Accepted(s) => s,
+                    _ => panic!("unreachable"),
+                };
+                socket.write_all(b"hi").await.unwrap();
+                socket.remote_multiaddr()
+            });
+
+            let mut socket = tcp.dial_from(remote_addr, port).await.unwrap();
+            assert_eq!(multiaddr_to_socketaddr(&socket.local_multiaddr()).unwrap().port(), port);
+            let mut buf = [0u8; 2];
+            socket.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"hi");
+
+            let ra = server.await.unwrap();
+            assert_eq!(multiaddr_to_socketaddr(&ra).unwrap().port(), port);
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn simultaneous_dial_from_listening_ports() {
+        task::block_on(async {
+            let mut tcp_a = TcpConfig::new().port_reuse(true);
+            let mut tcp_b = TcpConfig::new().port_reuse(true);
+            let mut listener_a = tcp_a.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+            let mut listener_b = tcp_b.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+            let addr_a = listener_a.multi_addr().cloned().unwrap();
+            let addr_b = listener_b.multi_addr().cloned().unwrap();
+            let port_a = multiaddr_to_socketaddr(&addr_a).unwrap().port();
+            let port_b = multiaddr_to_socketaddr(&addr_b).unwrap().port();
+
+            // keep the listeners accepting, a dial may still land on them
+            task::spawn(async move { while listener_a.accept().await.is_ok() {} });
+            task::spawn(async move { while listener_b.accept().await.is_ok() {} });
+
+            let (a, b) = futures::join!(tcp_a.dial_from(addr_b, port_a), tcp_b.dial_from(addr_a, port_b));
+
+            // depending on the timing, the connection is made by simultaneous open, or one of
+            // the dials is accepted by the remote listener and the other one fails
+            assert!(a.is_ok() || b.is_ok());
+            if let Ok(a) = a {
+                assert_eq!(multiaddr_to_socketaddr(&a.local_multiaddr()).unwrap().port(), port_a);
+            }
+            if let Ok(b) = b {
+                assert_eq!(multiaddr_to_socketaddr(&b.local_multiaddr()).unwrap().port(), port_b);
+            }
+        });
+    }
+
+    #[test]
+    fn dial_with_bound_source() {
+        task::block_on(async {
+            let mut listener = TcpConfig::new().listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+            let addr = listener.multi_addr().cloned().unwrap();
+            let server = task::spawn(async move {
+                match listener.accept().await.unwrap() {
+                    ListenerEvent::Accepted(s) => s.remote_multiaddr(),
+                    _ => panic!("unreachable"),
+                }
+            });
+
+            let source = IpAddr::from(Ipv4Addr::LOCALHOST);
+            let mut tcp = TcpConfig::new().bind_source(Some(source));
+            let socket = tcp.dial(addr).await.unwrap();
+            let la = multiaddr_to_socketaddr(&socket.local_multiaddr()).unwrap();
+            assert_eq!(la.ip(), source);
+
+            let ra = server.await.unwrap();
+            assert_eq!(multiaddr_to_socketaddr(&ra).unwrap(), la);
+        });
+    }
+
+    /// Fails the first operation with `Interrupted`, then reads and writes through.
+    struct Interrupting {
+        interrupted: bool,
+        written: Vec<u8>,
+    }
+
+    impl Interrupting {
+        fn interrupt(&mut self) -> bool {
+            !std::mem::replace(&mut self.interrupted, true)
+        }
+    }
+
+    impl AsyncRead for Interrupting {
+        fn poll_read(mut self: Pin<&mut Self>, _: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+            if self.interrupt() {
+                return Poll::Ready(Err(io::ErrorKind::Interrupted.into()));
+            }
+            buf[0] = 42;
+            Poll::Ready(Ok(1))
+        }
+    }
+
+    impl AsyncWrite for Interrupting {
+        fn poll_write(mut self: Pin<&mut Self>, _: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+            if self.interrupt() {
+                return Poll::Ready(Err(io::ErrorKind::Interrupted.into()));
+            }
+            self.written.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn interrupted_io_is_retried() {
+        task::block_on(async {
+            let mut io = Interrupting {
+                interrupted: false,
+                written: vec![],
+            };
+            let mut buf = [0u8; 4];
+            let n = future::poll_fn(|cx| retry_interrupted(|| Pin::new(&mut io).poll_read(cx, &mut buf)))
+                .await
+                .unwrap();
+            assert_eq!(&buf[..n], &[42]);
+
+            let mut io = Interrupting {
+                interrupted: false,
+                written: vec![],
+            };
+            let n = future::poll_fn(|cx| retry_interrupted(|| Pin::new(&mut io).poll_write(cx, b"hello")))
+                .await
+                .unwrap();
+            assert_eq!(n, 5);
+            assert_eq!(io.written, b"hello");
+        });
+    }
 }
@@ -0,0 +1,269 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Dialing through an HTTP `CONNECT` proxy.
+//!
+//! `ProxyTransport` wraps a `TcpConfig`. On dial, it connects to the proxy, asks
+//! it to open a tunnel to the target address with `CONNECT host:port` and, once
+//! the proxy has answered with a `2xx` status, hands out the tunneled stream to the
+//! upper layers. Listening is not affected by the proxy and is delegated to the
+//! inner `TcpConfig`.
+
+use async_trait::async_trait;
+use futures::{AsyncReadExt, AsyncWriteExt};
+use std::io;
+
+use libp2prs_core::transport::{IListener, ITransport, TransportError};
+use libp2prs_core::{multiaddr::protocol, Multiaddr, Transport};
+
+use crate::{multiaddr_to_socketaddr, TcpConfig, TcpTransStream};
+
+/// The maximum size of the response header we accept from the proxy.
+const MAX_RESPONSE_SIZE: usize = 8192;
+
+/// A TCP transport which dials through an HTTP `CONNECT` proxy.
+#[derive(Debug, Clone)]
+pub struct ProxyTransport {
+    /// The TCP transport used to reach the proxy and to listen.
+    inner: TcpConfig,
+    /// The address of the proxy.
+    proxy: Multiaddr,
+    /// The credentials for the `Basic` proxy authentication, if any.
+    auth: Option<(String, String)>,
+}
+
+impl ProxyTransport {
+    /// Creates a transport which tunnels all outgoing connections through
+    /// the HTTP proxy at `proxy`.
+    pub fn new(inner: TcpConfig, proxy: Multiaddr) -> Self {
+        ProxyTransport { inner, proxy, auth: None }
+    }
+
+    /// Sets the user name and password used to authenticate with the proxy.
+    pub fn with_auth(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.auth = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Builds the `CONNECT` request for `authority`.
+    fn connect_request(&self, authority: &str) -> String {
+        let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+        if let Some((username, password)) = &self.auth {
+            let credentials = base64::encode(format!("{}:{}", username, password));
+            request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", credentials));
+        }
+        request.push_str("\r\n");
+        request
+    }
+}
+
+#[async_trait]
+impl Transport for ProxyTransport {
+    type Output = TcpTransStream;
+
+    fn listen_on(&mut self, addr: Multiaddr) -> Result<IListener<Self::Output>, TransportError> {
+        self.inner.listen_on(addr)
+    }
+
+    async fn dial(&mut self, addr: Multiaddr) -> Result<Self::Output, TransportError> {
+        let socket_addr = multiaddr_to_socketaddr(&addr).map_err(|_| TransportError::MultiaddrNotSupported(addr.clone()))?;
+
+        log::debug!("Dialing {} through proxy {}", addr, self.proxy);
+
+        let mut stream = self.inner.dial(self.proxy.clone()).await?;
+
+        // SocketAddr formats IPv6 addresses with brackets, as required by RFC 7230
+        let request = self.connect_request(&socket_addr.to_string());
+        stream.write_all(request.as_bytes()).await?;
+        stream.flush().await?;
+
+        let response = read_response_header(&mut stream).await?;
+        let status = parse_status(&response)?;
+        if !(200..300).contains(&status) {
+            log::debug!("proxy {} refused to connect to {}: status {}", self.proxy, addr, status);
+            let kind = if status == 407 {
+                io::ErrorKind::PermissionDenied
+            } else {
+                io::ErrorKind::ConnectionRefused
+            };
+            return Err(TransportError::IoError(io::Error::new(
+                kind,
+                format!("proxy CONNECT failed with status {}", status),
+            )));
+        }
+
+        // the stream is now a tunnel to the target
+        stream.ra = addr;
+        Ok(stream)
+    }
+
+    fn box_clone(&self) -> ITransport<Self::Output> {
+        Box::new(self.clone())
+    }
+
+    fn protocols(&self) -> Vec<u32> {
+        vec![protocol::TCP]
+    }
+}
+
+/// Reads the response header, up to and including the empty line.
+///
+/// The header is read byte by byte, so that no tunneled data sent right after
+/// the header is consumed.
+async fn read_response_header(stream: &mut TcpTransStream) -> io::Result<Vec<u8>> {
+    let mut header = Vec::new();
+    let mut byte = [0u8; 1];
+    while !header.ends_with(b"\r\n\r\n") {
+        if header.len() >= MAX_RESPONSE_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "proxy response header too large"));
+        }
+        stream.read_exact(&mut byte).await?;
+        header.push(byte[0]);
+    }
+    Ok(header)
+}
+
+/// Extracts the status code from the status line, e.g. `HTTP/1.1 200 Connection established`.
+fn parse_status(header: &[u8]) -> io::Result<u16> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed proxy response");
+
+    let line = header.split(|b| *b == b'\r').next().ok_or_else(invalid)?;
+    let line = std::str::from_utf8(line).map_err(|_| invalid())?;
+    let mut parts = line.split(' ');
+    let version = parts.next().ok_or_else(invalid)?;
+    if !version.starts_with("HTTP/1.") {
+        return Err(invalid());
+    }
+    parts.next().and_then(|s| s.parse().ok()).ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_status, ProxyTransport};
+    use crate::TcpConfig;
+    use futures::{future, AsyncReadExt, AsyncWriteExt};
+    use libp2prs_core::transport::{ListenerEvent, TransportError};
+    use libp2prs_core::{Multiaddr, Transport};
+    use libp2prs_runtime::task;
+    use std::io;
+
+    /// Starts an echo server, returning its address.
+    fn echo_server() -> Multiaddr {
+        let mut listener = TcpConfig::new().listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+        let addr = listener.multi_addr().cloned().unwrap();
+        task::spawn(async move {
+            loop {
+                if let ListenerEvent::Accepted(socket) = listener.accept().await.unwrap() {
+                    task::spawn(async move {
+                        let (mut reader, mut writer) = socket.split();
+                        let _ = futures::io::copy(&mut reader, &mut writer).await;
+                    });
+                }
+            }
+        });
+        addr
+    }
+
+    /// Starts a minimal CONNECT proxy. It tunnels to the requested target when the
+    /// request carries `expected_auth`, and replies with 407 otherwise.
+    fn connect_proxy(expected_auth: Option<&'static str>) -> Multiaddr {
+        let mut listener = TcpConfig::new().listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+        let addr = listener.multi_addr().cloned().unwrap();
+        task::spawn(async move {
+            loop {
+                let mut socket = match listener.accept().await.unwrap() {
+                    ListenerEvent::Accepted(s) => s,
+                    _ => continue,
+                };
+                task::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut byte = [0u8; 1];
+                    while !request.ends_with(b"\r\n\r\n") {
+                        socket.read_exact(&mut byte).await.unwrap();
+                        request.push(byte[0]);
+                    }
+                    let request = String::from_utf8(request).unwrap();
+
+                    if let Some(auth) = expected_auth {
+                        if !request.contains(&format!("Proxy-Authorization: Basic {}\r\n", auth)) {
+                            socket
+                                .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+                                .await
+                                .unwrap();
+                            return;
+                        }
+                    }
+
+                    let target = request.split(' ').nth(1).unwrap();
+                    let sa: std::net::SocketAddr = target.parse().unwrap();
+                    let mut ma = Multiaddr::from(sa.ip());
+                    ma.push(libp2prs_core::multiaddr::protocol::Protocol::Tcp(sa.port()));
+                    let remote = TcpConfig::new().dial(ma).await.unwrap();
+
+                    socket.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n").await.unwrap();
+
+                    let (mut r1, mut w1) = socket.split();
+                    let (mut r2, mut w2) = remote.split();
+                    let _ = future::select(futures::io::copy(&mut r1, &mut w2), futures::io::copy(&mut r2, &mut w1)).await;
+                });
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn dial_through_proxy() {
+        task::block_on(async {
+            let target = echo_server();
+            let proxy = connect_proxy(Some("dXNlcjpwYXNz"));
+
+            let mut trans = ProxyTransport::new(TcpConfig::new(), proxy).with_auth("user", "pass");
+            let mut socket = trans.dial(target.clone()).await.unwrap();
+            assert_eq!(socket.ra, target);
+
+            socket.write_all(b"hello").await.unwrap();
+            let mut buf = [0u8; 5];
+            socket.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"hello");
+        });
+    }
+
+    #[test]
+    fn dial_through_proxy_rejected() {
+        task::block_on(async {
+            let target = echo_server();
+            let proxy = connect_proxy(Some("dXNlcjpwYXNz"));
+
+            let mut trans = ProxyTransport::new(TcpConfig::new(), proxy).with_auth("user", "wrong");
+            match trans.dial(target).await {
+                Err(TransportError::IoError(e)) => assert_eq!(e.kind(), io::ErrorKind::PermissionDenied),
+                r => panic!("unexpected result {:?}", r.map(|_| ())),
+            }
+        });
+    }
+
+    #[test]
+    fn parse_status_line() {
+        assert_eq!(parse_status(b"HTTP/1.1 200 Connection established\r\n\r\n").unwrap(), 200);
+        assert_eq!(parse_status(b"HTTP/1.0 403 Forbidden\r\n\r\n").unwrap(), 403);
+        assert!(parse_status(b"SSH-2.0-OpenSSH\r\n\r\n").is_err());
+        assert!(parse_status(b"HTTP/1.1 abc\r\n\r\n").is_err());
+    }
+}
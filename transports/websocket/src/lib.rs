@@ -122,7 +122,7 @@ impl Transport for WsConfig {
 
 #[cfg(test)]
 mod tests {
-    use super::WsConfig;
+    use super::{tls, WsConfig};
     use futures::{AsyncReadExt, AsyncWriteExt};
     use libp2prs_core::transport::ListenerEvent;
     use libp2prs_core::Multiaddr;
@@ -163,6 +163,60 @@ mod tests {
         });
     }
 
+    #[test]
+    fn wss_requires_libp2p_alpn() {
+        let listen_addr = "/ip4/127.0.0.1/tcp/38102/wss".parse().unwrap();
+        let dial_addr: Multiaddr = "/dns4/localhost/tcp/38102/wss".parse().unwrap();
+        task::block_on(async {
+            let mut ws_config = WsConfig::new();
+            ws_config.set_tls_config(tls_config(true, true));
+            let mut listener = ws_config.listen_on(listen_addr).expect("listener");
+            let s = task::spawn(async move {
+                let mut stream = match listener.accept().await.expect("no error") {
+                    ListenerEvent::Accepted(s) => s,
+                    _ => panic!("unreachable"),
+                };
+                let mut buf = vec![0_u8; 3];
+                stream.read_exact(&mut buf).await.expect("read_exact");
+                assert_eq!(buf, vec![1, 23, 5]);
+                // the handshake of the client without ALPN fails
+                assert!(listener.accept().await.is_err());
+            });
+
+            let mut ws_config = WsConfig::new_with_dns();
+            ws_config.set_tls_config(tls_config(false, true));
+            let mut conn = ws_config.dial(dial_addr.clone()).await.expect("dial with ALPN");
+            conn.write_all(&[1_u8, 23, 5]).await.expect("write all");
+            conn.close().await.expect("close");
+
+            let mut ws_config = WsConfig::new_with_dns();
+            ws_config.set_tls_config(tls_config(false, false));
+            assert!(ws_config.dial(dial_addr).await.is_err());
+
+            assert_eq!(s.await, Some(()));
+        });
+    }
+
+    fn tls_config(server: bool, alpn: bool) -> tls::Config {
+        use rustls::internal::pemfile::{certs, rsa_private_keys};
+
+        let mut builder = tls::Config::builder();
+        if server {
+            let key = rsa_private_keys(&mut &include_bytes!("../cert/end.rsa")[..]).unwrap().remove(0);
+            let chain = certs(&mut &include_bytes!("../cert/end.cert")[..]).unwrap();
+            builder
+                .server(tls::PrivateKey::new(key.0), chain.into_iter().map(|c| tls::Certificate::new(c.0)))
+                .unwrap();
+        } else {
+            let ca = certs(&mut &include_bytes!("../cert/ca.cert")[..]).unwrap().remove(0);
+            builder.add_trust(&tls::Certificate::new(ca.0)).unwrap();
+        }
+        if alpn {
+            builder.alpn(tls::LIBP2P_ALPN);
+        }
+        builder.finish()
+    }
+
     async fn server(listen_addr: Multiaddr) -> bool {
         let ws_config: WsConfig = WsConfig::new();
         let mut listener = ws_config
@@ -22,6 +22,9 @@
 use async_tls::{TlsAcceptor, TlsConnector};
 use std::{fmt, io, sync::Arc};
 
+/// The ALPN protocol of libp2p over TLS.
+pub const LIBP2P_ALPN: &[u8] = b"libp2p";
+
 /// TLS configuration.
 #[derive(Clone)]
 pub struct Config {
@@ -81,6 +84,7 @@ impl Config {
         Builder {
             client: client_config(),
             server: None,
+            alpn: None,
         }
     }
 }
@@ -97,6 +101,7 @@ fn client_config() -> rustls::ClientConfig {
 pub struct Builder {
     client: rustls::ClientConfig,
     server: Option<rustls::ServerConfig>,
+    alpn: Option<Vec<u8>>,
 }
 
 impl Builder {
@@ -118,8 +123,28 @@ impl Builder {
         Ok(self)
     }
 
+    /// Set the ALPN protocol, offered by the client and required by the server, e.g.
+    /// [`LIBP2P_ALPN`].
+    ///
+    /// The server fails the handshake of the clients which don't offer it, so that a generic
+    /// TLS client can't get in by accident.
+    pub fn alpn(&mut self, protocol: &[u8]) -> &mut Self {
+        self.alpn = Some(protocol.to_vec());
+        self
+    }
+
     /// Finish configuration.
-    pub fn finish(self) -> Config {
+    pub fn finish(mut self) -> Config {
+        if let Some(protocol) = self.alpn {
+            self.client.set_protocols(&[protocol.clone()]);
+            if let Some(server) = self.server.as_mut() {
+                server.set_protocols(&[protocol.clone()]);
+                server.cert_resolver = Arc::new(RequireAlpn {
+                    protocol,
+                    inner: server.cert_resolver.clone(),
+                });
+            }
+        }
         Config {
             client: Arc::new(self.client).into(),
             server: self.server.map(|s| Arc::new(s).into()),
@@ -127,6 +152,26 @@ impl Builder {
     }
 }
 
+/// Resolves the server certificate only for the clients offering the ALPN protocol, the
+/// handshake of the others fails for lack of a certificate.
+struct RequireAlpn {
+    protocol: Vec<u8>,
+    inner: Arc<dyn rustls::ResolvesServerCert>,
+}
+
+impl rustls::ResolvesServerCert for RequireAlpn {
+    fn resolve(&self, client_hello: rustls::ClientHello) -> Option<rustls::sign::CertifiedKey> {
+        let offered = client_hello
+            .alpn()
+            .map_or(false, |protocols| protocols.iter().any(|p| *p == &self.protocol[..]));
+        if !offered {
+            log::debug!("the client doesn't offer the required ALPN protocol");
+            return None;
+        }
+        self.inner.resolve(client_hello)
+    }
+}
+
 pub(crate) fn dns_name_ref(name: &str) -> Result<webpki::DNSNameRef<'_>, Error> {
     webpki::DNSNameRef::try_from_ascii_str(name).map_err(|_| Error::InvalidDnsName(name.into()))
 }